@@ -141,7 +141,7 @@ pub fn generate(definitions: &str) -> String {
 pub enum LocId {{",
         );
 
-        for (k, _) in translations.iter() {
+        for k in translations.keys() {
             _ = writeln!(out, "    {k},");
         }
 
@@ -178,7 +178,9 @@ const LANGUAGES: &[(&str, LangId)] = &[
             "\
 ];
 
-const TRANSLATIONS: [[&str; {}]; {}] = [
+// A `static`, because a `const` table would be inlined wherever it is used.
+// With enough strings, clippy also rejects the `const` as a `large_const_arrays`.
+static TRANSLATIONS: [[&str; {}]; {}] = [
 ",
             translations.len(),
             languages.len(),
@@ -186,8 +188,9 @@ const TRANSLATIONS: [[&str; {}]; {}] = [
 
         for lang in &languages {
             _ = writeln!(out, "    [");
-            for (_, v) in translations.iter() {
+            for v in translations.values() {
                 const DEFAULT: &String = &String::new();
+                // Untranslated strings intentionally fall back to English. See edit.toml.
                 let v = v.get(lang).or_else(|| v.get("en")).unwrap_or(DEFAULT);
                 _ = writeln!(out, "        {v:?},");
             }
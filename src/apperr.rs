@@ -8,6 +8,7 @@ use std::{io, result};
 use crate::sys;
 
 pub const APP_ICU_MISSING: Error = Error::new_app(0);
+pub const APP_REGEX_INVALID: Error = Error::new_app(1);
 
 /// Edit's transparent `Result` type.
 pub type Result<T> = result::Result<T, Error>;
@@ -0,0 +1,188 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A parser for the subset of TOML that our config files use:
+//! `[section]` headers and `key = value` pairs, where keys are bare or quoted
+//! and values are strings, integers or booleans. Anything else is skipped.
+
+use std::fs;
+use std::path::PathBuf;
+
+use edit::sys;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Entry<'a> {
+    /// The name of the enclosing `[section]`, or "" at the top.
+    pub section: &'a str,
+    pub key: String,
+    pub value: Value,
+}
+
+/// Returns the path of the config file with the given name.
+pub fn path(name: &str) -> Option<PathBuf> {
+    Some(sys::config_dir()?.join(name))
+}
+
+/// Reads the config file with the given name.
+/// A missing or unreadable file is treated as empty.
+pub fn read(name: &str) -> String {
+    path(name).and_then(|p| fs::read_to_string(p).ok()).unwrap_or_default()
+}
+
+/// Parses `text`. Malformed lines are skipped.
+pub fn parse(text: &str) -> Vec<Entry<'_>> {
+    let mut entries = Vec::new();
+    let mut section = "";
+
+    for line in text.lines() {
+        let line = line.trim();
+
+        if let Some(rest) = line.strip_prefix('[') {
+            if let Some((name, rest)) = rest.split_once(']')
+                && is_end_of_line(rest)
+            {
+                section = name.trim();
+            }
+            continue;
+        }
+
+        let Some((key, rest)) = parse_key(line) else {
+            continue;
+        };
+        let Some(rest) = rest.trim_start().strip_prefix('=') else {
+            continue;
+        };
+        let Some((value, rest)) = parse_value(rest.trim_start()) else {
+            continue;
+        };
+        if is_end_of_line(rest) {
+            entries.push(Entry { section, key, value });
+        }
+    }
+
+    entries
+}
+
+fn is_end_of_line(rest: &str) -> bool {
+    let rest = rest.trim_start();
+    rest.is_empty() || rest.starts_with('#')
+}
+
+fn parse_key(line: &str) -> Option<(String, &str)> {
+    if line.starts_with(['"', '\'']) {
+        return parse_string(line);
+    }
+    let end = line
+        .find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_' || ch == '-' || ch == '.'))
+        .unwrap_or(line.len());
+    if end == 0 {
+        return None;
+    }
+    Some((line[..end].to_string(), &line[end..]))
+}
+
+fn parse_value(str: &str) -> Option<(Value, &str)> {
+    if str.starts_with(['"', '\'']) {
+        let (s, rest) = parse_string(str)?;
+        return Some((Value::String(s), rest));
+    }
+
+    let end = str.find(|ch: char| ch.is_ascii_whitespace() || ch == '#').unwrap_or(str.len());
+    let (word, rest) = str.split_at(end);
+    let value = match word {
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        _ => Value::Integer(word.replace('_', "").parse().ok()?),
+    };
+    Some((value, rest))
+}
+
+/// Parses a basic ("...") or literal ('...') string at the start of `str`.
+fn parse_string(str: &str) -> Option<(String, &str)> {
+    let quote = str.chars().next()?;
+    let mut res = String::new();
+    let mut chars = str[1..].char_indices();
+
+    while let Some((i, ch)) = chars.next() {
+        if ch == quote {
+            return Some((res, &str[1 + i + 1..]));
+        }
+        if ch == '\\' && quote == '"' {
+            res.push(match chars.next()?.1 {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                '"' => '"',
+                '\\' => '\\',
+                _ => return None,
+            });
+        } else {
+            res.push(ch);
+        }
+    }
+
+    None
+}
+
+/// Formats `str` as a basic string that [`parse`] reads back unchanged.
+pub fn quote(str: &str) -> String {
+    let mut res = String::with_capacity(str.len() + 2);
+    res.push('"');
+    for ch in str.chars() {
+        match ch {
+            '\n' => res.push_str("\\n"),
+            '\t' => res.push_str("\\t"),
+            '\r' => res.push_str("\\r"),
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            _ => res.push(ch),
+        }
+    }
+    res.push('"');
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = r#"
+# A comment
+top = 1
+
+[keybindings]
+"ctrl+k ctrl+c" = "edit.copy"  # trailing comment
+'ctrl+q' = ''
+bare-key_1 = "a \"quoted\" \\ string"
+
+[settings]
+tab_size = 1_000
+word_wrap = true
+broken = "unterminated
+also broken = 1
+no_value =
+"#;
+        let entries = parse(text);
+        let e = |section, key: &str, value| Entry { section, key: key.to_string(), value };
+        assert_eq!(
+            entries,
+            vec![
+                e("", "top", Value::Integer(1)),
+                e("keybindings", "ctrl+k ctrl+c", Value::String("edit.copy".into())),
+                e("keybindings", "ctrl+q", Value::String("".into())),
+                e("keybindings", "bare-key_1", Value::String(r#"a "quoted" \ string"#.into())),
+                e("settings", "tab_size", Value::Integer(1000)),
+                e("settings", "word_wrap", Value::Boolean(true)),
+            ]
+        );
+    }
+}
@@ -1,15 +1,21 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
+use std::borrow::Cow;
 use std::collections::LinkedList;
-use std::ffi::OsStr;
-use std::fs::File;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::Write as _;
 use std::path::{Path, PathBuf};
+use std::process;
 
 use edit::buffer::{RcTextBuffer, TextBuffer};
 use edit::helpers::{CoordType, Point};
-use edit::{apperr, path, sys};
+use edit::{apperr, hash, icu, path, syntax, sys};
 
+use crate::editorconfig;
+use crate::recovery::{self, RecoveryFile};
+use crate::settings::Settings;
 use crate::state::DisplayablePathBuf;
 
 pub struct Document {
@@ -18,17 +24,38 @@ pub struct Document {
     pub dir: Option<DisplayablePathBuf>,
     pub filename: String,
     pub file_id: Option<sys::FileId>,
+    /// The state of the file on disk when we last read or wrote it.
+    pub file_stamp: Option<sys::FileStamp>,
+    /// Keeps the directory of the file watched, while the document is open.
+    dir_watch: Option<sys::DirWatch>,
+    /// Set by [`DocumentManager::check_disk_changes`] until the user decided what to do.
+    pub changed_on_disk: bool,
+    /// Whether to keep a copy of the previous file contents as `file~` when saving.
+    pub backup: bool,
+    /// Whether to remove trailing whitespace when saving, as requested by an `.editorconfig`.
+    pub trim_trailing_whitespace: bool,
+    /// The recovery file, if any, and the generation of the buffer it contains.
+    recovery_file: Option<PathBuf>,
+    recovery_generation: Option<u32>,
     pub new_file_counter: usize,
 }
 
 impl Document {
     pub fn save(&mut self, new_path: Option<PathBuf>) -> apperr::Result<()> {
         let path = new_path.as_deref().unwrap_or_else(|| self.path.as_ref().unwrap().as_path());
-        let mut file = DocumentManager::open_for_writing(path)?;
 
         {
             let mut tb = self.buffer.borrow_mut();
-            tb.write_file(&mut file)?;
+            if self.trim_trailing_whitespace {
+                tb.trim_trailing_whitespace();
+            }
+            let res =
+                DocumentManager::write_file(path, self.backup, &mut |file| tb.write_file(file));
+            if let Err(err) = res {
+                // `write_file` marks the buffer as clean, even if the file didn't make it to disk.
+                tb.mark_as_dirty();
+                return Err(err);
+            }
         }
 
         if let Ok(id) = sys::file_id(None, path) {
@@ -39,9 +66,44 @@ impl Document {
             self.set_path(path);
         }
 
+        self.update_file_stamp();
+
+        self.save_history();
         Ok(())
     }
 
+    /// Writes the undo history into the undo file, if the user opted into them.
+    /// It's only valid as long as the file on disk matches the buffer contents.
+    ///
+    /// Like recovery files, undo files may contain secrets that were deleted from the document.
+    /// Only the current user can access them, and they're replaced atomically.
+    fn save_history(&self) {
+        let Some(path) = self.path.as_deref().and_then(DocumentManager::undo_file_path) else {
+            return;
+        };
+
+        let tb = self.buffer.borrow();
+        if tb.is_dirty() || !tb.has_history() {
+            return;
+        }
+
+        let mut data = Vec::new();
+        tb.serialize_history(&mut data);
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        // Left over if the previous attempt failed halfway.
+        _ = fs::remove_file(&tmp);
+
+        let res = sys::create_private_file(&tmp)
+            .and_then(|mut file| Ok(file.write_all(&data)?))
+            .and_then(|_| Ok(fs::rename(&tmp, &path)?));
+        if res.is_err() {
+            _ = fs::remove_file(&tmp);
+        }
+    }
+
     pub fn reread(&mut self, encoding: Option<&'static str>) -> apperr::Result<()> {
         let path = self.path.as_ref().unwrap().as_path();
         let mut file = DocumentManager::open_for_reading(path)?;
@@ -55,13 +117,48 @@ impl Document {
             self.file_id = Some(id);
         }
 
+        self.update_file_stamp();
         Ok(())
     }
 
+    /// Returns whether the file on disk was modified since we last read or wrote it.
+    /// A deleted file doesn't count, since saving will simply recreate it.
+    pub fn is_changed_on_disk(&self) -> bool {
+        self.path.as_deref().is_some_and(
+            |path| matches!(sys::file_stamp(path), Ok(stamp) if Some(stamp) != self.file_stamp),
+        )
+    }
+
+    /// Forgets about any changes on disk. Used when the user wants to keep their version.
+    pub fn update_file_stamp(&mut self) {
+        self.file_stamp = self.path.as_deref().and_then(|path| sys::file_stamp(path).ok());
+        self.changed_on_disk = false;
+    }
+
+    /// Returns whether the recovery file is out of date: Either the buffer changed
+    /// since it was written, or it was saved and the recovery file is now unnecessary.
+    fn recovery_pending(&self) -> bool {
+        let tb = self.buffer.borrow();
+        if tb.is_dirty() {
+            self.recovery_generation != Some(tb.generation())
+        } else {
+            self.recovery_file.is_some()
+        }
+    }
+
+    fn remove_recovery_file(&mut self) {
+        if let Some(file) = self.recovery_file.take() {
+            recovery::remove(&file);
+        }
+        self.recovery_generation = None;
+    }
+
     fn set_path(&mut self, path: PathBuf) {
         let filename = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
         let dir = path.parent().map(ToOwned::to_owned).unwrap_or_default();
         self.filename = filename;
+        // Replaces the watch of the previous directory, if any.
+        self.dir_watch = sys::watch_directory(&dir);
         self.dir = Some(DisplayablePathBuf::from_path(dir));
         self.path = Some(path);
         self.update_file_mode();
@@ -69,13 +166,22 @@ impl Document {
 
     fn update_file_mode(&mut self) {
         let mut tb = self.buffer.borrow_mut();
-        tb.set_ruler(if self.filename == "COMMIT_EDITMSG" { 72 } else { 0 });
+        // Otherwise the ruler is left to the settings and the `.editorconfig`.
+        if self.filename == "COMMIT_EDITMSG" {
+            tb.set_ruler(72);
+        }
+        tb.set_language(self.path.as_deref().and_then(syntax::language_from_path));
     }
 }
 
 #[derive(Default)]
 pub struct DocumentManager {
     list: LinkedList<Document>,
+    /// The default for [`Document::backup`].
+    pub backup_files: bool,
+    /// The settings new documents start out with.
+    settings: Settings,
+    recovery_counter: usize,
 }
 
 impl DocumentManager {
@@ -84,6 +190,11 @@ impl DocumentManager {
         self.list.len()
     }
 
+    /// Iterates over the documents, starting with the active one.
+    pub fn iter(&self) -> impl Iterator<Item = &Document> {
+        self.list.iter()
+    }
+
     #[inline]
     pub fn active(&self) -> Option<&Document> {
         self.list.front()
@@ -108,18 +219,160 @@ impl DocumentManager {
         false
     }
 
+    /// Changes the settings for new documents and applies
+    /// the ones that changed to all open documents.
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        for doc in &self.list {
+            settings.apply_changes(&self.settings, &mut doc.buffer.borrow_mut());
+        }
+        self.settings = settings.clone();
+    }
+
+    /// Undo files are opt-in: They're only used if the `undo` directory
+    /// in the [`sys::state_dir`] exists, similar to Vim's `undodir`.
+    /// The README tells users how to create it.
+    fn undo_file_path(path: &Path) -> Option<PathBuf> {
+        let dir = sys::state_dir()?.join("undo");
+        if !dir.is_dir() {
+            return None;
+        }
+
+        let name = format!("{:016x}.undo", hash::hash(0, path.as_os_str().as_encoded_bytes()));
+        Some(dir.join(name))
+    }
+
+    /// Flags all documents whose file was modified by someone else.
+    /// Returns true if any new document got flagged.
+    pub fn check_disk_changes(&mut self) -> bool {
+        let mut any = false;
+        for doc in &mut self.list {
+            if !doc.changed_on_disk && doc.is_changed_on_disk() {
+                doc.changed_on_disk = true;
+                any = true;
+            }
+        }
+        any
+    }
+
     pub fn remove_active(&mut self) {
-        self.list.pop_front();
+        if let Some(mut doc) = self.list.pop_front() {
+            // Undo & redo may have changed the history since the last save.
+            doc.save_history();
+            doc.remove_recovery_file();
+        }
+    }
+
+    /// Returns whether [`DocumentManager::write_recovery_files`] has anything to do.
+    pub fn recovery_pending(&self) -> bool {
+        self.list.iter().any(Document::recovery_pending)
+    }
+
+    /// Writes snapshots of the documents modified since their last snapshot into their
+    /// recovery files, and deletes the recovery files of those that have been saved since.
+    pub fn write_recovery_files(&mut self) {
+        for doc in &mut self.list {
+            if !doc.recovery_pending() {
+                continue;
+            }
+
+            let generation = {
+                let tb = doc.buffer.borrow();
+                tb.is_dirty().then(|| tb.generation())
+            };
+            let Some(generation) = generation else {
+                doc.remove_recovery_file();
+                continue;
+            };
+
+            if doc.recovery_file.is_none() {
+                self.recovery_counter += 1;
+                doc.recovery_file = recovery::new_file_path(self.recovery_counter);
+            }
+            if let Some(file) = &doc.recovery_file {
+                // This is best-effort. There's nobody we could report an error to anyway.
+                _ = recovery::write(file, &doc.buffer.borrow(), doc.path.as_deref(), &doc.filename);
+            }
+            // Even if writing failed, so that we don't retry until the next modification.
+            doc.recovery_generation = Some(generation);
+        }
+    }
+
+    /// Deletes all recovery files. Used when exiting normally.
+    pub fn remove_recovery_files(&mut self) {
+        for doc in &mut self.list {
+            doc.remove_recovery_file();
+        }
+    }
+
+    /// Opens a document from a recovery file left behind by a crashed instance.
+    /// The recovery file becomes the document's own, so that it's kept until it gets
+    /// replaced by the next snapshot, or the document is saved or closed.
+    pub fn add_recovered(&mut self, rf: &RecoveryFile) -> apperr::Result<&mut Document> {
+        let buffer = self.create_buffer()?;
+        {
+            let mut file = recovery::open(rf)?;
+            let mut tb = buffer.borrow_mut();
+            // The recovery file contains the buffer contents as-is.
+            tb.read_file(&mut file, Some("UTF-8"))?;
+
+            let encodings = icu::get_available_encodings();
+            if let Some(enc) = encodings.all.iter().find(|enc| enc.canonical == rf.encoding) {
+                tb.set_encoding(enc.canonical);
+            }
+            tb.mark_as_dirty();
+        }
+
+        let mut doc = Document {
+            buffer,
+            path: None,
+            dir: None,
+            filename: Default::default(),
+            file_id: None,
+            file_stamp: None,
+            dir_watch: None,
+            changed_on_disk: false,
+            backup: self.backup_files,
+            trim_trailing_whitespace: false,
+            recovery_file: None,
+            recovery_generation: None,
+            new_file_counter: 0,
+        };
+
+        // Rename it after this instance, so that others don't offer to recover it, too.
+        self.recovery_counter += 1;
+        doc.recovery_file = match recovery::new_file_path(self.recovery_counter) {
+            Some(file) if fs::rename(&rf.file, &file).is_ok() => Some(file),
+            _ => Some(rf.file.clone()),
+        };
+
+        if let Some(path) = &rf.path {
+            doc.file_id = sys::file_id(None, path).ok();
+            doc.set_path(path.clone());
+            doc.update_file_stamp();
+        } else {
+            self.gen_untitled_name(&mut doc);
+        }
+
+        self.remove_active_if_pristine();
+        self.list.push_front(doc);
+        Ok(self.list.front_mut().unwrap())
     }
 
     pub fn add_untitled(&mut self) -> apperr::Result<&mut Document> {
-        let buffer = Self::create_buffer()?;
+        let buffer = self.create_buffer()?;
         let mut doc = Document {
             buffer,
             path: None,
             dir: Default::default(),
             filename: Default::default(),
             file_id: None,
+            file_stamp: None,
+            dir_watch: None,
+            changed_on_disk: false,
+            backup: self.backup_files,
+            trim_trailing_whitespace: false,
+            recovery_file: None,
+            recovery_generation: None,
             new_file_counter: 0,
         };
         self.gen_untitled_name(&mut doc);
@@ -160,17 +413,27 @@ impl DocumentManager {
             return Ok(doc);
         }
 
-        let buffer = Self::create_buffer()?;
+        let editorconfig = editorconfig::Properties::resolve(&path);
+        let buffer = self.create_buffer()?;
         {
+            let mut tb = buffer.borrow_mut();
+
             if let Some(file) = &mut file {
-                let mut tb = buffer.borrow_mut();
-                tb.read_file(file, None)?;
+                tb.read_file(file, editorconfig.read_encoding())?;
+
+                if let Some(undo_path) = Self::undo_file_path(&path)
+                    && let Ok(data) = fs::read(undo_path)
+                {
+                    tb.deserialize_history(&data);
+                }
 
                 if let Some(goto) = goto
                     && goto != Default::default()
                 {
                     tb.cursor_move_to_logical(goto);
                 }
+            } else if let Some(encoding) = editorconfig.encoding() {
+                tb.set_encoding(encoding);
             }
         }
 
@@ -180,22 +443,37 @@ impl DocumentManager {
             dir: None,
             filename: Default::default(),
             file_id,
+            file_stamp: None,
+            dir_watch: None,
+            changed_on_disk: false,
+            backup: self.backup_files,
+            trim_trailing_whitespace: editorconfig.trim_trailing_whitespace.unwrap_or(false),
+            recovery_file: None,
+            recovery_generation: None,
             new_file_counter: 0,
         };
         doc.set_path(path);
+        doc.update_file_stamp();
+
+        // An explicit configuration takes precedence over what `read_file` detected
+        // and the defaults that `set_path` picked for the file name.
+        editorconfig.apply(&mut doc.buffer.borrow_mut());
 
+        self.remove_active_if_pristine();
+        self.list.push_front(doc);
+        Ok(self.list.front_mut().unwrap())
+    }
+
+    /// If the current document is a pristine Untitled document with no
+    /// name and no ID, we remove it, so that a new document can replace it.
+    fn remove_active_if_pristine(&mut self) {
         if let Some(active) = self.active()
             && active.path.is_none()
             && active.file_id.is_none()
             && !active.buffer.borrow().is_dirty()
         {
-            // If the current document is a pristine Untitled document with no
-            // name and no ID, replace it with the new document.
             self.remove_active();
         }
-
-        self.list.push_front(doc);
-        Ok(self.list.front_mut().unwrap())
     }
 
     pub fn reflow_all(&self) {
@@ -213,12 +491,72 @@ impl DocumentManager {
         File::create(path).map_err(apperr::Error::from)
     }
 
-    fn create_buffer() -> apperr::Result<RcTextBuffer> {
+    /// Saves a file by having `write` write it to a temporary file in the same directory,
+    /// which then atomically replaces the original. This way, a crash or a full disk can't
+    /// leave a half-written file behind. If the original can't be replaced without losing
+    /// its permissions, ownership or hard links, we fall back to writing it in place.
+    /// New files are written in place as well, so that they get the default permissions.
+    ///
+    /// If `backup` is true, the previous contents are kept as `path~`.
+    fn write_file(
+        path: &Path,
+        backup: bool,
+        write: &mut dyn FnMut(&mut File) -> apperr::Result<()>,
+    ) -> apperr::Result<()> {
+        // Replace the file a symlink points to, not the symlink itself.
+        // If it's dangling, writing in place will create the file it points to.
+        let target = match fs::symlink_metadata(path) {
+            Ok(meta) if meta.is_symlink() => fs::canonicalize(path).ok().map(Cow::Owned),
+            _ => Some(Cow::Borrowed(path)),
+        };
+
+        if let Some(target) = target
+            && target.exists()
+        {
+            if backup {
+                let mut backup_path = target.as_os_str().to_owned();
+                backup_path.push("~");
+                fs::copy(&target, backup_path)?;
+            }
+
+            let mut tmp_name = OsString::from(".");
+            tmp_name.push(target.file_name().unwrap_or_default());
+            tmp_name.push(format!(".{}.tmp", process::id()));
+            let tmp_path = target.with_file_name(tmp_name);
+
+            // Only we can read the temporary file until `replace_file` gives it the permissions of the original.
+            if let Ok(mut file) = sys::create_private_file(&tmp_path) {
+                // If writing fails, writing in place won't succeed either, but would destroy the original.
+                if let Err(err) = write(&mut file).and_then(|_| Ok(file.sync_all()?)) {
+                    _ = fs::remove_file(&tmp_path);
+                    return Err(err);
+                }
+                drop(file);
+
+                if sys::replace_file(&tmp_path, &target).is_ok() {
+                    // Without this, the rename may not survive a crash. Still, the file has been saved
+                    // at this point, and reporting an error would mark the document as dirty again.
+                    _ = sys::sync_parent_dir(&target);
+                    return Ok(());
+                }
+            }
+
+            // Don't leave the temporary file behind when falling back to writing in place,
+            // including a stale one that prevented us from creating it.
+            _ = fs::remove_file(&tmp_path);
+        }
+
+        let mut file = Self::open_for_writing(path)?;
+        write(&mut file)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    fn create_buffer(&self) -> apperr::Result<RcTextBuffer> {
         let buffer = TextBuffer::new_rc(false)?;
         {
             let mut tb = buffer.borrow_mut();
-            tb.set_insert_final_newline(!cfg!(windows)); // As mandated by POSIX.
-            tb.set_margin_enabled(true);
+            self.settings.apply(&mut tb);
             tb.set_line_highlight_enabled(true);
         }
         Ok(buffer)
@@ -226,7 +564,7 @@ impl DocumentManager {
 
     // Parse a filename in the form of "filename:line:char".
     // Returns the position of the first colon and the line/char coordinates.
-    fn parse_filename_goto(path: &Path) -> (&Path, Option<Point>) {
+    pub fn parse_filename_goto(path: &Path) -> (&Path, Option<Point>) {
         fn parse(s: &[u8]) -> Option<CoordType> {
             if s.is_empty() {
                 return None;
@@ -286,6 +624,225 @@ impl DocumentManager {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_write_file() {
+        let dir = std::env::temp_dir().join(format!("edit-test-write-file-{}", process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("file.txt");
+        let write = |contents: &'static str| {
+            move |file: &mut File| -> apperr::Result<()> {
+                use std::io::Write as _;
+                Ok(file.write_all(contents.as_bytes())?)
+            }
+        };
+
+        // New files get created.
+        DocumentManager::write_file(&path, false, &mut write("a")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a");
+
+        // Permissions survive the replacement.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{MetadataExt as _, PermissionsExt as _};
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+            let ino = fs::metadata(&path).unwrap().ino();
+            DocumentManager::write_file(&path, false, &mut write("b")).unwrap();
+            let meta = fs::metadata(&path).unwrap();
+            assert_eq!(meta.permissions().mode() & 0o777, 0o640);
+            // A new inode means that the file was replaced and not written in place.
+            assert_ne!(meta.ino(), ino);
+        }
+
+        // Backups contain the previous contents.
+        DocumentManager::write_file(&path, true, &mut write("c")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "c");
+        assert_eq!(fs::read_to_string(dir.join("file.txt~")).unwrap(), "b");
+
+        // Symlinks stay symlinks.
+        #[cfg(unix)]
+        {
+            let link = dir.join("link.txt");
+            std::os::unix::fs::symlink(&path, &link).unwrap();
+            DocumentManager::write_file(&link, false, &mut write("d")).unwrap();
+            assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+            assert_eq!(fs::read_to_string(&path).unwrap(), "d");
+        }
+
+        // Files with other hard links are written in place.
+        #[cfg(unix)]
+        {
+            let hard_link = dir.join("hard_link.txt");
+            fs::hard_link(&path, &hard_link).unwrap();
+            DocumentManager::write_file(&path, false, &mut write("e")).unwrap();
+            assert_eq!(fs::read_to_string(&hard_link).unwrap(), "e");
+            fs::remove_file(&hard_link).unwrap();
+        }
+
+        // So are files whose temporary file can't be created, which then gets cleaned up.
+        fs::write(dir.join(format!(".file.txt.{}.tmp", process::id())), "stale").unwrap();
+        DocumentManager::write_file(&path, false, &mut write("f")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "f");
+
+        // No temporary files are left behind.
+        let count = fs::read_dir(&dir).unwrap().count();
+        assert_eq!(count, if cfg!(unix) { 3 } else { 2 });
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_stamp() {
+        let dir = std::env::temp_dir().join(format!("edit-test-file-stamp-{}", process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("file.txt");
+        let mtime = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        let write = |contents: &str, path: &Path| {
+            fs::write(path, contents).unwrap();
+            File::options().write(true).open(path).unwrap().set_modified(mtime).unwrap();
+        };
+
+        write("a", &path);
+        let stamp = sys::file_stamp(&path).unwrap();
+        assert!(stamp == sys::file_stamp(&path).unwrap());
+
+        // Changing the size is noticed, even if the modification time stays the same.
+        write("ab", &path);
+        assert!(stamp != sys::file_stamp(&path).unwrap());
+
+        // So is changing the modification time.
+        write("a", &path);
+        let stamp = sys::file_stamp(&path).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now())
+            .unwrap();
+        assert!(stamp != sys::file_stamp(&path).unwrap());
+
+        // Replacing the file by renaming another one over it is noticed via the inode,
+        // even if size and modification time are identical.
+        #[cfg(unix)]
+        {
+            write("a", &path);
+            let stamp = sys::file_stamp(&path).unwrap();
+            let tmp = dir.join("file.tmp");
+            write("b", &tmp);
+            fs::rename(&tmp, &path).unwrap();
+            assert!(stamp != sys::file_stamp(&path).unwrap());
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_disk_changes() {
+        let dir = std::env::temp_dir().join(format!("edit-test-disk-changes-{}", process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("file.txt");
+        fs::write(&path, "a\n").unwrap();
+
+        // Avoids `add_file_path`, which needs the scratch arenas (see `scratch_init`).
+        let mut docs = DocumentManager::default();
+        let doc = docs.add_untitled().unwrap();
+        doc.set_path(path.clone());
+        doc.update_file_stamp();
+        docs.add_untitled().unwrap();
+        assert!(!docs.check_disk_changes());
+
+        // Only the document of the modified file gets flagged, and only once.
+        fs::write(&path, "ab\n").unwrap();
+        assert!(docs.check_disk_changes());
+        assert_eq!(docs.iter().filter(|doc| doc.changed_on_disk).count(), 1);
+        assert!(!docs.check_disk_changes());
+
+        // Keeping our version forgets about the change.
+        docs.update_active(|doc| doc.path.is_some());
+        docs.active_mut().unwrap().update_file_stamp();
+        assert!(!docs.active().unwrap().changed_on_disk);
+        assert!(!docs.check_disk_changes());
+
+        // Saving updates the stamp, so our own writes aren't reported.
+        docs.active_mut().unwrap().save(None).unwrap();
+        assert!(!docs.check_disk_changes());
+
+        // A deleted file doesn't count, since saving recreates it.
+        fs::remove_file(&path).unwrap();
+        assert!(!docs.check_disk_changes());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Sets up the scratch arenas that `add_file_path` needs. They're global,
+    /// so the tests that use them must hold the returned lock.
+    fn scratch_init() -> std::sync::MutexGuard<'static, ()> {
+        static INIT: std::sync::Once = std::sync::Once::new();
+        static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+        INIT.call_once(|| edit::arena::init(crate::SCRATCH_ARENA_CAPACITY).unwrap());
+        LOCK.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    #[test]
+    fn test_add_file_path_ruler() {
+        let _scratch = scratch_init();
+        let dir = std::env::temp_dir().join(format!("edit-test-ruler-{}", process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("file.txt"), "a\n").unwrap();
+        fs::write(dir.join("COMMIT_EDITMSG"), "a\n").unwrap();
+
+        let mut docs = DocumentManager::default();
+        docs.apply_settings(&Settings { ruler: 80, ..Default::default() });
+
+        let doc = docs.add_file_path(&dir.join("file.txt")).unwrap();
+        assert_eq!(doc.buffer.borrow().ruler(), 80);
+        let doc = docs.add_file_path(&dir.join("COMMIT_EDITMSG")).unwrap();
+        assert_eq!(doc.buffer.borrow().ruler(), 72);
+
+        // Renaming the file via Save As keeps it, too.
+        docs.update_active(|doc| doc.filename == "file.txt");
+        let doc = docs.active_mut().unwrap();
+        doc.save(Some(dir.join("other.txt"))).unwrap();
+        assert_eq!(doc.buffer.borrow().ruler(), 80);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_add_file_path_editorconfig() {
+        let _scratch = scratch_init();
+        let dir = std::env::temp_dir().join(format!("edit-test-editorconfig-{}", process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(".editorconfig"),
+            "root = true\n[*]\nmax_line_length = 100\nindent_style = tab\ntab_width = 8\n",
+        )
+        .unwrap();
+        fs::write(dir.join("file.txt"), "a\n    b\n").unwrap();
+        fs::write(dir.join("COMMIT_EDITMSG"), "a\n").unwrap();
+
+        let mut docs = DocumentManager::default();
+        docs.apply_settings(&Settings { ruler: 80, ..Default::default() });
+
+        // The `.editorconfig` beats both the settings and what was detected from the contents.
+        let doc = docs.add_file_path(&dir.join("file.txt")).unwrap();
+        let tb = doc.buffer.borrow();
+        assert_eq!(tb.ruler(), 100);
+        assert!(tb.indent_with_tabs());
+        assert_eq!(tb.tab_size(), 8);
+        drop(tb);
+
+        // ...and the default for commit messages.
+        let doc = docs.add_file_path(&dir.join("COMMIT_EDITMSG")).unwrap();
+        assert_eq!(doc.buffer.borrow().ruler(), 100);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn test_parse_last_numbers() {
         fn parse(s: &str) -> (&str, Option<Point>) {
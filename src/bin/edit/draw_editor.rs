@@ -1,19 +1,24 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
+use std::mem;
 use std::num::ParseIntError;
+use std::time::SystemTime;
 
-use edit::framebuffer::IndexedColor;
+use edit::arena::{Arena, ArenaString};
+use edit::framebuffer::{Attributes, IndexedColor};
 use edit::helpers::*;
-use edit::icu;
 use edit::input::{kbmod, vk};
 use edit::tui::*;
+use edit::{arena_format, syntax};
 
+use crate::documents::DocumentManager;
 use crate::localization::*;
 use crate::state::*;
+use crate::{recovery, search_history};
 
 pub fn draw_editor(ctx: &mut Context, state: &mut State) {
-    if !matches!(state.wants_search.kind, StateSearchKind::Hidden | StateSearchKind::Disabled) {
+    if state.wants_search.kind != StateSearchKind::Hidden {
         draw_search(ctx, state);
     }
 
@@ -37,18 +42,13 @@ pub fn draw_editor(ctx: &mut Context, state: &mut State) {
 }
 
 fn draw_search(ctx: &mut Context, state: &mut State) {
-    if let Err(err) = icu::init() {
-        error_log_add(ctx, state, err);
-        state.wants_search.kind = StateSearchKind::Disabled;
-        return;
-    }
-
-    let Some(doc) = state.documents.active() else {
+    let Some(buffer) = state.documents.active().map(|doc| doc.buffer.clone()) else {
         state.wants_search.kind = StateSearchKind::Hidden;
         return;
     };
 
     let mut action = None;
+    let mut remember = false;
     let mut focus = StateSearchKind::Hidden;
 
     if state.wants_search.focus {
@@ -57,7 +57,10 @@ fn draw_search(ctx: &mut Context, state: &mut State) {
 
         // If the selection is empty, focus the search input field.
         // Otherwise, focus the replace input field, if it exists.
-        if let Some(selection) = doc.buffer.borrow_mut().extract_user_selection(false) {
+        // When searching within the selection, it's the scope and not the needle.
+        if !state.search_options.in_selection
+            && let Some(selection) = buffer.borrow_mut().extract_user_selection(false)
+        {
             state.search_needle = String::from_utf8_lossy_owned(selection);
             focus = state.wants_search.kind;
         }
@@ -69,7 +72,12 @@ fn draw_search(ctx: &mut Context, state: &mut State) {
     ctx.attr_foreground_rgba(ctx.indexed(IndexedColor::Black));
     {
         if ctx.contains_focus() && ctx.consume_shortcut(vk::ESCAPE) {
-            state.wants_search.kind = StateSearchKind::Hidden;
+            if state.wants_search_history != StateSearchHistory::None {
+                state.wants_search_history = StateSearchHistory::None;
+            } else {
+                state.wants_search.kind = StateSearchKind::Hidden;
+                buffer.borrow_mut().find_clear();
+            }
         }
 
         ctx.table_begin("needle");
@@ -78,6 +86,7 @@ fn draw_search(ctx: &mut Context, state: &mut State) {
             {
                 ctx.table_next_row();
                 ctx.label("label", loc(LocId::SearchNeedleLabel));
+                draw_search_history_button(ctx, state, StateSearchHistory::Needles);
 
                 if ctx.editline("needle", &mut state.search_needle) {
                     action = Some(SearchAction::Search);
@@ -90,7 +99,23 @@ fn draw_search(ctx: &mut Context, state: &mut State) {
                 if focus == StateSearchKind::Search {
                     ctx.steal_focus();
                 }
-                if ctx.is_focused() && ctx.consume_shortcut(vk::RETURN) {
+                if ctx.is_focused() {
+                    if ctx.consume_shortcut(vk::RETURN) {
+                        action = Some(SearchAction::Search);
+                        remember = true;
+                    } else if let Some(older) = consume_history_shortcut(ctx)
+                        && search_history::recall(
+                            &state.search_history.needles,
+                            &mut state.search_needle,
+                            older,
+                        )
+                    {
+                        action = Some(SearchAction::Search);
+                    }
+                }
+                if state.wants_search_history == StateSearchHistory::Needles
+                    && draw_search_history_list(ctx, state, StateSearchHistory::Needles)
+                {
                     action = Some(SearchAction::Search);
                 }
             }
@@ -98,6 +123,7 @@ fn draw_search(ctx: &mut Context, state: &mut State) {
             if state.wants_search.kind == StateSearchKind::Replace {
                 ctx.table_next_row();
                 ctx.label("label", loc(LocId::SearchReplacementLabel));
+                draw_search_history_button(ctx, state, StateSearchHistory::Replacements);
 
                 ctx.editline("replacement", &mut state.search_replacement);
                 ctx.attr_intrinsic_size(Size { width: COORD_TYPE_SAFE_MAX, height: 1 });
@@ -109,8 +135,17 @@ fn draw_search(ctx: &mut Context, state: &mut State) {
                         action = Some(SearchAction::Replace);
                     } else if ctx.consume_shortcut(kbmod::CTRL_ALT | vk::RETURN) {
                         action = Some(SearchAction::ReplaceAll);
+                    } else if let Some(older) = consume_history_shortcut(ctx) {
+                        search_history::recall(
+                            &state.search_history.replacements,
+                            &mut state.search_replacement,
+                            older,
+                        );
                     }
                 }
+                if state.wants_search_history == StateSearchHistory::Replacements {
+                    draw_search_history_list(ctx, state, StateSearchHistory::Replacements);
+                }
             }
         }
         ctx.table_end();
@@ -138,6 +173,11 @@ fn draw_search(ctx: &mut Context, state: &mut State) {
                 loc(LocId::SearchUseRegex),
                 &mut state.search_options.use_regex,
             );
+            change |= ctx.checkbox(
+                "in-selection",
+                loc(LocId::SearchInSelection),
+                &mut state.search_options.in_selection,
+            );
             if state.wants_search.kind == StateSearchKind::Replace
                 && ctx.button("replace-all", loc(LocId::SearchReplaceAll), ButtonStyle::default())
             {
@@ -146,8 +186,38 @@ fn draw_search(ctx: &mut Context, state: &mut State) {
             }
             if ctx.button("close", loc(LocId::SearchClose), ButtonStyle::default()) {
                 state.wants_search.kind = StateSearchKind::Hidden;
+                buffer.borrow_mut().find_clear();
             }
 
+            let count = match buffer.borrow_mut().find_match_count() {
+                Some(count) => {
+                    let mut label = ArenaString::from_str(
+                        ctx.arena(),
+                        loc(if count.current.is_some() {
+                            LocId::SearchMatchCount
+                        } else {
+                            LocId::SearchMatchTotal
+                        }),
+                    );
+                    if let Some(current) = count.current {
+                        label.replace_once_in_place(
+                            "{current}",
+                            &arena_format!(ctx.arena(), "{current}"),
+                        );
+                    }
+                    // Counting happens between inputs and stops at `MAX_SEARCH_MATCHES`.
+                    let total = if count.incomplete {
+                        arena_format!(ctx.arena(), "{}+", count.total)
+                    } else {
+                        arena_format!(ctx.arena(), "{}", count.total)
+                    };
+                    label.replace_once_in_place("{total}", &total);
+                    label
+                }
+                None => ArenaString::new_in(ctx.arena()),
+            };
+            ctx.label("count", &count);
+
             if change {
                 action = change_action;
                 state.wants_search.focus = true;
@@ -159,10 +229,91 @@ fn draw_search(ctx: &mut Context, state: &mut State) {
     ctx.block_end();
 
     if let Some(action) = action {
+        if remember || !matches!(action, SearchAction::Search) {
+            search_history_add(ctx, state, !matches!(action, SearchAction::Search));
+        }
         search_execute(ctx, state, action);
     }
 }
 
+/// Up and Down in the search bar editlines recall older and newer history entries.
+fn consume_history_shortcut(ctx: &mut Context) -> Option<bool> {
+    if ctx.consume_shortcut(vk::UP) {
+        Some(true)
+    } else if ctx.consume_shortcut(vk::DOWN) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+fn draw_search_history_button(ctx: &mut Context, state: &mut State, kind: StateSearchHistory) {
+    if ctx.button("history", "▾", ButtonStyle::default().bracketed(false)) {
+        state.wants_search_history =
+            if state.wants_search_history == kind { StateSearchHistory::None } else { kind };
+        ctx.needs_rerender();
+    }
+}
+
+/// Draws the history dropdown below the preceding editline.
+/// Returns true if an entry was picked into the needle or replacement.
+fn draw_search_history_list(
+    ctx: &mut Context,
+    state: &mut State,
+    kind: StateSearchHistory,
+) -> bool {
+    let (entries, text) = match kind {
+        StateSearchHistory::Replacements => {
+            (&state.search_history.replacements, &mut state.search_replacement)
+        }
+        _ => (&state.search_history.needles, &mut state.search_needle),
+    };
+    let mut picked = false;
+
+    ctx.list_begin("history-list");
+    ctx.attr_float(FloatSpec {
+        anchor: Anchor::Last,
+        gravity_x: 0.0,
+        gravity_y: 0.0,
+        offset_x: 0.0,
+        offset_y: 1.0,
+    });
+    ctx.attr_border();
+    ctx.attr_background_rgba(ctx.indexed(IndexedColor::White));
+    ctx.attr_foreground_rgba(ctx.indexed(IndexedColor::Black));
+    ctx.focus_on_first_present();
+    {
+        for entry in entries {
+            // Multi-line regexes are shown on a single line.
+            let label = arena_format!(ctx.arena(), "{}", entry.replace('\n', "⏎"));
+            if ctx.list_item(false, &label) == ListSelection::Activated {
+                text.clone_from(entry);
+                picked = true;
+            }
+        }
+    }
+    ctx.list_end();
+
+    if picked || !ctx.contains_focus() {
+        state.wants_search_history = StateSearchHistory::None;
+        state.wants_search.focus = picked;
+        ctx.needs_rerender();
+    }
+    picked
+}
+
+/// Moves the current needle, and the replacement if `replacement` is true,
+/// to the front of the search history and saves it.
+pub fn search_history_add(ctx: &mut Context, state: &mut State, replacement: bool) {
+    let mut changed = state.search_history.add_needle(&state.search_needle);
+    if replacement {
+        changed |= state.search_history.add_replacement(&state.search_replacement);
+    }
+    if changed && let Err(err) = state.search_history.save() {
+        error_log_add(ctx, state, err);
+    }
+}
+
 pub enum SearchAction {
     Search,
     Replace,
@@ -197,7 +348,11 @@ pub fn search_execute(ctx: &mut Context, state: &mut State, action: SearchAction
 pub fn draw_handle_save(ctx: &mut Context, state: &mut State) {
     if let Some(doc) = state.documents.active_mut() {
         if doc.path.is_some() {
-            if let Err(err) = doc.save(None) {
+            if doc.is_changed_on_disk() {
+                // Don't silently overwrite someone else's changes.
+                state.wants_save_overwrite = true;
+                ctx.needs_rerender();
+            } else if let Err(err) = doc.save(None) {
                 error_log_add(ctx, state, err);
             }
         } else {
@@ -211,6 +366,217 @@ pub fn draw_handle_save(ctx: &mut Context, state: &mut State) {
     state.wants_save = false;
 }
 
+pub fn draw_dialog_save_overwrite(ctx: &mut Context, state: &mut State) {
+    let mut save;
+    let mut done = false;
+
+    ctx.modal_begin("save-overwrite", loc(LocId::SaveOverwriteWarning));
+    ctx.attr_background_rgba(ctx.indexed(IndexedColor::Red));
+    ctx.attr_foreground_rgba(ctx.indexed(IndexedColor::BrightWhite));
+    {
+        let contains_focus = ctx.contains_focus();
+
+        ctx.label("description", loc(LocId::SaveOverwriteWarningDescription));
+        ctx.attr_overflow(Overflow::TruncateTail);
+        ctx.attr_padding(Rect::three(1, 2, 1));
+
+        ctx.table_begin("choices");
+        ctx.inherit_focus();
+        ctx.attr_padding(Rect::three(0, 2, 1));
+        ctx.attr_position(Position::Center);
+        ctx.table_set_cell_gap(Size { width: 2, height: 0 });
+        {
+            ctx.table_next_row();
+            ctx.inherit_focus();
+
+            save = ctx.button("yes", loc(LocId::Yes), ButtonStyle::default());
+            ctx.inherit_focus();
+            done |= ctx.button("no", loc(LocId::No), ButtonStyle::default());
+        }
+        ctx.table_end();
+
+        if contains_focus {
+            save |= ctx.consume_shortcut(vk::Y);
+            done |= ctx.consume_shortcut(vk::N);
+        }
+    }
+    done |= ctx.modal_end();
+
+    if save {
+        if let Some(doc) = state.documents.active_mut()
+            && let Err(err) = doc.save(None)
+        {
+            error_log_add(ctx, state, err);
+        }
+    } else if done {
+        // Not saving means that closing the document or exiting must be cancelled as well.
+        state.wants_close = false;
+        state.wants_exit = false;
+    }
+
+    if save || done {
+        state.wants_save_overwrite = false;
+        ctx.needs_rerender();
+    }
+}
+
+pub fn draw_dialog_disk_changed(ctx: &mut Context, state: &mut State) {
+    enum Action {
+        None,
+        Reload,
+        Keep,
+        Compare,
+    }
+    let mut action = Action::None;
+
+    ctx.modal_begin("disk-changed", loc(LocId::DiskChangedDialogTitle));
+    {
+        let contains_focus = ctx.contains_focus();
+
+        ctx.label("description", loc(LocId::DiskChangedDialogDescription));
+        ctx.attr_overflow(Overflow::TruncateTail);
+        ctx.attr_padding(Rect::three(1, 2, 1));
+
+        ctx.table_begin("choices");
+        ctx.inherit_focus();
+        ctx.attr_padding(Rect::three(0, 2, 1));
+        ctx.attr_position(Position::Center);
+        ctx.table_set_cell_gap(Size { width: 2, height: 0 });
+        {
+            ctx.table_next_row();
+            ctx.inherit_focus();
+
+            if ctx.button(
+                "reload",
+                loc(LocId::DiskChangedDialogReload),
+                ButtonStyle::default().accelerator('R'),
+            ) {
+                action = Action::Reload;
+            }
+            ctx.inherit_focus();
+            if ctx.button(
+                "keep",
+                loc(LocId::DiskChangedDialogKeep),
+                ButtonStyle::default().accelerator('K'),
+            ) {
+                action = Action::Keep;
+            }
+            if ctx.button(
+                "compare",
+                loc(LocId::DiskChangedDialogCompare),
+                ButtonStyle::default().accelerator('C'),
+            ) {
+                action = Action::Compare;
+            }
+
+            if contains_focus {
+                if ctx.consume_shortcut(vk::R) {
+                    action = Action::Reload;
+                } else if ctx.consume_shortcut(vk::K) {
+                    action = Action::Keep;
+                } else if ctx.consume_shortcut(vk::C) {
+                    action = Action::Compare;
+                }
+            }
+        }
+        ctx.table_end();
+    }
+    if ctx.modal_end() {
+        action = Action::Keep;
+    }
+
+    let Some(doc) = state.documents.active_mut() else {
+        return;
+    };
+
+    match action {
+        Action::None => return,
+        // Don't discard unsaved changes without asking.
+        Action::Reload if doc.buffer.borrow().is_dirty() => state.wants_reload_discard = true,
+        Action::Reload => reload_active(ctx, state),
+        Action::Keep => doc.update_file_stamp(),
+        Action::Compare => {
+            // Open the version on disk in a new document, next to ours. `changed_on_disk`
+            // stays set, so that the user gets asked again once they return to the original.
+            let path = doc.path.clone().unwrap();
+            let filename = format!("{}{}", doc.filename, loc(LocId::DiskChangedCompareSuffix));
+            let res = state.documents.add_untitled().and_then(|copy| {
+                let mut file = DocumentManager::open_for_reading(&path)?;
+                {
+                    let mut tb = copy.buffer.borrow_mut();
+                    tb.read_file(&mut file, None)?;
+                    tb.set_language(syntax::language_from_path(&path));
+                }
+                copy.filename = filename;
+                Ok(())
+            });
+            if let Err(err) = res {
+                error_log_add(ctx, state, err);
+            }
+        }
+    }
+
+    ctx.needs_rerender();
+}
+
+pub fn draw_dialog_reload_discard(ctx: &mut Context, state: &mut State) {
+    let mut reload;
+    let mut done = false;
+
+    ctx.modal_begin("reload-discard", loc(LocId::ReloadDiscardWarning));
+    ctx.attr_background_rgba(ctx.indexed(IndexedColor::Red));
+    ctx.attr_foreground_rgba(ctx.indexed(IndexedColor::BrightWhite));
+    {
+        let contains_focus = ctx.contains_focus();
+
+        ctx.label("description", loc(LocId::ReloadDiscardWarningDescription));
+        ctx.attr_overflow(Overflow::TruncateTail);
+        ctx.attr_padding(Rect::three(1, 2, 1));
+
+        ctx.table_begin("choices");
+        ctx.inherit_focus();
+        ctx.attr_padding(Rect::three(0, 2, 1));
+        ctx.attr_position(Position::Center);
+        ctx.table_set_cell_gap(Size { width: 2, height: 0 });
+        {
+            ctx.table_next_row();
+            ctx.inherit_focus();
+
+            reload = ctx.button("yes", loc(LocId::Yes), ButtonStyle::default());
+            ctx.inherit_focus();
+            done |= ctx.button("no", loc(LocId::No), ButtonStyle::default());
+        }
+        ctx.table_end();
+
+        if contains_focus {
+            reload |= ctx.consume_shortcut(vk::Y);
+            done |= ctx.consume_shortcut(vk::N);
+        }
+    }
+    done |= ctx.modal_end();
+
+    // Declining returns to the "File Changed" dialog, since `changed_on_disk` is still set.
+    if reload {
+        reload_active(ctx, state);
+    }
+    if reload || done {
+        state.wants_reload_discard = false;
+        ctx.needs_rerender();
+    }
+}
+
+/// Replaces the contents of the active document with the file on disk.
+fn reload_active(ctx: &mut Context, state: &mut State) {
+    let Some(doc) = state.documents.active_mut() else {
+        return;
+    };
+    let encoding = doc.buffer.borrow().encoding();
+    if let Err(err) = doc.reread(Some(encoding)) {
+        doc.update_file_stamp();
+        error_log_add(ctx, state, err);
+    }
+}
+
 pub fn draw_handle_wants_close(ctx: &mut Context, state: &mut State) {
     let Some(doc) = state.documents.active() else {
         state.wants_close = false;
@@ -345,6 +711,217 @@ pub fn draw_goto_menu(ctx: &mut Context, state: &mut State) {
     }
 }
 
+pub fn draw_dialog_history(ctx: &mut Context, state: &mut State) {
+    let Some(doc) = state.documents.active() else {
+        state.wants_history = false;
+        return;
+    };
+    let mut tb = doc.buffer.borrow_mut();
+    // Selecting an entry previews it, so we need to remember where to return to on cancel.
+    let origin = *state.history_origin.get_or_insert_with(|| tb.history_current());
+    let states = tb.history_states();
+    let now = SystemTime::now();
+    let mut goto = None;
+    let mut done = false;
+
+    ctx.modal_begin("history", loc(LocId::HistoryDialogTitle));
+    {
+        let width = (ctx.size().width - 20).max(10);
+        let height = (ctx.size().height - 10).max(10);
+
+        ctx.scrollarea_begin("scrollarea", Size { width, height });
+        ctx.attr_background_rgba(ctx.indexed_alpha(IndexedColor::Black, 1, 4));
+        ctx.inherit_focus();
+        {
+            ctx.list_begin("states");
+            ctx.inherit_focus();
+
+            // Newest first, since that's most likely what you're looking for.
+            for s in states.iter().rev() {
+                ctx.styled_list_item_begin();
+                ctx.attr_overflow(Overflow::TruncateTail);
+                ctx.styled_label_add_text(if s.id == origin { "* " } else { "  " });
+
+                if s.id == 0 {
+                    ctx.styled_label_add_text(loc(LocId::HistoryDialogOriginal));
+                } else {
+                    ctx.styled_label_add_text(&arena_format!(
+                        ctx.arena(),
+                        "#{:<5} -{:<9} +{} -{}",
+                        s.id,
+                        format_age(ctx.arena(), now, s.timestamp),
+                        s.added,
+                        s.deleted
+                    ));
+
+                    // The state was created after undoing, which forked off a new branch.
+                    if s.parent + 1 != s.id {
+                        ctx.styled_label_add_text("   ");
+                        ctx.styled_label_set_attributes(Attributes::Italic);
+                        ctx.styled_label_add_text(loc(LocId::HistoryDialogBranch));
+                    }
+                }
+
+                match ctx.styled_list_item_end(s.current) {
+                    ListSelection::Selected => goto = Some(s.id),
+                    ListSelection::Activated => done = true,
+                    ListSelection::Unchanged => {}
+                }
+            }
+
+            ctx.list_end();
+        }
+        ctx.scrollarea_end();
+    }
+    if ctx.modal_end() {
+        goto = Some(origin);
+        done = true;
+    }
+
+    if let Some(id) = goto {
+        tb.history_goto(id);
+        tb.make_cursor_visible();
+        ctx.needs_rerender();
+    }
+    if done {
+        state.wants_history = false;
+        state.history_origin = None;
+        ctx.needs_rerender();
+    }
+}
+
+pub fn draw_dialog_recovery(ctx: &mut Context, state: &mut State) {
+    enum Action {
+        None,
+        Recover(usize),
+        RecoverAll,
+        DiscardAll,
+        Later,
+    }
+    let mut action = Action::None;
+    let now = SystemTime::now();
+
+    ctx.modal_begin("recovery", loc(LocId::RecoveryDialogTitle));
+    {
+        let contains_focus = ctx.contains_focus();
+        let width = (ctx.size().width - 20).max(10);
+        let height = (state.recovery_files.len() as CoordType).clamp(1, 10);
+
+        ctx.label("description", loc(LocId::RecoveryDialogDescription));
+        ctx.attr_overflow(Overflow::TruncateTail);
+        ctx.attr_padding(Rect::three(1, 2, 1));
+
+        ctx.scrollarea_begin("scrollarea", Size { width, height });
+        ctx.attr_background_rgba(ctx.indexed_alpha(IndexedColor::Black, 1, 4));
+        ctx.attr_padding(Rect::two(0, 2));
+        ctx.inherit_focus();
+        {
+            ctx.list_begin("files");
+            ctx.inherit_focus();
+
+            for (i, rf) in state.recovery_files.iter().enumerate() {
+                ctx.styled_list_item_begin();
+                ctx.attr_overflow(Overflow::TruncateTail);
+                ctx.styled_label_add_text(&arena_format!(
+                    ctx.arena(),
+                    "-{:<9} {}",
+                    format_age(ctx.arena(), now, rf.timestamp),
+                    rf.filename
+                ));
+
+                if let Some(dir) = rf.path.as_deref().and_then(|p| p.parent()) {
+                    ctx.styled_label_add_text("   ");
+                    ctx.styled_label_set_attributes(Attributes::Italic);
+                    ctx.styled_label_add_text(&dir.to_string_lossy());
+                }
+
+                if ctx.styled_list_item_end(false) == ListSelection::Activated {
+                    action = Action::Recover(i);
+                }
+            }
+
+            ctx.list_end();
+        }
+        ctx.scrollarea_end();
+
+        ctx.table_begin("choices");
+        ctx.inherit_focus();
+        ctx.attr_padding(Rect::three(1, 2, 1));
+        ctx.attr_position(Position::Center);
+        ctx.table_set_cell_gap(Size { width: 2, height: 0 });
+        {
+            ctx.table_next_row();
+
+            if ctx.button(
+                "recover-all",
+                loc(LocId::RecoveryDialogRecoverAll),
+                ButtonStyle::default().accelerator('R'),
+            ) {
+                action = Action::RecoverAll;
+            }
+            if ctx.button(
+                "discard-all",
+                loc(LocId::RecoveryDialogDiscardAll),
+                ButtonStyle::default().accelerator('D'),
+            ) {
+                action = Action::DiscardAll;
+            }
+            if ctx.button("later", loc(LocId::RecoveryDialogLater), ButtonStyle::default()) {
+                action = Action::Later;
+            }
+
+            if contains_focus {
+                if ctx.consume_shortcut(vk::R) {
+                    action = Action::RecoverAll;
+                } else if ctx.consume_shortcut(vk::D) {
+                    action = Action::DiscardAll;
+                }
+            }
+        }
+        ctx.table_end();
+    }
+    if ctx.modal_end() {
+        action = Action::Later;
+    }
+
+    let files = match action {
+        Action::None => return,
+        Action::Recover(i) => vec![state.recovery_files.remove(i)],
+        Action::RecoverAll => mem::take(&mut state.recovery_files),
+        Action::DiscardAll => {
+            for rf in state.recovery_files.drain(..) {
+                recovery::remove(&rf.file);
+            }
+            Vec::new()
+        }
+        // The files stay around and we'll ask again next time.
+        Action::Later => {
+            state.recovery_files.clear();
+            Vec::new()
+        }
+    };
+
+    // Oldest first, so that the newest one ends up as the active document.
+    for rf in files.iter().rev() {
+        if let Err(err) = state.documents.add_recovered(rf) {
+            error_log_add(ctx, state, err);
+        }
+    }
+
+    ctx.needs_rerender();
+}
+
+/// Formats the time between `timestamp` and `now` compactly, e.g. "5m 07s".
+fn format_age<'a>(arena: &'a Arena, now: SystemTime, timestamp: SystemTime) -> ArenaString<'a> {
+    let age = now.duration_since(timestamp).unwrap_or_default().as_secs();
+    match age {
+        0..60 => arena_format!(arena, "{age}s"),
+        60..3600 => arena_format!(arena, "{}m {:02}s", age / 60, age % 60),
+        3600..86400 => arena_format!(arena, "{}h {:02}m", age / 3600, age / 60 % 60),
+        _ => arena_format!(arena, "{}d {}h", age / 86400, age / 3600 % 24),
+    }
+}
+
 fn validate_goto_point(line: &str) -> Result<Point, ParseIntError> {
     let mut coords = [0; 2];
     let (y, x) = line.split_once(':').unwrap_or((line, "0"));
@@ -0,0 +1,333 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::env;
+
+use edit::arena::{ArenaString, scratch_arena};
+use edit::arena_format;
+use edit::buffer::SearchOptions;
+use edit::framebuffer::{Attributes, IndexedColor};
+use edit::helpers::*;
+use edit::input::vk;
+use edit::oklab::StraightRgba;
+use edit::tui::*;
+
+use crate::draw_editor::search_history_add;
+use crate::localization::*;
+use crate::state::*;
+
+/// Text before a hit is cut to roughly this many bytes, so that the hit remains visible.
+const MAX_PREVIEW_PREFIX: usize = 40;
+
+pub fn draw_find_in_files(ctx: &mut Context, state: &mut State) {
+    let width = (ctx.size().width - 20).max(10);
+    let height = (ctx.size().height - 16).max(5);
+    let scratch = scratch_arena(None);
+
+    // A row for each file that contains hits, followed by a row for each hit.
+    let mut rows = Vec::new_in(&*scratch);
+    for (f, file) in state.find_in_files.results.iter().enumerate() {
+        rows.push((f, None));
+        rows.extend((0..file.hits.len()).map(|h| (f, Some(h))));
+    }
+
+    let selected = state.find_in_files_selected.min(rows.len().saturating_sub(1));
+    let mut restart = false;
+    let mut replace_all = false;
+    let mut scroll_to_selection = false;
+    let mut activated = None;
+
+    ctx.modal_begin("find-in-files", loc(LocId::EditFindInFiles));
+    {
+        ctx.table_begin("needle");
+        ctx.table_set_cell_gap(Size { width: 1, height: 0 });
+        ctx.attr_padding(Rect::two(0, 1));
+        {
+            ctx.table_next_row();
+            ctx.label("label", loc(LocId::SearchNeedleLabel));
+
+            restart |= ctx.editline("needle", &mut state.search_needle);
+            if !state.search_success {
+                ctx.attr_background_rgba(ctx.indexed(IndexedColor::Red));
+                ctx.attr_foreground_rgba(ctx.indexed(IndexedColor::BrightWhite));
+            }
+            ctx.attr_intrinsic_size(Size { width: width - 12, height: 1 });
+            if state.find_in_files_focus {
+                state.find_in_files_focus = false;
+                ctx.steal_focus();
+            }
+
+            ctx.table_next_row();
+            ctx.label("label", loc(LocId::SearchReplacementLabel));
+
+            restart |= ctx.editline("replacement", &mut state.search_replacement);
+            ctx.attr_intrinsic_size(Size { width: width - 12, height: 1 });
+        }
+        ctx.table_end();
+
+        ctx.table_begin("options");
+        ctx.table_set_cell_gap(Size { width: 2, height: 0 });
+        ctx.attr_padding(Rect::two(0, 1));
+        {
+            ctx.table_next_row();
+
+            restart |= ctx.checkbox(
+                "match-case",
+                loc(LocId::SearchMatchCase),
+                &mut state.search_options.match_case,
+            );
+            restart |= ctx.checkbox(
+                "whole-word",
+                loc(LocId::SearchWholeWord),
+                &mut state.search_options.whole_word,
+            );
+            restart |= ctx.checkbox(
+                "use-regex",
+                loc(LocId::SearchUseRegex),
+                &mut state.search_options.use_regex,
+            );
+            // Replacing is only offered for the complete results, since it's based on them.
+            if !state.find_in_files.results.is_empty()
+                && !state.find_in_files.is_searching()
+                && !state.find_in_files.is_truncated()
+            {
+                replace_all =
+                    ctx.button("replace-all", loc(LocId::SearchReplaceAll), ButtonStyle::default());
+            }
+        }
+        ctx.table_end();
+
+        let status = if state.find_in_files.is_searching() {
+            arena_format!(&*scratch, "{}", loc(LocId::FindInFilesSearching))
+        } else if let Some(count) = state.find_in_files.replaced_files {
+            let mut status = ArenaString::from_str(&scratch, loc(LocId::FindInFilesReplaced));
+            status.replace_once_in_place("{count}", &arena_format!(&*scratch, "{count}"));
+            status
+        } else {
+            arena_format!(
+                &*scratch,
+                "{}{}{}  {}{}",
+                loc(LocId::FindInFilesMatches),
+                state.find_in_files.hit_count,
+                if state.find_in_files.is_truncated() { "+" } else { "" },
+                loc(LocId::FindInFilesFiles),
+                state.find_in_files.results.len(),
+            )
+        };
+        ctx.label("status", &status);
+        ctx.attr_padding(Rect::two(0, 1));
+
+        if ctx.consume_shortcut(vk::UP) {
+            state.find_in_files_selected = selected.saturating_sub(1);
+            scroll_to_selection = true;
+            ctx.needs_rerender();
+        } else if ctx.consume_shortcut(vk::DOWN) {
+            state.find_in_files_selected = (selected + 1).min(rows.len().saturating_sub(1));
+            scroll_to_selection = true;
+            ctx.needs_rerender();
+        } else if ctx.consume_shortcut(vk::RETURN) && !rows.is_empty() {
+            activated = Some(selected);
+        }
+
+        ctx.scrollarea_begin("scrollarea", Size { width, height });
+        ctx.attr_background_rgba(ctx.indexed_alpha(IndexedColor::Black, 1, 4));
+        {
+            // Keep the selection in view, which is drawn in the next frame.
+            if scroll_to_selection {
+                let selected = state.find_in_files_selected as CoordType;
+                ctx.scrollarea_scroll_to(Point { x: 0, y: (selected - height + 1).max(0) });
+            }
+
+            ctx.block_begin("hits");
+            for (i, &(f, h)) in rows.iter().enumerate() {
+                let file = &state.find_in_files.results[f];
+
+                ctx.next_block_id_mixin(i as u64);
+                ctx.styled_label_begin("hit");
+                ctx.attr_overflow(Overflow::TruncateTail);
+                ctx.attr_padding(Rect::two(0, 1));
+                if i == selected {
+                    ctx.attr_reverse();
+                }
+
+                match h {
+                    None => {
+                        ctx.styled_label_set_attributes(Attributes::Underlined);
+                        ctx.styled_label_add_text(&file.path);
+                        ctx.styled_label_set_attributes(Attributes::None);
+                        ctx.styled_label_add_text(&arena_format!(
+                            &*scratch,
+                            " ({})",
+                            file.hits.len()
+                        ));
+                    }
+                    Some(h) => {
+                        let hit = &file.hits[h];
+                        let line = &hit.line;
+                        let range = hit.line_range.clone();
+
+                        // Skip the indentation and overly long text before the hit.
+                        let mut beg = line.len() - line.trim_start().len();
+                        beg = beg.min(range.start);
+                        if range.start - beg > MAX_PREVIEW_PREFIX {
+                            beg = range.start - MAX_PREVIEW_PREFIX;
+                            while !line.is_char_boundary(beg) {
+                                beg += 1;
+                            }
+                        }
+
+                        ctx.styled_label_add_text(&arena_format!(
+                            &*scratch,
+                            "  {:>5}: ",
+                            hit.beg.y + 1
+                        ));
+                        ctx.styled_label_add_text(&line[beg..range.start]);
+                        match &hit.replacement {
+                            Some(replacement) => {
+                                ctx.styled_label_set_foreground(ctx.indexed(IndexedColor::Red));
+                                ctx.styled_label_add_text(&line[range.clone()]);
+                                ctx.styled_label_set_foreground(ctx.indexed(IndexedColor::Green));
+                                ctx.styled_label_add_text(replacement);
+                            }
+                            None => {
+                                ctx.styled_label_set_foreground(
+                                    ctx.indexed(IndexedColor::BrightBlue),
+                                );
+                                ctx.styled_label_add_text(&line[range.clone()]);
+                            }
+                        }
+                        ctx.styled_label_set_foreground(StraightRgba::zero());
+                        ctx.styled_label_add_text(&line[range.end..]);
+                    }
+                }
+                ctx.styled_label_end();
+
+                if ctx.was_mouse_down() {
+                    activated = Some(i);
+                }
+            }
+            ctx.block_end();
+        }
+        ctx.scrollarea_end();
+    }
+    let done = ctx.modal_end();
+
+    if restart {
+        // An empty replacement doesn't get a preview, but "Replace All" still deletes the hits.
+        let replacement = Some(state.search_replacement.as_str()).filter(|r| !r.is_empty());
+        state.find_in_files.start(
+            env::current_dir().unwrap_or_default(),
+            &state.search_needle,
+            state.search_options,
+            replacement,
+        );
+        state.find_in_files_selected = 0;
+        state.search_success = true;
+    }
+
+    if replace_all {
+        state.wants_find_in_files_replace = true;
+    }
+
+    if let Some(i) = activated {
+        search_history_add(ctx, state, false);
+
+        let (f, h) = rows[i];
+        let file = &state.find_in_files.results[f];
+        let path = state.find_in_files.root().join(&file.path);
+        let hit = h.map(|h| &file.hits[h]);
+
+        match state.documents.add_file_path(&path) {
+            Ok(doc) => {
+                if let Some(hit) = hit {
+                    let mut tb = doc.buffer.borrow_mut();
+                    tb.cursor_move_to_logical(hit.beg);
+                    tb.selection_update_logical(hit.end);
+                    tb.make_cursor_visible();
+                }
+            }
+            Err(err) => error_log_add(ctx, state, err),
+        }
+
+        // The results are kept, so that the next hit is just a reopening of the dialog away.
+        state.find_in_files_selected = i;
+        state.wants_find_in_files = false;
+    } else if done {
+        state.wants_find_in_files = false;
+    }
+}
+
+pub fn draw_dialog_find_in_files_replace(ctx: &mut Context, state: &mut State) {
+    let mut replace;
+    let mut done = false;
+
+    ctx.modal_begin("find-in-files-replace", loc(LocId::FindInFilesReplaceWarning));
+    ctx.attr_background_rgba(ctx.indexed(IndexedColor::Red));
+    ctx.attr_foreground_rgba(ctx.indexed(IndexedColor::BrightWhite));
+    {
+        let contains_focus = ctx.contains_focus();
+
+        ctx.label("description", loc(LocId::FindInFilesReplaceWarningDescription));
+        ctx.attr_overflow(Overflow::TruncateTail);
+        ctx.attr_padding(Rect::three(1, 2, 1));
+
+        ctx.table_begin("choices");
+        ctx.inherit_focus();
+        ctx.attr_padding(Rect::three(0, 2, 1));
+        ctx.attr_position(Position::Center);
+        ctx.table_set_cell_gap(Size { width: 2, height: 0 });
+        {
+            ctx.table_next_row();
+            ctx.inherit_focus();
+
+            replace = ctx.button("yes", loc(LocId::Yes), ButtonStyle::default());
+            ctx.inherit_focus();
+            done |= ctx.button("no", loc(LocId::No), ButtonStyle::default());
+        }
+        ctx.table_end();
+
+        if contains_focus {
+            replace |= ctx.consume_shortcut(vk::Y);
+            done |= ctx.consume_shortcut(vk::N);
+        }
+    }
+    done |= ctx.modal_end();
+
+    if replace {
+        search_history_add(ctx, state, true);
+        let root = state.find_in_files.root().to_path_buf();
+        let results = std::mem::take(&mut state.find_in_files.results);
+
+        // The selection of open documents doesn't restrict the replacements.
+        let options = SearchOptions { in_selection: false, ..state.search_options };
+
+        // Each file is replaced in a single undo step and left unsaved for review.
+        let mut replaced_files = 0;
+        for file in &results {
+            let res = state.documents.add_file_path(&root.join(&file.path)).and_then(|doc| {
+                let mut tb = doc.buffer.borrow_mut();
+                let generation = tb.generation();
+                tb.find_and_replace_all(
+                    &state.search_needle,
+                    options,
+                    state.search_replacement.as_bytes(),
+                )?;
+                Ok(tb.generation() != generation)
+            });
+            match res {
+                Ok(replaced) => replaced_files += replaced as usize,
+                Err(err) => error_log_add(ctx, state, err),
+            }
+        }
+
+        // The dialog stays open to report how many files were changed.
+        state.find_in_files.clear();
+        state.find_in_files.replaced_files = Some(replaced_files);
+        state.find_in_files_selected = 0;
+        done = true;
+    }
+
+    if done {
+        state.wants_find_in_files_replace = false;
+    }
+}
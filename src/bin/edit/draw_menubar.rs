@@ -3,10 +3,12 @@
 
 use edit::arena_format;
 use edit::helpers::*;
-use edit::input::{kbmod, vk};
+use edit::input::vk;
 use edit::tui::*;
 
+use crate::keybindings;
 use crate::localization::*;
+use crate::settings::{ClipboardSync, Theme};
 use crate::state::*;
 
 pub fn draw_menubar(ctx: &mut Context, state: &mut State) {
@@ -19,7 +21,7 @@ pub fn draw_menubar(ctx: &mut Context, state: &mut State) {
         if ctx.menubar_menu_begin(loc(LocId::File), 'F') {
             draw_menu_file(ctx, state);
         }
-        if !contains_focus && ctx.consume_shortcut(vk::F10) {
+        if !contains_focus && ctx.consume_shortcut(keybindings::VIEW_FOCUS_MENUBAR) {
             ctx.steal_focus();
         }
         if state.documents.active().is_some() {
@@ -38,24 +40,24 @@ pub fn draw_menubar(ctx: &mut Context, state: &mut State) {
 }
 
 fn draw_menu_file(ctx: &mut Context, state: &mut State) {
-    if ctx.menubar_menu_button(loc(LocId::FileNew), 'N', kbmod::CTRL | vk::N) {
+    if ctx.menubar_menu_button(loc(LocId::FileNew), 'N', keybindings::FILE_NEW) {
         draw_add_untitled_document(ctx, state);
     }
-    if ctx.menubar_menu_button(loc(LocId::FileOpen), 'O', kbmod::CTRL | vk::O) {
+    if ctx.menubar_menu_button(loc(LocId::FileOpen), 'O', keybindings::FILE_OPEN) {
         state.wants_file_picker = StateFilePicker::Open;
     }
     if state.documents.active().is_some() {
-        if ctx.menubar_menu_button(loc(LocId::FileSave), 'S', kbmod::CTRL | vk::S) {
+        if ctx.menubar_menu_button(loc(LocId::FileSave), 'S', keybindings::FILE_SAVE) {
             state.wants_save = true;
         }
         if ctx.menubar_menu_button(loc(LocId::FileSaveAs), 'A', vk::NULL) {
             state.wants_file_picker = StateFilePicker::SaveAs;
         }
-        if ctx.menubar_menu_button(loc(LocId::FileClose), 'C', kbmod::CTRL | vk::W) {
+        if ctx.menubar_menu_button(loc(LocId::FileClose), 'C', keybindings::FILE_CLOSE) {
             state.wants_close = true;
         }
     }
-    if ctx.menubar_menu_button(loc(LocId::FileExit), 'X', kbmod::CTRL | vk::Q) {
+    if ctx.menubar_menu_button(loc(LocId::FileExit), 'X', keybindings::FILE_EXIT) {
         state.wants_exit = true;
     }
     ctx.menubar_menu_end();
@@ -65,37 +67,50 @@ fn draw_menu_edit(ctx: &mut Context, state: &mut State) {
     let doc = state.documents.active().unwrap();
     let mut tb = doc.buffer.borrow_mut();
 
-    if ctx.menubar_menu_button(loc(LocId::EditUndo), 'U', kbmod::CTRL | vk::Z) {
+    if ctx.menubar_menu_button(loc(LocId::EditUndo), 'U', keybindings::EDIT_UNDO) {
         tb.undo();
         ctx.needs_rerender();
     }
-    if ctx.menubar_menu_button(loc(LocId::EditRedo), 'R', kbmod::CTRL | vk::Y) {
+    if ctx.menubar_menu_button(loc(LocId::EditRedo), 'R', keybindings::EDIT_REDO) {
         tb.redo();
         ctx.needs_rerender();
     }
-    if ctx.menubar_menu_button(loc(LocId::EditCut), 'T', kbmod::CTRL | vk::X) {
+    if ctx.menubar_menu_button(loc(LocId::EditHistory), 'H', vk::NULL) {
+        state.wants_history = true;
+    }
+    if ctx.menubar_menu_button(loc(LocId::EditTimeBack), 'B', vk::NULL) {
+        tb.history_time_travel(-300);
+        ctx.needs_rerender();
+    }
+    if ctx.menubar_menu_button(loc(LocId::EditTimeForward), 'O', vk::NULL) {
+        tb.history_time_travel(300);
+        ctx.needs_rerender();
+    }
+    if ctx.menubar_menu_button(loc(LocId::EditCut), 'T', keybindings::EDIT_CUT) {
         tb.cut(ctx.clipboard_mut());
         ctx.needs_rerender();
     }
-    if ctx.menubar_menu_button(loc(LocId::EditCopy), 'C', kbmod::CTRL | vk::C) {
+    if ctx.menubar_menu_button(loc(LocId::EditCopy), 'C', keybindings::EDIT_COPY) {
         tb.copy(ctx.clipboard_mut());
         ctx.needs_rerender();
     }
-    if ctx.menubar_menu_button(loc(LocId::EditPaste), 'P', kbmod::CTRL | vk::V) {
+    if ctx.menubar_menu_button(loc(LocId::EditPaste), 'P', keybindings::EDIT_PASTE) {
         tb.paste(ctx.clipboard_ref());
         ctx.needs_rerender();
     }
-    if state.wants_search.kind != StateSearchKind::Disabled {
-        if ctx.menubar_menu_button(loc(LocId::EditFind), 'F', kbmod::CTRL | vk::F) {
-            state.wants_search.kind = StateSearchKind::Search;
-            state.wants_search.focus = true;
-        }
-        if ctx.menubar_menu_button(loc(LocId::EditReplace), 'L', kbmod::CTRL | vk::R) {
-            state.wants_search.kind = StateSearchKind::Replace;
-            state.wants_search.focus = true;
-        }
+    if ctx.menubar_menu_button(loc(LocId::EditFind), 'F', keybindings::EDIT_FIND) {
+        state.wants_search.kind = StateSearchKind::Search;
+        state.wants_search.focus = true;
     }
-    if ctx.menubar_menu_button(loc(LocId::EditSelectAll), 'A', kbmod::CTRL | vk::A) {
+    if ctx.menubar_menu_button(loc(LocId::EditReplace), 'L', keybindings::EDIT_REPLACE) {
+        state.wants_search.kind = StateSearchKind::Replace;
+        state.wants_search.focus = true;
+    }
+    if ctx.menubar_menu_button(loc(LocId::EditFindInFiles), 'I', keybindings::EDIT_FIND_IN_FILES) {
+        state.wants_find_in_files = true;
+        state.find_in_files_focus = true;
+    }
+    if ctx.menubar_menu_button(loc(LocId::EditSelectAll), 'A', keybindings::EDIT_SELECT_ALL) {
         tb.select_all();
         ctx.needs_rerender();
     }
@@ -111,16 +126,47 @@ fn draw_menu_view(ctx: &mut Context, state: &mut State) {
         if ctx.menubar_menu_button(loc(LocId::ViewFocusStatusbar), 'S', vk::NULL) {
             state.wants_statusbar_focus = true;
         }
-        if ctx.menubar_menu_button(loc(LocId::ViewGoToFile), 'F', kbmod::CTRL | vk::P) {
+        if ctx.menubar_menu_button(loc(LocId::ViewGoToFile), 'F', keybindings::VIEW_GO_TO_FILE) {
             state.wants_go_to_file = true;
         }
-        if ctx.menubar_menu_button(loc(LocId::FileGoto), 'G', kbmod::CTRL | vk::G) {
+        if ctx.menubar_menu_button(
+            loc(LocId::ViewCommandPalette),
+            'P',
+            keybindings::VIEW_COMMAND_PALETTE,
+        ) {
+            state.wants_command_palette = true;
+        }
+        if ctx.menubar_menu_button(loc(LocId::FileGoto), 'G', keybindings::VIEW_GO_TO_LINE) {
             state.wants_goto = true;
         }
-        if ctx.menubar_menu_checkbox(loc(LocId::ViewWordWrap), 'W', kbmod::ALT | vk::Z, word_wrap) {
+        if ctx.menubar_menu_checkbox(
+            loc(LocId::ViewWordWrap),
+            'W',
+            keybindings::VIEW_WORD_WRAP,
+            word_wrap,
+        ) {
             tb.set_word_wrap(!word_wrap);
             ctx.needs_rerender();
         }
+        if ctx.menubar_menu_button(loc(LocId::ViewFold), 'L', keybindings::VIEW_FOLD) {
+            tb.fold_at_cursor();
+            ctx.needs_rerender();
+        }
+        if ctx.menubar_menu_button(loc(LocId::ViewUnfold), 'U', keybindings::VIEW_UNFOLD) {
+            tb.unfold_at_cursor();
+            ctx.needs_rerender();
+        }
+        if ctx.menubar_menu_button(loc(LocId::ViewFoldAll), 'A', keybindings::VIEW_FOLD_ALL) {
+            tb.fold_all();
+            ctx.needs_rerender();
+        }
+        if ctx.menubar_menu_button(loc(LocId::ViewUnfoldAll), 'N', keybindings::VIEW_UNFOLD_ALL) {
+            tb.unfold_all();
+            ctx.needs_rerender();
+        }
+        if ctx.menubar_menu_button(loc(LocId::ViewSettings), 'T', vk::NULL) {
+            state.wants_settings = true;
+        }
     }
 
     ctx.menubar_menu_end();
@@ -178,3 +224,144 @@ pub fn draw_dialog_about(ctx: &mut Context, state: &mut State) {
         state.wants_about = false;
     }
 }
+
+pub fn draw_dialog_settings(ctx: &mut Context, state: &mut State) {
+    let mut settings = state.settings.clone();
+    let mut done = false;
+
+    ctx.modal_begin("settings", loc(LocId::SettingsDialogTitle));
+    {
+        ctx.table_begin("options");
+        ctx.inherit_focus();
+        ctx.attr_padding(Rect::three(1, 2, 1));
+        ctx.table_set_cell_gap(Size { width: 2, height: 0 });
+        {
+            // Each button cycles through the possible values.
+            ctx.table_next_row();
+            ctx.inherit_focus();
+            ctx.label("tab-size-label", loc(LocId::SettingsTabSize));
+            if ctx.button(
+                "tab-size",
+                &arena_format!(ctx.arena(), "{}", settings.tab_size),
+                ButtonStyle::default(),
+            ) {
+                settings.tab_size = settings.tab_size % 8 + 1;
+            }
+            ctx.inherit_focus();
+
+            ctx.table_next_row();
+            ctx.label("indentation-label", loc(LocId::SettingsIndentation));
+            if ctx.button(
+                "indentation",
+                loc(if settings.indent_with_tabs {
+                    LocId::IndentationTabs
+                } else {
+                    LocId::IndentationSpaces
+                }),
+                ButtonStyle::default(),
+            ) {
+                settings.indent_with_tabs = !settings.indent_with_tabs;
+            }
+
+            ctx.table_next_row();
+            ctx.label("ruler-label", loc(LocId::SettingsRuler));
+            let ruler = if settings.ruler > 0 {
+                &arena_format!(ctx.arena(), "{}", settings.ruler)
+            } else {
+                loc(LocId::SettingsRulerOff)
+            };
+            if ctx.button("ruler", ruler, ButtonStyle::default()) {
+                settings.ruler = match settings.ruler {
+                    ..80 => 80,
+                    80..100 => 100,
+                    100..120 => 120,
+                    _ => 0,
+                };
+            }
+
+            ctx.table_next_row();
+            ctx.label("theme-label", loc(LocId::SettingsTheme));
+            let theme = loc(match settings.theme {
+                Theme::Terminal => LocId::SettingsThemeTerminal,
+                Theme::Dark => LocId::SettingsThemeDark,
+                Theme::Light => LocId::SettingsThemeLight,
+            });
+            if ctx.button("theme", theme, ButtonStyle::default()) {
+                settings.theme = match settings.theme {
+                    Theme::Terminal => Theme::Dark,
+                    Theme::Dark => Theme::Light,
+                    Theme::Light => Theme::Terminal,
+                };
+            }
+
+            ctx.table_next_row();
+            ctx.label("clipboard-label", loc(LocId::SettingsClipboard));
+            let clipboard = loc(match settings.clipboard {
+                ClipboardSync::Ask => LocId::SettingsClipboardAsk,
+                ClipboardSync::Always => LocId::Always,
+                ClipboardSync::Never => LocId::SettingsClipboardNever,
+            });
+            if ctx.button("clipboard", clipboard, ButtonStyle::default()) {
+                settings.clipboard = match settings.clipboard {
+                    ClipboardSync::Ask => ClipboardSync::Always,
+                    ClipboardSync::Always => ClipboardSync::Never,
+                    ClipboardSync::Never => ClipboardSync::Ask,
+                };
+            }
+
+            ctx.table_next_row();
+            ctx.checkbox("word-wrap", loc(LocId::ViewWordWrap), &mut settings.word_wrap);
+            ctx.table_next_row();
+            ctx.checkbox(
+                "final-newline",
+                loc(LocId::SettingsFinalNewline),
+                &mut settings.insert_final_newline,
+            );
+            ctx.table_next_row();
+            ctx.checkbox(
+                "line-numbers",
+                loc(LocId::SettingsLineNumbers),
+                &mut settings.line_numbers,
+            );
+            ctx.table_next_row();
+            ctx.checkbox(
+                "fold-brackets",
+                loc(LocId::SettingsFoldBrackets),
+                &mut settings.fold_brackets,
+            );
+            ctx.table_next_row();
+            ctx.checkbox(
+                "fold-markers",
+                loc(LocId::SettingsFoldMarkers),
+                &mut settings.fold_markers,
+            );
+            ctx.table_next_row();
+            ctx.checkbox("auto-close", loc(LocId::SettingsAutoClose), &mut settings.auto_close);
+        }
+        ctx.table_end();
+
+        if ctx.button("ok", loc(LocId::Ok), ButtonStyle::default()) {
+            done = true;
+        }
+        ctx.attr_position(Position::Center);
+        ctx.attr_padding(Rect::three(0, 2, 1));
+    }
+    if ctx.modal_end() {
+        done = true;
+    }
+
+    // Changes apply immediately. The theme is applied by the main loop.
+    if settings != state.settings {
+        state.documents.apply_settings(&settings);
+        state.settings = settings;
+        if let Err(err) = state.settings.save() {
+            error_log_add(ctx, state, err);
+        }
+        ctx.needs_rerender();
+    }
+
+    if done {
+        state.wants_settings = false;
+        ctx.needs_rerender();
+    }
+}
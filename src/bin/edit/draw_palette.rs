@@ -0,0 +1,489 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The command palette: A fuzzy-searchable list of all menubar actions and editor commands.
+
+use edit::arena::scratch_arena;
+use edit::arena_format;
+use edit::buffer::{MoveLineDirection, SortOptions};
+use edit::framebuffer::{Attributes, IndexedColor};
+use edit::fuzzy::score_fuzzy;
+use edit::helpers::*;
+use edit::input::{InputKey, vk};
+use edit::oklab::StraightRgba;
+use edit::tui::*;
+
+use crate::keybindings;
+use crate::localization::*;
+use crate::state::*;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Command {
+    FileNew,
+    FileOpen,
+    FileSave,
+    FileSaveAs,
+    FileClose,
+    FileExit,
+    EditUndo,
+    EditRedo,
+    EditHistory,
+    EditTimeBack,
+    EditTimeForward,
+    EditCut,
+    EditCopy,
+    EditPaste,
+    EditFind,
+    EditReplace,
+    EditFindInFiles,
+    EditSelectAll,
+    EditGoToBracket,
+    EditSelectToBracket,
+    EditToggleLineComment,
+    EditToggleBlockComment,
+    EditSortLinesAscending,
+    EditSortLinesDescending,
+    EditSortLinesCaseInsensitive,
+    EditSortLinesNatural,
+    EditRemoveDuplicateLines,
+    EditReverseLines,
+    EditShuffleLines,
+    EditJoinLines,
+    EditDuplicateLinesUp,
+    EditDuplicateLinesDown,
+    EditDeleteLines,
+    EditInsertLineAbove,
+    EditInsertLineBelow,
+    ViewFocusStatusbar,
+    ViewGoToFile,
+    ViewGoToLine,
+    ViewWordWrap,
+    ViewFold,
+    ViewUnfold,
+    ViewFoldAll,
+    ViewUnfoldAll,
+    ViewEncodingReopen,
+    ViewEncodingConvert,
+    ViewIndentation,
+    ViewNewlines,
+    ViewSettings,
+    HelpAbout,
+}
+
+const COMMANDS: &[Command] = &[
+    Command::FileNew,
+    Command::FileOpen,
+    Command::FileSave,
+    Command::FileSaveAs,
+    Command::FileClose,
+    Command::FileExit,
+    Command::EditUndo,
+    Command::EditRedo,
+    Command::EditHistory,
+    Command::EditTimeBack,
+    Command::EditTimeForward,
+    Command::EditCut,
+    Command::EditCopy,
+    Command::EditPaste,
+    Command::EditFind,
+    Command::EditReplace,
+    Command::EditFindInFiles,
+    Command::EditSelectAll,
+    Command::EditGoToBracket,
+    Command::EditSelectToBracket,
+    Command::EditToggleLineComment,
+    Command::EditToggleBlockComment,
+    Command::EditSortLinesAscending,
+    Command::EditSortLinesDescending,
+    Command::EditSortLinesCaseInsensitive,
+    Command::EditSortLinesNatural,
+    Command::EditRemoveDuplicateLines,
+    Command::EditReverseLines,
+    Command::EditShuffleLines,
+    Command::EditJoinLines,
+    Command::EditDuplicateLinesUp,
+    Command::EditDuplicateLinesDown,
+    Command::EditDeleteLines,
+    Command::EditInsertLineAbove,
+    Command::EditInsertLineBelow,
+    Command::ViewFocusStatusbar,
+    Command::ViewGoToFile,
+    Command::ViewGoToLine,
+    Command::ViewWordWrap,
+    Command::ViewFold,
+    Command::ViewUnfold,
+    Command::ViewFoldAll,
+    Command::ViewUnfoldAll,
+    Command::ViewEncodingReopen,
+    Command::ViewEncodingConvert,
+    Command::ViewIndentation,
+    Command::ViewNewlines,
+    Command::ViewSettings,
+    Command::HelpAbout,
+];
+
+impl Command {
+    /// The menu the command belongs to, which prefixes its label.
+    fn category(self) -> LocId {
+        use Command::*;
+        match self {
+            FileNew | FileOpen | FileSave | FileSaveAs | FileClose | FileExit => LocId::File,
+            EditUndo
+            | EditRedo
+            | EditHistory
+            | EditTimeBack
+            | EditTimeForward
+            | EditCut
+            | EditCopy
+            | EditPaste
+            | EditFind
+            | EditReplace
+            | EditFindInFiles
+            | EditSelectAll
+            | EditGoToBracket
+            | EditSelectToBracket
+            | EditToggleLineComment
+            | EditToggleBlockComment
+            | EditSortLinesAscending
+            | EditSortLinesDescending
+            | EditSortLinesCaseInsensitive
+            | EditSortLinesNatural
+            | EditRemoveDuplicateLines
+            | EditReverseLines
+            | EditShuffleLines
+            | EditJoinLines
+            | EditDuplicateLinesUp
+            | EditDuplicateLinesDown
+            | EditDeleteLines
+            | EditInsertLineAbove
+            | EditInsertLineBelow => LocId::Edit,
+            HelpAbout => LocId::Help,
+            _ => LocId::View,
+        }
+    }
+
+    fn label(self) -> LocId {
+        use Command::*;
+        match self {
+            FileNew => LocId::FileNew,
+            FileOpen => LocId::FileOpen,
+            FileSave => LocId::FileSave,
+            FileSaveAs => LocId::FileSaveAs,
+            FileClose => LocId::FileClose,
+            FileExit => LocId::FileExit,
+            EditUndo => LocId::EditUndo,
+            EditRedo => LocId::EditRedo,
+            EditHistory => LocId::EditHistory,
+            EditTimeBack => LocId::EditTimeBack,
+            EditTimeForward => LocId::EditTimeForward,
+            EditCut => LocId::EditCut,
+            EditCopy => LocId::EditCopy,
+            EditPaste => LocId::EditPaste,
+            EditFind => LocId::EditFind,
+            EditReplace => LocId::EditReplace,
+            EditFindInFiles => LocId::EditFindInFiles,
+            EditSelectAll => LocId::EditSelectAll,
+            EditGoToBracket => LocId::EditGoToBracket,
+            EditSelectToBracket => LocId::EditSelectToBracket,
+            EditToggleLineComment => LocId::EditToggleLineComment,
+            EditToggleBlockComment => LocId::EditToggleBlockComment,
+            EditSortLinesAscending => LocId::EditSortLinesAscending,
+            EditSortLinesDescending => LocId::EditSortLinesDescending,
+            EditSortLinesCaseInsensitive => LocId::EditSortLinesCaseInsensitive,
+            EditSortLinesNatural => LocId::EditSortLinesNatural,
+            EditRemoveDuplicateLines => LocId::EditRemoveDuplicateLines,
+            EditReverseLines => LocId::EditReverseLines,
+            EditShuffleLines => LocId::EditShuffleLines,
+            EditJoinLines => LocId::EditJoinLines,
+            EditDuplicateLinesUp => LocId::EditDuplicateLinesUp,
+            EditDuplicateLinesDown => LocId::EditDuplicateLinesDown,
+            EditDeleteLines => LocId::EditDeleteLines,
+            EditInsertLineAbove => LocId::EditInsertLineAbove,
+            EditInsertLineBelow => LocId::EditInsertLineBelow,
+            ViewFocusStatusbar => LocId::ViewFocusStatusbar,
+            ViewGoToFile => LocId::ViewGoToFile,
+            ViewGoToLine => LocId::FileGoto,
+            ViewWordWrap => LocId::ViewWordWrap,
+            ViewFold => LocId::ViewFold,
+            ViewUnfold => LocId::ViewUnfold,
+            ViewFoldAll => LocId::ViewFoldAll,
+            ViewUnfoldAll => LocId::ViewUnfoldAll,
+            ViewEncodingReopen => LocId::EncodingReopen,
+            ViewEncodingConvert => LocId::EncodingConvert,
+            ViewIndentation => LocId::CommandPaletteIndentation,
+            ViewNewlines => LocId::CommandPaletteNewlines,
+            ViewSettings => LocId::ViewSettings,
+            HelpAbout => LocId::HelpAbout,
+        }
+    }
+
+    /// The name of the command in `keybindings.toml`, for those that have a shortcut.
+    fn binding(self) -> Option<&'static str> {
+        use Command::*;
+        Some(match self {
+            FileNew => "file.new",
+            FileOpen => "file.open",
+            FileSave => "file.save",
+            FileSaveAs => "file.save_as",
+            FileClose => "file.close",
+            FileExit => "file.exit",
+            EditUndo => "edit.undo",
+            EditRedo => "edit.redo",
+            EditCut => "edit.cut",
+            EditCopy => "edit.copy",
+            EditPaste => "edit.paste",
+            EditFind => "edit.find",
+            EditReplace => "edit.replace",
+            EditFindInFiles => "edit.find_in_files",
+            EditSelectAll => "edit.select_all",
+            EditGoToBracket => "edit.go_to_bracket",
+            EditToggleLineComment => "edit.toggle_line_comment",
+            EditToggleBlockComment => "edit.toggle_block_comment",
+            ViewGoToFile => "view.go_to_file",
+            ViewGoToLine => "view.go_to_line",
+            ViewWordWrap => "view.word_wrap",
+            ViewFold => "view.fold",
+            ViewUnfold => "view.unfold",
+            ViewFoldAll => "view.fold_all",
+            ViewUnfoldAll => "view.unfold_all",
+            _ => return None,
+        })
+    }
+
+    /// The default shortcut. The palette shows the chord it's currently bound to.
+    fn shortcut(self) -> InputKey {
+        self.binding().and_then(keybindings::default_shortcut).unwrap_or(vk::NULL)
+    }
+
+    fn is_available(self, state: &State) -> bool {
+        use Command::*;
+        let doc = state.documents.active();
+        match self {
+            FileNew | FileOpen | FileExit | HelpAbout => true,
+            EditFindInFiles => true,
+            // Can't reopen a file that doesn't exist.
+            ViewEncodingReopen => doc.is_some_and(|doc| doc.path.is_some()),
+            _ => doc.is_some(),
+        }
+    }
+
+    fn execute(self, ctx: &mut Context, state: &mut State) {
+        use Command::*;
+
+        match self {
+            FileNew => draw_add_untitled_document(ctx, state),
+            FileOpen => state.wants_file_picker = StateFilePicker::Open,
+            FileSave => state.wants_save = true,
+            FileSaveAs => state.wants_file_picker = StateFilePicker::SaveAs,
+            FileClose => state.wants_close = true,
+            FileExit => state.wants_exit = true,
+            EditHistory => state.wants_history = true,
+            EditFind | EditReplace => {
+                state.wants_search.kind = if self == EditFind {
+                    StateSearchKind::Search
+                } else {
+                    StateSearchKind::Replace
+                };
+                state.wants_search.focus = true;
+            }
+            EditFindInFiles => {
+                state.wants_find_in_files = true;
+                state.find_in_files_focus = true;
+            }
+            ViewFocusStatusbar => state.wants_statusbar_focus = true,
+            ViewGoToFile => state.wants_go_to_file = true,
+            ViewGoToLine => state.wants_goto = true,
+            ViewEncodingReopen => state.wants_encoding_change = StateEncodingChange::Reopen,
+            ViewEncodingConvert => state.wants_encoding_change = StateEncodingChange::Convert,
+            ViewIndentation => {
+                state.wants_statusbar_focus = true;
+                state.wants_indentation_picker = true;
+            }
+            ViewSettings => state.wants_settings = true,
+            HelpAbout => state.wants_about = true,
+            _ => {
+                let Some(doc) = state.documents.active() else {
+                    return;
+                };
+                let mut tb = doc.buffer.borrow_mut();
+                match self {
+                    EditUndo => tb.undo(),
+                    EditRedo => tb.redo(),
+                    EditTimeBack => tb.history_time_travel(-300),
+                    EditTimeForward => tb.history_time_travel(300),
+                    EditCut => tb.cut(ctx.clipboard_mut()),
+                    EditCopy => tb.copy(ctx.clipboard_mut()),
+                    EditPaste => tb.paste(ctx.clipboard_ref()),
+                    EditSelectAll => tb.select_all(),
+                    EditGoToBracket => tb.jump_to_matching_bracket(),
+                    EditSelectToBracket => tb.select_to_matching_bracket(),
+                    EditToggleLineComment => tb.toggle_line_comment(),
+                    EditToggleBlockComment => tb.toggle_block_comment(),
+                    EditSortLinesAscending => tb.sort_lines(SortOptions::default()),
+                    EditSortLinesDescending => {
+                        tb.sort_lines(SortOptions { descending: true, ..Default::default() })
+                    }
+                    EditSortLinesCaseInsensitive => {
+                        tb.sort_lines(SortOptions { case_insensitive: true, ..Default::default() })
+                    }
+                    EditSortLinesNatural => {
+                        tb.sort_lines(SortOptions { natural: true, ..Default::default() })
+                    }
+                    EditRemoveDuplicateLines => tb.remove_duplicate_lines(),
+                    EditReverseLines => tb.reverse_lines(),
+                    EditShuffleLines => tb.shuffle_lines(),
+                    EditJoinLines => tb.join_lines(),
+                    EditDuplicateLinesUp => tb.duplicate_lines(MoveLineDirection::Up),
+                    EditDuplicateLinesDown => tb.duplicate_lines(MoveLineDirection::Down),
+                    EditDeleteLines => tb.delete_lines(),
+                    EditInsertLineAbove => tb.insert_blank_line(MoveLineDirection::Up),
+                    EditInsertLineBelow => tb.insert_blank_line(MoveLineDirection::Down),
+                    ViewWordWrap => {
+                        let word_wrap = tb.is_word_wrap_enabled();
+                        tb.set_word_wrap(!word_wrap);
+                    }
+                    ViewFold => tb.fold_at_cursor(),
+                    ViewUnfold => tb.unfold_at_cursor(),
+                    ViewFoldAll => tb.fold_all(),
+                    ViewUnfoldAll => tb.unfold_all(),
+                    ViewNewlines => {
+                        let crlf = tb.is_crlf();
+                        tb.normalize_newlines(!crlf);
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        }
+
+        ctx.needs_rerender();
+    }
+}
+
+pub fn draw_command_palette(ctx: &mut Context, state: &mut State) {
+    let width = (ctx.size().width - 20).max(10);
+    let height = (ctx.size().height - 10).max(10);
+    let scratch = scratch_arena(None);
+    let mut activated = None;
+    let mut done = false;
+
+    // Rank the commands by how well their "Category: Label" matches the needle.
+    let needle = state.command_palette_needle.trim_ascii();
+    let mut results = Vec::new_in(&*scratch);
+    for &command in COMMANDS {
+        if !command.is_available(state) {
+            continue;
+        }
+        let text =
+            arena_format!(&*scratch, "{}: {}", loc(command.category()), loc(command.label()));
+        if needle.is_empty() {
+            results.push((0, command, text, Vec::new_in(&*scratch)));
+        } else {
+            let (score, positions) = score_fuzzy(&scratch, &text, needle, true);
+            if score > 0 {
+                results.push((score, command, text, positions));
+            }
+        }
+    }
+    // The sort is stable, so an empty needle keeps the menu order.
+    results.sort_by_key(|r| std::cmp::Reverse(r.0));
+
+    let selected = state.command_palette_selected.min(results.len().saturating_sub(1));
+    let mut scroll_to_selection = false;
+
+    ctx.modal_begin("command-palette", loc(LocId::ViewCommandPalette));
+    {
+        if ctx.editline("needle", &mut state.command_palette_needle) {
+            state.command_palette_selected = 0;
+            scroll_to_selection = true;
+        }
+        ctx.attr_intrinsic_size(Size { width, height: 1 });
+        ctx.steal_focus();
+
+        if ctx.consume_shortcut(vk::UP) {
+            state.command_palette_selected = selected.saturating_sub(1);
+            scroll_to_selection = true;
+            ctx.needs_rerender();
+        } else if ctx.consume_shortcut(vk::DOWN) {
+            state.command_palette_selected = (selected + 1).min(results.len().saturating_sub(1));
+            scroll_to_selection = true;
+            ctx.needs_rerender();
+        } else if ctx.consume_shortcut(vk::RETURN) {
+            activated = results.get(selected).map(|r| r.1);
+        }
+
+        ctx.scrollarea_begin("scrollarea", Size { width, height });
+        ctx.attr_background_rgba(ctx.indexed_alpha(IndexedColor::Black, 1, 4));
+        {
+            // Keep the selection in view, which is drawn in the next frame.
+            if scroll_to_selection {
+                let selected = state.command_palette_selected as CoordType;
+                ctx.scrollarea_scroll_to(Point { x: 0, y: (selected - height + 1).max(0) });
+            }
+
+            ctx.block_begin("commands");
+            for (i, (_, command, text, positions)) in results.iter().enumerate() {
+                ctx.next_block_id_mixin(i as u64);
+                ctx.styled_label_begin("command");
+                ctx.attr_overflow(Overflow::TruncateTail);
+                ctx.attr_padding(Rect::two(0, 2));
+                if i == selected {
+                    ctx.attr_reverse();
+                }
+
+                fuzzy_label_add_text(ctx, text, positions);
+
+                let shortcut = ctx.shortcut_text(command.shortcut());
+                if !shortcut.is_empty() {
+                    ctx.styled_label_add_text("   ");
+                    ctx.styled_label_set_attributes(Attributes::Italic);
+                    ctx.styled_label_add_text(&shortcut);
+                }
+                ctx.styled_label_end();
+
+                if ctx.was_mouse_down() {
+                    activated = Some(*command);
+                }
+            }
+            ctx.block_end();
+        }
+        ctx.scrollarea_end();
+    }
+    done |= ctx.modal_end();
+
+    if let Some(command) = activated {
+        // Close the palette first, so that the command can open dialogs of its own.
+        close_command_palette(ctx, state);
+        command.execute(ctx, state);
+    } else if done {
+        close_command_palette(ctx, state);
+    }
+}
+
+fn close_command_palette(ctx: &mut Context, state: &mut State) {
+    state.wants_command_palette = false;
+    state.command_palette_needle.clear();
+    state.command_palette_selected = 0;
+    ctx.needs_rerender();
+}
+
+/// Adds `text` to the current styled label and highlights the characters at the
+/// `positions` returned by [`score_fuzzy`], which are indices of `char`s, not bytes.
+pub fn fuzzy_label_add_text(ctx: &mut Context, text: &str, positions: &[usize]) {
+    let highlight = ctx.indexed(IndexedColor::BrightBlue);
+    let mut positions = positions.iter().copied().peekable();
+    let mut highlighted = false;
+    let mut beg = 0;
+
+    for (idx, (off, _)) in text.char_indices().enumerate() {
+        let matched = positions.next_if_eq(&idx).is_some();
+        if matched != highlighted {
+            ctx.styled_label_add_text(&text[beg..off]);
+            ctx.styled_label_set_foreground(if matched { highlight } else { StraightRgba::zero() });
+            highlighted = matched;
+            beg = off;
+        }
+    }
+
+    ctx.styled_label_add_text(&text[beg..]);
+    ctx.styled_label_set_foreground(StraightRgba::zero());
+}
@@ -1,6 +1,9 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
+use std::env;
+use std::path::Path;
+
 use edit::arena::scratch_arena;
 use edit::framebuffer::{Attributes, IndexedColor};
 use edit::fuzzy::score_fuzzy;
@@ -9,6 +12,8 @@ use edit::input::vk;
 use edit::tui::*;
 use edit::{arena_format, icu};
 
+use crate::documents::DocumentManager;
+use crate::draw_palette::fuzzy_label_add_text;
 use crate::localization::*;
 use crate::state::*;
 
@@ -301,48 +306,167 @@ fn encoding_picker_update_list(state: &mut State) {
         }
     }
 
-    matches.sort_by(|a, b| b.0.cmp(&a.0));
+    matches.sort_by_key(|a| std::cmp::Reverse(a.0));
     state.encoding_picker_results = Some(Vec::from_iter(matches.iter().map(|(_, enc)| *enc)));
 }
 
 pub fn draw_go_to_file(ctx: &mut Context, state: &mut State) {
+    /// Only the best matches are shown, since laying out all files in a large tree is slow.
+    const MAX_FILE_RESULTS: usize = 500;
+
+    enum Row {
+        /// The n-th open document.
+        Document(usize),
+        /// An index into the `FileIndex`.
+        File(usize),
+    }
+
+    let width = (ctx.size().width - 20).max(10);
+    let height = (ctx.size().height - 10).max(10);
+    let scratch = scratch_arena(None);
+
+    // The index is kept while the dialog is closed, and refreshed when it's opened again.
+    state.file_index.update(env::current_dir().unwrap_or_default());
+
+    // The needle may end in a ":line:column" suffix, which isn't part of the search.
+    let input = arena_format!(&*scratch, "{}", state.go_to_file_needle);
+    let (needle, goto) = DocumentManager::parse_filename_goto(Path::new(input.as_str()));
+    let needle = needle.to_str().unwrap_or_default();
+    let goto_suffix = &input[needle.len()..];
+    let needle = needle.trim_ascii();
+    state.file_index.set_needle(needle);
+
+    // Open documents come first, in their MRU order, followed by the files in the tree.
+    let mut rows = Vec::new_in(&*scratch);
+    let mut open_paths = Vec::new_in(&*scratch);
+    for (i, doc) in state.documents.iter().enumerate() {
+        if needle.is_empty() || score_fuzzy(&scratch, &doc.filename, needle, true).0 > 0 {
+            rows.push(Row::Document(i));
+        }
+        if let Some(path) = &doc.path {
+            open_paths.push(path.as_path());
+        }
+    }
+    let root = state.file_index.root().to_path_buf();
+    let mut files = Vec::new_in(&*scratch);
+    files.extend(state.file_index.results().iter().take(MAX_FILE_RESULTS).map(|&(_, i)| i));
+    for i in files {
+        let path = root.join(state.file_index.path(i));
+        if !open_paths.contains(&path.as_path()) {
+            rows.push(Row::File(i));
+        }
+    }
+
+    let selected = state.go_to_file_selected.min(rows.len().saturating_sub(1));
+    let mut scroll_to_selection = false;
+    let mut activated = None;
+
     ctx.modal_begin("go-to-file", loc(LocId::ViewGoToFile));
     {
-        let width = (ctx.size().width - 20).max(10);
-        let height = (ctx.size().height - 10).max(10);
+        if ctx.editline("needle", &mut state.go_to_file_needle) {
+            state.go_to_file_selected = 0;
+            scroll_to_selection = true;
+        }
+        ctx.attr_intrinsic_size(Size { width, height: 1 });
+        ctx.steal_focus();
+
+        if ctx.consume_shortcut(vk::UP) {
+            state.go_to_file_selected = selected.saturating_sub(1);
+            scroll_to_selection = true;
+            ctx.needs_rerender();
+        } else if ctx.consume_shortcut(vk::DOWN) {
+            state.go_to_file_selected = (selected + 1).min(rows.len().saturating_sub(1));
+            scroll_to_selection = true;
+            ctx.needs_rerender();
+        } else if ctx.consume_shortcut(vk::RETURN) && !rows.is_empty() {
+            activated = Some(selected);
+        }
 
         ctx.scrollarea_begin("scrollarea", Size { width, height });
         ctx.attr_background_rgba(ctx.indexed_alpha(IndexedColor::Black, 1, 4));
-        ctx.inherit_focus();
         {
-            ctx.list_begin("documents");
-            ctx.inherit_focus();
-
-            if state.documents.update_active(|doc| {
-                let tb = doc.buffer.borrow();
+            // Keep the selection in view, which is drawn in the next frame.
+            if scroll_to_selection {
+                let selected = state.go_to_file_selected as CoordType;
+                ctx.scrollarea_scroll_to(Point { x: 0, y: (selected - height + 1).max(0) });
+            }
 
-                ctx.styled_list_item_begin();
+            ctx.block_begin("files");
+            for (i, row) in rows.iter().enumerate() {
+                ctx.next_block_id_mixin(i as u64);
+                ctx.styled_label_begin("file");
                 ctx.attr_overflow(Overflow::TruncateTail);
-                ctx.styled_label_add_text(if tb.is_dirty() { "* " } else { "  " });
-                ctx.styled_label_add_text(&doc.filename);
+                ctx.attr_padding(Rect::two(0, 1));
+                if i == selected {
+                    ctx.attr_reverse();
+                }
+
+                match *row {
+                    Row::Document(n) => {
+                        let doc = state.documents.iter().nth(n).unwrap();
+                        let dirty = doc.buffer.borrow().is_dirty();
+                        let (_, positions) = score_fuzzy(&scratch, &doc.filename, needle, true);
+
+                        ctx.styled_label_add_text(if dirty { "* " } else { "  " });
+                        fuzzy_label_add_text(ctx, &doc.filename, &positions);
+
+                        if let Some(path) = &doc.dir {
+                            ctx.styled_label_add_text("   ");
+                            ctx.styled_label_set_attributes(Attributes::Italic);
+                            ctx.styled_label_add_text(path.as_str());
+                        }
+                    }
+                    Row::File(n) => {
+                        let path = state.file_index.path(n);
+                        let (_, positions) = score_fuzzy(&scratch, path, needle, true);
 
-                if let Some(path) = &doc.dir {
-                    ctx.styled_label_add_text("   ");
-                    ctx.styled_label_set_attributes(Attributes::Italic);
-                    ctx.styled_label_add_text(path.as_str());
+                        ctx.styled_label_add_text("  ");
+                        fuzzy_label_add_text(ctx, path, &positions);
+                    }
                 }
+                ctx.styled_label_end();
 
-                ctx.styled_list_item_end(false) == ListSelection::Activated
-            }) {
-                state.wants_go_to_file = false;
-                ctx.needs_rerender();
+                if ctx.was_mouse_down() {
+                    activated = Some(i);
+                }
             }
-
-            ctx.list_end();
+            ctx.block_end();
         }
         ctx.scrollarea_end();
     }
-    if ctx.modal_end() {
+    let mut done = ctx.modal_end();
+
+    match activated.map(|i| &rows[i]) {
+        Some(&Row::Document(n)) => {
+            let mut i = 0;
+            state.documents.update_active(|_| {
+                i += 1;
+                i > n
+            });
+            if let Some(goto) = goto
+                && let Some(doc) = state.documents.active()
+            {
+                let mut tb = doc.buffer.borrow_mut();
+                tb.cursor_move_to_logical(goto);
+                tb.make_cursor_visible();
+            }
+            done = true;
+        }
+        Some(&Row::File(n)) => {
+            let path = root.join(format!("{}{}", state.file_index.path(n), goto_suffix));
+            if let Err(err) = state.documents.add_file_path(&path) {
+                error_log_add(ctx, state, err);
+            }
+            done = true;
+        }
+        None => {}
+    }
+
+    if done {
         state.wants_go_to_file = false;
+        state.go_to_file_needle.clear();
+        state.go_to_file_selected = 0;
+        state.file_index.mark_outdated();
+        ctx.needs_rerender();
     }
 }
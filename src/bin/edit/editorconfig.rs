@@ -0,0 +1,246 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Support for [EditorConfig](https://editorconfig.org) files.
+//!
+//! Starting at the directory of a file, we walk up the tree and read each `.editorconfig`
+//! until we find one with `root = true`. Sections whose glob matches the file apply their
+//! properties, with files closer to the file and later sections taking precedence.
+
+use std::fs;
+use std::path::Path;
+
+use edit::buffer::TextBuffer;
+use edit::helpers::CoordType;
+use edit::icu;
+
+use crate::glob;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IndentSize {
+    /// Use the `tab_width`.
+    Tab,
+    Columns(CoordType),
+}
+
+/// The properties that apply to a file. `None` means unspecified.
+#[derive(Default, PartialEq, Eq, Debug)]
+pub struct Properties {
+    pub indent_with_tabs: Option<bool>,
+    pub indent_size: Option<IndentSize>,
+    pub tab_width: Option<CoordType>,
+    pub crlf: Option<bool>,
+    /// The name of the encoding, as used by [`TextBuffer::encoding`].
+    pub charset: Option<&'static str>,
+    pub trim_trailing_whitespace: Option<bool>,
+    pub insert_final_newline: Option<bool>,
+    /// 0 if explicitly turned off.
+    pub max_line_length: Option<CoordType>,
+}
+
+impl Properties {
+    /// Finds the `.editorconfig` files that apply to `path` and resolves their properties.
+    pub fn resolve(path: &Path) -> Self {
+        let mut files = Vec::new();
+
+        for dir in path.ancestors().skip(1) {
+            if let Ok(text) = fs::read_to_string(dir.join(".editorconfig")) {
+                let root = is_root(&text);
+                files.push((dir, text));
+                if root {
+                    break;
+                }
+            }
+        }
+
+        let mut props = Self::default();
+        // The closest file has the highest precedence, so it's applied last.
+        for (dir, text) in files.iter().rev() {
+            let Ok(relative) = path.strip_prefix(dir) else {
+                continue;
+            };
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            props.parse(text, &relative);
+        }
+        props
+    }
+
+    /// Parses an `.editorconfig` file and applies all sections matching `path`,
+    /// which must be relative to the directory of the file and use `/` as the separator.
+    fn parse(&mut self, text: &str, path: &str) {
+        let mut matching = false;
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                matching = line.rfind(']').is_some_and(|end| glob::matches(&line[1..end], path));
+                continue;
+            }
+
+            if matching && let Some((key, value)) = line.split_once('=') {
+                self.set(&key.trim().to_ascii_lowercase(), &value.trim().to_ascii_lowercase());
+            }
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        fn columns(value: &str) -> Option<CoordType> {
+            value.parse().ok().filter(|&n| n > 0)
+        }
+
+        // "unset" removes a property. Unknown values do so as well, which is close enough.
+        match key {
+            "indent_style" => {
+                self.indent_with_tabs = match value {
+                    "tab" => Some(true),
+                    "space" => Some(false),
+                    _ => None,
+                }
+            }
+            "indent_size" => {
+                self.indent_size = match value {
+                    "tab" => Some(IndentSize::Tab),
+                    _ => columns(value).map(IndentSize::Columns),
+                }
+            }
+            "tab_width" => self.tab_width = columns(value),
+            "end_of_line" => {
+                self.crlf = match value {
+                    "lf" => Some(false),
+                    "crlf" => Some(true),
+                    _ => None,
+                }
+            }
+            "charset" => {
+                self.charset = match value {
+                    "utf-8" => Some("UTF-8"),
+                    "utf-8-bom" => Some("UTF-8 BOM"),
+                    "utf-16le" => Some("UTF-16LE"),
+                    "utf-16be" => Some("UTF-16BE"),
+                    "latin1" => Some("ISO-8859-1"),
+                    _ => None,
+                }
+            }
+            "trim_trailing_whitespace" => self.trim_trailing_whitespace = parse_bool(value),
+            "insert_final_newline" => self.insert_final_newline = parse_bool(value),
+            "max_line_length" => {
+                self.max_line_length = if value == "off" { Some(0) } else { columns(value) }
+            }
+            _ => {}
+        }
+    }
+
+    /// The width of a tab, which is also the indentation width, since we don't distinguish them.
+    pub fn tab_size(&self) -> Option<CoordType> {
+        match (self.indent_with_tabs, self.indent_size, self.tab_width) {
+            // When indenting with tabs, the tab width is what's visible.
+            (Some(true), _, Some(width)) => Some(width),
+            (_, Some(IndentSize::Columns(size)), _) => Some(size),
+            (_, _, width) => width,
+        }
+    }
+
+    /// The encoding from the `charset`, if it's specified and supported.
+    pub fn encoding(&self) -> Option<&'static str> {
+        let charset = self.charset?;
+        let encodings = icu::get_available_encodings();
+        encodings.all.iter().find(|enc| enc.canonical == charset).map(|enc| enc.canonical)
+    }
+
+    /// The encoding to read an existing file with.
+    pub fn read_encoding(&self) -> Option<&'static str> {
+        // Whether there's a BOM is detected while reading.
+        self.encoding().map(|enc| if enc == "UTF-8 BOM" { "UTF-8" } else { enc })
+    }
+
+    /// Applies the properties to a buffer, overriding any detected from its contents.
+    pub fn apply(&self, tb: &mut TextBuffer) {
+        if let Some(indent_with_tabs) = self.indent_with_tabs {
+            tb.set_indent_with_tabs(indent_with_tabs);
+        }
+        if let Some(tab_size) = self.tab_size() {
+            tb.set_tab_size(tab_size);
+        }
+        if let Some(crlf) = self.crlf {
+            tb.set_crlf(crlf);
+        }
+        if let Some(insert_final_newline) = self.insert_final_newline {
+            tb.set_insert_final_newline(insert_final_newline);
+        }
+        if let Some(max_line_length) = self.max_line_length {
+            tb.set_ruler(max_line_length);
+        }
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Checks for `root = true` in the preamble.
+fn is_root(text: &str) -> bool {
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            break;
+        }
+        if let Some((key, value)) = line.split_once('=')
+            && key.trim().eq_ignore_ascii_case("root")
+        {
+            return value.trim().eq_ignore_ascii_case("true");
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "
+root = true
+
+[*]
+indent_style = space
+indent_size = 4
+end_of_line = LF
+insert_final_newline = true
+
+; Makefiles need tabs.
+[Makefile]
+indent_style = tab
+indent_size = tab
+tab_width = 8
+
+[*.md]
+trim_trailing_whitespace = false
+max_line_length = off
+insert_final_newline = unset
+";
+        assert!(is_root(text));
+
+        let mut props = Properties::default();
+        props.parse(text, "src/Makefile");
+        assert_eq!(props.indent_with_tabs, Some(true));
+        assert_eq!(props.indent_size, Some(IndentSize::Tab));
+        assert_eq!(props.tab_size(), Some(8));
+        assert_eq!(props.crlf, Some(false));
+
+        let mut props = Properties::default();
+        props.parse(text, "README.md");
+        assert_eq!(props.tab_size(), Some(4));
+        assert_eq!(props.trim_trailing_whitespace, Some(false));
+        assert_eq!(props.max_line_length, Some(0));
+        assert_eq!(props.insert_final_newline, None);
+    }
+}
@@ -0,0 +1,325 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! An index of the files below the working directory for the "Go to File" dialog.
+//!
+//! The directory tree is crawled incrementally by [`FileIndex::crawl`], in slices of
+//! limited duration, so that the UI stays responsive in large trees. When the index is
+//! refreshed, the previous files stay available until the new crawl is complete.
+//! `.gitignore` files are respected, and binary or huge files are skipped.
+
+use std::fs::{self, File};
+use std::io::Read as _;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use edit::arena::scratch_arena;
+use edit::fuzzy::score_fuzzy;
+use edit::helpers::MEBI;
+
+use crate::glob;
+
+/// Files larger than this are skipped.
+const MAX_FILE_SIZE: u64 = 16 * MEBI as u64;
+/// Stop crawling after this many files, so that opening edit in `/` doesn't eat all memory.
+const MAX_FILES: usize = 100_000;
+
+struct IgnorePattern {
+    glob: String,
+    negate: bool,
+    dir_only: bool,
+}
+
+/// The patterns of a `.gitignore` file.
+struct IgnoreFile {
+    /// The directory of the file, relative to the root, with a trailing `/` unless it's the root.
+    dir: String,
+    patterns: Vec<IgnorePattern>,
+}
+
+impl IgnoreFile {
+    fn parse(dir: String, text: &str) -> Self {
+        let mut patterns = Vec::new();
+
+        for line in text.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (negate, line) = match line.strip_prefix('!') {
+                Some(line) => (true, line),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            if !line.is_empty() {
+                patterns.push(IgnorePattern { glob: line.to_string(), negate, dir_only });
+            }
+        }
+
+        Self { dir, patterns }
+    }
+}
+
+/// A directory that still needs to be crawled and the `.gitignore` files that apply to it.
+struct PendingDir {
+    /// Relative to the root, with a trailing `/` unless it's the root.
+    path: String,
+    ignores: Rc<Vec<Rc<IgnoreFile>>>,
+}
+
+/// The directory that's being crawled, which may take several calls to [`FileIndex::crawl`].
+struct CrawlingDir {
+    path: String,
+    ignores: Rc<Vec<Rc<IgnoreFile>>>,
+    entries: fs::ReadDir,
+    subdirs: Vec<PendingDir>,
+}
+
+#[derive(Default)]
+pub struct FileIndex {
+    root: PathBuf,
+    pending: Vec<PendingDir>,
+    current: Option<CrawlingDir>,
+    /// The paths of all files found so far, relative to the root and separated by `/`.
+    files: Vec<String>,
+    /// While refreshing, the files found so far, which replace `files` once we're done.
+    next_files: Option<Vec<String>>,
+    /// Set by [`FileIndex::mark_outdated`].
+    outdated: bool,
+
+    needle: String,
+    /// The score and index into `files` of each match for the `needle`, best first.
+    results: Vec<(i32, usize)>,
+    /// How many of the `files` have been scored against the `needle`.
+    scored: usize,
+}
+
+impl FileIndex {
+    /// Discards the index and starts crawling `root` from scratch.
+    pub fn start(&mut self, root: PathBuf) {
+        *self = Self { needle: std::mem::take(&mut self.needle), ..Default::default() };
+        self.root = root;
+        self.pending.push(PendingDir { path: String::new(), ignores: Default::default() });
+    }
+
+    /// Marks the index as outdated, so that the next [`FileIndex::update`] crawls the tree again.
+    pub fn mark_outdated(&mut self) {
+        self.outdated = true;
+    }
+
+    /// Starts crawling `root` if the index doesn't cover it yet, or refreshes it if it's outdated.
+    pub fn update(&mut self, root: PathBuf) {
+        if root != self.root {
+            self.start(root);
+        } else if self.outdated && !self.is_crawling() {
+            self.outdated = false;
+            self.next_files = Some(Vec::new());
+            self.pending.push(PendingDir { path: String::new(), ignores: Default::default() });
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn is_crawling(&self) -> bool {
+        !self.pending.is_empty() || self.current.is_some()
+    }
+
+    /// Crawls directory entries until `budget` is used up or we're done.
+    pub fn crawl(&mut self, budget: Duration) {
+        let beg = Instant::now();
+
+        while beg.elapsed() < budget {
+            if self.next_files.as_ref().unwrap_or(&self.files).len() >= MAX_FILES {
+                self.pending.clear();
+                self.current = None;
+                break;
+            }
+
+            let mut dir = match self.current.take() {
+                Some(dir) => dir,
+                None => match self.pending.pop() {
+                    Some(dir) => {
+                        self.current = self.open_dir(dir);
+                        continue;
+                    }
+                    None => break,
+                },
+            };
+
+            match dir.entries.next() {
+                Some(entry) => {
+                    if let Ok(entry) = entry {
+                        self.crawl_entry(&mut dir, entry);
+                    }
+                    self.current = Some(dir);
+                }
+                None => {
+                    // Reversed, so that they're popped in alphabetical order.
+                    dir.subdirs.sort_unstable_by(|a, b| b.path.cmp(&a.path));
+                    self.pending.extend(dir.subdirs);
+                }
+            }
+        }
+
+        if !self.is_crawling()
+            && let Some(files) = self.next_files.take()
+        {
+            self.files = files;
+            self.results.clear();
+            self.scored = 0;
+        }
+    }
+
+    fn open_dir(&self, dir: PendingDir) -> Option<CrawlingDir> {
+        let abs = self.root.join(&dir.path);
+        let entries = fs::read_dir(&abs).ok()?;
+
+        let mut ignores = dir.ignores;
+        if let Ok(text) = fs::read_to_string(abs.join(".gitignore")) {
+            let mut list = Vec::clone(&ignores);
+            list.push(Rc::new(IgnoreFile::parse(dir.path.clone(), &text)));
+            ignores = Rc::new(list);
+        }
+
+        Some(CrawlingDir { path: dir.path, ignores, entries, subdirs: Vec::new() })
+    }
+
+    fn crawl_entry(&mut self, dir: &mut CrawlingDir, entry: fs::DirEntry) {
+        let Ok(file_type) = entry.file_type() else {
+            return;
+        };
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            return;
+        };
+        // Symlinks to directories aren't followed, since they may form cycles.
+        let is_dir = file_type.is_dir();
+        if name == ".git" || !(is_dir || file_type.is_file() || file_type.is_symlink()) {
+            return;
+        }
+
+        let path = format!("{}{}", dir.path, name);
+        if is_ignored(&dir.ignores, &path, is_dir) {
+            return;
+        }
+
+        if is_dir {
+            dir.subdirs.push(PendingDir { path: path + "/", ignores: dir.ignores.clone() });
+        } else if is_text_file(&entry.path()) {
+            self.next_files.as_mut().unwrap_or(&mut self.files).push(path);
+        }
+    }
+
+    /// Sets the needle that [`FileIndex::results`] are matched against.
+    pub fn set_needle(&mut self, needle: &str) {
+        if self.needle != needle {
+            self.needle.clear();
+            self.needle.push_str(needle);
+            self.results.clear();
+            self.scored = 0;
+        }
+    }
+
+    /// Returns the files that match the needle, best first, as indices for [`FileIndex::path`].
+    /// Files found since the last call are scored first.
+    pub fn results(&mut self) -> &[(i32, usize)] {
+        if self.scored < self.files.len() {
+            let scratch = scratch_arena(None);
+
+            for (i, path) in self.files.iter().enumerate().skip(self.scored) {
+                if self.needle.is_empty() {
+                    self.results.push((0, i));
+                } else {
+                    let local_scratch = scratch_arena(Some(&scratch));
+                    let (score, _) = score_fuzzy(&local_scratch, path, &self.needle, true);
+                    if score > 0 {
+                        self.results.push((score, i));
+                    }
+                }
+            }
+
+            self.scored = self.files.len();
+            // The sort is stable, so files with equal scores stay in crawl order.
+            self.results.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+        }
+
+        &self.results
+    }
+
+    /// The number of files found so far.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// The path of the file with the given index, relative to the root.
+    pub fn path(&self, index: usize) -> &str {
+        &self.files[index]
+    }
+}
+
+fn is_ignored(ignores: &[Rc<IgnoreFile>], path: &str, is_dir: bool) -> bool {
+    let mut ignored = false;
+
+    // Later patterns and files in subdirectories take precedence.
+    for file in ignores {
+        let Some(relative) = path.strip_prefix(&file.dir) else {
+            continue;
+        };
+        for pattern in &file.patterns {
+            if ignored != !pattern.negate
+                && (is_dir || !pattern.dir_only)
+                && glob::matches(&pattern.glob, relative)
+            {
+                ignored = !pattern.negate;
+            }
+        }
+    }
+
+    ignored
+}
+
+/// Returns false for huge files and those that look binary, because they contain NUL bytes.
+fn is_text_file(path: &Path) -> bool {
+    if !fs::metadata(path).is_ok_and(|m| m.is_file() && m.len() <= MAX_FILE_SIZE) {
+        return false;
+    }
+    let Ok(mut file) = File::open(path) else {
+        return false;
+    };
+
+    let mut buf = [0; 1024];
+    let len = file.read(&mut buf).unwrap_or(0);
+    !buf[..len].contains(&0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_ignored() {
+        let root = Rc::new(IgnoreFile::parse(
+            String::new(),
+            "# Build output\n/target\n*.log\n!keep.log\nnode_modules/\n",
+        ));
+        let sub = Rc::new(IgnoreFile::parse("src/".to_string(), "*.tmp\n!/target\n"));
+        let ignores = [root, sub];
+
+        assert!(is_ignored(&ignores, "target", true));
+        assert!(!is_ignored(&ignores, "src/target", true));
+        assert!(is_ignored(&ignores, "src/debug.log", false));
+        assert!(!is_ignored(&ignores, "src/keep.log", false));
+        assert!(is_ignored(&ignores, "web/node_modules", true));
+        assert!(!is_ignored(&ignores, "web/node_modules", false));
+        assert!(is_ignored(&ignores, "src/a/b.tmp", false));
+        assert!(!is_ignored(&ignores, "b.tmp", false));
+        assert!(!is_ignored(&ignores, "src/main.rs", false));
+    }
+}
@@ -0,0 +1,141 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Searching through all files below the working directory.
+//!
+//! The files are found with a [`FileIndex`] and searched one after another by
+//! [`FindInFiles::step`], in slices of limited duration, like the index itself is crawled.
+//! Open documents are searched instead of their files on disk, including unsaved changes.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use edit::apperr;
+use edit::buffer::{SearchHit, SearchOptions, TextBuffer};
+
+use crate::documents::DocumentManager;
+use crate::file_index::FileIndex;
+
+/// Stop searching after this many hits, because displaying them gets slow.
+pub const MAX_HITS: usize = 2000;
+
+/// The hits within a single file.
+pub struct FileHits {
+    /// Relative to the root of the search.
+    pub path: String,
+    pub hits: Vec<SearchHit>,
+}
+
+#[derive(Default)]
+pub struct FindInFiles {
+    index: FileIndex,
+    pattern: String,
+    options: SearchOptions,
+    replacement: Option<String>,
+    /// The next file in the `index` to search.
+    next_file: usize,
+    /// Reused for reading the files that aren't open.
+    buffer: Option<TextBuffer>,
+
+    pub results: Vec<FileHits>,
+    pub hit_count: usize,
+    /// The number of files changed by the last "Replace All", for reporting it.
+    pub replaced_files: Option<usize>,
+}
+
+impl FindInFiles {
+    /// Starts a new search of all files below `root`. With a `replacement`,
+    /// each hit contains a preview of what it would be replaced with.
+    pub fn start(
+        &mut self,
+        root: PathBuf,
+        pattern: &str,
+        options: SearchOptions,
+        replacement: Option<&str>,
+    ) {
+        *self = Self { buffer: self.buffer.take(), ..Default::default() };
+        if pattern.is_empty() {
+            return;
+        }
+
+        self.index.start(root);
+        self.pattern.push_str(pattern);
+        self.options = options;
+        self.replacement = replacement.map(str::to_string);
+    }
+
+    /// Aborts the search and discards the results.
+    pub fn clear(&mut self) {
+        *self = Self { buffer: self.buffer.take(), ..Default::default() };
+    }
+
+    pub fn root(&self) -> &Path {
+        self.index.root()
+    }
+
+    pub fn is_searching(&self) -> bool {
+        !self.pattern.is_empty()
+            && self.hit_count < MAX_HITS
+            && (self.index.is_crawling() || self.next_file < self.index.len())
+    }
+
+    /// Returns true if the search stopped at [`MAX_HITS`], so there may be more hits.
+    pub fn is_truncated(&self) -> bool {
+        self.hit_count >= MAX_HITS
+    }
+
+    /// Crawls the tree and searches files until `budget` is used up or we're done.
+    /// An invalid pattern stops the search and is returned as an error.
+    pub fn step(&mut self, documents: &DocumentManager, budget: Duration) -> apperr::Result<()> {
+        let beg = Instant::now();
+
+        while beg.elapsed() < budget && self.is_searching() {
+            if self.next_file >= self.index.len() {
+                self.index.crawl(budget.saturating_sub(beg.elapsed()));
+                continue;
+            }
+
+            let path = self.index.path(self.next_file).to_string();
+            self.next_file += 1;
+
+            let res = self.search_file(documents, &path);
+            if res.is_err() {
+                self.pattern.clear();
+            }
+            let hits = res?;
+            if !hits.is_empty() {
+                self.hit_count += hits.len();
+                self.results.push(FileHits { path, hits });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn search_file(
+        &mut self,
+        documents: &DocumentManager,
+        path: &str,
+    ) -> apperr::Result<Vec<SearchHit>> {
+        let abs = self.index.root().join(path);
+        let limit = MAX_HITS - self.hit_count;
+        let replacement = self.replacement.as_deref().map(str::as_bytes);
+
+        if let Some(doc) = documents.iter().find(|doc| doc.path.as_deref() == Some(abs.as_path())) {
+            return doc.buffer.borrow().find_all(&self.pattern, self.options, replacement, limit);
+        }
+
+        let tb = match &mut self.buffer {
+            Some(tb) => tb,
+            None => self.buffer.insert(TextBuffer::new(false)?),
+        };
+        // Files that can't be read are skipped, since they may have vanished in the meantime.
+        let Ok(mut file) = DocumentManager::open_for_reading(&abs) else {
+            return Ok(Vec::new());
+        };
+        if tb.read_file(&mut file, None).is_err() {
+            return Ok(Vec::new());
+        }
+        tb.find_all(&self.pattern, self.options, replacement, limit)
+    }
+}
@@ -0,0 +1,185 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Glob patterns as used by `.editorconfig` and `.gitignore` files:
+//! `*` matches anything but `/`, `**` matches anything, `?` a single character,
+//! `[a-z]` and `[!a-z]` a character class, `{a,b}` alternatives and `{1..10}` a number.
+//! Globs without a `/` match the filename in any directory.
+
+/// Checks whether `glob` matches `path`, which is relative to the directory
+/// of the file the glob is from and uses `/` as the separator.
+pub fn matches(glob: &str, path: &str) -> bool {
+    let glob: Vec<char> = if let Some(glob) = glob.strip_prefix('/') {
+        glob.chars().collect()
+    } else if glob.contains('/') {
+        glob.chars().collect()
+    } else {
+        // Globs without a slash match the filename in any directory.
+        "**/".chars().chain(glob.chars()).collect()
+    };
+    let path: Vec<char> = path.chars().collect();
+    matches_chars(&glob, &path)
+}
+
+fn matches_chars(pat: &[char], text: &[char]) -> bool {
+    let Some(&p) = pat.first() else {
+        return text.is_empty();
+    };
+
+    match p {
+        '*' if pat.get(1) == Some(&'*') => {
+            let rest = &pat[2..];
+            // "**/" may also match no directory at all.
+            if rest.first() == Some(&'/') && matches_chars(&rest[1..], text) {
+                return true;
+            }
+            (0..=text.len()).any(|i| matches_chars(rest, &text[i..]))
+        }
+        '*' => {
+            let rest = &pat[1..];
+            let max = text.iter().position(|&c| c == '/').unwrap_or(text.len());
+            (0..=max).any(|i| matches_chars(rest, &text[i..]))
+        }
+        '?' => text.first().is_some_and(|&c| c != '/') && matches_chars(&pat[1..], &text[1..]),
+        '[' => match match_bracket(&pat[1..], text.first().copied()) {
+            Some((matched, len)) => matched && matches_chars(&pat[1 + len..], &text[1..]),
+            None => text.first() == Some(&'[') && matches_chars(&pat[1..], &text[1..]),
+        },
+        '{' => match split_braces(&pat[1..]) {
+            Some((alternatives, len)) => {
+                let rest = &pat[1 + len..];
+                if let [alt] = &alternatives[..] {
+                    return match_range(alt, rest, text);
+                }
+                alternatives.iter().any(|alt| {
+                    let mut p = alt.to_vec();
+                    p.extend_from_slice(rest);
+                    matches_chars(&p, text)
+                })
+            }
+            None => text.first() == Some(&'{') && matches_chars(&pat[1..], &text[1..]),
+        },
+        '\\' if pat.len() > 1 => {
+            text.first() == Some(&pat[1]) && matches_chars(&pat[2..], &text[1..])
+        }
+        _ => text.first() == Some(&p) && matches_chars(&pat[1..], &text[1..]),
+    }
+}
+
+/// Matches `ch` against the bracket expression at the start of `pat` (after the "[").
+/// Returns whether it matched and the length of the expression including the "]",
+/// or `None` if it's not terminated.
+fn match_bracket(pat: &[char], ch: Option<char>) -> Option<(bool, usize)> {
+    let negate = pat.first() == Some(&'!');
+    let mut i = negate as usize;
+    let mut matched = false;
+
+    loop {
+        let &c = pat.get(i)?;
+        if c == ']' && i > negate as usize {
+            break;
+        }
+        if c == '/' {
+            // Brackets never match a path separator.
+            return None;
+        }
+        if pat.get(i + 1) == Some(&'-') && pat.get(i + 2).is_some_and(|&e| e != ']') {
+            let end = pat[i + 2];
+            matched |= ch.is_some_and(|ch| (c..=end).contains(&ch));
+            i += 3;
+        } else {
+            matched |= ch == Some(c);
+            i += 1;
+        }
+    }
+
+    Some((ch.is_some() && matched != negate, i + 1))
+}
+
+/// Splits the brace expression at the start of `pat` (after the "{") at its top-level commas.
+/// Returns the alternatives and the length of the expression including the "}",
+/// or `None` if it's not terminated.
+fn split_braces(pat: &[char]) -> Option<(Vec<&[char]>, usize)> {
+    let mut alternatives = Vec::new();
+    let mut depth = 0;
+    let mut beg = 0;
+    let mut i = 0;
+
+    while i < pat.len() {
+        match pat[i] {
+            '\\' => i += 1,
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            '}' => {
+                alternatives.push(&pat[beg..i]);
+                return Some((alternatives, i + 1));
+            }
+            ',' if depth == 0 => {
+                alternatives.push(&pat[beg..i]);
+                beg = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    None
+}
+
+/// Handles a brace expression without commas, which is either
+/// a numeric range like `{1..10}`, or matched literally.
+fn match_range(alt: &[char], rest: &[char], text: &[char]) -> bool {
+    let s: String = alt.iter().collect();
+    if let Some((lo, hi)) = s.split_once("..")
+        && let (Ok(lo), Ok(hi)) = (lo.parse::<i64>(), hi.parse::<i64>())
+    {
+        let sign = (text.first() == Some(&'-')) as usize;
+        let digits = text[sign..].iter().take_while(|c| c.is_ascii_digit()).count();
+        if digits == 0 {
+            return false;
+        }
+        let num: String = text[..sign + digits].iter().collect();
+        return num.parse::<i64>().is_ok_and(|n| (lo.min(hi)..=lo.max(hi)).contains(&n))
+            && matches_chars(rest, &text[sign + digits..]);
+    }
+
+    let mut p = vec!['{'];
+    p.extend_from_slice(alt);
+    p.push('}');
+    p.extend_from_slice(rest);
+    // Avoid recursing into the same brace expression.
+    text.starts_with(&p[..alt.len() + 2]) && matches_chars(rest, &text[alt.len() + 2..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("*", "file.rs"));
+        assert!(matches("*", "src/file.rs"));
+        assert!(matches("*.rs", "src/bin/file.rs"));
+        assert!(!matches("*.rs", "file.rs.txt"));
+        assert!(matches("/*.rs", "file.rs"));
+        assert!(!matches("/*.rs", "src/file.rs"));
+        assert!(matches("src/*.rs", "src/file.rs"));
+        assert!(!matches("src/*.rs", "src/bin/file.rs"));
+        assert!(matches("src/**.rs", "src/bin/file.rs"));
+        assert!(matches("src/**/file.rs", "src/file.rs"));
+        assert!(matches("file.?s", "file.rs"));
+        assert!(matches("*.{rs,toml}", "Cargo.toml"));
+        assert!(matches("*.{rs,{c,h}}", "main.h"));
+        assert!(!matches("*.{rs,toml}", "main.c"));
+        // Braces without a comma or range are literal.
+        assert!(matches("{Makefile}", "{Makefile}"));
+        assert!(!matches("{Makefile}", "Makefile"));
+        assert!(matches("[Mm]akefile", "makefile"));
+        assert!(matches("file[0-9].txt", "file5.txt"));
+        assert!(!matches("file[!0-9].txt", "file5.txt"));
+        assert!(matches("file{1..10}.txt", "file10.txt"));
+        assert!(!matches("file{1..10}.txt", "file11.txt"));
+        assert!(matches("\\*.txt", "*.txt"));
+        assert!(!matches("\\*.txt", "a.txt"));
+    }
+}
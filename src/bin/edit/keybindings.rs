@@ -0,0 +1,146 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Custom keybindings from the `keybindings.toml` file in the [`edit::sys::config_dir`].
+//!
+//! Each line binds a chord to a command, for instance:
+//! ```toml
+//! "ctrl+k ctrl+c" = "edit.copy"
+//! "ctrl+shift+z" = "edit.redo"
+//! "ctrl+q" = ""  # unbinds Ctrl+Q
+//! ```
+//!
+//! Custom keybindings work by translating the chord into the command's default shortcut.
+//! A command that gets bound to a new chord therefore loses its default shortcut, so that
+//! it can be given to another command. To keep the default as well, bind it explicitly:
+//! ```toml
+//! "ctrl+k ctrl+c" = "edit.copy"
+//! "ctrl+c" = "edit.copy"
+//! ```
+
+use edit::input::{InputKey, kbmod, vk};
+use edit::keymap::{self, Keymap};
+
+use crate::config::{self, Value};
+
+// The default shortcuts of all commands that can be bound. The `draw()` chain in `main.rs` and
+// the menubar dispatch on these, and [`default_shortcut`] looks them up by name.
+pub const FILE_NEW: InputKey = vk::N.with_modifiers(kbmod::CTRL);
+pub const FILE_OPEN: InputKey = vk::O.with_modifiers(kbmod::CTRL);
+pub const FILE_SAVE: InputKey = vk::S.with_modifiers(kbmod::CTRL);
+pub const FILE_SAVE_AS: InputKey = vk::S.with_modifiers(kbmod::CTRL_SHIFT);
+pub const FILE_CLOSE: InputKey = vk::W.with_modifiers(kbmod::CTRL);
+pub const FILE_EXIT: InputKey = vk::Q.with_modifiers(kbmod::CTRL);
+pub const EDIT_UNDO: InputKey = vk::Z.with_modifiers(kbmod::CTRL);
+pub const EDIT_REDO: InputKey = vk::Y.with_modifiers(kbmod::CTRL);
+pub const EDIT_CUT: InputKey = vk::X.with_modifiers(kbmod::CTRL);
+pub const EDIT_COPY: InputKey = vk::C.with_modifiers(kbmod::CTRL);
+pub const EDIT_PASTE: InputKey = vk::V.with_modifiers(kbmod::CTRL);
+pub const EDIT_FIND: InputKey = vk::F.with_modifiers(kbmod::CTRL);
+pub const EDIT_FIND_NEXT: InputKey = vk::F3;
+pub const EDIT_REPLACE: InputKey = vk::R.with_modifiers(kbmod::CTRL);
+pub const EDIT_FIND_IN_FILES: InputKey = vk::F.with_modifiers(kbmod::CTRL_SHIFT);
+pub const EDIT_SELECT_ALL: InputKey = vk::A.with_modifiers(kbmod::CTRL);
+pub const EDIT_SELECT_LINE: InputKey = vk::L.with_modifiers(kbmod::CTRL);
+pub const EDIT_ADD_NEXT_OCCURRENCE: InputKey = vk::D.with_modifiers(kbmod::CTRL);
+pub const EDIT_ADD_CARET_ABOVE: InputKey = vk::UP.with_modifiers(kbmod::CTRL_ALT);
+pub const EDIT_ADD_CARET_BELOW: InputKey = vk::DOWN.with_modifiers(kbmod::CTRL_ALT);
+pub const EDIT_MOVE_LINES_UP: InputKey = vk::UP.with_modifiers(kbmod::ALT);
+pub const EDIT_MOVE_LINES_DOWN: InputKey = vk::DOWN.with_modifiers(kbmod::ALT);
+pub const EDIT_GO_TO_BRACKET: InputKey = vk::OEM_5.with_modifiers(kbmod::CTRL_SHIFT);
+pub const EDIT_TOGGLE_LINE_COMMENT: InputKey = vk::OEM_2.with_modifiers(kbmod::CTRL);
+pub const EDIT_TOGGLE_BLOCK_COMMENT: InputKey = vk::A.with_modifiers(kbmod::ALT_SHIFT);
+pub const VIEW_FOCUS_MENUBAR: InputKey = vk::F10;
+pub const VIEW_GO_TO_FILE: InputKey = vk::P.with_modifiers(kbmod::CTRL);
+pub const VIEW_COMMAND_PALETTE: InputKey = vk::P.with_modifiers(kbmod::CTRL_SHIFT);
+pub const VIEW_GO_TO_LINE: InputKey = vk::G.with_modifiers(kbmod::CTRL);
+pub const VIEW_WORD_WRAP: InputKey = vk::Z.with_modifiers(kbmod::ALT);
+pub const VIEW_FOLD: InputKey = vk::UP.with_modifiers(kbmod::CTRL_SHIFT);
+pub const VIEW_UNFOLD: InputKey = vk::DOWN.with_modifiers(kbmod::CTRL_SHIFT);
+pub const VIEW_FOLD_ALL: InputKey = vk::UP.with_modifiers(kbmod::CTRL_ALT_SHIFT);
+pub const VIEW_UNFOLD_ALL: InputKey = vk::DOWN.with_modifiers(kbmod::CTRL_ALT_SHIFT);
+
+/// The names of the commands in `keybindings.toml` and their default shortcuts.
+/// Those that the textarea handles must match `Context::textarea_handle_input`.
+const COMMANDS: &[(&str, InputKey)] = &[
+    ("file.new", FILE_NEW),
+    ("file.open", FILE_OPEN),
+    ("file.save", FILE_SAVE),
+    ("file.save_as", FILE_SAVE_AS),
+    ("file.close", FILE_CLOSE),
+    ("file.exit", FILE_EXIT),
+    ("edit.undo", EDIT_UNDO),
+    ("edit.redo", EDIT_REDO),
+    ("edit.cut", EDIT_CUT),
+    ("edit.copy", EDIT_COPY),
+    ("edit.paste", EDIT_PASTE),
+    ("edit.find", EDIT_FIND),
+    ("edit.find_next", EDIT_FIND_NEXT),
+    ("edit.replace", EDIT_REPLACE),
+    ("edit.find_in_files", EDIT_FIND_IN_FILES),
+    ("edit.select_all", EDIT_SELECT_ALL),
+    ("edit.select_line", EDIT_SELECT_LINE),
+    ("edit.add_next_occurrence", EDIT_ADD_NEXT_OCCURRENCE),
+    ("edit.add_caret_above", EDIT_ADD_CARET_ABOVE),
+    ("edit.add_caret_below", EDIT_ADD_CARET_BELOW),
+    ("edit.move_lines_up", EDIT_MOVE_LINES_UP),
+    ("edit.move_lines_down", EDIT_MOVE_LINES_DOWN),
+    ("edit.go_to_bracket", EDIT_GO_TO_BRACKET),
+    ("edit.toggle_line_comment", EDIT_TOGGLE_LINE_COMMENT),
+    ("edit.toggle_block_comment", EDIT_TOGGLE_BLOCK_COMMENT),
+    ("view.focus_menubar", VIEW_FOCUS_MENUBAR),
+    ("view.go_to_file", VIEW_GO_TO_FILE),
+    ("view.command_palette", VIEW_COMMAND_PALETTE),
+    ("view.go_to_line", VIEW_GO_TO_LINE),
+    ("view.word_wrap", VIEW_WORD_WRAP),
+    ("view.fold", VIEW_FOLD),
+    ("view.unfold", VIEW_UNFOLD),
+    ("view.fold_all", VIEW_FOLD_ALL),
+    ("view.unfold_all", VIEW_UNFOLD_ALL),
+];
+
+/// Returns the default shortcut of the command with the given name.
+/// The command palette uses this to show the shortcuts of its commands, too.
+pub fn default_shortcut(command: &str) -> Option<InputKey> {
+    COMMANDS.iter().find(|&&(name, _)| name == command).map(|&(_, key)| key)
+}
+
+/// Loads the user's keybindings. Invalid entries are ignored.
+pub fn load() -> Keymap {
+    let text = config::read("keybindings.toml");
+    let mut keymap = Keymap::new();
+
+    for entry in config::parse(&text) {
+        let Value::String(command) = &entry.value else {
+            continue;
+        };
+        let Some(chord) = keymap::parse_chord(&entry.key) else {
+            continue;
+        };
+        let target = if command.is_empty() {
+            vk::NULL
+        } else if let Some(key) = default_shortcut(command) {
+            key
+        } else {
+            continue;
+        };
+        keymap.bind(chord, target);
+    }
+
+    keymap
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands() {
+        // Bindings translate into the default shortcut, so it must identify the command.
+        for (i, &(name, key)) in COMMANDS.iter().enumerate() {
+            assert!(default_shortcut(name) == Some(key));
+            assert!(COMMANDS[i + 1..].iter().all(|&(n, k)| n != name && k != key), "{name}");
+        }
+        assert!(default_shortcut("edit.nothing").is_none());
+    }
+}
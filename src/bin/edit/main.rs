@@ -3,34 +3,48 @@
 
 #![feature(allocator_api, linked_list_cursors, string_from_utf8_lossy_owned)]
 
+mod config;
 mod documents;
 mod draw_editor;
 mod draw_filepicker;
+mod draw_find_in_files;
 mod draw_menubar;
+mod draw_palette;
 mod draw_statusbar;
+mod editorconfig;
+mod file_index;
+mod find_in_files;
+mod glob;
+mod keybindings;
 mod localization;
+mod recovery;
+mod search_history;
+mod settings;
 mod state;
 
 use std::borrow::Cow;
 #[cfg(feature = "debug-latency")]
 use std::fmt::Write;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{env, process};
 
 use draw_editor::*;
 use draw_filepicker::*;
+use draw_find_in_files::*;
 use draw_menubar::*;
+use draw_palette::*;
 use draw_statusbar::*;
 use edit::arena::{self, Arena, ArenaString, scratch_arena};
-use edit::framebuffer::{self, IndexedColor};
+use edit::framebuffer::{self, INDEXED_COLORS_COUNT, IndexedColor};
 use edit::helpers::{CoordType, KIBI, MEBI, MetricFormatter, Rect, Size};
-use edit::input::{self, kbmod, vk};
 use edit::oklab::StraightRgba;
 use edit::tui::*;
 use edit::vt::{self, Token};
-use edit::{apperr, arena_format, base64, path, sys, unicode};
+use edit::{apperr, arena_format, base64, input, path, sys, unicode};
 use localization::*;
+use search_history::SearchHistory;
+use settings::{ClipboardSync, Settings, Theme};
 use state::*;
 
 #[cfg(target_pointer_width = "32")]
@@ -38,6 +52,13 @@ const SCRATCH_ARENA_CAPACITY: usize = 128 * MEBI;
 #[cfg(target_pointer_width = "64")]
 const SCRATCH_ARENA_CAPACITY: usize = 512 * MEBI;
 
+// How often to check open files for external modifications, if we can't watch them.
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+// How often to write the recovery files of modified documents.
+const RECOVERY_INTERVAL: Duration = Duration::from_secs(4);
+// How long to crawl the directory tree for "Go to File" per frame.
+const CRAWL_SLICE: Duration = Duration::from_millis(10);
+
 fn main() -> process::ExitCode {
     if cfg!(debug_assertions) {
         let hook = std::panic::take_hook();
@@ -66,6 +87,9 @@ fn run() -> apperr::Result<()> {
     localization::init();
 
     let mut state = State::new()?;
+    state.settings = Settings::load();
+    state.search_history = SearchHistory::load();
+    state.documents.apply_settings(&state.settings);
     if handle_args(&mut state)? {
         return Ok(());
     }
@@ -81,32 +105,23 @@ fn run() -> apperr::Result<()> {
     let mut input_parser = input::Parser::new();
     let mut tui = Tui::new()?;
 
-    let _restore = setup_terminal(&mut tui, &mut state, &mut vt_parser);
+    let (_restore, terminal_colors) = setup_terminal(&mut state, &mut vt_parser);
+    let mut theme = state.settings.theme;
+    apply_theme(&mut tui, &mut state, terminal_colors.as_ref());
 
-    state.menubar_color_bg = tui.indexed(IndexedColor::Background).oklab_blend(tui.indexed_alpha(
-        IndexedColor::BrightBlue,
-        1,
-        2,
-    ));
-    state.menubar_color_fg = tui.contrasted(state.menubar_color_bg);
-    let floater_bg = tui
-        .indexed_alpha(IndexedColor::Background, 2, 3)
-        .oklab_blend(tui.indexed_alpha(IndexedColor::Foreground, 1, 3));
-    let floater_fg = tui.contrasted(floater_bg);
     tui.setup_modifier_translations(ModifierTranslations {
         ctrl: loc(LocId::Ctrl),
         alt: loc(LocId::Alt),
         shift: loc(LocId::Shift),
     });
-    tui.set_floater_default_bg(floater_bg);
-    tui.set_floater_default_fg(floater_fg);
-    tui.set_modal_default_bg(floater_bg);
-    tui.set_modal_default_fg(floater_fg);
+    tui.setup_keymap(keybindings::load());
 
     sys::inject_window_size_into_stdin();
 
     #[cfg(feature = "debug-latency")]
     let mut last_latency_width = 0;
+    let mut last_disk_check = Instant::now();
+    let mut last_recovery_write = Instant::now();
 
     loop {
         #[cfg(feature = "debug-latency")]
@@ -117,11 +132,44 @@ fn run() -> apperr::Result<()> {
         // Process a batch of input.
         {
             let scratch = scratch_arena(None);
-            let read_timeout = vt_parser.read_timeout().min(tui.read_timeout());
+            let mut read_timeout = vt_parser.read_timeout().min(tui.read_timeout());
+            // Wake up to write recovery files, even if the user stopped typing.
+            if state.documents.recovery_pending() {
+                read_timeout = read_timeout
+                    .min(RECOVERY_INTERVAL.saturating_sub(last_recovery_write.elapsed()));
+            }
+            // Keep crawling the directory tree for "Go to File" and "Find in Files",
+            // and counting the search hits in the active document, between inputs.
+            let counting = state
+                .documents
+                .active()
+                .is_some_and(|doc| doc.buffer.borrow_mut().find_is_counting());
+            if state.file_index.is_crawling() || state.find_in_files.is_searching() || counting {
+                read_timeout = Duration::ZERO;
+            }
             let Some(input) = sys::read_stdin(&scratch, read_timeout) else {
                 break;
             };
 
+            if state.file_index.is_crawling() {
+                state.file_index.crawl(CRAWL_SLICE);
+            }
+            if state.find_in_files.is_searching()
+                && state.find_in_files.step(&state.documents, CRAWL_SLICE).is_err()
+            {
+                state.search_success = false;
+            }
+            if counting && let Some(doc) = state.documents.active() {
+                doc.buffer.borrow_mut().find_count_step(CRAWL_SLICE);
+            }
+
+            // Check for files modified by other programs. Without a file watcher
+            // (see `sys::watch_directory`) we only notice them while the user is active.
+            if sys::take_watch_triggered() || last_disk_check.elapsed() >= DISK_CHECK_INTERVAL {
+                state.documents.check_disk_changes();
+                last_disk_check = Instant::now();
+            }
+
             #[cfg(feature = "debug-latency")]
             {
                 time_beg = std::time::Instant::now();
@@ -147,6 +195,14 @@ fn run() -> apperr::Result<()> {
             } {}
         }
 
+        // The colors are baked into the frame, so it needs to be drawn again.
+        if state.settings.theme != theme {
+            theme = state.settings.theme;
+            apply_theme(&mut tui, &mut state, terminal_colors.as_ref());
+            let mut ctx = tui.create_context(None);
+            draw(&mut ctx, &mut state);
+        }
+
         // Continue rendering until the layout has settled.
         // This can take >1 frame, if the input focus is tossed between different controls.
         while tui.needs_settling() {
@@ -161,9 +217,16 @@ fn run() -> apperr::Result<()> {
         }
 
         if state.exit {
+            state.documents.remove_recovery_files();
             break;
         }
 
+        if last_recovery_write.elapsed() >= RECOVERY_INTERVAL && state.documents.recovery_pending()
+        {
+            state.documents.write_recovery_files();
+            last_recovery_write = Instant::now();
+        }
+
         // Render the UI and write it to the terminal.
         {
             let scratch = scratch_arena(None);
@@ -248,6 +311,10 @@ fn handle_args(state: &mut State) -> apperr::Result<bool> {
                 print_version();
                 return Ok(true);
             }
+            if arg == "-b" || arg == "--backup" {
+                state.documents.backup_files = true;
+                continue;
+            }
         }
 
         let p = cwd.join(Path::new(&arg));
@@ -274,6 +341,9 @@ fn handle_args(state: &mut State) -> apperr::Result<bool> {
         state.documents.add_untitled()?;
     }
 
+    // Offer to recover the documents of instances that crashed.
+    state.recovery_files = recovery::scan();
+
     if dir.is_none()
         && let Some(parent) = paths.last().and_then(|p| p.parent())
     {
@@ -290,6 +360,7 @@ fn print_help() {
         "Options:\n",
         "    -h, --help       Print this help message\n",
         "    -v, --version    Print the version number\n",
+        "    -b, --backup     Keep the previous contents of saved files as FILE~\n",
         "\n",
         "Arguments:\n",
         "    FILE[:LINE[:COLUMN]]    The file to open, optionally with line and column (e.g., foo.txt:123:45)\n",
@@ -320,15 +391,41 @@ fn draw(ctx: &mut Context, state: &mut State) {
     if state.wants_save {
         draw_handle_save(ctx, state);
     }
+    if state.wants_save_overwrite {
+        draw_dialog_save_overwrite(ctx, state);
+    }
+    if state.wants_reload_discard {
+        draw_dialog_reload_discard(ctx, state);
+    } else if state.documents.active().is_some_and(|doc| doc.changed_on_disk) {
+        draw_dialog_disk_changed(ctx, state);
+    }
     if state.wants_encoding_change != StateEncodingChange::None {
         draw_dialog_encoding_change(ctx, state);
     }
     if state.wants_go_to_file {
         draw_go_to_file(ctx, state);
     }
+    if state.wants_find_in_files {
+        draw_find_in_files(ctx, state);
+    }
+    if state.wants_find_in_files_replace {
+        draw_dialog_find_in_files_replace(ctx, state);
+    }
+    if state.wants_command_palette {
+        draw_command_palette(ctx, state);
+    }
     if state.wants_about {
         draw_dialog_about(ctx, state);
     }
+    if state.wants_settings {
+        draw_dialog_settings(ctx, state);
+    }
+    if state.wants_history {
+        draw_dialog_history(ctx, state);
+    }
+    if !state.recovery_files.is_empty() {
+        draw_dialog_recovery(ctx, state);
+    }
     if ctx.clipboard_ref().wants_host_sync() {
         draw_handle_clipboard_change(ctx, state);
     }
@@ -339,31 +436,35 @@ fn draw(ctx: &mut Context, state: &mut State) {
     if let Some(key) = ctx.keyboard_input() {
         // Shortcuts that are not handled as part of the textarea, etc.
 
-        if key == kbmod::CTRL | vk::N {
+        if key == keybindings::FILE_NEW {
             draw_add_untitled_document(ctx, state);
-        } else if key == kbmod::CTRL | vk::O {
+        } else if key == keybindings::FILE_OPEN {
             state.wants_file_picker = StateFilePicker::Open;
-        } else if key == kbmod::CTRL | vk::S {
+        } else if key == keybindings::FILE_SAVE {
             state.wants_save = true;
-        } else if key == kbmod::CTRL_SHIFT | vk::S {
+        } else if key == keybindings::FILE_SAVE_AS {
             state.wants_file_picker = StateFilePicker::SaveAs;
-        } else if key == kbmod::CTRL | vk::W {
+        } else if key == keybindings::FILE_CLOSE {
             state.wants_close = true;
-        } else if key == kbmod::CTRL | vk::P {
+        } else if key == keybindings::VIEW_GO_TO_FILE {
             state.wants_go_to_file = true;
-        } else if key == kbmod::CTRL | vk::Q {
+        } else if key == keybindings::VIEW_COMMAND_PALETTE {
+            state.wants_command_palette = true;
+        } else if key == keybindings::FILE_EXIT {
             state.wants_exit = true;
-        } else if key == kbmod::CTRL | vk::G {
+        } else if key == keybindings::VIEW_GO_TO_LINE {
             state.wants_goto = true;
-        } else if key == kbmod::CTRL | vk::F && state.wants_search.kind != StateSearchKind::Disabled
-        {
+        } else if key == keybindings::EDIT_FIND {
             state.wants_search.kind = StateSearchKind::Search;
             state.wants_search.focus = true;
-        } else if key == kbmod::CTRL | vk::R && state.wants_search.kind != StateSearchKind::Disabled
-        {
+        } else if key == keybindings::EDIT_REPLACE {
             state.wants_search.kind = StateSearchKind::Replace;
             state.wants_search.focus = true;
-        } else if key == vk::F3 {
+        } else if key == keybindings::EDIT_FIND_IN_FILES {
+            state.wants_find_in_files = true;
+            state.find_in_files_focus = true;
+        } else if key == keybindings::EDIT_FIND_NEXT {
+            search_history_add(ctx, state, false);
             search_execute(ctx, state, SearchAction::Search);
         } else {
             return;
@@ -420,7 +521,14 @@ const LARGE_CLIPBOARD_THRESHOLD: usize = 128 * KIBI;
 fn draw_handle_clipboard_change(ctx: &mut Context, state: &mut State) {
     let data_len = ctx.clipboard_ref().read().len();
 
-    if state.osc_clipboard_always_send || data_len < LARGE_CLIPBOARD_THRESHOLD {
+    if state.settings.clipboard == ClipboardSync::Never {
+        ctx.clipboard_mut().mark_as_synchronized();
+        return;
+    }
+    if state.osc_clipboard_always_send
+        || state.settings.clipboard == ClipboardSync::Always
+        || data_len < LARGE_CLIPBOARD_THRESHOLD
+    {
         ctx.clipboard_mut().mark_as_synchronized();
         state.osc_clipboard_sync = true;
         return;
@@ -538,7 +646,11 @@ impl Drop for RestoreModes {
     }
 }
 
-fn setup_terminal(tui: &mut Tui, state: &mut State, vt_parser: &mut vt::Parser) -> RestoreModes {
+/// Returns the terminal's color palette, if it reported one.
+fn setup_terminal(
+    state: &mut State,
+    vt_parser: &mut vt::Parser,
+) -> (RestoreModes, Option<[StraightRgba; INDEXED_COLORS_COUNT]>) {
     sys::write_stdout(concat!(
         // 1049: Alternative Screen Buffer
         //   I put the ASB switch in the beginning, just in case the terminal performs
@@ -651,11 +763,35 @@ fn setup_terminal(tui: &mut Tui, state: &mut State, vt_parser: &mut vt::Parser)
         state.documents.reflow_all();
     }
 
-    if color_responses == indexed_colors.len() {
-        tui.setup_indexed_colors(indexed_colors);
+    let terminal_colors = (color_responses == indexed_colors.len()).then_some(indexed_colors);
+    (RestoreModes, terminal_colors)
+}
+
+fn apply_theme(
+    tui: &mut Tui,
+    state: &mut State,
+    terminal_colors: Option<&[StraightRgba; INDEXED_COLORS_COUNT]>,
+) {
+    match (state.settings.theme, terminal_colors) {
+        (Theme::Terminal, Some(colors)) => tui.setup_indexed_colors(*colors),
+        (Theme::Terminal | Theme::Dark, _) => tui.setup_theme(framebuffer::DEFAULT_THEME),
+        (Theme::Light, _) => tui.setup_theme(framebuffer::LIGHT_THEME),
     }
 
-    RestoreModes
+    state.menubar_color_bg = tui.indexed(IndexedColor::Background).oklab_blend(tui.indexed_alpha(
+        IndexedColor::BrightBlue,
+        1,
+        2,
+    ));
+    state.menubar_color_fg = tui.contrasted(state.menubar_color_bg);
+    let floater_bg = tui
+        .indexed_alpha(IndexedColor::Background, 2, 3)
+        .oklab_blend(tui.indexed_alpha(IndexedColor::Foreground, 1, 3));
+    let floater_fg = tui.contrasted(floater_bg);
+    tui.set_floater_default_bg(floater_bg);
+    tui.set_floater_default_fg(floater_fg);
+    tui.set_modal_default_bg(floater_bg);
+    tui.set_modal_default_fg(floater_fg);
 }
 
 /// Strips all C0 control characters from the string and replaces them with "_".
@@ -0,0 +1,280 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Recovery files ("swap files") for documents with unsaved changes.
+//!
+//! While a document is dirty, a snapshot of it is periodically written into
+//! the `recovery` directory in the [`sys::state_dir`]. They're deleted once
+//! the document is saved or closed, or when we exit normally. Any that are
+//! left over belonged to an instance that crashed (or whose terminal died).
+//!
+//! A recovery file consists of a small header followed by the UTF-8 contents:
+//! * `b"EDITSWAP"` and a version byte
+//! * the original path, filename and encoding, each as a u32 length + bytes
+//!
+//! The files are named `{pid}-{counter}.swap`, so that we can tell
+//! whether the instance that wrote them is still running.
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+#[cfg(unix)]
+use std::os::unix::ffi::OsStringExt as _;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+
+use edit::buffer::TextBuffer;
+use edit::{apperr, sys};
+
+const MAGIC: &[u8; 8] = b"EDITSWAP";
+const VERSION: u8 = 1;
+
+/// A recovery file left over by a previous instance.
+pub struct RecoveryFile {
+    /// The path of the recovery file itself.
+    pub file: PathBuf,
+    /// The path of the document, unless it was Untitled.
+    pub path: Option<PathBuf>,
+    pub filename: String,
+    pub encoding: String,
+    /// When the snapshot was taken.
+    pub timestamp: SystemTime,
+    /// Where the contents start.
+    header_len: u64,
+}
+
+fn recovery_dir() -> Option<PathBuf> {
+    Some(sys::state_dir()?.join("recovery"))
+}
+
+/// Returns a new, unique path for a recovery file of this instance.
+pub fn new_file_path(counter: usize) -> Option<PathBuf> {
+    Some(recovery_dir()?.join(format!("{}-{}.swap", process::id(), counter)))
+}
+
+/// Writes a snapshot of `tb` into the recovery file at `file`.
+///
+/// The snapshot is written to a temporary file first, which then replaces the previous one,
+/// so that a crash or a full disk can't destroy it. The files may contain secrets,
+/// which is why only the current user can access them.
+///
+/// This runs on the UI thread, so it doesn't wait for the data to reach the disk.
+/// That's only needed to survive a crash of the OS, not of edit or its terminal.
+pub fn write(
+    file: &Path,
+    tb: &TextBuffer,
+    path: Option<&Path>,
+    filename: &str,
+) -> apperr::Result<()> {
+    if let Some(dir) = file.parent() {
+        sys::create_private_dir_all(dir)?;
+    }
+
+    let mut tmp = file.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    // Left over if the previous attempt failed halfway.
+    _ = fs::remove_file(&tmp);
+
+    let res = write_snapshot(&tmp, tb, path, filename).and_then(|_| Ok(fs::rename(&tmp, file)?));
+    if res.is_err() {
+        _ = fs::remove_file(&tmp);
+    }
+    res
+}
+
+fn write_snapshot(
+    file: &Path,
+    tb: &TextBuffer,
+    path: Option<&Path>,
+    filename: &str,
+) -> apperr::Result<()> {
+    let mut w = BufWriter::new(sys::create_private_file(file)?);
+    write_header(&mut w, path, filename, tb.encoding())?;
+
+    let mut offset = 0;
+    loop {
+        let chunk = tb.read_forward(offset);
+        if chunk.is_empty() {
+            break;
+        }
+        w.write_all(chunk)?;
+        offset += chunk.len();
+    }
+
+    w.flush()?;
+    Ok(())
+}
+
+/// Finds the recovery files of all instances that aren't running anymore.
+pub fn scan() -> Vec<RecoveryFile> {
+    let mut files = Vec::new();
+    let Some(entries) = recovery_dir().and_then(|dir| fs::read_dir(dir).ok()) else {
+        return files;
+    };
+
+    for entry in entries.flatten() {
+        let file = entry.path();
+        let Some(name) = file.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        // The temporary files of an unfinished write are incomplete.
+        let (name, incomplete) = match name.strip_suffix(".tmp") {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        let Some(pid) = name
+            .strip_suffix(".swap")
+            .and_then(|n| n.split_once('-'))
+            .and_then(|(pid, _)| pid.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        if sys::process_exists(pid) {
+            continue;
+        }
+        if incomplete {
+            _ = fs::remove_file(&file);
+            continue;
+        }
+
+        if let Ok(mut f) = File::open(&file)
+            && let Some((path, filename, encoding)) = read_header(&mut f)
+            && let Ok(header_len) = f.stream_position()
+        {
+            let timestamp = f.metadata().and_then(|m| m.modified()).unwrap_or(SystemTime::now());
+            files.push(RecoveryFile { file, path, filename, encoding, timestamp, header_len });
+        }
+    }
+
+    // Newest first.
+    files.sort_by_key(|f| std::cmp::Reverse(f.timestamp));
+    files
+}
+
+/// Opens the recovery file and skips past its header, so that the contents can be read.
+pub fn open(rf: &RecoveryFile) -> apperr::Result<File> {
+    let mut f = File::open(&rf.file)?;
+    f.seek(SeekFrom::Start(rf.header_len))?;
+    Ok(f)
+}
+
+/// Deletes the recovery file. This is best-effort.
+pub fn remove(file: &Path) {
+    _ = fs::remove_file(file);
+}
+
+fn write_header(
+    w: &mut impl Write,
+    path: Option<&Path>,
+    filename: &str,
+    encoding: &str,
+) -> io::Result<()> {
+    let path = path.map_or(&b""[..], |p| p.as_os_str().as_encoded_bytes());
+
+    w.write_all(MAGIC)?;
+    w.write_all(&[VERSION])?;
+    for field in [path, filename.as_bytes(), encoding.as_bytes()] {
+        w.write_all(&(field.len() as u32).to_le_bytes())?;
+        w.write_all(field)?;
+    }
+    Ok(())
+}
+
+fn read_header(f: &mut impl Read) -> Option<(Option<PathBuf>, String, String)> {
+    fn read_field(f: &mut impl Read) -> Option<Vec<u8>> {
+        let mut len = [0; 4];
+        f.read_exact(&mut len).ok()?;
+        let len = u32::from_le_bytes(len) as usize;
+        // Paths and names are short. Anything longer means the file is corrupt.
+        if len > 64 * 1024 {
+            return None;
+        }
+        let mut buf = vec![0; len];
+        f.read_exact(&mut buf).ok()?;
+        Some(buf)
+    }
+
+    let mut magic = [0; 9];
+    f.read_exact(&mut magic).ok()?;
+    if &magic[..8] != MAGIC || magic[8] != VERSION {
+        return None;
+    }
+
+    let path = read_field(f)?;
+    let filename = String::from_utf8(read_field(f)?).ok()?;
+    let encoding = String::from_utf8(read_field(f)?).ok()?;
+    let path = if path.is_empty() {
+        None
+    } else {
+        // On Windows, `as_encoded_bytes` is UTF-8 for all valid paths.
+        #[cfg(unix)]
+        let path = OsString::from_vec(path);
+        #[cfg(windows)]
+        let path = OsString::from(String::from_utf8(path).ok()?);
+        Some(PathBuf::from(path))
+    };
+
+    Some((path, filename, encoding))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recovery_header() {
+        let path = PathBuf::from("/some/dir/file.txt");
+        let mut data = Vec::new();
+        write_header(&mut data, Some(&path), "file.txt", "UTF-16LE").unwrap();
+        data.extend_from_slice(b"contents");
+
+        let mut reader = &data[..];
+        let (p, filename, encoding) = read_header(&mut reader).unwrap();
+        assert_eq!(p, Some(path));
+        assert_eq!(filename, "file.txt");
+        assert_eq!(encoding, "UTF-16LE");
+        assert_eq!(reader, b"contents");
+
+        let mut data = Vec::new();
+        write_header(&mut data, None, "Untitled-1.txt", "UTF-8").unwrap();
+        assert_eq!(read_header(&mut &data[..]).unwrap().0, None);
+
+        // Corrupt files are ignored.
+        assert!(read_header(&mut &data[..data.len() - 1]).is_none());
+        assert!(read_header(&mut &b"EDITSWAP\x01\xff\xff\xff\xff"[..]).is_none());
+    }
+
+    #[test]
+    fn test_recovery_write() {
+        let dir = std::env::temp_dir().join(format!("edit-test-recovery-{}", process::id()));
+        _ = fs::remove_dir_all(&dir);
+        let file = dir.join("recovery").join("1-1.swap");
+
+        let mut tb = TextBuffer::new(false).unwrap();
+        tb.write_raw(b"secret");
+        write(&file, &tb, None, "Untitled-1.txt").unwrap();
+        tb.write_raw(b"!");
+        write(&file, &tb, None, "Untitled-1.txt").unwrap();
+
+        let mut f = File::open(&file).unwrap();
+        read_header(&mut f).unwrap();
+        let mut contents = String::new();
+        f.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "secret!");
+
+        // The previous snapshot was replaced, not written over.
+        assert_eq!(fs::read_dir(file.parent().unwrap()).unwrap().count(), 1);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+            let mode = |p: &Path| fs::metadata(p).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(&file), 0o600);
+            assert_eq!(mode(file.parent().unwrap()), 0o700);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The most recently used search needles and replacements.
+//!
+//! They're kept in `search_history.toml` in the [`sys::state_dir`], most recent first:
+//! ```toml
+//! [needles]
+//! 0 = "fn \\w+"
+//! [replacements]
+//! 0 = "$1"
+//! ```
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+
+use edit::{apperr, sys};
+
+use crate::config::{self, Value};
+
+const FILENAME: &str = "search_history.toml";
+/// Older entries are dropped.
+const MAX_ENTRIES: usize = 50;
+
+#[derive(Default, PartialEq, Eq, Debug)]
+pub struct SearchHistory {
+    pub needles: Vec<String>,
+    pub replacements: Vec<String>,
+}
+
+impl SearchHistory {
+    /// Loads the history file. A missing or invalid file results in an empty history.
+    pub fn load() -> Self {
+        let text = path().and_then(|p| fs::read_to_string(p).ok()).unwrap_or_default();
+        Self::parse(&text)
+    }
+
+    fn parse(text: &str) -> Self {
+        let mut h = Self::default();
+
+        for entry in config::parse(text) {
+            let list = match entry.section {
+                "needles" => &mut h.needles,
+                "replacements" => &mut h.replacements,
+                _ => continue,
+            };
+            if let Value::String(s) = entry.value
+                && !s.is_empty()
+                && list.len() < MAX_ENTRIES
+            {
+                list.push(s);
+            }
+        }
+
+        h
+    }
+
+    /// Writes the history file.
+    pub fn save(&self) -> apperr::Result<()> {
+        let Some(path) = path() else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.serialize())?;
+        Ok(())
+    }
+
+    fn serialize(&self) -> String {
+        let mut str = String::new();
+        for (section, list) in [("needles", &self.needles), ("replacements", &self.replacements)] {
+            _ = writeln!(str, "[{section}]");
+            for (i, entry) in list.iter().enumerate() {
+                _ = writeln!(str, "{i} = {}", config::quote(entry));
+            }
+        }
+        str
+    }
+
+    /// Moves `needle` to the front of the needles. Returns true if the history changed.
+    pub fn add_needle(&mut self, needle: &str) -> bool {
+        add(&mut self.needles, needle)
+    }
+
+    /// Moves `replacement` to the front of the replacements. Returns true if the history changed.
+    pub fn add_replacement(&mut self, replacement: &str) -> bool {
+        add(&mut self.replacements, replacement)
+    }
+}
+
+fn path() -> Option<PathBuf> {
+    Some(sys::state_dir()?.join(FILENAME))
+}
+
+fn add(list: &mut Vec<String>, entry: &str) -> bool {
+    if entry.is_empty() || list.first().is_some_and(|e| e == entry) {
+        return false;
+    }
+    list.retain(|e| e != entry);
+    list.insert(0, entry.to_string());
+    list.truncate(MAX_ENTRIES);
+    true
+}
+
+/// Replaces `text` with the entry of `list` that's older (`older == true`) or newer than it.
+/// Going past the newest entry clears `text`. Returns true if `text` changed.
+pub fn recall(list: &[String], text: &mut String, older: bool) -> bool {
+    let pos = list.iter().position(|e| e == text);
+    let next = match (pos, older) {
+        (None, true) => 0,
+        (Some(i), true) => i + 1,
+        (Some(i), false) if i > 0 => i - 1,
+        (Some(_), false) => {
+            text.clear();
+            return true;
+        }
+        (None, false) => return false,
+    };
+
+    match list.get(next) {
+        Some(entry) => {
+            text.clone_from(entry);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history() {
+        let mut h = SearchHistory::default();
+        assert!(h.add_needle("foo"));
+        assert!(h.add_needle("a \"b\"\n\\c"));
+        assert!(h.add_needle("foo"));
+        assert!(!h.add_needle("foo"));
+        assert!(!h.add_needle(""));
+        h.add_replacement("$1");
+        assert_eq!(h.needles, ["foo", "a \"b\"\n\\c"]);
+        assert_eq!(SearchHistory::parse(&h.serialize()), h);
+
+        let mut text = String::from("typed");
+        assert!(recall(&h.needles, &mut text, true));
+        assert_eq!(text, "foo");
+        assert!(recall(&h.needles, &mut text, true));
+        assert!(!recall(&h.needles, &mut text, true));
+        assert_eq!(text, "a \"b\"\n\\c");
+        assert!(recall(&h.needles, &mut text, false));
+        assert!(recall(&h.needles, &mut text, false));
+        assert_eq!(text, "");
+    }
+}
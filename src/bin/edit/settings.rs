@@ -0,0 +1,228 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The user's editor defaults, stored in `settings.toml` in the [`edit::sys::config_dir`].
+
+use std::fmt::Write as _;
+use std::fs;
+
+use edit::apperr;
+use edit::buffer::{FoldOptions, TextBuffer};
+use edit::helpers::CoordType;
+
+use crate::config::{self, Value};
+
+const FILENAME: &str = "settings.toml";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    /// Use the colors reported by the terminal.
+    Terminal,
+    Dark,
+    Light,
+}
+
+/// Whether copied text is sent to the terminal's clipboard via OSC 52.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardSync {
+    /// Always, except for large amounts of text, where we ask first.
+    Ask,
+    Always,
+    Never,
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Settings {
+    pub tab_size: CoordType,
+    pub indent_with_tabs: bool,
+    pub word_wrap: bool,
+    /// 0 disables the ruler.
+    pub ruler: CoordType,
+    pub insert_final_newline: bool,
+    pub line_numbers: bool,
+    /// Fold blocks in brackets, in addition to indented ones.
+    pub fold_brackets: bool,
+    /// Fold regions between `#region` and `#endregion` comments.
+    pub fold_markers: bool,
+    /// Insert the closing bracket or quote along with the opening one.
+    pub auto_close: bool,
+    pub theme: Theme,
+    pub clipboard: ClipboardSync,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            tab_size: 4,
+            indent_with_tabs: false,
+            word_wrap: false,
+            ruler: 0,
+            insert_final_newline: !cfg!(windows), // As mandated by POSIX.
+            line_numbers: true,
+            fold_brackets: true,
+            fold_markers: true,
+            auto_close: true,
+            theme: Theme::Terminal,
+            clipboard: ClipboardSync::Ask,
+        }
+    }
+}
+
+impl Settings {
+    /// Applies all settings to a new buffer.
+    pub fn apply(&self, tb: &mut TextBuffer) {
+        tb.set_tab_size(self.tab_size);
+        tb.set_indent_with_tabs(self.indent_with_tabs);
+        tb.set_word_wrap(self.word_wrap);
+        tb.set_ruler(self.ruler);
+        tb.set_insert_final_newline(self.insert_final_newline);
+        tb.set_margin_enabled(self.line_numbers);
+        tb.set_fold_options(self.fold_options());
+        tb.set_auto_close(self.auto_close);
+    }
+
+    /// Applies the settings that differ from `prev` to an existing buffer.
+    /// The others may have been changed for just this buffer, or detected from its contents.
+    pub fn apply_changes(&self, prev: &Self, tb: &mut TextBuffer) {
+        if self.tab_size != prev.tab_size {
+            tb.set_tab_size(self.tab_size);
+        }
+        if self.indent_with_tabs != prev.indent_with_tabs {
+            tb.set_indent_with_tabs(self.indent_with_tabs);
+        }
+        if self.word_wrap != prev.word_wrap {
+            tb.set_word_wrap(self.word_wrap);
+        }
+        if self.ruler != prev.ruler {
+            tb.set_ruler(self.ruler);
+        }
+        if self.insert_final_newline != prev.insert_final_newline {
+            tb.set_insert_final_newline(self.insert_final_newline);
+        }
+        if self.line_numbers != prev.line_numbers {
+            tb.set_margin_enabled(self.line_numbers);
+        }
+        if self.fold_options() != prev.fold_options() {
+            tb.set_fold_options(self.fold_options());
+        }
+        if self.auto_close != prev.auto_close {
+            tb.set_auto_close(self.auto_close);
+        }
+    }
+
+    fn fold_options(&self) -> FoldOptions {
+        FoldOptions { brackets: self.fold_brackets, markers: self.fold_markers }
+    }
+
+    /// Loads the settings file. Missing or invalid entries keep their default.
+    pub fn load() -> Self {
+        Self::parse(&config::read(FILENAME))
+    }
+
+    fn parse(text: &str) -> Self {
+        let mut s = Self::default();
+
+        for entry in config::parse(text) {
+            if !entry.section.is_empty() {
+                continue;
+            }
+            match (entry.key.as_str(), entry.value) {
+                ("tab_size", Value::Integer(v @ 1..=8)) => s.tab_size = v as CoordType,
+                ("indent_with_tabs", Value::Boolean(v)) => s.indent_with_tabs = v,
+                ("word_wrap", Value::Boolean(v)) => s.word_wrap = v,
+                ("ruler", Value::Integer(v @ 0..=1000)) => s.ruler = v as CoordType,
+                ("insert_final_newline", Value::Boolean(v)) => s.insert_final_newline = v,
+                ("line_numbers", Value::Boolean(v)) => s.line_numbers = v,
+                ("fold_brackets", Value::Boolean(v)) => s.fold_brackets = v,
+                ("fold_markers", Value::Boolean(v)) => s.fold_markers = v,
+                ("auto_close", Value::Boolean(v)) => s.auto_close = v,
+                ("theme", Value::String(v)) => {
+                    s.theme = match v.as_str() {
+                        "terminal" => Theme::Terminal,
+                        "dark" => Theme::Dark,
+                        "light" => Theme::Light,
+                        _ => continue,
+                    }
+                }
+                ("clipboard", Value::String(v)) => {
+                    s.clipboard = match v.as_str() {
+                        "ask" => ClipboardSync::Ask,
+                        "always" => ClipboardSync::Always,
+                        "never" => ClipboardSync::Never,
+                        _ => continue,
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        s
+    }
+
+    /// Writes the settings file.
+    pub fn save(&self) -> apperr::Result<()> {
+        let Some(path) = config::path(FILENAME) else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.serialize())?;
+        Ok(())
+    }
+
+    fn serialize(&self) -> String {
+        let theme = match self.theme {
+            Theme::Terminal => "terminal",
+            Theme::Dark => "dark",
+            Theme::Light => "light",
+        };
+        let clipboard = match self.clipboard {
+            ClipboardSync::Ask => "ask",
+            ClipboardSync::Always => "always",
+            ClipboardSync::Never => "never",
+        };
+
+        let mut str = String::new();
+        _ = writeln!(str, "tab_size = {}", self.tab_size);
+        _ = writeln!(str, "indent_with_tabs = {}", self.indent_with_tabs);
+        _ = writeln!(str, "word_wrap = {}", self.word_wrap);
+        _ = writeln!(str, "ruler = {}", self.ruler);
+        _ = writeln!(str, "insert_final_newline = {}", self.insert_final_newline);
+        _ = writeln!(str, "line_numbers = {}", self.line_numbers);
+        _ = writeln!(str, "fold_brackets = {}", self.fold_brackets);
+        _ = writeln!(str, "fold_markers = {}", self.fold_markers);
+        _ = writeln!(str, "auto_close = {}", self.auto_close);
+        _ = writeln!(str, "theme = \"{theme}\"");
+        _ = writeln!(str, "clipboard = \"{clipboard}\"");
+        str
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_roundtrip() {
+        let s = Settings {
+            tab_size: 2,
+            indent_with_tabs: true,
+            word_wrap: true,
+            ruler: 80,
+            insert_final_newline: false,
+            line_numbers: false,
+            fold_brackets: false,
+            fold_markers: false,
+            auto_close: false,
+            theme: Theme::Light,
+            clipboard: ClipboardSync::Never,
+        };
+        assert!(Settings::parse(&s.serialize()) == s);
+
+        // Invalid values keep their default.
+        let s =
+            Settings::parse("tab_size = 0\nruler = \"80\"\ntheme = \"pink\"\nword_wrap = true\n");
+        assert!(s.tab_size == 4 && s.ruler == 0 && s.theme == Theme::Terminal && s.word_wrap);
+    }
+}
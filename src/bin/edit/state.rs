@@ -13,7 +13,12 @@ use edit::tui::*;
 use edit::{apperr, buffer, icu, sys};
 
 use crate::documents::DocumentManager;
+use crate::file_index::FileIndex;
+use crate::find_in_files::FindInFiles;
 use crate::localization::*;
+use crate::recovery::RecoveryFile;
+use crate::search_history::SearchHistory;
+use crate::settings::Settings;
 
 #[repr(transparent)]
 pub struct FormatApperr(apperr::Error);
@@ -28,6 +33,7 @@ impl std::fmt::Display for FormatApperr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.0 {
             apperr::APP_ICU_MISSING => f.write_str(loc(LocId::ErrorIcuMissing)),
+            apperr::APP_REGEX_INVALID => f.write_str(loc(LocId::ErrorRegexInvalid)),
             apperr::Error::App(code) => write!(f, "Unknown app error code: {code}"),
             apperr::Error::Icu(code) => icu::apperr_format(f, code),
             apperr::Error::Sys(code) => sys::apperr_format(f, code),
@@ -100,11 +106,18 @@ pub struct StateSearch {
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum StateSearchKind {
     Hidden,
-    Disabled,
     Search,
     Replace,
 }
 
+/// The search bar editline whose history dropdown is open.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum StateSearchHistory {
+    None,
+    Needles,
+    Replacements,
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum StateFilePicker {
     None,
@@ -151,6 +164,8 @@ pub struct State {
     pub search_replacement: String,
     pub search_options: buffer::SearchOptions,
     pub search_success: bool,
+    pub search_history: SearchHistory,
+    pub wants_search_history: StateSearchHistory,
 
     pub wants_encoding_picker: bool,
     pub wants_encoding_change: StateEncodingChange,
@@ -158,10 +173,28 @@ pub struct State {
     pub encoding_picker_results: Option<Vec<icu::Encoding>>,
 
     pub wants_save: bool,
+    pub wants_save_overwrite: bool, // The file changed on disk and saving would overwrite it.
+    pub wants_reload_discard: bool, // The file changed on disk and reloading would discard our changes.
     pub wants_statusbar_focus: bool,
     pub wants_indentation_picker: bool,
     pub wants_go_to_file: bool,
+    pub go_to_file_needle: String,
+    pub go_to_file_selected: usize,
+    pub file_index: FileIndex,
+    pub wants_find_in_files: bool,
+    pub find_in_files_focus: bool,
+    pub find_in_files_selected: usize,
+    pub find_in_files: FindInFiles,
+    pub wants_find_in_files_replace: bool,
+    pub wants_command_palette: bool,
+    pub command_palette_needle: String,
+    pub command_palette_selected: usize,
     pub wants_about: bool,
+    pub wants_settings: bool,
+    pub settings: Settings,
+    pub wants_history: bool,
+    pub recovery_files: Vec<RecoveryFile>, // Shows the recovery dialog while non-empty.
+    pub history_origin: Option<usize>, // The state to return to if the history dialog is cancelled.
     pub wants_close: bool,
     pub wants_exit: bool,
     pub wants_goto: bool,
@@ -199,17 +232,37 @@ impl State {
             search_replacement: Default::default(),
             search_options: Default::default(),
             search_success: true,
+            search_history: Default::default(),
+            wants_search_history: StateSearchHistory::None,
 
             wants_encoding_picker: false,
             encoding_picker_needle: Default::default(),
             encoding_picker_results: Default::default(),
 
             wants_save: false,
+            wants_save_overwrite: false,
+            wants_reload_discard: false,
             wants_statusbar_focus: false,
             wants_encoding_change: StateEncodingChange::None,
             wants_indentation_picker: false,
             wants_go_to_file: false,
+            go_to_file_needle: Default::default(),
+            go_to_file_selected: 0,
+            file_index: Default::default(),
+            wants_find_in_files: false,
+            find_in_files_focus: false,
+            find_in_files_selected: 0,
+            find_in_files: Default::default(),
+            wants_find_in_files_replace: false,
+            wants_command_palette: false,
+            command_palette_needle: Default::default(),
+            command_palette_selected: 0,
             wants_about: false,
+            wants_settings: false,
+            settings: Default::default(),
+            wants_history: false,
+            recovery_files: Vec::new(),
+            history_origin: None,
             wants_close: false,
             wants_exit: false,
             wants_goto: false,
@@ -0,0 +1,162 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Finds the partner of a bracket.
+//!
+//! Brackets are counted without regard for strings or comments, like most editors do.
+
+use crate::document::ReadableDocument;
+use crate::simd::memchr2;
+
+/// Returns the partner of the bracket `ch` and whether `ch` is the opening one.
+pub fn partner(ch: u8) -> Option<(u8, bool)> {
+    match ch {
+        b'(' => Some((b')', true)),
+        b'[' => Some((b']', true)),
+        b'{' => Some((b'}', true)),
+        b')' => Some((b'(', false)),
+        b']' => Some((b'[', false)),
+        b'}' => Some((b'{', false)),
+        _ => None,
+    }
+}
+
+/// Returns the character that closes a pair opened by `ch`, if it's a bracket or quote.
+pub fn closing_pair(ch: u8) -> Option<u8> {
+    match ch {
+        b'"' | b'\'' => Some(ch),
+        _ => partner(ch).filter(|&(_, opening)| opening).map(|(closing, _)| closing),
+    }
+}
+
+/// Returns the offset of the bracket that pairs with the one at `offset`.
+/// Returns `None` if there's no bracket at `offset`, or if it has no partner.
+///
+/// Gives up on partners more than `max_distance` bytes away. This bounds the time
+/// it takes for code that runs whenever the cursor moves.
+pub fn find_partner_within(
+    doc: &dyn ReadableDocument,
+    offset: usize,
+    max_distance: usize,
+) -> Option<usize> {
+    let ch = *doc.read_forward(offset).first()?;
+    let (other, opening) = partner(ch)?;
+
+    if opening {
+        let mut depth = 0usize;
+        let end = offset.saturating_add(max_distance).saturating_add(1);
+        let mut off = offset;
+        loop {
+            let chunk = doc.read_forward(off);
+            let chunk = &chunk[..chunk.len().min(end - off)];
+            if chunk.is_empty() {
+                return None;
+            }
+
+            let mut i = memchr2(ch, other, chunk, 0);
+            while i < chunk.len() {
+                if chunk[i] == ch {
+                    depth += 1;
+                } else {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(off + i);
+                    }
+                }
+                i = memchr2(ch, other, chunk, i + 1);
+            }
+
+            off += chunk.len();
+        }
+    } else {
+        find_opening_before(doc, offset, ch, max_distance)
+    }
+}
+
+/// Returns the offset of the opening partner for the bracket `closing`, if it were at `offset`.
+/// Gives up on partners more than `max_distance` bytes away.
+pub fn find_opening_before(
+    doc: &dyn ReadableDocument,
+    offset: usize,
+    closing: u8,
+    max_distance: usize,
+) -> Option<usize> {
+    let (opening, false) = partner(closing)? else {
+        return None;
+    };
+    let beg = offset.saturating_sub(max_distance);
+    let mut off = offset;
+    let mut depth = 1usize;
+
+    loop {
+        let chunk = doc.read_backward(off);
+        let chunk = &chunk[chunk.len().saturating_sub(off - beg)..];
+        if chunk.is_empty() {
+            return None;
+        }
+
+        for (i, &c) in chunk.iter().enumerate().rev() {
+            if c == closing {
+                depth += 1;
+            } else if c == opening {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(off - chunk.len() + i);
+                }
+            }
+        }
+
+        off -= chunk.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::GapBuffer;
+
+    #[test]
+    fn test_find_partner() {
+        let text = b"a(b[c]{d}) ) (";
+        let doc = &text[..];
+        assert_eq!(find_partner_within(&doc, 1, usize::MAX), Some(9));
+        assert_eq!(find_partner_within(&doc, 9, usize::MAX), Some(1));
+        assert_eq!(find_partner_within(&doc, 3, usize::MAX), Some(5));
+        assert_eq!(find_partner_within(&doc, 8, usize::MAX), Some(6));
+        assert_eq!(find_partner_within(&doc, 0, usize::MAX), None);
+        assert_eq!(find_partner_within(&doc, 11, usize::MAX), None);
+        assert_eq!(find_partner_within(&doc, 13, usize::MAX), None);
+    }
+
+    #[test]
+    fn test_find_partner_within() {
+        let text = b"(a[b]c)";
+        let doc = &text[..];
+        assert_eq!(find_partner_within(&doc, 0, 6), Some(6));
+        assert_eq!(find_partner_within(&doc, 0, 5), None);
+        assert_eq!(find_partner_within(&doc, 6, 6), Some(0));
+        assert_eq!(find_partner_within(&doc, 6, 5), None);
+        assert_eq!(find_partner_within(&doc, 4, 2), Some(2));
+    }
+
+    #[test]
+    fn test_find_opening_before() {
+        let text = b"{ ( ) ";
+        let doc = &text[..];
+        assert_eq!(find_opening_before(&doc, 6, b'}', usize::MAX), Some(0));
+        assert_eq!(find_opening_before(&doc, 6, b'}', 5), None);
+        assert_eq!(find_opening_before(&doc, 6, b')', usize::MAX), None);
+        assert_eq!(find_opening_before(&doc, 4, b')', usize::MAX), Some(2));
+    }
+
+    #[test]
+    fn test_find_partner_across_gap() {
+        let mut buf = GapBuffer::new(true).unwrap();
+        buf.replace(0..0, b"{ ( ) }");
+        // Move the gap into the middle of the pair.
+        buf.replace(3..3, b"x");
+        assert_eq!(find_partner_within(&buf, 0, usize::MAX), Some(7));
+        assert_eq!(find_partner_within(&buf, 7, usize::MAX), Some(0));
+        assert_eq!(find_partner_within(&buf, 2, usize::MAX), Some(5));
+    }
+}
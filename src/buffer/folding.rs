@@ -0,0 +1,456 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Finds the regions of lines that can be folded away.
+//!
+//! A region starts at a "header" line, which remains visible when folded. It's either
+//! a marker region (`// #region` to `// #endregion`, or `{{{` to `}}}`), a block in brackets
+//! that opens on the header line, or the lines after the header that are indented deeper.
+
+use std::ops::Range;
+
+use crate::document::ReadableDocument;
+use crate::helpers::CoordType;
+use crate::simd::memchr2;
+
+/// Which kinds of regions can be folded, in addition to indented blocks.
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub struct FoldOptions {
+    /// Blocks enclosed in `()`, `[]` or `{}`.
+    pub brackets: bool,
+    /// Regions between `region` and `endregion` (or `{{{` and `}}}`) comments.
+    pub markers: bool,
+}
+
+/// Reads a document line by line.
+struct Lines<'a> {
+    doc: &'a dyn ReadableDocument,
+    off: usize,
+    line: Vec<u8>,
+}
+
+impl<'a> Lines<'a> {
+    /// `off` must be at the start of a line.
+    fn new(doc: &'a dyn ReadableDocument, off: usize) -> Self {
+        Self { doc, off, line: Vec::new() }
+    }
+
+    /// Returns the next line without its trailing newline.
+    fn next(&mut self) -> Option<&[u8]> {
+        self.line.clear();
+
+        let mut found = false;
+        loop {
+            let chunk = self.doc.read_forward(self.off);
+            if chunk.is_empty() {
+                break;
+            }
+            let end = memchr2(b'\n', b'\n', chunk, 0);
+            self.line.extend_from_slice(&chunk[..end]);
+            found = true;
+            if end < chunk.len() {
+                self.off += end + 1;
+                break;
+            }
+            self.off += chunk.len();
+        }
+
+        if !found {
+            return None;
+        }
+        if self.line.last() == Some(&b'\r') {
+            self.line.pop();
+        }
+        Some(&self.line)
+    }
+}
+
+/// Returns a cheap guess whether the line at `off` starts a region,
+/// for showing fold toggles. [`region_len`] returns the actual extent.
+pub fn is_region_start(
+    doc: &dyn ReadableDocument,
+    off: usize,
+    tab_size: CoordType,
+    options: FoldOptions,
+) -> bool {
+    let mut lines = Lines::new(doc, off);
+    let Some(header) = lines.next() else {
+        return false;
+    };
+
+    if options.markers && marker(header) > 0 {
+        return true;
+    }
+    if options.brackets && unclosed_brackets(header).0 > 0 {
+        return true;
+    }
+
+    let Some(indent) = indent(header, tab_size) else {
+        return false;
+    };
+    while let Some(line) = lines.next() {
+        if let Some(i) = self::indent(line, tab_size) {
+            return i > indent;
+        }
+    }
+    false
+}
+
+/// Returns how many lines after the line at `off` can be folded into it. 0 if none.
+pub fn region_len(
+    doc: &dyn ReadableDocument,
+    off: usize,
+    tab_size: CoordType,
+    options: FoldOptions,
+) -> CoordType {
+    let mut lines = Lines::new(doc, off);
+    let Some(header) = lines.next() else {
+        return 0;
+    };
+    let is_marker = options.markers && marker(header) > 0;
+    let (unclosed, end_depth) = unclosed_brackets(header);
+    let indent = indent(header, tab_size);
+
+    if is_marker {
+        // The end marker is folded away as well.
+        let mut depth = 1;
+        let mut len = 0;
+        while let Some(line) = lines.next() {
+            len += 1;
+            depth += marker(line);
+            if depth <= 0 {
+                return len;
+            }
+        }
+        return 0;
+    }
+
+    if options.brackets && unclosed > 0 {
+        let len = bracket_region_len(&mut Lines::new(doc, lines.off), end_depth - unclosed);
+        if len > 0 {
+            return len;
+        }
+    }
+
+    let Some(indent) = indent else {
+        return 0;
+    };
+    let mut len = 0;
+    let mut last = 0;
+    while let Some(line) = lines.next() {
+        len += 1;
+        match self::indent(line, tab_size) {
+            Some(i) if i > indent => last = len,
+            Some(_) => break,
+            // Blank lines only belong to the region if more indented lines follow.
+            None => {}
+        }
+    }
+    last
+}
+
+/// Returns the regions of all lines in the document, as ranges from the header to the last line.
+/// They're the same as [`region_len`] returns for each line, but found in a single pass.
+pub fn all_regions(
+    doc: &dyn ReadableDocument,
+    tab_size: CoordType,
+    options: FoldOptions,
+) -> Vec<Range<CoordType>> {
+    /// The length of each kind of region that starts at a line. Which one applies
+    /// is decided at the end, since we don't know yet whether a block gets closed.
+    #[derive(Default)]
+    struct Header {
+        /// `Some(0)` for an unclosed marker region, which has no fallback.
+        marker: Option<CoordType>,
+        brackets: CoordType,
+        indent: CoordType,
+    }
+
+    let mut headers: Vec<Header> = Vec::new();
+    // The headers of the regions that are still open. For brackets, that's
+    // with the depth at which they close, and for indented blocks with their indentation.
+    let mut markers: Vec<CoordType> = Vec::new();
+    let mut brackets: Vec<(CoordType, i32)> = Vec::new();
+    let mut indents: Vec<(CoordType, CoordType)> = Vec::new();
+    let mut depth = 0;
+    let mut last_nonblank = 0;
+    let mut lines = Lines::new(doc, 0);
+    let mut y: CoordType = 0;
+
+    while let Some(line) = lines.next() {
+        headers.push(Header::default());
+
+        if options.markers {
+            let marker = marker(line);
+            if marker > 0 {
+                headers[y as usize].marker = Some(0);
+                markers.push(y);
+            } else if marker < 0
+                && let Some(start) = markers.pop()
+            {
+                headers[start as usize].marker = Some(y - start);
+            }
+        }
+
+        if options.brackets {
+            for_each_bracket(line, |i, delta| {
+                depth += delta;
+                while let Some(&(start, level)) = brackets.last()
+                    && depth <= level
+                {
+                    brackets.pop();
+                    // A closing bracket at the start of the line stays visible.
+                    let leading = line[..i].iter().all(u8::is_ascii_whitespace);
+                    headers[start as usize].brackets = y - start - leading as CoordType;
+                }
+            });
+            if unclosed_brackets(line).0 > 0 {
+                // The block ends where the last bracket left open on this line gets closed.
+                brackets.push((y, depth - 1));
+            }
+        }
+
+        if let Some(indent) = indent(line, tab_size) {
+            while let Some(&(start, i)) = indents.last()
+                && i >= indent
+            {
+                indents.pop();
+                headers[start as usize].indent = last_nonblank - start;
+            }
+            indents.push((y, indent));
+            last_nonblank = y;
+        }
+
+        y += 1;
+    }
+
+    for (start, _) in indents {
+        headers[start as usize].indent = last_nonblank - start;
+    }
+
+    let mut regions = Vec::new();
+    for (y, header) in headers.into_iter().enumerate() {
+        let len = match header.marker {
+            Some(len) => len,
+            None if header.brackets > 0 => header.brackets,
+            None => header.indent,
+        };
+        if len > 0 {
+            let y = y as CoordType;
+            regions.push(y..y + len + 1);
+        }
+    }
+    regions
+}
+
+/// Given the `lines` after a header that leaves the bracket depth above `level`,
+/// returns the number of lines up to where the depth drops back to `level`.
+/// A closing bracket at the start of that line stays visible, like the header.
+fn bracket_region_len(lines: &mut Lines, level: i32) -> CoordType {
+    let mut depth = level + 1;
+    let mut len = 0;
+
+    while let Some(line) = lines.next() {
+        len += 1;
+        let mut leading = true;
+        let mut closed = false;
+
+        for_each_bracket(line, |i, delta| {
+            if closed {
+                return;
+            }
+            depth += delta;
+            if depth <= level {
+                closed = true;
+                leading = line[..i].iter().all(u8::is_ascii_whitespace);
+            }
+        });
+
+        if closed {
+            return if leading { len - 1 } else { len };
+        }
+    }
+
+    0
+}
+
+/// Returns the number of brackets that are still open at the end of the `line`
+/// and the bracket depth at its end, relative to its start.
+fn unclosed_brackets(line: &[u8]) -> (i32, i32) {
+    let mut depth = 0;
+    let mut min = 0;
+    for_each_bracket(line, |_, delta| {
+        depth += delta;
+        min = min.min(depth);
+    });
+    (depth - min, depth)
+}
+
+/// Calls `f` with the offset of each bracket in `line` and +1 if it's an opening one.
+/// Brackets in string and char literals are skipped.
+fn for_each_bracket(line: &[u8], mut f: impl FnMut(usize, i32)) {
+    let mut i = 0;
+    while i < line.len() {
+        match line[i] {
+            b'(' | b'[' | b'{' => f(i, 1),
+            b')' | b']' | b'}' => f(i, -1),
+            b'"' => {
+                i += 1;
+                while i < line.len() && line[i] != b'"' {
+                    i += if line[i] == b'\\' { 2 } else { 1 };
+                }
+            }
+            // Only skip char literals like '{' or '\'', because a lone ' may be an apostrophe.
+            b'\'' => {
+                if line.get(i + 2) == Some(&b'\'') {
+                    i += 2;
+                } else if line.get(i + 1) == Some(&b'\\') && line.get(i + 3) == Some(&b'\'') {
+                    i += 3;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+}
+
+/// Returns 1 if `line` starts a marker region, -1 if it ends one, and 0 otherwise.
+fn marker(line: &[u8]) -> i32 {
+    if line.windows(3).any(|w| w == b"{{{") {
+        return 1;
+    }
+    if line.windows(3).any(|w| w == b"}}}") {
+        return -1;
+    }
+
+    // Skip the comment prefix, e.g. `//`, `#`, `--`, `/*`, `<!--` or `;`.
+    let prefix = line
+        .iter()
+        .take_while(|&&ch| ch.is_ascii_whitespace() || b"/#*-!<;%'".contains(&ch))
+        .count();
+    let text = &line[prefix..];
+    // C and C++ use `#pragma region`.
+    let text = text.strip_prefix(b"pragma").map_or(text, <[u8]>::trim_ascii_start);
+
+    let word = |text: &[u8], word: &[u8]| {
+        text.starts_with(word) && text.get(word.len()).is_none_or(|ch| !ch.is_ascii_alphanumeric())
+    };
+    if word(text, b"region") {
+        1
+    } else if word(text, b"endregion") {
+        -1
+    } else {
+        0
+    }
+}
+
+/// Returns the width of the indentation of `line`, or `None` if it's blank.
+fn indent(line: &[u8], tab_size: CoordType) -> Option<CoordType> {
+    let mut width = 0;
+    for &ch in line {
+        match ch {
+            b' ' => width += 1,
+            b'\t' => width += tab_size - width % tab_size,
+            _ => return Some(width),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region_lens(text: &str, options: FoldOptions) -> Vec<CoordType> {
+        let res = region_lens_unchecked(text, options);
+        let mut off = 0;
+        for &len in &res {
+            assert_eq!(is_region_start(&text.as_bytes(), off, 4, options), len != 0);
+            off += text[off..].find('\n').map_or(0, |i| i + 1);
+        }
+        res
+    }
+
+    /// Like `region_lens`, without checking the guesses of `is_region_start`,
+    /// which may be wrong for regions that aren't closed.
+    fn region_lens_unchecked(text: &str, options: FoldOptions) -> Vec<CoordType> {
+        let doc = text.as_bytes();
+        let mut res = Vec::new();
+        let mut off = 0;
+        loop {
+            res.push(region_len(&doc, off, 4, options));
+            match text[off..].find('\n') {
+                Some(i) => off += i + 1,
+                None => break,
+            }
+        }
+
+        // Finding all regions at once gives the same result.
+        let all: Vec<_> = (0..res.len() as CoordType)
+            .filter(|&y| res[y as usize] > 0)
+            .map(|y| y..y + res[y as usize] + 1)
+            .collect();
+        assert_eq!(all_regions(&doc, 4, options), all, "{text:?}");
+        res
+    }
+
+    #[test]
+    fn test_indentation() {
+        let text = "a\n  b\n\n  c\n    d\n\ne\n";
+        assert_eq!(region_lens(text, FoldOptions::default()), [4, 0, 0, 1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_brackets() {
+        let options = FoldOptions { brackets: true, markers: false };
+        let text = "fn a() {\nb('}');\n} else {\nc\n}\nd(1,\n2)\n";
+        assert_eq!(region_lens(text, options), [1, 0, 1, 0, 0, 1, 0, 0]);
+        // Without brackets, nothing is indented.
+        assert_eq!(region_lens(text, FoldOptions::default()), [0; 8]);
+    }
+
+    #[test]
+    fn test_all_regions() {
+        // Combines lines of all kinds of regions, to compare `all_regions` with `region_len`.
+        let pool = [
+            "fn a() {",
+            "}",
+            "} else {",
+            "    x(1,",
+            "  2);",
+            "",
+            "    ",
+            "// #region",
+            "// #endregion",
+            "\ty",
+            "        z",
+            "a({[",
+            "]})",
+            "'{'",
+            "\"}\"",
+        ];
+        let mut state = 1u32;
+        for _ in 0..500 {
+            let mut text = String::new();
+            for _ in 0..12 {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                text.push_str(pool[(state >> 16) as usize % pool.len()]);
+                text.push('\n');
+            }
+            for brackets in [false, true] {
+                for markers in [false, true] {
+                    region_lens_unchecked(&text, FoldOptions { brackets, markers });
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_markers() {
+        let options = FoldOptions { brackets: false, markers: true };
+        let text = "// #region a\nx\n# region\ny\n#endregion\n//#endregion\n";
+        assert_eq!(region_lens(text, options), [5, 0, 2, 0, 0, 0, 0]);
+        assert_eq!(marker(b"regional"), 0);
+        assert_eq!(marker(b"#pragma region foo"), 1);
+    }
+}
@@ -0,0 +1,762 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The undo/redo history.
+//!
+//! Instead of a linear undo stack, the history is a tree of document states:
+//! Each [`HistoryNode`] stores the changes that turn its parent's state into its own.
+//! Making an edit after undoing something creates a new branch, instead of discarding
+//! what was undone. Every node is timestamped, which allows "time travel", as well as
+//! browsing the history in chronological order.
+//!
+//! The changes of each node are stored either in "applied" or "unapplied" form:
+//! Applying a [`HistoryEntry`] swaps its `added` and `deleted` contents (among others),
+//! so walking back and forth through the tree naturally keeps them in the right form.
+//! Nodes on the path from the root to the current state are applied, all others aren't.
+//!
+//! The history can be persisted across sessions, using a simple little-endian binary dump
+//! of the tree. It's prefixed with a hash of the document contents at the time it was written,
+//! because the history is only meaningful if it's applied to the exact same text.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::*;
+use crate::hash::hash;
+
+const MAGIC: &[u8; 8] = b"EDITUNDO";
+const VERSION: u32 = 2;
+
+/// When the number of nodes exceeds this, the oldest ones get discarded.
+const MAX_NODES: usize = 1000;
+
+/// A document state in the history tree.
+pub(super) struct HistoryNode {
+    parent: usize,
+    /// The child that redo moves to: Whichever one was created or visited last.
+    last_child: Option<usize>,
+    /// When this state was last modified.
+    timestamp: SystemTime,
+    /// The changes from the parent's state to this one.
+    /// Entries with the same [`HistoryEntry::generation_before`] form a single undo step.
+    entries: Vec<HistoryEntry>,
+}
+
+/// The undo/redo history tree. The root (index 0) is the initial document state.
+pub(super) struct History {
+    nodes: Vec<HistoryNode>,
+    current: usize,
+    /// The highest [`GapBuffer::generation`] seen so far. New branches start after it,
+    /// so that no two states share the same generation (and thus `is_dirty()` works).
+    generation_max: u32,
+}
+
+/// Describes a state in the history, for [`TextBuffer::history_states`].
+pub struct HistoryState {
+    /// Pass this to [`TextBuffer::history_goto`] to return to this state.
+    pub id: usize,
+    /// The state this one was derived from.
+    pub parent: usize,
+    pub timestamp: SystemTime,
+    /// The number of bytes added and deleted compared to the parent.
+    pub added: usize,
+    pub deleted: usize,
+    /// Whether this is the document's current state.
+    pub current: bool,
+}
+
+impl History {
+    pub(super) fn new() -> Self {
+        Self {
+            nodes: vec![HistoryNode {
+                parent: 0,
+                last_child: None,
+                timestamp: SystemTime::now(),
+                entries: Vec::new(),
+            }],
+            current: 0,
+            generation_max: 0,
+        }
+    }
+
+    /// Returns the most recent entry, for extending it with further edits.
+    pub(super) fn current_entry_mut(&mut self) -> &mut HistoryEntry {
+        self.nodes[self.current].entries.last_mut().unwrap()
+    }
+
+    /// Adds a new entry. If it belongs to the same edit group as the current node's entries,
+    /// it's added to that undo step. Otherwise, a new undo step (node) is created.
+    ///
+    /// Returns the generation the buffer should continue at.
+    pub(super) fn push_entry(&mut self, entry: HistoryEntry, buffer_generation: u32) -> u32 {
+        let node = &mut self.nodes[self.current];
+        if node.entries.first().is_some_and(|e| e.generation_before == entry.generation_before) {
+            node.timestamp = SystemTime::now();
+            node.entries.push(entry);
+            return buffer_generation;
+        }
+
+        self.prune();
+
+        let id = self.nodes.len();
+        self.nodes[self.current].last_child = Some(id);
+        self.nodes.push(HistoryNode {
+            parent: self.current,
+            last_child: None,
+            timestamp: SystemTime::now(),
+            entries: vec![entry],
+        });
+        self.current = id;
+
+        // Skip past all generations used by other branches.
+        self.generation_max
+    }
+
+    /// Must be called after each edit with the buffer's new generation.
+    pub(super) fn update_generation(&mut self, generation: u32) {
+        self.generation_max = self.generation_max.max(generation);
+    }
+
+    /// Marks the current node as modified just now.
+    pub(super) fn touch(&mut self) {
+        self.nodes[self.current].timestamp = SystemTime::now();
+    }
+
+    /// Returns the path from the root to `id`, including both.
+    fn ancestors(&self, mut id: usize) -> Vec<usize> {
+        let mut path = vec![id];
+        while id != 0 {
+            id = self.nodes[id].parent;
+            path.push(id);
+        }
+        path.reverse();
+        path
+    }
+
+    /// Returns the undo steps (in order) and redo steps (in order) to get from the current node to `target`.
+    fn path_to(&self, target: usize) -> (Vec<usize>, Vec<usize>) {
+        let from = self.ancestors(self.current);
+        let to = self.ancestors(target);
+        let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+        let undo = from[common..].iter().rev().copied().collect();
+        let redo = to[common..].to_vec();
+        (undo, redo)
+    }
+
+    /// Discards the oldest states once there are too many of them,
+    /// by turning one of the steps towards the current state into the new root.
+    /// Any branches that split off before that are lost.
+    fn prune(&mut self) {
+        let len = self.nodes.len();
+        if len < MAX_NODES {
+            return;
+        }
+
+        // Parents always precede their children, so we can compute subtree sizes back to front.
+        let mut sizes = vec![1; len];
+        for id in (1..len).rev() {
+            sizes[self.nodes[id].parent] += sizes[id];
+        }
+
+        // Pick the oldest state towards the current one whose subtree is small enough.
+        let target_len = MAX_NODES - MAX_NODES / 4;
+        let path = self.ancestors(self.current);
+        let root = path.iter().copied().find(|&id| sizes[id] <= target_len).unwrap_or(self.current);
+        if root == 0 {
+            return;
+        }
+
+        let mut keep = vec![false; len];
+        keep[root] = true;
+        for id in root + 1..len {
+            keep[id] = keep[self.nodes[id].parent];
+        }
+
+        // Compact the nodes and remap the indices.
+        let mut map = vec![usize::MAX; len];
+        let mut nodes = Vec::with_capacity(sizes[root]);
+        for (id, node) in mem::take(&mut self.nodes).into_iter().enumerate() {
+            if keep[id] {
+                map[id] = nodes.len();
+                nodes.push(node);
+            }
+        }
+        for node in &mut nodes[1..] {
+            node.parent = map[node.parent];
+        }
+        for node in &mut nodes {
+            node.last_child = node.last_child.map(|c| map[c]).filter(|&c| c != usize::MAX);
+        }
+
+        // The new root holds the changes from a state that no longer exists.
+        nodes[0].parent = 0;
+        nodes[0].entries.clear();
+        self.nodes = nodes;
+        self.current = map[self.current];
+    }
+}
+
+impl TextBuffer {
+    /// Undo the last edit operation.
+    pub fn undo(&mut self) {
+        let current = self.history.current;
+        if current != 0 {
+            self.history_walk(&[current], &[]);
+        }
+    }
+
+    /// Redo the last undo operation.
+    pub fn redo(&mut self) {
+        if let Some(child) = self.history.nodes[self.history.current].last_child {
+            self.history_walk(&[], &[child]);
+        }
+    }
+
+    /// Returns all states in the history in the order they were created.
+    pub fn history_states(&self) -> Vec<HistoryState> {
+        let applied = self.history.ancestors(self.history.current);
+
+        self.history
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(id, node)| {
+                let mut added: usize = node.entries.iter().map(|e| e.added.len()).sum();
+                let mut deleted: usize = node.entries.iter().map(|e| e.deleted.len()).sum();
+                if id != 0 && !applied.contains(&id) {
+                    // Unapplied changes are stored inverted.
+                    mem::swap(&mut added, &mut deleted);
+                }
+                HistoryState {
+                    id,
+                    parent: node.parent,
+                    timestamp: node.timestamp,
+                    added,
+                    deleted,
+                    current: id == self.history.current,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the ID of the current state. See [`TextBuffer::history_states`].
+    pub fn history_current(&self) -> usize {
+        self.history.current
+    }
+
+    /// Returns the document to the given state. See [`TextBuffer::history_states`].
+    pub fn history_goto(&mut self, id: usize) {
+        if id < self.history.nodes.len() && id != self.history.current {
+            let (undo, redo) = self.history.path_to(id);
+            self.history_walk(&undo, &redo);
+        }
+    }
+
+    /// Travels through time by the given number of seconds, relative to the current state:
+    /// The document is returned to how it looked at that point, across all branches.
+    pub fn history_time_travel(&mut self, seconds: i64) {
+        let now = self.history.nodes[self.history.current].timestamp;
+        let delta = Duration::from_secs(seconds.unsigned_abs());
+        let target = if seconds < 0 {
+            now.checked_sub(delta).unwrap_or(UNIX_EPOCH)
+        } else {
+            now.checked_add(delta).unwrap_or(now)
+        };
+
+        // The state that was most recently modified at that time. If none, the initial state.
+        let id = self
+            .history
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.timestamp <= target)
+            .max_by_key(|(_, node)| node.timestamp)
+            .map_or(0, |(id, _)| id);
+        self.history_goto(id);
+    }
+
+    /// Undoes the `undo` nodes and then redoes the `redo` nodes, in the given order.
+    fn history_walk(&mut self, undo: &[usize], redo: &[usize]) {
+        for &id in undo {
+            let parent = self.history.nodes[id].parent;
+            self.history_apply_node(id, true);
+            self.history.nodes[parent].last_child = Some(id);
+            self.history.current = parent;
+        }
+
+        for &id in redo {
+            let parent = self.history.nodes[id].parent;
+            self.history_apply_node(id, false);
+            self.history.nodes[parent].last_child = Some(id);
+            self.history.current = id;
+        }
+
+        self.last_history_type = HistoryType::Other;
+        self.folds_reveal(self.cursor.logical_pos.y);
+
+        // History restored from an undo file doesn't know the visual line count.
+        if self.stats.visual_lines < 0 {
+            self.stats.visual_lines = if self.word_wrap_column > 0 {
+                self.cursor_move_to_logical_internal(Default::default(), Point::MAX).visual_pos.y
+                    + 1
+            } else {
+                self.stats.logical_lines
+            };
+        }
+
+        self.recalc_after_content_changed();
+    }
+
+    /// Applies (or reverts, depending on their current form) the changes of the given node.
+    fn history_apply_node(&mut self, id: usize, undo: bool) {
+        let buffer_generation = self.buffer.generation();
+        let mut entries = mem::take(&mut self.history.nodes[id].entries);
+
+        // Undo in reverse order.
+        if undo {
+            for change in entries.iter_mut().rev() {
+                self.history_apply_entry(change, buffer_generation);
+            }
+        } else {
+            for change in entries.iter_mut() {
+                self.history_apply_entry(change, buffer_generation);
+            }
+        }
+
+        self.history.nodes[id].entries = entries;
+    }
+
+    fn history_apply_entry(&mut self, change: &mut HistoryEntry, buffer_generation: u32) {
+        // Move to the point where the modification took place.
+        let cursor = self.cursor_move_to_logical_internal(self.cursor, change.cursor);
+
+        let safe_cursor = if self.word_wrap_column > 0 {
+            // If word-wrap is enabled, we need to move the cursor to the beginning of the line.
+            // This is because the undo/redo operation may have changed the visual position of the cursor.
+            self.goto_line_start(cursor, cursor.logical_pos.y)
+        } else {
+            cursor
+        };
+
+        // Undo: Whatever was deleted is now added and vice versa.
+        mem::swap(&mut change.deleted, &mut change.added);
+
+        // The edit bypasses `edit_write` and `edit_delete`, which would otherwise keep these in sync.
+        self.auto_closed.clear();
+
+        // Delete the inserted portion.
+        let deleted_lines = change.deleted.iter().filter(|&&c| c == b'\n').count() as CoordType;
+        self.buffer.allocate_gap(cursor.offset, 0, change.deleted.len());
+        self.line_cache.delete(cursor.offset..cursor.offset + change.deleted.len(), deleted_lines);
+        self.find_scope_deleted(cursor.offset..cursor.offset + change.deleted.len());
+        self.folds_edited(cursor.logical_pos.y, deleted_lines, 0);
+        if let Some(h) = &mut self.highlighter {
+            h.invalidate(cursor.logical_pos.y);
+        }
+
+        // Reinsert the deleted portion.
+        {
+            let added = &change.added[..];
+            let mut beg = 0;
+            let mut offset = cursor.offset;
+
+            while beg < added.len() {
+                let (end, line) = simd::lines_fwd(added, beg, 0, 1);
+                let has_newline = line != 0;
+                let link = &added[beg..end];
+                let line = unicode::strip_newline(link);
+                let mut written;
+
+                {
+                    let gap = self.buffer.allocate_gap(offset, line.len() + 2, 0);
+                    written = slice_copy_safe(gap, line);
+
+                    if has_newline {
+                        if self.newlines_are_crlf && written < gap.len() {
+                            gap[written] = b'\r';
+                            written += 1;
+                        }
+                        if written < gap.len() {
+                            gap[written] = b'\n';
+                            written += 1;
+                        }
+                    }
+
+                    self.buffer.commit_gap(written);
+                }
+
+                beg = end;
+                offset += written;
+            }
+
+            self.line_cache.insert(
+                &self.buffer,
+                cursor.offset,
+                cursor.logical_pos.y,
+                offset - cursor.offset,
+            );
+            self.find_scope_written(cursor.offset, offset - cursor.offset);
+            let added_lines = added.iter().filter(|&&c| c == b'\n').count() as CoordType;
+            self.folds_edited(cursor.logical_pos.y, 0, added_lines);
+        }
+
+        // Restore the previous line statistics.
+        mem::swap(&mut self.stats, &mut change.stats_before);
+
+        // Restore the previous selection.
+        mem::swap(&mut self.selection, &mut change.selection_before);
+
+        // Restore the previous secondary cursors.
+        mem::swap(&mut self.carets, &mut change.carets_before);
+        self.block_selection = None;
+
+        // Pretend as if the buffer was never modified.
+        self.buffer.set_generation(change.generation_before);
+        change.generation_before = buffer_generation;
+
+        // Restore the previous cursor.
+        let cursor_before = self.cursor_move_to_logical_internal(safe_cursor, change.cursor_before);
+        change.cursor_before = self.cursor.logical_pos;
+        // Can't use `set_cursor_internal` here, because we haven't updated the line stats yet.
+        self.cursor = cursor_before;
+    }
+
+    /// Computes a hash of the entire document contents.
+    pub fn content_hash(&self) -> u64 {
+        // The gap buffer may split the contents anywhere, so we need to hash
+        // in fixed-size blocks to get the same result regardless of the gap.
+        const BLOCK: usize = 64 * KIBI;
+        let mut block = Vec::with_capacity(BLOCK);
+        let mut seed = 0;
+        let mut off = 0;
+
+        loop {
+            let chunk = self.read_forward(off);
+            if chunk.is_empty() {
+                break;
+            }
+
+            let len = chunk.len().min(BLOCK - block.len());
+            block.extend_from_slice(&chunk[..len]);
+            off += len;
+
+            if block.len() == BLOCK {
+                seed = hash(seed, &block);
+                block.clear();
+            }
+        }
+
+        hash(seed, &block)
+    }
+
+    /// Does the buffer have any undo or redo history?
+    pub fn has_history(&self) -> bool {
+        self.history.nodes.len() > 1
+    }
+
+    /// Serializes the undo/redo history, so that it can be restored
+    /// with [`TextBuffer::deserialize_history`] in a later session.
+    pub fn serialize_history(&self, out: &mut Vec<u8>) {
+        let mut w = Writer(out);
+        w.bytes(MAGIC);
+        w.u32(VERSION);
+        w.u64(self.content_hash());
+        w.u32(self.buffer.generation());
+        w.u32(self.history.generation_max);
+        w.point(self.cursor.logical_pos);
+        w.u64(self.history.current as u64);
+        w.u64(self.history.nodes.len() as u64);
+
+        for node in &self.history.nodes {
+            w.u64(node.parent as u64);
+            w.u64(node.last_child.map_or(u64::MAX, |c| c as u64));
+            w.u64(node.timestamp.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64));
+            w.u32(node.entries.len() as u32);
+            for entry in &node.entries {
+                w.entry(entry);
+            }
+        }
+    }
+
+    /// Restores the undo/redo history written by [`TextBuffer::serialize_history`].
+    /// It's only restored if the document contents are unchanged since then.
+    /// Returns false if that's not the case or the data is invalid.
+    pub fn deserialize_history(&mut self, data: &[u8]) -> bool {
+        let mut r = Reader { data, off: 0 };
+        if r.bytes_exact(MAGIC.len()) != Some(MAGIC) || r.u32() != Some(VERSION) {
+            return false;
+        }
+        if r.u64() != Some(self.content_hash()) {
+            return false;
+        }
+
+        let Some((generation, cursor, history)) = (|| {
+            let generation = r.u32()?;
+            let generation_max = r.u32()?;
+            let cursor = r.point()?;
+            let current = r.index()?;
+            let count = r.index()?;
+            let mut nodes = Vec::new();
+
+            for id in 0..count {
+                let parent = r.index()?;
+                let last_child = match r.u64()? {
+                    u64::MAX => None,
+                    c => Some(usize::try_from(c).ok()?),
+                };
+                let timestamp = UNIX_EPOCH + Duration::from_millis(r.u64()?);
+                let entry_count = r.u32()?;
+                let mut entries = Vec::new();
+                for _ in 0..entry_count {
+                    entries.push(r.entry()?);
+                }
+
+                // Parents always precede their children.
+                if (id != 0 && parent >= id) || last_child.is_some_and(|c| c <= id || c >= count) {
+                    return None;
+                }
+                nodes.push(HistoryNode { parent, last_child, timestamp, entries });
+            }
+
+            if current >= nodes.len() {
+                return None;
+            }
+
+            Some((generation, cursor, History { nodes, current, generation_max }))
+        })() else {
+            return false;
+        };
+
+        self.history = history;
+        self.last_history_type = HistoryType::Other;
+        self.buffer.set_generation(generation);
+        self.mark_as_clean();
+        self.cursor_move_to_logical(cursor);
+        true
+    }
+}
+
+struct Writer<'a>(&'a mut Vec<u8>);
+
+impl Writer<'_> {
+    fn bytes(&mut self, data: &[u8]) {
+        self.0.extend_from_slice(data);
+    }
+
+    fn u32(&mut self, val: u32) {
+        self.bytes(&val.to_le_bytes());
+    }
+
+    fn u64(&mut self, val: u64) {
+        self.bytes(&val.to_le_bytes());
+    }
+
+    fn coord(&mut self, val: CoordType) {
+        self.u64(val as i64 as u64);
+    }
+
+    fn point(&mut self, val: Point) {
+        self.coord(val.x);
+        self.coord(val.y);
+    }
+
+    fn blob(&mut self, data: &[u8]) {
+        self.u64(data.len() as u64);
+        self.bytes(data);
+    }
+
+    fn selection(&mut self, val: Option<TextBufferSelection>) {
+        match val {
+            Some(s) => {
+                self.u32(1);
+                self.point(s.beg);
+                self.point(s.end);
+            }
+            None => self.u32(0),
+        }
+    }
+
+    fn entry(&mut self, entry: &HistoryEntry) {
+        self.point(entry.cursor_before);
+        self.selection(entry.selection_before);
+        self.u32(entry.carets_before.len() as u32);
+        for caret in &entry.carets_before {
+            self.point(caret.pos);
+            self.selection(caret.selection);
+        }
+        // The visual line count depends on the window size and is recomputed on load.
+        self.coord(entry.stats_before.logical_lines);
+        self.u32(entry.generation_before);
+        self.point(entry.cursor);
+        self.blob(&entry.deleted);
+        self.blob(&entry.added);
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    off: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes_exact(&mut self, len: usize) -> Option<&'a [u8]> {
+        let data = self.data.get(self.off..self.off.checked_add(len)?)?;
+        self.off += len;
+        Some(data)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes_exact(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes_exact(8)?.try_into().ok()?))
+    }
+
+    fn index(&mut self) -> Option<usize> {
+        usize::try_from(self.u64()?).ok()
+    }
+
+    fn coord(&mut self) -> Option<CoordType> {
+        CoordType::try_from(self.u64()? as i64).ok()
+    }
+
+    fn point(&mut self) -> Option<Point> {
+        Some(Point { x: self.coord()?, y: self.coord()? })
+    }
+
+    fn blob(&mut self) -> Option<Vec<u8>> {
+        let len = self.index()?;
+        Some(self.bytes_exact(len)?.to_vec())
+    }
+
+    fn selection(&mut self) -> Option<Option<TextBufferSelection>> {
+        match self.u32()? {
+            0 => Some(None),
+            1 => Some(Some(TextBufferSelection { beg: self.point()?, end: self.point()? })),
+            _ => None,
+        }
+    }
+
+    fn entry(&mut self) -> Option<HistoryEntry> {
+        let cursor_before = self.point()?;
+        let selection_before = self.selection()?;
+        let caret_count = self.u32()?;
+        let mut carets_before = Vec::new();
+        for _ in 0..caret_count {
+            carets_before
+                .push(TextBufferCaret { pos: self.point()?, selection: self.selection()? });
+        }
+        let logical_lines = self.coord()?;
+
+        Some(HistoryEntry {
+            cursor_before,
+            selection_before,
+            carets_before,
+            stats_before: TextBufferStatistics { logical_lines, visual_lines: -1 },
+            generation_before: self.u32()?,
+            cursor: self.point()?,
+            deleted: self.blob()?,
+            added: self.blob()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::tests::{buffer_from, contents};
+
+    /// Same as [`contents`], but doesn't mark the buffer as clean.
+    fn peek(tb: &TextBuffer) -> String {
+        let mut s = String::new();
+        tb.buffer.copy_into(&mut s);
+        s
+    }
+
+    #[test]
+    fn test_history_tree() {
+        let mut tb = buffer_from("a");
+        tb.write_raw(b"b");
+        tb.undo();
+        tb.write_raw(b"c");
+        assert_eq!(contents(&mut tb), "ac");
+
+        // The undone "b" is kept as a separate branch.
+        let states = tb.history_states();
+        assert_eq!(states.len(), 4);
+        assert_eq!((states[2].parent, states[2].added, states[2].deleted), (1, 1, 0));
+        assert_eq!((states[3].parent, states[3].added, states[3].deleted), (1, 1, 0));
+        assert!(states[3].current);
+
+        tb.history_goto(2);
+        assert_eq!(peek(&tb), "ab");
+        assert!(tb.is_dirty());
+        tb.history_goto(3);
+        assert_eq!(peek(&tb), "ac");
+        assert!(!tb.is_dirty());
+        tb.history_goto(0);
+        assert_eq!(peek(&tb), "");
+
+        // Redo follows the most recently visited branch.
+        tb.undo();
+        tb.redo();
+        tb.redo();
+        assert_eq!(peek(&tb), "ac");
+        assert_eq!(tb.history_current(), 3);
+        assert!(!tb.is_dirty());
+    }
+
+    #[test]
+    fn test_history_time_travel() {
+        let mut tb = buffer_from("a");
+        tb.write_raw(b"b");
+        tb.write_raw(b"c");
+
+        let now = SystemTime::now();
+        for (node, minutes) in tb.history.nodes.iter_mut().zip([15, 10, 5, 0]) {
+            node.timestamp = now - Duration::from_secs(minutes * 60);
+        }
+
+        tb.history_time_travel(-4 * 60);
+        assert_eq!(peek(&tb), "ab");
+        tb.history_time_travel(-7 * 60);
+        assert_eq!(peek(&tb), "");
+        tb.history_time_travel(11 * 60);
+        assert_eq!(peek(&tb), "ab");
+    }
+
+    #[test]
+    fn test_history_roundtrip() {
+        let mut tb = buffer_from("hello\n");
+        tb.cursor_move_to_logical(Point { x: 5, y: 0 });
+        tb.write_raw(b" world");
+        tb.undo();
+        tb.cursor_move_to_logical(Point { x: 0, y: 1 });
+        tb.write_raw(b"foo");
+        assert_eq!(contents(&mut tb), "hello\nfoo");
+
+        let mut data = Vec::new();
+        tb.serialize_history(&mut data);
+
+        // The history only applies to the exact same contents.
+        let mut other = buffer_from("hello\nbar");
+        assert!(!other.deserialize_history(&data));
+        assert!(!other.deserialize_history(&data[..data.len() - 1]));
+
+        let mut tb = buffer_from("hello\nfoo");
+        assert!(tb.deserialize_history(&data));
+        assert!(!tb.is_dirty());
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 3, y: 1 });
+
+        tb.undo();
+        assert_eq!(contents(&mut tb), "hello\n");
+        tb.history_goto(2);
+        assert_eq!(contents(&mut tb), "hello world\n");
+        tb.history_goto(0);
+        assert_eq!(contents(&mut tb), "");
+        tb.history_goto(3);
+        assert_eq!(contents(&mut tb), "hello\nfoo");
+        assert_eq!(tb.logical_line_count(), 2);
+    }
+}
@@ -1,116 +1,249 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! An index of line start offsets for [`super::TextBuffer`].
+//!
+//! Seeking to a line means counting newlines, which for a far-away line in a
+//! large file means scanning gigabytes of text. This cache remembers the offset
+//! of every `CACHE_EVERY`-th line, so that seeking only needs to scan from the
+//! closest remembered line. It's updated incrementally on every edit.
+//!
+//! With word wrap, seeking also needs the visual row of a line, which means measuring
+//! all the lines before it. The cache thus also remembers the rows of its line starts.
+//! They're measured lazily while seeking past them, since they depend on the layout.
+
 use std::ops::Range;
 
-use crate::{document::ReadableDocument, simd::memchr2};
+use crate::cell::SemiRefCell;
+use crate::document::ReadableDocument;
+use crate::helpers::CoordType;
+use crate::simd::memchr2;
 
 /// Cache a line/offset pair every CACHE_EVERY lines to speed up line/offset calculations
-const CACHE_EVERY: usize = 1024 * 64;
+const CACHE_EVERY: CoordType = 4096;
 
-#[derive(Clone)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct CachePoint {
+    /// The offset of the start of the line.
     pub index: usize,
-    pub line: usize,
-    // pub snapshot: ParserSnapshot
+    /// The logical line number.
+    pub line: CoordType,
 }
 
+/// Sorted by both `index` and `line`. The first point is always the start of the document.
 pub struct LineCache {
     cache: Vec<CachePoint>,
+    /// The visual rows of the leading points in `cache`. Edits drop the rows of the points
+    /// after them and [`LineCache::push_measured`] adds them back as they get measured.
+    rows: SemiRefCell<Vec<CoordType>>,
 }
 
 impl LineCache {
     pub fn new() -> Self {
-        Self { cache: vec![] }
+        Self { cache: vec![CachePoint { index: 0, line: 0 }], rows: SemiRefCell::new(vec![0]) }
     }
 
-    pub fn from_document<T: ReadableDocument>(&mut self, document: &T) {
-        self.cache.clear();
+    /// Rebuilds the cache from scratch.
+    pub fn rebuild(&mut self, document: &dyn ReadableDocument) {
+        self.cache.truncate(1);
+        self.clear_measured();
+        self.scan(document, 0, 0, usize::MAX);
+    }
 
-        let mut offset = 0;
-        let mut line = 0;
-        loop {
-            let text = document.read_forward(offset);
-            if text.is_empty() { return; }
-            
-            let mut off = 0;
-            loop {
-                off = memchr2(b'\n', b'\n', text, off);
-                if off == text.len() { break; }
+    /// Updates the cache after an insertion.
+    /// `offset` is where the insertion occurred, `line` the line number at `offset`,
+    /// and `len` the length of the inserted text, which must already be in `document`.
+    pub fn insert(
+        &mut self,
+        document: &dyn ReadableDocument,
+        offset: usize,
+        line: CoordType,
+        len: usize,
+    ) {
+        if len == 0 {
+            return;
+        }
 
-                if line % CACHE_EVERY == 0 {
-                    self.cache.push(CachePoint { index: offset+off, line });
-                }
-                line += 1;
-                off += 1;
-            }
+        // Points at `offset` stay valid: Text inserted at the start of a line becomes part of it.
+        let idx = self.cache.partition_point(|p| p.index <= offset);
+        let tail = self.cache.split_off(idx);
+        self.rows.borrow_mut().truncate(idx);
+        let newlines = self.scan(document, offset, line, offset + len);
 
-            offset += text.len();
-        }
+        self.cache.extend(
+            tail.into_iter().map(|p| CachePoint { index: p.index + len, line: p.line + newlines }),
+        );
     }
 
     /// Updates the cache after a deletion.
-    /// `range` is the deleted byte range, and `text` is the content that was deleted.
-    pub fn delete(&mut self, range: Range<usize>, text: &Vec<u8>) {
-        let mut newlines = 0;
-        for c in text {
-            if *c == b'\n' {
-                newlines += 1;
-            }
+    /// `range` is the deleted byte range, and `newlines` the number of newlines within it.
+    pub fn delete(&mut self, range: Range<usize>, newlines: CoordType) {
+        if range.is_empty() {
+            return;
         }
 
-        let mut beg_del = None;
-        let mut end_del = None;
-        for (i, point) in self.cache.iter_mut().enumerate() {
-            if point.index >= range.start {
-                if point.index < range.end {
-                    // cache point is within the deleted range
-                    if beg_del.is_none() { beg_del = Some(i); }
-                    end_del = Some(i + 1);
-                }
-                else {
-                    point.index -= text.len();
-                    point.line -= newlines;
-                }
-            }
+        // A point at `range.start` is still the start of its line, but any line started
+        // within the range (or right after it) is now merged into the line at `range.start`.
+        let beg = self.cache.partition_point(|p| p.index <= range.start);
+        let end = self.cache.partition_point(|p| p.index <= range.end);
+        self.cache.drain(beg..end);
+        self.rows.borrow_mut().truncate(beg);
+
+        for p in &mut self.cache[beg..] {
+            p.index -= range.len();
+            p.line -= newlines;
         }
+    }
 
-        if let (Some(beg), Some(end)) = (beg_del, end_del) {
-            self.cache.drain(beg..end);
+    /// Finds the closest cached line start at or before the given `line`, and its visual row.
+    /// With `measured`, only points whose row was measured are considered. Otherwise,
+    /// the row is the line number, which is only correct without word wrap.
+    pub fn nearest_offset(&self, line: CoordType, measured: bool) -> (CachePoint, CoordType) {
+        let idx = self.cache.partition_point(|p| p.line <= line);
+        self.get(idx.saturating_sub(1), measured)
+    }
+
+    /// Like [`LineCache::nearest_offset`], but for the closest line start at or before `offset`.
+    pub fn nearest_before(&self, offset: usize, measured: bool) -> (CachePoint, CoordType) {
+        let idx = self.cache.partition_point(|p| p.index <= offset);
+        self.get(idx.saturating_sub(1), measured)
+    }
+
+    fn get(&self, idx: usize, measured: bool) -> (CachePoint, CoordType) {
+        if measured {
+            let rows = self.rows.borrow();
+            let idx = idx.min(rows.len() - 1);
+            (self.cache[idx], rows[idx])
+        } else {
+            (self.cache[idx], self.cache[idx].line)
         }
     }
 
-    /// Updates the cache after an insertion.
-    /// `offset` is where the insertion occurs, and `text` is the inserted content.
-    pub fn insert(&mut self, offset: usize, text: &[u8]) {
-        // Count how many newlines were inserted
+    /// Returns the first point whose visual row hasn't been measured yet.
+    pub fn next_unmeasured(&self) -> Option<CachePoint> {
+        self.cache.get(self.rows.borrow().len()).copied()
+    }
+
+    /// Remembers the visual `row` of the point returned by [`LineCache::next_unmeasured`].
+    pub fn push_measured(&self, row: CoordType) {
+        self.rows.borrow_mut().push(row);
+    }
+
+    /// Forgets the visual rows of the points after `offset`.
+    pub fn drop_measured_after(&mut self, offset: usize) {
+        let idx = self.cache.partition_point(|p| p.index <= offset);
+        self.rows.borrow_mut().truncate(idx);
+    }
+
+    /// Forgets the visual rows, because the layout changed.
+    pub fn clear_measured(&mut self) {
+        self.rows.borrow_mut().truncate(1);
+    }
+
+    /// Counts the newlines in `offset..end` of `document`, where `offset` is on `line`,
+    /// and adds cache points for all lines starting in that range that are due for one.
+    /// Returns the number of newlines.
+    fn scan(
+        &mut self,
+        document: &dyn ReadableDocument,
+        mut offset: usize,
+        line: CoordType,
+        end: usize,
+    ) -> CoordType {
         let mut newlines = 0;
-        for c in text {
-            if *c == b'\n' {
-                newlines += 1;
+
+        while offset < end {
+            let chunk = document.read_forward(offset);
+            if chunk.is_empty() {
+                break;
             }
-        }
 
-        let len = text.len();
-        for point in &mut self.cache {
-            if point.index > offset {
-                point.index += len;
-                point.line += newlines;
+            let chunk = &chunk[..chunk.len().min(end - offset)];
+            let mut off = 0;
+
+            loop {
+                off = memchr2(b'\n', b'\n', chunk, off);
+                if off == chunk.len() {
+                    break;
+                }
+
+                off += 1;
+                newlines += 1;
+
+                let l = line + newlines;
+                if l % CACHE_EVERY == 0 {
+                    self.cache.push(CachePoint { index: offset + off, line: l });
+                }
             }
+
+            offset += chunk.len();
         }
 
-        // TODO: This also needs to insert new cache points
+        newlines
     }
+}
 
-    /// Finds the nearest cached line-offset pair relative to a target line.
-    /// If `reverse` is false, it returns the closest *before* the target.
-    /// If `reverse` is true, it returns the closest *after or at* the target.
-    pub fn nearest_offset(&self, target_count: usize, reverse: bool) -> Option<CachePoint> {
-        match self.cache.binary_search_by_key(&target_count, |p| p.line) {
-            Ok(i) => Some(self.cache[i].clone()),
-            Err(i) => {
-                if i == 0 || i == self.cache.len() { None }  // target < lowest cache point || target > highest cache point
-                else {
-                    Some(self.cache[ if reverse {i} else {i-1} ].clone())
-                }
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_with_lines(count: usize) -> String {
+        "x\n".repeat(count)
+    }
+
+    #[test]
+    fn test_line_cache() {
+        let mut text = text_with_lines(3 * CACHE_EVERY as usize);
+        let mut cache = LineCache::new();
+        cache.rebuild(&text);
+        assert_eq!(cache.cache.len(), 4);
+        assert_eq!(cache.nearest_offset(5000, false).0, CachePoint { index: 8192, line: 4096 });
+
+        // Insert 4096 lines at the start of line 10.
+        text.insert_str(20, &text_with_lines(CACHE_EVERY as usize));
+        cache.insert(&text, 20, 10, 2 * CACHE_EVERY as usize);
+        assert_eq!(cache.nearest_offset(4106, false).0, CachePoint { index: 8192, line: 4096 });
+        assert_eq!(cache.nearest_offset(8192, false).0, CachePoint { index: 16384, line: 8192 });
+        assert_eq!(cache.cache.len(), 5);
+        // Since all lines are of equal length, the offsets are easy to check.
+        assert!(cache.cache.iter().all(|p| p.index == 2 * p.line as usize));
+
+        // Delete lines 1 to 5000, which contain the point for line 4096.
+        cache.delete(2..10000, 4999);
+        assert_eq!(cache.cache.len(), 4);
+        assert_eq!(cache.nearest_offset(3000, false).0, CachePoint { index: 0, line: 0 });
+        assert!(cache.cache.iter().all(|p| p.index == 2 * p.line as usize));
+    }
+
+    #[test]
+    fn test_line_cache_rows() {
+        let mut text = text_with_lines(3 * CACHE_EVERY as usize);
+        let mut cache = LineCache::new();
+        cache.rebuild(&text);
+
+        // Pretend that each line takes up 2 rows.
+        while let Some(p) = cache.next_unmeasured() {
+            cache.push_measured(2 * p.line);
         }
+        assert_eq!(
+            cache.nearest_offset(9000, true),
+            (CachePoint { index: 16384, line: 8192 }, 16384)
+        );
+
+        // An edit drops the rows after it, until they're measured again.
+        text.insert_str(10000, "x\n");
+        cache.insert(&text, 10000, 5000, 2);
+        assert_eq!(
+            cache.nearest_offset(9000, true),
+            (CachePoint { index: 8192, line: 4096 }, 8192)
+        );
+        assert_eq!(
+            cache.nearest_offset(9000, false),
+            (CachePoint { index: 16386, line: 8193 }, 8193)
+        );
+        assert_eq!(cache.next_unmeasured(), Some(CachePoint { index: 16386, line: 8193 }));
+
+        cache.clear_measured();
+        assert_eq!(cache.nearest_before(10000, true), (CachePoint { index: 0, line: 0 }, 0));
     }
 }
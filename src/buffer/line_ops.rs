@@ -0,0 +1,174 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Transformations of whole lines, like sorting them.
+//!
+//! The lines are passed without their trailing newlines.
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::arena::scratch_arena;
+use crate::hash::wymix;
+use crate::icu;
+
+/// Options for [`super::TextBuffer::sort_lines`].
+#[derive(Default, Clone, Copy, Eq, PartialEq)]
+pub struct SortOptions {
+    /// If true, the lines are sorted from Z to A.
+    pub descending: bool,
+    /// If true, "a" and "A" are considered equal.
+    pub case_insensitive: bool,
+    /// If true, runs of digits are compared by their numeric value, so that "2" sorts before "10".
+    pub natural: bool,
+}
+
+/// Sorts the `lines` using the locale's collation. Equal lines keep their order.
+pub fn sort(lines: &mut Vec<Vec<u8>>, options: SortOptions) {
+    let mut keyed: Vec<_> = lines
+        .drain(..)
+        .map(|line| {
+            let key = if options.case_insensitive {
+                let scratch = scratch_arena(None);
+                icu::fold_case(&scratch, &String::from_utf8_lossy(&line)).as_bytes().to_vec()
+            } else {
+                line.clone()
+            };
+            (key, line)
+        })
+        .collect();
+
+    keyed.sort_by(|(a, _), (b, _)| {
+        let ord = if options.natural { compare_natural(a, b) } else { icu::compare_strings(a, b) };
+        if options.descending { ord.reverse() } else { ord }
+    });
+
+    lines.extend(keyed.into_iter().map(|(_, line)| line));
+}
+
+/// Compares runs of digits by their value and everything else via collation.
+fn compare_natural(mut a: &[u8], mut b: &[u8]) -> Ordering {
+    // Splits off the leading run of digits or non-digits.
+    fn run(s: &[u8]) -> (bool, &[u8], &[u8]) {
+        let digits = s.first().is_some_and(u8::is_ascii_digit);
+        let len = s.iter().take_while(|c| c.is_ascii_digit() == digits).count();
+        (digits, &s[..len], &s[len..])
+    }
+
+    fn trim_zeros(s: &[u8]) -> &[u8] {
+        &s[s.iter().take_while(|&&c| c == b'0').count()..]
+    }
+
+    loop {
+        match (a.is_empty(), b.is_empty()) {
+            (true, true) => return Ordering::Equal,
+            (true, false) => return Ordering::Less,
+            (false, true) => return Ordering::Greater,
+            (false, false) => {}
+        }
+
+        let (a_digits, a_run, a_rest) = run(a);
+        let (b_digits, b_run, b_rest) = run(b);
+
+        let ord = if a_digits && b_digits {
+            let (a_num, b_num) = (trim_zeros(a_run), trim_zeros(b_run));
+            // Without leading zeros, the longer number is the larger one.
+            a_num.len().cmp(&b_num.len()).then_with(|| a_num.cmp(b_num))
+        } else {
+            icu::compare_strings(a_run, b_run)
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+
+        a = a_rest;
+        b = b_rest;
+    }
+}
+
+/// Removes lines that are equal to an earlier one.
+pub fn remove_duplicates(lines: &mut Vec<Vec<u8>>) {
+    let mut seen = HashSet::new();
+    lines.retain(|line| seen.insert(line.clone()));
+}
+
+/// Puts the `lines` into a random order.
+pub fn shuffle(lines: &mut [Vec<u8>]) {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+    let mut state = nanos;
+
+    // Fisher-Yates with wyrand as the generator.
+    for i in (1..lines.len()).rev() {
+        state = state.wrapping_add(0xa0761d6478bd642f);
+        let rand = wymix(state, state ^ 0xe7037ed1a0b428db);
+        lines.swap(i, (rand % (i as u64 + 1)) as usize);
+    }
+}
+
+/// Joins the `lines` into one, separated by a space. The indentation of
+/// all but the first line is removed, as is trailing whitespace.
+pub fn join(lines: &mut Vec<Vec<u8>>) {
+    let mut iter = lines.drain(..);
+    let Some(mut res) = iter.next() else {
+        return;
+    };
+
+    for line in iter {
+        let line = line.trim_ascii();
+        res.truncate(res.trim_ascii_end().len());
+        if !line.is_empty() {
+            if !res.is_empty() {
+                res.push(b' ');
+            }
+            res.extend_from_slice(line);
+        }
+    }
+
+    *lines = vec![res];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<Vec<u8>> {
+        text.split('\n').map(|l| l.as_bytes().to_vec()).collect()
+    }
+
+    fn text(lines: &[Vec<u8>]) -> String {
+        String::from_utf8(lines.join(&b'\n')).unwrap()
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut l = lines("c10\na2\nb2\na10");
+        sort(&mut l, SortOptions::default());
+        assert_eq!(text(&l), "a10\na2\nb2\nc10");
+
+        sort(&mut l, SortOptions { natural: true, ..Default::default() });
+        assert_eq!(text(&l), "a2\na10\nb2\nc10");
+
+        sort(&mut l, SortOptions { descending: true, natural: true, ..Default::default() });
+        assert_eq!(text(&l), "c10\nb2\na10\na2");
+
+        // Equal lines keep their order.
+        let mut l = lines("b\nA\nC\na");
+        sort(&mut l, SortOptions { case_insensitive: true, ..Default::default() });
+        assert_eq!(text(&l), "A\na\nb\nC");
+    }
+
+    #[test]
+    fn test_remove_duplicates() {
+        let mut l = lines("a\nb\na\nc\nb");
+        remove_duplicates(&mut l);
+        assert_eq!(text(&l), "a\nb\nc");
+    }
+
+    #[test]
+    fn test_join() {
+        let mut l = lines("a {  \n    b;\n\n  }");
+        join(&mut l);
+        assert_eq!(text(&l), "a { b; }");
+    }
+}
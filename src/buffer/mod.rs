@@ -4,8 +4,9 @@
 //! A text buffer for a text editor.
 //!
 //! Implements a Unicode-aware, layout-aware text buffer for terminals.
-//! It's based on a gap buffer. It has no line cache and instead relies
-//! on the performance of the ucd module for fast text navigation.
+//! It's based on a gap buffer. Apart from a sparse index of line starts
+//! (see [`line_cache`]), it relies on the performance of the ucd module
+//! for fast text navigation.
 //!
 //! ---
 //!
@@ -20,12 +21,16 @@
 //! The solution to the former is to keep line caches, which further complicates the architecture.
 //! There's no solution for the latter. However, there's a chance that the performance will still be sufficient.
 
+mod brackets;
+mod folding;
 mod gap_buffer;
+mod history;
+mod line_cache;
+mod line_ops;
 mod navigation;
 
 use std::borrow::Cow;
 use std::cell::UnsafeCell;
-use std::collections::LinkedList;
 use std::fmt::Write as _;
 use std::fs::File;
 use std::io::{Read as _, Write as _};
@@ -33,8 +38,14 @@ use std::mem::{self, MaybeUninit};
 use std::ops::Range;
 use std::rc::Rc;
 use std::str;
+use std::time::{Duration, Instant};
 
+pub use folding::FoldOptions;
 pub use gap_buffer::GapBuffer;
+use history::History;
+pub use history::HistoryState;
+use line_cache::{CachePoint, LineCache};
+pub use line_ops::SortOptions;
 
 use crate::arena::{Arena, ArenaString, scratch_arena};
 use crate::cell::SemiRefCell;
@@ -44,8 +55,9 @@ use crate::framebuffer::{Framebuffer, IndexedColor};
 use crate::helpers::*;
 use crate::oklab::StraightRgba;
 use crate::simd::memchr2;
+use crate::syntax::{Highlighter, Language};
 use crate::unicode::{self, Cursor, MeasurementConfig, Utf8Chars};
-use crate::{apperr, icu, simd};
+use crate::{apperr, icu, regex, simd};
 
 /// The margin template is used for line numbers.
 /// The max. line number we should ever expect is probably 64-bit,
@@ -59,6 +71,14 @@ const VISUAL_SPACE_PREFIX_ADD: usize = '･'.len_utf8() - 1;
 const VISUAL_TAB: &str = "￫       ";
 const VISUAL_TAB_PREFIX_ADD: usize = '￫'.len_utf8() - 1;
 
+/// The number of search hits after which [`TextBuffer::find_count_step`] stops counting.
+/// It keeps the memory and the scrollbar markers bounded for searches like `.`.
+pub const MAX_SEARCH_MATCHES: usize = 10000;
+
+/// How far away the partner of a bracket is searched for when we do so implicitly:
+/// For highlighting the one at the cursor, or for indenting a closing bracket as it's typed.
+const BRACKET_SEARCH_DISTANCE: usize = 1024 * 1024;
+
 /// Stores statistics about the whole document.
 #[derive(Copy, Clone)]
 pub struct TextBufferStatistics {
@@ -76,6 +96,27 @@ struct TextBufferSelection {
     end: Point,
 }
 
+/// A secondary cursor, in addition to [`TextBuffer::cursor`].
+///
+/// Only the logical position is stored, because the full [`Cursor`]
+/// would get invalidated by any edit made at another cursor.
+#[derive(Copy, Clone)]
+struct TextBufferCaret {
+    pos: Point,
+    selection: Option<TextBufferSelection>,
+}
+
+impl TextBufferCaret {
+    /// Returns the sorted start and end of the selection,
+    /// or just the position twice if there's none.
+    fn range(&self) -> [Point; 2] {
+        match self.selection {
+            Some(TextBufferSelection { beg, end }) => minmax(beg, end),
+            None => [self.pos, self.pos],
+        }
+    }
+}
+
 /// In order to group actions into a single undo step,
 /// we need to know the type of action that was performed.
 /// This stores the action type.
@@ -92,6 +133,8 @@ struct HistoryEntry {
     cursor_before: Point,
     /// [`TextBuffer::selection`] before the change was made.
     selection_before: Option<TextBufferSelection>,
+    /// [`TextBuffer::carets`] before the change was made.
+    carets_before: Vec<TextBufferCaret>,
     /// [`TextBuffer::stats`] before the change was made.
     stats_before: TextBufferStatistics,
     /// [`GapBuffer::generation`] before the change was made.
@@ -107,19 +150,17 @@ struct HistoryEntry {
     added: Vec<u8>,
 }
 
-/// Caches an ICU search operation.
+/// Caches a search operation.
 struct ActiveSearch {
     /// The search pattern.
     pattern: String,
     /// The search options.
     options: SearchOptions,
-    /// The ICU `UText` object.
-    text: icu::Text,
-    /// The ICU `URegularExpression` object.
-    regex: icu::Regex,
+    /// The regex engine that runs the search.
+    matcher: Matcher,
     /// [`GapBuffer::generation`] when the search was created.
     /// This is used to detect if we need to refresh the
-    /// [`ActiveSearch::regex`] object.
+    /// [`ActiveSearch::matcher`] object.
     buffer_generation: u32,
     /// [`TextBuffer::selection_generation`] when the search was
     /// created. When the user manually selects text, we need to
@@ -129,6 +170,121 @@ struct ActiveSearch {
     next_search_offset: usize,
     /// If we know there were no hits, we can skip searching.
     no_matches: bool,
+    /// With [`SearchOptions::in_selection`], the offsets of the selection that the
+    /// search is restricted to. Edits move it along with the text.
+    scope: Option<Range<usize>>,
+    /// The offsets of the hits counted so far, up to [`MAX_SEARCH_MATCHES`].
+    matches: Vec<Range<usize>>,
+    /// The visual lines that contain the start of a hit in `matches`, without duplicates.
+    match_lines: Vec<CoordType>,
+    /// Where [`TextBuffer::find_count_step`] continues counting.
+    /// `None` once all hits were counted or [`MAX_SEARCH_MATCHES`] was reached.
+    count_cursor: Option<Cursor>,
+    /// [`GapBuffer::generation`] when counting started.
+    /// `None` if it needs to start (again).
+    matches_generation: Option<u32>,
+}
+
+/// The progress of counting the hits of the active search, see [`TextBuffer::find_match_count`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchMatchCount {
+    /// The 1-based index of the selected hit, or `None` if the selection isn't a hit.
+    pub current: Option<usize>,
+    /// The number of hits counted so far.
+    pub total: usize,
+    /// True if there may be more hits than `total`, because counting is
+    /// still in progress or stopped at [`MAX_SEARCH_MATCHES`].
+    pub incomplete: bool,
+}
+
+/// ICU's regex engine, or if ICU isn't available, our own [`regex::Regex`].
+enum Matcher {
+    Icu {
+        /// The ICU `UText` object.
+        text: icu::Text,
+        /// The ICU `URegularExpression` object.
+        regex: icu::Regex,
+    },
+    Native {
+        regex: regex::Regex,
+        /// The offset at which the next search starts.
+        offset: usize,
+        /// Hits must start at or before this offset. See [`Matcher::set_region`].
+        limit: usize,
+        /// If the last hit was empty, the next search starts a char later,
+        /// just like with ICU. Otherwise, it would find the same hit forever.
+        last_empty: bool,
+    },
+}
+
+impl Matcher {
+    /// Refreshes the matcher after the contents of the buffer changed,
+    /// and sets it to the given offset.
+    fn set_text(&mut self, offset: usize) {
+        match self {
+            // SAFETY: The `text` is owned by `self` and so it outlives the `regex`.
+            Self::Icu { text, regex } => unsafe { regex.set_text(text, offset) },
+            Self::Native { .. } => self.reset(offset),
+        }
+    }
+
+    /// Sets the matcher to the given offset.
+    fn reset(&mut self, offset: usize) {
+        match self {
+            Self::Icu { regex, .. } => regex.reset(offset),
+            Self::Native { offset: o, limit, last_empty, .. } => {
+                *o = offset;
+                *limit = usize::MAX;
+                *last_empty = false;
+            }
+        }
+    }
+
+    /// Restricts the matcher to the given range and sets it to its start.
+    /// [`Matcher::reset`] lifts the restriction again.
+    fn set_region(&mut self, range: Range<usize>) {
+        match self {
+            Self::Icu { regex, .. } => regex.set_region(range),
+            Self::Native { offset, limit, last_empty, .. } => {
+                *offset = range.start;
+                *limit = range.end;
+                *last_empty = false;
+            }
+        }
+    }
+
+    /// Finds the next hit in `doc`, which must be the buffer the matcher was created for.
+    fn next(&mut self, doc: &GapBuffer) -> Option<Range<usize>> {
+        match self {
+            Self::Icu { regex, .. } => regex.next(),
+            Self::Native { regex, offset, limit, last_empty } => {
+                if *last_empty {
+                    let mut chars = Utf8Chars::new(doc.read_forward(*offset), 0);
+                    chars.next()?;
+                    *offset += chars.offset();
+                }
+                let range = regex.find_in(doc, *offset, *limit)?;
+                *offset = range.end;
+                *last_empty = range.is_empty();
+                Some(range)
+            }
+        }
+    }
+
+    fn group_count(&mut self) -> i32 {
+        match self {
+            Self::Icu { regex, .. } => regex.group_count(),
+            Self::Native { regex, .. } => regex.group_count() as i32,
+        }
+    }
+
+    /// Gets the range of a captured group of the last hit.
+    fn group(&mut self, group: i32) -> Option<Range<usize>> {
+        match self {
+            Self::Icu { regex, .. } => regex.group(group),
+            Self::Native { regex, .. } => regex.group(group as usize),
+        }
+    }
 }
 
 /// Options for a search operation.
@@ -140,6 +296,24 @@ pub struct SearchOptions {
     pub whole_word: bool,
     /// If true, the search uses regex.
     pub use_regex: bool,
+    /// If true, the search is restricted to the selection at the time the search started.
+    pub in_selection: bool,
+}
+
+/// A hit found by [`TextBuffer::find_all`].
+#[derive(Clone)]
+pub struct SearchHit {
+    /// The logical position of the start of the hit.
+    pub beg: Point,
+    /// The logical position of the end of the hit.
+    pub end: Point,
+    /// The line containing the start of the hit, without the newline.
+    pub line: String,
+    /// The byte range of the hit within `line`. It ends at the end of the line
+    /// if the hit spans multiple lines.
+    pub line_range: Range<usize>,
+    /// What the hit would be replaced with.
+    pub replacement: Option<String>,
 }
 
 enum RegexReplacement<'a> {
@@ -170,15 +344,21 @@ struct ActiveEditGroupInfo {
     cursor_before: Point,
     /// [`TextBuffer::selection`] before the change was made.
     selection_before: Option<TextBufferSelection>,
+    /// [`TextBuffer::carets`] before the change was made.
+    carets_before: Vec<TextBufferCaret>,
     /// [`TextBuffer::stats`] before the change was made.
     stats_before: TextBufferStatistics,
     /// [`GapBuffer::generation`] before the change was made.
     ///
     /// **NOTE:** Entries with the same generation are grouped together.
     generation_before: u32,
+    /// Groups may be nested, e.g. when indenting at multiple cursors.
+    /// Only the outermost one records the overrides.
+    depth: u32,
 }
 
 /// Char- or word-wise navigation? Your choice.
+#[derive(Clone, Copy)]
 pub enum CursorMovement {
     Grapheme,
     Word,
@@ -208,9 +388,9 @@ pub type RcTextBuffer = Rc<TextBufferCell>;
 /// A text buffer for a text editor.
 pub struct TextBuffer {
     buffer: GapBuffer,
+    line_cache: LineCache,
 
-    undo_stack: LinkedList<SemiRefCell<HistoryEntry>>,
-    redo_stack: LinkedList<SemiRefCell<HistoryEntry>>,
+    history: History,
     last_history_type: HistoryType,
     last_save_generation: u32,
 
@@ -228,7 +408,22 @@ pub struct TextBuffer {
     cursor_for_rendering: Option<Cursor>,
     selection: Option<TextBufferSelection>,
     selection_generation: u32,
+    // Secondary cursors, sorted by position. Doesn't contain `cursor`.
+    carets: Vec<TextBufferCaret>,
+    // The anchors of an active block selection, which `carets` got created from.
+    // Unlike `selection`, `.x` is a visual column (ignoring word wrap) and `.y` a logical line.
+    block_selection: Option<TextBufferSelection>,
     search: Option<UnsafeCell<ActiveSearch>>,
+    highlighter: Option<Highlighter>,
+    // The folded regions as logical lines, sorted by their start. The first line of each
+    // (the header) remains visible. Regions may be nested within each other.
+    folds: Vec<Range<CoordType>>,
+    // The layout rows hidden by `folds`, merged and sorted. Must be updated on every edit or reflow.
+    fold_rows: Vec<Range<CoordType>>,
+    fold_options: FoldOptions,
+    // The bracket at the cursor and its partner, cached by buffer generation and cursor offset,
+    // because the partner may be far away in large files.
+    bracket_pair: Option<(u32, usize, Option<[usize; 2]>)>,
 
     width: CoordType,
     margin_width: CoordType,
@@ -243,6 +438,10 @@ pub struct TextBuffer {
     newlines_are_crlf: bool,
     insert_final_newline: bool,
     overtype: bool,
+    auto_close: bool,
+    /// The pairs of brackets and quotes that auto-close inserted, from the offset of the
+    /// opening one to that of the closing one. Only those get typed over or deleted together.
+    auto_closed: Vec<Range<usize>>,
 
     wants_cursor_visibility: bool,
 }
@@ -260,9 +459,9 @@ impl TextBuffer {
     pub fn new(small: bool) -> apperr::Result<Self> {
         Ok(Self {
             buffer: GapBuffer::new(small)?,
+            line_cache: LineCache::new(),
 
-            undo_stack: LinkedList::new(),
-            redo_stack: LinkedList::new(),
+            history: History::new(),
             last_history_type: HistoryType::Other,
             last_save_generation: 0,
 
@@ -276,7 +475,14 @@ impl TextBuffer {
             cursor_for_rendering: None,
             selection: None,
             selection_generation: 0,
+            carets: Vec::new(),
+            block_selection: None,
             search: None,
+            highlighter: None,
+            folds: Vec::new(),
+            fold_rows: Vec::new(),
+            fold_options: Default::default(),
+            bracket_pair: None,
 
             width: 0,
             margin_width: 0,
@@ -291,6 +497,8 @@ impl TextBuffer {
             newlines_are_crlf: cfg!(windows), // Windows users want CRLF
             insert_final_newline: false,
             overtype: false,
+            auto_close: false,
+            auto_closed: Vec::new(),
 
             wants_cursor_visibility: false,
         })
@@ -308,9 +516,9 @@ impl TextBuffer {
     }
 
     /// Number of visual lines in the document,
-    /// that is, the number of lines after layout.
+    /// that is, the number of lines after layout, minus the folded ones.
     pub fn visual_line_count(&self) -> CoordType {
-        self.stats.visual_lines
+        self.stats.visual_lines - self.fold_rows.iter().map(|r| r.end - r.start).sum::<CoordType>()
     }
 
     /// Does the buffer need to be saved?
@@ -357,10 +565,29 @@ impl TextBuffer {
         self.newlines_are_crlf = crlf;
     }
 
+    /// The language used for syntax highlighting, if any.
+    pub fn language(&self) -> Option<&'static Language> {
+        self.highlighter.as_ref().map(|h| h.language())
+    }
+
+    /// Sets the language used for syntax highlighting. `None` disables it.
+    pub fn set_language(&mut self, language: Option<&'static Language>) {
+        if self.language().map(|l| l.name) != language.map(|l| l.name) {
+            self.highlighter = language.map(Highlighter::new);
+        }
+    }
+
+    fn highlighter_invalidate(&mut self, line: CoordType) {
+        if let Some(h) = &mut self.highlighter {
+            h.invalidate(line);
+        }
+    }
+
     /// Changes the newline type used in the document.
     ///
     /// NOTE: Cannot be undone.
     pub fn normalize_newlines(&mut self, crlf: bool) {
+        self.highlighter_invalidate(0);
         let newline: &[u8] = if crlf { b"\r\n" } else { b"\n" };
         let mut off = 0;
 
@@ -423,6 +650,8 @@ impl TextBuffer {
             cursor.offset = cursor_for_rendering_offset;
         }
 
+        self.line_cache.rebuild(&self.buffer);
+        self.auto_closed.clear();
         self.newlines_are_crlf = crlf;
     }
 
@@ -432,6 +661,12 @@ impl TextBuffer {
         self.insert_final_newline = enabled;
     }
 
+    /// If enabled, typing an opening bracket or quote inserts the closing one as well,
+    /// or wraps the selection in the pair.
+    pub fn set_auto_close(&mut self, enabled: bool) {
+        self.auto_close = enabled;
+    }
+
     /// Whether to insert or overtype text when writing.
     pub fn is_overtype(&self) -> bool {
         self.overtype
@@ -451,7 +686,8 @@ impl TextBuffer {
     /// Gets the visual cursor position, that is,
     /// the position in laid out rows and columns.
     pub fn cursor_visual_pos(&self) -> Point {
-        self.cursor.visual_pos
+        let pos = self.cursor.visual_pos;
+        Point { x: pos.x, y: self.fold_row_from_layout(pos.y) }
     }
 
     /// Gets the width of the left margin.
@@ -578,6 +814,11 @@ impl TextBuffer {
         self.line_highlight_enabled = enabled;
     }
 
+    /// Returns the ruler column, or 0 if there's none.
+    pub fn ruler(&self) -> CoordType {
+        self.ruler
+    }
+
     /// Sets a ruler column, e.g. 80.
     pub fn set_ruler(&mut self, column: CoordType) {
         self.ruler = column;
@@ -612,7 +853,14 @@ impl TextBuffer {
 
         self.cursor_for_rendering = None;
 
+        // The visual lines of the search hits may have changed.
+        if let Some(search) = &mut self.search {
+            search.get_mut().matches_generation = None;
+        }
+
         if force || self.word_wrap_column != word_wrap_column_before {
+            self.line_cache.clear_measured();
+
             // Recalculate the cursor position.
             self.cursor = self.cursor_move_to_logical_internal(
                 if self.word_wrap_column > 0 {
@@ -631,6 +879,8 @@ impl TextBuffer {
                 self.stats.visual_lines = self.stats.logical_lines;
             }
         }
+
+        self.folds_update_rows();
     }
 
     /// Replaces the entire buffer contents with the given `text`.
@@ -643,16 +893,22 @@ impl TextBuffer {
             let delete = self.buffer.len() - self.cursor.offset;
             if delete != 0 {
                 self.buffer.allocate_gap(self.cursor.offset, 0, delete);
+                self.line_cache.rebuild(&self.buffer);
             }
         }
     }
 
     fn recalc_after_content_swap(&mut self) {
         // If the buffer was changed, nothing we previously saved can be relied upon.
-        self.undo_stack.clear();
-        self.redo_stack.clear();
+        self.history = History::new();
         self.last_history_type = HistoryType::Other;
         self.cursor = Default::default();
+        self.carets.clear();
+        self.block_selection = None;
+        self.folds.clear();
+        self.auto_closed.clear();
+        self.highlighter_invalidate(0);
+        self.line_cache.rebuild(&self.buffer);
         self.set_selection(None);
         self.mark_as_clean();
         self.reflow();
@@ -1005,7 +1261,8 @@ impl TextBuffer {
 
     /// Moves the cursor to `visual_pos` and updates the selection to contain it.
     pub fn selection_update_visual(&mut self, visual_pos: Point) {
-        self.set_cursor_for_selection(self.cursor_move_to_visual_internal(self.cursor, visual_pos));
+        let pos = Point { x: visual_pos.x, y: self.fold_row_to_layout(visual_pos.y) };
+        self.set_cursor_for_selection(self.cursor_move_to_visual_internal(self.cursor, pos));
     }
 
     /// Moves the cursor to `logical_pos` and updates the selection to contain it.
@@ -1079,12 +1336,349 @@ impl TextBuffer {
         had_selection
     }
 
+    /// Returns the number of cursors, including the primary one.
+    pub fn caret_count(&self) -> usize {
+        self.carets.len() + 1
+    }
+
+    /// Removes all cursors except for the primary one.
+    /// Returns `true` if there were any.
+    pub fn clear_carets(&mut self) -> bool {
+        let had_carets = !self.carets.is_empty();
+        self.carets.clear();
+        self.block_selection = None;
+        had_carets
+    }
+
+    /// Is there an active block (column) selection?
+    pub fn has_block_selection(&self) -> bool {
+        self.block_selection.is_some()
+    }
+
+    /// Extends the block selection to the given visual position.
+    /// Starts a new one at the cursor if there's none.
+    pub fn block_selection_update_visual(&mut self, visual_pos: Point) {
+        let anchor = self.block_selection_anchor();
+        let pos = Point { x: visual_pos.x, y: self.fold_row_to_layout(visual_pos.y) };
+        let cursor = self.cursor_move_to_visual_internal(self.cursor, pos);
+        // Unlike regular selections, blocks may extend past the end of a line.
+        let column = cursor.column + (visual_pos.x - cursor.visual_pos.x).max(0);
+        self.block_selection_set(anchor, Point { x: column, y: cursor.logical_pos.y });
+    }
+
+    /// Moves the corner of the block selection by `delta.x` graphemes and `delta.y` lines.
+    /// Starts a new one at the cursor if there's none.
+    pub fn block_selection_update_delta(&mut self, delta: Point) {
+        let anchor = self.block_selection_anchor();
+        let mut end = match self.block_selection {
+            Some(TextBufferSelection { end, .. }) => end,
+            None => Point { x: self.cursor.column, y: self.cursor.logical_pos.y },
+        };
+
+        if delta.x != 0 {
+            // Within the text we step over entire graphemes (e.g. tabs and wide glyphs),
+            // but past the end of the line there's only whitespace left.
+            let cursor = self.cursor_move_to_column_internal(self.cursor, end);
+            let next = self.cursor_move_to_logical_internal(
+                cursor,
+                Point { x: cursor.logical_pos.x + delta.x.signum(), y: cursor.logical_pos.y },
+            );
+            end.x = if cursor.column == end.x
+                && next.logical_pos.y == cursor.logical_pos.y
+                && next.offset != cursor.offset
+            {
+                next.column
+            } else {
+                end.x + delta.x.signum()
+            };
+        }
+
+        end.y += delta.y;
+        self.block_selection_set(anchor, end);
+    }
+
+    fn block_selection_anchor(&self) -> Point {
+        if let Some(TextBufferSelection { beg, .. }) = self.block_selection {
+            return beg;
+        }
+
+        let pos = self.selection.map_or(self.cursor.logical_pos, |s| s.beg);
+        let cursor = self.cursor_move_to_logical_internal(self.cursor, pos);
+        Point { x: cursor.column, y: cursor.logical_pos.y }
+    }
+
+    /// Turns the block spanning from `anchor` to `end` into one cursor and selection per line.
+    /// Lines that end before the left edge of the block are skipped.
+    fn block_selection_set(&mut self, anchor: Point, end: Point) {
+        let end = Point { x: end.x.max(0), y: end.y.clamp(0, self.stats.logical_lines - 1) };
+        let [top, bottom] = minmax(anchor.y, end.y);
+        let left = anchor.x.min(end.x);
+
+        let mut cursor = self.cursor;
+        let mut primary = self.cursor;
+        let mut primary_selection = None;
+        self.carets.clear();
+
+        for y in top..=bottom {
+            let beg = self.cursor_move_to_column_internal(cursor, Point { x: anchor.x, y });
+            cursor = self.cursor_move_to_column_internal(beg, Point { x: end.x, y });
+
+            let selection = (beg.offset != cursor.offset)
+                .then_some(TextBufferSelection { beg: beg.logical_pos, end: cursor.logical_pos });
+
+            if y == end.y {
+                primary = cursor;
+                primary_selection = selection;
+            } else if cursor.column >= left {
+                self.carets.push(TextBufferCaret { pos: cursor.logical_pos, selection });
+            }
+        }
+
+        self.set_cursor_internal(primary);
+        self.last_history_type = HistoryType::Other;
+        self.set_selection(primary_selection);
+        self.block_selection = Some(TextBufferSelection { beg: anchor, end });
+    }
+
+    /// Adds a cursor `delta` visual lines above (< 0) or below (> 0) the
+    /// top- or bottom-most cursor respectively, at the given visual `column`.
+    /// The new cursor becomes the primary one.
+    pub fn add_caret_vertical(&mut self, delta: CoordType, column: CoordType) {
+        let mut pos = self.cursor.logical_pos;
+        for c in &self.carets {
+            if (delta < 0 && c.pos < pos) || (delta > 0 && c.pos > pos) {
+                pos = c.pos;
+            }
+        }
+
+        let outermost = self.cursor_move_to_logical_internal(self.cursor, pos);
+        let y = self.fold_row_from_layout(outermost.visual_pos.y) + delta;
+        if delta == 0 || y < 0 || y >= self.visual_line_count() {
+            return;
+        }
+
+        let y = self.fold_row_to_layout(y);
+        let cursor = self.cursor_move_to_visual_internal(outermost, Point { x: column, y });
+        self.carets
+            .push(TextBufferCaret { pos: self.cursor.logical_pos, selection: self.selection });
+        self.set_cursor_internal(cursor);
+        self.last_history_type = HistoryType::Other;
+        self.set_selection(None);
+        self.carets_normalize();
+        self.make_cursor_visible();
+    }
+
+    /// If the cursor has no selection, this selects the word under it.
+    /// Otherwise, it adds a new cursor that selects the next occurrence of the
+    /// selected text, wrapping around at the end of the document (= Ctrl+D).
+    /// The new cursor becomes the primary one.
+    pub fn add_caret_next_occurrence(&mut self) {
+        let Some((beg, end)) = self.selection_range_internal(false) else {
+            self.for_each_caret(|tb| {
+                if !tb.has_selection() {
+                    tb.select_word();
+                }
+            });
+            return;
+        };
+
+        let mut needle = Vec::new();
+        self.buffer.extract_raw(beg.offset..end.offset, &mut needle, 0);
+
+        // Occurrences that already have a cursor are skipped.
+        let scratch = scratch_arena(None);
+        let mut taken = Vec::new_in(&*scratch);
+        let mut cursor = self.cursor;
+        for c in &self.carets {
+            cursor = self.cursor_move_to_logical_internal(cursor, c.range()[0]);
+            taken.push(cursor.offset);
+        }
+
+        let mut off = end.offset;
+        let hit = loop {
+            let Some(hit) =
+                self.find_literal(&needle, off).or_else(|| self.find_literal(&needle, 0))
+            else {
+                return;
+            };
+            if hit == beg.offset {
+                // We went all the way around. Every occurrence has a cursor already.
+                return;
+            }
+            if !taken.contains(&hit) {
+                break hit;
+            }
+            off = hit + 1;
+        };
+
+        let beg = self.cursor_move_to_offset_internal(self.cursor, hit);
+        let end = self.cursor_move_to_offset_internal(beg, hit + needle.len());
+        self.carets
+            .push(TextBufferCaret { pos: self.cursor.logical_pos, selection: self.selection });
+        self.set_cursor_internal(end);
+        self.last_history_type = HistoryType::Other;
+        self.set_selection(Some(TextBufferSelection {
+            beg: beg.logical_pos,
+            end: end.logical_pos,
+        }));
+        self.carets_normalize();
+        self.make_cursor_visible();
+    }
+
+    /// Runs `f` once for every cursor, starting with the one at the bottom of the document.
+    ///
+    /// Inside `f` the buffer behaves as if the respective cursor (and its selection) were
+    /// the only one. All edits made by `f` are grouped into a single undo step.
+    pub fn for_each_caret(&mut self, mut f: impl FnMut(&mut Self)) {
+        if self.carets.is_empty() {
+            f(self);
+            return;
+        }
+
+        struct CaretOffsets {
+            cursor: usize,
+            selection: Option<(usize, usize)>,
+            primary: bool,
+        }
+
+        self.edit_begin_grouping();
+
+        let scratch = scratch_arena(None);
+        let mut carets = Vec::new_in(&*scratch);
+        carets.extend(self.carets.drain(..).map(|c| (c, false)));
+        carets.push((
+            TextBufferCaret { pos: self.cursor.logical_pos, selection: self.selection },
+            true,
+        ));
+        // Going bottom-up means that edits don't shift the cursors that are yet to be processed.
+        carets.sort_unstable_by(|(a, _), (b, _)| b.range()[0].cmp(&a.range()[0]));
+
+        let mut results = Vec::with_capacity_in(carets.len(), &*scratch);
+        // Everything past this offset may have been modified by a previous call to `f`.
+        let mut limit = usize::MAX;
+
+        for &(caret, primary) in &carets {
+            let mut beg = self.cursor_move_to_logical_internal(self.cursor, caret.range()[0]);
+            if beg.offset > limit {
+                beg = self.cursor_move_to_offset_internal(beg, limit);
+            }
+            let mut cursor = self.cursor_move_to_logical_internal(beg, caret.pos);
+            if cursor.offset > limit {
+                cursor = self.cursor_move_to_offset_internal(cursor, limit);
+            }
+
+            self.set_cursor_internal(cursor);
+            self.last_history_type = HistoryType::Other;
+            self.set_selection(caret.selection);
+
+            let length_before = self.text_length();
+            f(self);
+            let delta = self.text_length() as isize - length_before as isize;
+
+            // The cursors below this one need to be shifted by however much text `f` added or removed.
+            let modified = beg.offset.min(self.cursor.offset);
+            for r in &mut results {
+                let shift = |off: usize| off.saturating_add_signed(delta).max(modified);
+                let r: &mut CaretOffsets = r;
+                r.cursor = shift(r.cursor);
+                r.selection = r.selection.map(|(b, e)| (shift(b), shift(e)));
+            }
+
+            let selection = self.selection.map(|s| {
+                let b = self.cursor_move_to_logical_internal(self.cursor, s.beg);
+                let e = self.cursor_move_to_logical_internal(self.cursor, s.end);
+                (b.offset, e.offset)
+            });
+            results.push(CaretOffsets { cursor: self.cursor.offset, selection, primary });
+            limit = limit.min(modified);
+        }
+
+        // Turn the offsets back into logical positions, going top-down.
+        let mut cursor = self.cursor;
+        let mut primary = None;
+        for r in results.iter().rev() {
+            let selection = r.selection.map(|(b, e)| {
+                let b = self.cursor_move_to_offset_internal(cursor, b);
+                let e = self.cursor_move_to_offset_internal(b, e);
+                TextBufferSelection { beg: b.logical_pos, end: e.logical_pos }
+            });
+            cursor = self.cursor_move_to_offset_internal(cursor, r.cursor);
+
+            if r.primary {
+                primary = Some((cursor, selection));
+            } else {
+                self.carets.push(TextBufferCaret { pos: cursor.logical_pos, selection });
+            }
+        }
+
+        if let Some((cursor, selection)) = primary {
+            self.set_cursor_internal(cursor);
+            self.set_selection(selection);
+        }
+
+        self.carets_normalize();
+        self.last_history_type = HistoryType::Other;
+        self.edit_end_grouping();
+    }
+
+    /// Sorts the secondary cursors and merges those that overlap.
+    fn carets_normalize(&mut self) {
+        if self.carets.is_empty() {
+            return;
+        }
+
+        let primary = TextBufferCaret { pos: self.cursor.logical_pos, selection: self.selection };
+        let mut carets: Vec<_> = self.carets.drain(..).map(|c| (c, false)).collect();
+        carets.push((primary, true));
+        carets.sort_unstable_by_key(|(c, _)| c.range()[0]);
+
+        let mut merged: Vec<(TextBufferCaret, bool)> = Vec::with_capacity(carets.len());
+        let mut primary_merged = false;
+
+        for (c, is_primary) in carets {
+            if let Some((last, last_is_primary)) = merged.last_mut() {
+                let [last_beg, last_end] = last.range();
+                let [beg, end] = c.range();
+
+                if beg == last_beg || beg < last_end {
+                    let end = end.max(last_end);
+                    last.pos = end;
+                    last.selection = if last_beg < end {
+                        Some(TextBufferSelection { beg: last_beg, end })
+                    } else {
+                        None
+                    };
+                    primary_merged |= is_primary || *last_is_primary;
+                    *last_is_primary |= is_primary;
+                    continue;
+                }
+            }
+
+            merged.push((c, is_primary));
+        }
+
+        let idx = merged.iter().position(|&(_, is_primary)| is_primary).unwrap_or(0);
+        let (primary, _) = merged.remove(idx);
+        self.carets.extend(merged.into_iter().map(|(c, _)| c));
+
+        if primary_merged {
+            let cursor = self.cursor_move_to_logical_internal(self.cursor, primary.pos);
+            self.set_cursor_internal(cursor);
+            self.set_selection(primary.selection);
+        }
+    }
+
     /// Find the next occurrence of the given `pattern` and select it.
     pub fn find_and_select(&mut self, pattern: &str, options: SearchOptions) -> apperr::Result<()> {
+        let scope = self.find_scope(options);
+
         if let Some(search) = &mut self.search {
             let search = search.get_mut();
-            // When the search input changes we must reset the search.
-            if search.pattern != pattern || search.options != options {
+            // When the search input or the selection it's restricted to changes we must reset the search.
+            if search.pattern != pattern
+                || search.options != options
+                || (options.in_selection && search.scope != scope)
+            {
                 self.search = None;
             }
 
@@ -1103,7 +1697,8 @@ impl TextBuffer {
         let search = match &self.search {
             Some(search) => unsafe { &mut *search.get() },
             None => {
-                let search = self.find_construct_search(pattern, options)?;
+                let mut search = self.find_construct_search(pattern, options)?;
+                search.scope = scope;
                 self.search = Some(UnsafeCell::new(search));
                 unsafe { &mut *self.search.as_ref().unwrap().get() }
             }
@@ -1148,7 +1743,13 @@ impl TextBuffer {
                     Self::find_parse_replacement(&scratch, &mut *search, replacement);
                 let replacement =
                     self.find_fill_replacement(&mut *search, replacement, &parsed_replacements);
+                // This moves the scope of the search, too.
                 self.write(&replacement, self.cursor, true);
+
+                // Replacing the hit isn't a selection by the user, which would reset the search.
+                if let Some(search) = &mut self.search {
+                    search.get_mut().selection_generation = self.selection_generation;
+                }
             }
         }
 
@@ -1166,22 +1767,88 @@ impl TextBuffer {
         let mut search = self.find_construct_search(pattern, options)?;
         let mut offset = 0;
         let parsed_replacements = Self::find_parse_replacement(&scratch, &mut search, replacement);
+        search.scope = self.find_scope(options);
+
+        // All replacements are undone in a single step.
+        self.edit_begin_grouping();
 
         loop {
             self.find_select_next(&mut search, offset, false);
-            if !self.has_selection() {
+            let Some((beg, end)) = self.selection_range() else {
                 break;
-            }
+            };
 
             let replacement =
                 self.find_fill_replacement(&mut search, replacement, &parsed_replacements);
             self.write(&replacement, self.cursor, true);
+            Self::find_scope_replaced(&mut search, end.offset - beg.offset, replacement.len());
             offset = self.cursor.offset;
         }
 
+        // The hits of the active search are now restricted to the shifted selection, too.
+        if let Some(active) = &mut self.search
+            && search.scope.is_some()
+        {
+            active.get_mut().scope = search.scope;
+        }
+
+        self.edit_end_grouping();
         Ok(())
     }
 
+    /// Finds up to `limit` occurrences of the given `pattern`, without changing the selection.
+    /// If a `replacement` is given, each hit contains what [`TextBuffer::find_and_replace_all`]
+    /// would replace it with.
+    pub fn find_all(
+        &self,
+        pattern: &str,
+        options: SearchOptions,
+        replacement: Option<&[u8]>,
+        limit: usize,
+    ) -> apperr::Result<Vec<SearchHit>> {
+        let scratch = scratch_arena(None);
+        let mut search = self.find_construct_search(pattern, options)?;
+        let parsed_replacements = replacement
+            .map(|r| Self::find_parse_replacement(&scratch, &mut search, r))
+            .unwrap_or_else(|| Vec::new_in(&*scratch));
+        let mut hits = Vec::new();
+        let mut cursor = Cursor::default();
+
+        while hits.len() < limit
+            && let Some(range) = search.matcher.next(&self.buffer)
+        {
+            let beg = self.cursor_move_to_offset_internal(cursor, range.start);
+            let end = self.cursor_move_to_offset_internal(beg, range.end);
+            let line_beg = self.goto_line_start(beg, beg.logical_pos.y);
+            let line_end = self.cursor_move_to_logical_internal(
+                beg,
+                Point { x: CoordType::MAX, y: beg.logical_pos.y },
+            );
+
+            let mut line = Vec::new();
+            self.buffer.extract_raw(line_beg.offset..line_end.offset, &mut line, 0);
+            let line = String::from_utf8_lossy(&line).into_owned();
+            let line_range = range.start - line_beg.offset
+                ..range.end.clamp(range.start, line_end.offset) - line_beg.offset;
+
+            let replacement = replacement.map(|r| {
+                let r = self.find_fill_replacement(&mut search, r, &parsed_replacements);
+                String::from_utf8_lossy(&r).into_owned()
+            });
+
+            hits.push(SearchHit {
+                beg: beg.logical_pos,
+                end: end.logical_pos,
+                line,
+                line_range,
+                replacement,
+            });
+            cursor = end;
+        }
+
+        Ok(hits)
+    }
+
     fn find_construct_search(
         &self,
         pattern: &str,
@@ -1216,77 +1883,304 @@ impl TextBuffer {
             Cow::Borrowed(pattern)
         };
 
-        let mut flags = icu::Regex::MULTILINE;
-        if !options.match_case {
-            flags |= icu::Regex::CASE_INSENSITIVE;
-        }
-        if !options.use_regex && !options.whole_word {
-            flags |= icu::Regex::LITERAL;
-        }
+        let case_insensitive = !options.match_case;
+        let literal = !options.use_regex && !options.whole_word;
+
+        let matcher = if icu::init().is_ok() {
+            let mut flags = icu::Regex::MULTILINE;
+            if case_insensitive {
+                flags |= icu::Regex::CASE_INSENSITIVE;
+            }
+            if literal {
+                flags |= icu::Regex::LITERAL;
+            }
 
-        // Move the start of the search to the start of the selection,
-        // or otherwise to the current cursor position.
+            let text = unsafe { icu::Text::new(self)? };
+            let regex = unsafe { icu::Regex::new(&sanitized_pattern, flags, &text)? };
+            Matcher::Icu { text, regex }
+        } else {
+            // Our own engine is always in multiline mode.
+            let mut flags = 0;
+            if case_insensitive {
+                flags |= regex::Regex::CASE_INSENSITIVE;
+            }
+            if literal {
+                flags |= regex::Regex::LITERAL;
+            }
 
-        let text = unsafe { icu::Text::new(self)? };
-        let regex = unsafe { icu::Regex::new(&sanitized_pattern, flags, &text)? };
+            let regex = regex::Regex::new(&sanitized_pattern, flags)?;
+            Matcher::Native { regex, offset: 0, limit: usize::MAX, last_empty: false }
+        };
 
         Ok(ActiveSearch {
             pattern: pattern.to_string(),
             options,
-            text,
-            regex,
+            matcher,
             buffer_generation: self.buffer.generation(),
             selection_generation: 0,
             next_search_offset: 0,
             no_matches: false,
+            scope: None,
+            matches: Vec::new(),
+            match_lines: Vec::new(),
+            count_cursor: None,
+            matches_generation: None,
         })
     }
 
-    fn find_select_next(&mut self, search: &mut ActiveSearch, offset: usize, wrap: bool) {
-        if search.buffer_generation != self.buffer.generation() {
-            unsafe { search.regex.set_text(&mut search.text, offset) };
-            search.buffer_generation = self.buffer.generation();
-            search.next_search_offset = offset;
-        } else if search.next_search_offset != offset {
-            search.next_search_offset = offset;
-            search.regex.reset(offset);
+    /// Returns the offsets of the selection that a search with [`SearchOptions::in_selection`]
+    /// is restricted to. If the selection is a hit of the active search, its scope is kept,
+    /// because typing the pattern would otherwise restrict the search to the previous hit.
+    fn find_scope(&self, options: SearchOptions) -> Option<Range<usize>> {
+        if !options.in_selection {
+            return None;
         }
 
-        let mut hit = search.regex.next();
-
-        // If we hit the end of the buffer, and we know that there's something to find,
-        // start the search again from the beginning (= wrap around).
-        if wrap && hit.is_none() && search.next_search_offset != 0 {
-            search.next_search_offset = 0;
-            search.regex.reset(0);
-            hit = search.regex.next();
+        if let Some(search) = &self.search {
+            let search = unsafe { &*search.get() };
+            if search.scope.is_some() && search.selection_generation == self.selection_generation {
+                return search.scope.clone();
+            }
         }
 
-        search.selection_generation = if let Some(range) = hit {
-            // Now the search offset is no more at the start of the buffer.
-            search.next_search_offset = range.end;
+        self.selection_range().map(|(beg, end)| beg.offset..end.offset)
+    }
 
-            let beg = self.cursor_move_to_offset_internal(self.cursor, range.start);
-            let end = self.cursor_move_to_offset_internal(beg, range.end);
+    /// Moves the end of the `search` scope after a hit of `hit_len` bytes
+    /// was replaced with `replacement_len` bytes.
+    fn find_scope_replaced(search: &mut ActiveSearch, hit_len: usize, replacement_len: usize) {
+        if let Some(scope) = &mut search.scope {
+            scope.end = (scope.end + replacement_len).saturating_sub(hit_len);
+        }
+    }
 
-            unsafe { self.set_cursor(end) };
-            self.make_cursor_visible();
+    /// Moves the search scope after `len` bytes were written at `off`.
+    /// Text written at either edge of the scope ends up inside of it.
+    fn find_scope_written(&mut self, off: usize, len: usize) {
+        if let Some(search) = &mut self.search
+            && let Some(scope) = &mut search.get_mut().scope
+        {
+            if scope.start > off {
+                scope.start += len;
+            }
+            if scope.end >= off {
+                scope.end += len;
+            }
+        }
+    }
 
-            self.set_selection(Some(TextBufferSelection {
-                beg: beg.logical_pos,
-                end: end.logical_pos,
-            }))
-        } else {
-            // Avoid searching through the entire document again if we know there's nothing to find.
-            search.no_matches = true;
-            self.set_selection(None)
-        };
+    /// Moves the search scope after the bytes in `range` were deleted.
+    fn find_scope_deleted(&mut self, range: Range<usize>) {
+        if let Some(search) = &mut self.search
+            && let Some(scope) = &mut search.get_mut().scope
+        {
+            let count = range.len();
+            let shift = |o: usize| if o >= range.end { o - count } else { o.min(range.start) };
+            *scope = shift(scope.start)..shift(scope.end);
+        }
     }
 
-    fn find_parse_replacement<'a>(
-        arena: &'a Arena,
-        search: &mut ActiveSearch,
-        replacement: &[u8],
+    /// Stops highlighting the hits of the last search.
+    pub fn find_clear(&mut self) {
+        self.search = None;
+    }
+
+    /// Returns how many hits of the active search [`TextBuffer::find_count_step`] counted so far,
+    /// and which of them is selected.
+    pub fn find_match_count(&mut self) -> Option<SearchMatchCount> {
+        let mut search = self.search.take()?;
+        let s = search.get_mut();
+        self.find_restart_count(s);
+
+        let mut current = None;
+        if self.selection.is_some() && s.selection_generation == self.selection_generation {
+            // The selected hit is the last one found, which ends where the next search starts.
+            current = s.matches.binary_search_by_key(&s.next_search_offset, |r| r.end).ok();
+        }
+        let res = Some(SearchMatchCount {
+            current: current.map(|i| i + 1),
+            total: s.matches.len(),
+            incomplete: s.count_cursor.is_some() || s.matches.len() >= MAX_SEARCH_MATCHES,
+        });
+
+        self.search = Some(search);
+        res
+    }
+
+    /// Returns the visual lines with hits of the active search counted so far,
+    /// for marking them on the scrollbar.
+    pub fn find_match_lines(&mut self) -> &[CoordType] {
+        match &mut self.search {
+            Some(search) => &search.get_mut().match_lines,
+            None => &[],
+        }
+    }
+
+    /// Returns true if the hits of the active search still need to be counted
+    /// with [`TextBuffer::find_count_step`].
+    pub fn find_is_counting(&mut self) -> bool {
+        let generation = self.buffer.generation();
+        self.search.as_mut().is_some_and(|search| {
+            let s = search.get_mut();
+            s.matches_generation != Some(generation) || s.count_cursor.is_some()
+        })
+    }
+
+    /// Counts the hits of the active search for about `budget`, continuing where the last call
+    /// stopped. This keeps large documents responsive, as it's called between inputs.
+    pub fn find_count_step(&mut self, budget: Duration) {
+        let Some(mut search) = self.search.take() else {
+            return;
+        };
+        let s = search.get_mut();
+        self.find_restart_count(s);
+
+        if let Some(mut cursor) = s.count_cursor.take() {
+            let start = Instant::now();
+            let scope = s.scope.clone().unwrap_or(0..usize::MAX);
+            s.matcher.reset(cursor.offset);
+
+            while let Some(range) = s.matcher.next(&self.buffer)
+                && range.end <= scope.end
+            {
+                cursor = self.cursor_move_to_offset_internal(cursor, range.start);
+                let line = self.fold_row_from_layout(cursor.visual_pos.y);
+                if s.match_lines.last() != Some(&line) {
+                    s.match_lines.push(line);
+                }
+                s.matches.push(range);
+
+                if s.matches.len() >= MAX_SEARCH_MATCHES {
+                    break;
+                }
+                if start.elapsed() >= budget {
+                    // Continue after the hit. Empty ones would otherwise be found again.
+                    let range = s.matches.last().unwrap();
+                    let next = if range.is_empty() {
+                        self.cursor_move_delta_internal(cursor, CursorMovement::Grapheme, 1)
+                    } else {
+                        self.cursor_move_to_offset_internal(cursor, range.end)
+                    };
+                    // An empty hit at the end of the buffer is the last one.
+                    if next.offset > range.start || !range.is_empty() {
+                        s.count_cursor = Some(next);
+                    }
+                    break;
+                }
+            }
+
+            // Continue the next `find_select_next` where the last one stopped.
+            s.matcher.reset(s.next_search_offset);
+        }
+
+        self.search = Some(search);
+    }
+
+    /// Starts counting the hits of the `search` from scratch if the buffer changed.
+    fn find_restart_count(&self, search: &mut ActiveSearch) {
+        let generation = self.buffer.generation();
+        if search.matches_generation == Some(generation) {
+            return;
+        }
+
+        if search.buffer_generation != generation {
+            search.matcher.set_text(0);
+            search.buffer_generation = generation;
+        }
+
+        let scope = search.scope.clone().unwrap_or(0..usize::MAX);
+        search.matches.clear();
+        search.match_lines.clear();
+        search.count_cursor = Some(self.cursor_move_to_offset_internal(
+            Cursor::default(),
+            scope.start.min(self.text_length()),
+        ));
+        search.next_search_offset = search.next_search_offset.min(self.text_length());
+        search.matcher.reset(search.next_search_offset);
+        search.matches_generation = Some(generation);
+    }
+
+    /// Collects the hits of the `search` between the given offsets, for highlighting them.
+    fn find_collect_matches<'a>(
+        &self,
+        arena: &'a Arena,
+        search: &mut ActiveSearch,
+        range: Range<usize>,
+    ) -> Vec<Range<usize>, &'a Arena> {
+        let mut matches = Vec::new_in(arena);
+
+        if search.buffer_generation != self.buffer.generation() {
+            search.matcher.set_text(0);
+            search.buffer_generation = self.buffer.generation();
+        }
+
+        let scope = search.scope.clone().unwrap_or(0..usize::MAX);
+        let beg = range.start.max(scope.start);
+        let end = range.end.min(scope.end);
+        if beg <= end {
+            search.matcher.set_region(beg..end);
+            while let Some(range) = search.matcher.next(&self.buffer) {
+                if range.start > end {
+                    break;
+                }
+                matches.push(range);
+            }
+        }
+
+        // Continue the next `find_select_next` where the last one stopped.
+        search.next_search_offset = search.next_search_offset.min(self.text_length());
+        search.matcher.reset(search.next_search_offset);
+        matches
+    }
+
+    fn find_select_next(&mut self, search: &mut ActiveSearch, offset: usize, wrap: bool) {
+        let scope = search.scope.clone().unwrap_or(0..usize::MAX);
+        let offset = offset.clamp(scope.start, scope.end);
+
+        if search.buffer_generation != self.buffer.generation() {
+            search.matcher.set_text(offset);
+            search.buffer_generation = self.buffer.generation();
+            search.next_search_offset = offset;
+        } else if search.next_search_offset != offset {
+            search.next_search_offset = offset;
+            search.matcher.reset(offset);
+        }
+
+        let mut hit = search.matcher.next(&self.buffer).filter(|r| r.end <= scope.end);
+
+        // If we hit the end of the buffer, and we know that there's something to find,
+        // start the search again from the beginning (= wrap around).
+        if wrap && hit.is_none() && search.next_search_offset != scope.start {
+            search.next_search_offset = scope.start;
+            search.matcher.reset(scope.start);
+            hit = search.matcher.next(&self.buffer).filter(|r| r.end <= scope.end);
+        }
+
+        search.selection_generation = if let Some(range) = hit {
+            // Now the search offset is no more at the start of the buffer.
+            search.next_search_offset = range.end;
+
+            let beg = self.cursor_move_to_offset_internal(self.cursor, range.start);
+            let end = self.cursor_move_to_offset_internal(beg, range.end);
+
+            unsafe { self.set_cursor(end) };
+            self.make_cursor_visible();
+
+            self.set_selection(Some(TextBufferSelection {
+                beg: beg.logical_pos,
+                end: end.logical_pos,
+            }))
+        } else {
+            // Avoid searching through the entire document again if we know there's nothing to find.
+            search.no_matches = true;
+            self.set_selection(None)
+        };
+    }
+
+    fn find_parse_replacement<'a>(
+        arena: &'a Arena,
+        search: &mut ActiveSearch,
+        replacement: &[u8],
     ) -> Vec<RegexReplacement<'a>, &'a Arena> {
         let mut res = Vec::new_in(arena);
 
@@ -1294,7 +2188,7 @@ impl TextBuffer {
             return res;
         }
 
-        let group_count = search.regex.group_count();
+        let group_count = search.matcher.group_count();
         let mut text = Vec::new_in(arena);
         let mut text_beg = 0;
 
@@ -1396,7 +2290,7 @@ impl TextBuffer {
                 match replacement {
                     RegexReplacement::Text(text) => res.extend_from_slice(text),
                     RegexReplacement::Group(group) => {
-                        if let Some(range) = search.regex.group(*group) {
+                        if let Some(range) = search.matcher.group(*group) {
                             self.buffer.extract_raw(range, &mut res, usize::MAX);
                         }
                     }
@@ -1407,6 +2301,38 @@ impl TextBuffer {
         }
     }
 
+    /// Returns the offset of the next exact occurrence of `needle` at or after `off`.
+    fn find_literal(&self, needle: &[u8], mut off: usize) -> Option<usize> {
+        let &first = needle.first()?;
+
+        loop {
+            let chunk = self.read_forward(off);
+            if chunk.is_empty() {
+                return None;
+            }
+
+            let i = memchr2(first, first, chunk, 0);
+            if i < chunk.len() && self.find_literal_matches_at(off + i, needle) {
+                return Some(off + i);
+            }
+
+            off += (i + 1).min(chunk.len());
+        }
+    }
+
+    fn find_literal_matches_at(&self, mut off: usize, mut needle: &[u8]) -> bool {
+        while !needle.is_empty() {
+            let chunk = self.read_forward(off);
+            let len = chunk.len().min(needle.len());
+            if len == 0 || chunk[..len] != needle[..len] {
+                return false;
+            }
+            off += len;
+            needle = &needle[len..];
+        }
+        true
+    }
+
     fn measurement_config(&self) -> MeasurementConfig<'_> {
         MeasurementConfig::new(&self.buffer)
             .with_word_wrap_column(self.word_wrap_column)
@@ -1414,9 +2340,23 @@ impl TextBuffer {
     }
 
     fn goto_line_start(&self, cursor: Cursor, y: CoordType) -> Cursor {
+        // The cursor we seek and measure from, which has a correct visual position.
+        let mut base = cursor;
         let mut result = cursor;
         let mut seek_to_line_start = true;
 
+        // Skip ahead to the closest cached line start, if that's closer. With word wrap, only
+        // the ones with a measured visual row can be used, which we then measure from.
+        if y > 0 {
+            let (point, row) = self.line_cache.nearest_offset(y, self.word_wrap_column > 0);
+            if y - point.line < (y - cursor.logical_pos.y).abs() {
+                base = Self::cursor_at_cache_point(point, row);
+                result = base;
+                // Cache points are always at the start of a line.
+                seek_to_line_start = false;
+            }
+        }
+
         if y > result.logical_pos.y {
             while y > result.logical_pos.y {
                 let chunk = self.read_forward(result.offset);
@@ -1434,7 +2374,7 @@ impl TextBuffer {
             // line of text without trailing newline. The only way to make sure is to seek
             // backwards to the line start again. But otherwise we can skip that.
             seek_to_line_start =
-                result.offset == self.text_length() && result.offset != cursor.offset;
+                result.offset == self.text_length() && result.offset != base.offset;
         }
 
         if seek_to_line_start {
@@ -1453,7 +2393,7 @@ impl TextBuffer {
             }
         }
 
-        if result.offset == cursor.offset {
+        if result.offset == base.offset {
             return result;
         }
 
@@ -1464,43 +2404,81 @@ impl TextBuffer {
         result.wrap_opp = false;
 
         if self.word_wrap_column > 0 {
-            let upward = result.offset < cursor.offset;
-            let (top, bottom) = if upward { (result, cursor) } else { (cursor, result) };
-
-            let mut bottom_remeasured =
-                self.measurement_config().with_cursor(top).goto_logical(bottom.logical_pos);
-
-            // The second problem is that visual positions can be ambiguous. A single logical position
-            // can map to two visual positions: One at the end of the preceding line in front of
-            // a word wrap, and another at the start of the next line after the same word wrap.
-            //
-            // This, however, only applies if we go upwards, because only then `bottom ≅ cursor`,
-            // and thus only then this `bottom` is ambiguous. Otherwise, `bottom ≅ result`
-            // and `result` is at a line start which is never ambiguous.
-            if upward {
+            if result.offset > base.offset {
+                // Going downwards, we can measure the cache points we pass along the way.
+                let top = self.line_cache_measure(base, result.offset);
+                result.visual_pos.y = self
+                    .measurement_config()
+                    .with_cursor(top)
+                    .goto_logical(result.logical_pos)
+                    .visual_pos
+                    .y;
+            } else {
+                let (top, bottom) = (result, base);
+                let mut bottom_remeasured =
+                    self.measurement_config().with_cursor(top).goto_logical(bottom.logical_pos);
+
+                // The second problem is that visual positions can be ambiguous. A single logical position
+                // can map to two visual positions: One at the end of the preceding line in front of
+                // a word wrap, and another at the start of the next line after the same word wrap.
+                //
+                // This, however, only applies if we go upwards, because only then `bottom ≅ base`,
+                // and thus only then this `bottom` is ambiguous. Otherwise, `bottom ≅ result`
+                // and `result` is at a line start which is never ambiguous.
                 let a = bottom_remeasured.visual_pos.x;
                 let b = bottom.visual_pos.x;
                 bottom_remeasured.visual_pos.y = bottom_remeasured.visual_pos.y
                     + (a != 0 && b == 0) as CoordType
                     - (a == 0 && b != 0) as CoordType;
-            }
 
-            let mut delta = bottom_remeasured.visual_pos.y - top.visual_pos.y;
-            if upward {
-                delta = -delta;
+                let delta = bottom_remeasured.visual_pos.y - top.visual_pos.y;
+                result.visual_pos.y = base.visual_pos.y - delta;
             }
-
-            result.visual_pos.y = cursor.visual_pos.y + delta;
         }
 
         result
     }
 
+    /// Returns a cursor at the line start of a [`LineCache`] point with the given visual `row`.
+    fn cursor_at_cache_point(point: CachePoint, row: CoordType) -> Cursor {
+        Cursor {
+            offset: point.index,
+            logical_pos: Point { x: 0, y: point.line },
+            visual_pos: Point { x: 0, y: row },
+            column: 0,
+            wrap_opp: false,
+        }
+    }
+
+    /// Measures the visual rows of the [`LineCache`] points from `cursor` up to `offset`
+    /// that aren't known yet, so that later seeks can start from them.
+    /// Returns the cursor at the last one of them, or `cursor` if there were none.
+    fn line_cache_measure(&self, mut cursor: Cursor, offset: usize) -> Cursor {
+        while let Some(point) = self.line_cache.next_unmeasured()
+            && point.index >= cursor.offset
+            && point.index <= offset
+        {
+            cursor = self
+                .measurement_config()
+                .with_cursor(cursor)
+                .goto_logical(Point { x: 0, y: point.line });
+            self.line_cache.push_measured(cursor.visual_pos.y);
+        }
+        cursor
+    }
+
     fn cursor_move_to_offset_internal(&self, mut cursor: Cursor, offset: usize) -> Cursor {
         if offset == cursor.offset {
             return cursor;
         }
 
+        // Start from the closest cached line start before the target, if that's closer.
+        // With word wrap, only the ones with a measured visual row can be used.
+        let (point, row) = self.line_cache.nearest_before(offset, self.word_wrap_column > 0);
+        if offset - point.index < offset.abs_diff(cursor.offset) {
+            cursor = Self::cursor_at_cache_point(point, row);
+        }
+
         // goto_line_start() is fast for seeking across lines _if_ line wrapping is disabled.
         // For backward seeking we have to use it either way, so we're covered there.
         // This implements the forward seeking portion, if it's approx. worth doing so.
@@ -1523,6 +2501,10 @@ impl TextBuffer {
             cursor = self.goto_line_start(cursor, cursor.logical_pos.y - 1);
         }
 
+        if self.word_wrap_column > 0 {
+            cursor = self.line_cache_measure(cursor, offset);
+        }
+
         self.measurement_config().with_cursor(cursor).goto_offset(offset)
     }
 
@@ -1570,6 +2552,21 @@ impl TextBuffer {
         self.measurement_config().with_cursor(cursor).goto_visual(pos)
     }
 
+    /// Moves to the visual column `pos.x` on the logical line `pos.y`.
+    /// Unlike [`TextBuffer::cursor_move_to_visual_internal()`], word wrap has no influence on this.
+    fn cursor_move_to_column_internal(&self, cursor: Cursor, pos: Point) -> Cursor {
+        let line = self.goto_line_start(cursor, pos.y.max(0));
+        let mut beg = line;
+        beg.visual_pos = Point { x: 0, y: line.logical_pos.y };
+        beg.column = 0;
+
+        let target = MeasurementConfig::new(&self.buffer)
+            .with_tab_size(self.tab_size)
+            .with_cursor(beg)
+            .goto_visual(Point { x: pos.x, y: line.logical_pos.y });
+        self.cursor_move_to_offset_internal(line, target.offset)
+    }
+
     fn cursor_move_delta_internal(
         &self,
         mut cursor: Cursor,
@@ -1623,7 +2620,7 @@ impl TextBuffer {
             }
             CursorMovement::Word => {
                 let doc = &self.buffer as &dyn ReadableDocument;
-                let mut offset = self.cursor.offset;
+                let mut offset = cursor.offset;
 
                 while delta != 0 {
                     if delta < 0 {
@@ -1653,12 +2650,26 @@ impl TextBuffer {
 
     /// Moves the cursor to the given visual position.
     pub fn cursor_move_to_visual(&mut self, pos: Point) {
+        let pos = Point { x: pos.x, y: self.fold_row_to_layout(pos.y) };
         unsafe { self.set_cursor(self.cursor_move_to_visual_internal(self.cursor, pos)) }
     }
 
     /// Moves the cursor by the given delta.
     pub fn cursor_move_delta(&mut self, granularity: CursorMovement, delta: CoordType) {
-        unsafe { self.set_cursor(self.cursor_move_delta_internal(self.cursor, granularity, delta)) }
+        let mut cursor = self.cursor_move_delta_internal(self.cursor, granularity, delta);
+
+        // Step over folded regions instead of unfolding them.
+        let y = cursor.logical_pos.y;
+        if let Some(fold) = self.folds.iter().find(|f| f.start < y && y < f.end) {
+            let pos = if delta > 0 {
+                Point { x: 0, y: fold.end }
+            } else {
+                Point { x: CoordType::MAX, y: fold.start }
+            };
+            cursor = self.cursor_move_to_logical_internal(cursor, pos);
+        }
+
+        unsafe { self.set_cursor(cursor) }
     }
 
     /// Sets the cursor to the given position, and clears the selection.
@@ -1669,7 +2680,10 @@ impl TextBuffer {
     /// that the TextBuffer has not been modified since you received the cursor from this class.
     pub unsafe fn set_cursor(&mut self, cursor: Cursor) {
         self.set_cursor_internal(cursor);
+        self.auto_closed_prune();
         self.last_history_type = HistoryType::Other;
+        self.carets.clear();
+        self.block_selection = None;
         self.set_selection(None);
     }
 
@@ -1680,7 +2694,10 @@ impl TextBuffer {
         };
 
         self.set_cursor_internal(cursor);
+        self.auto_closed_prune();
         self.last_history_type = HistoryType::Other;
+        self.carets.clear();
+        self.block_selection = None;
 
         let end = self.cursor.logical_pos;
         self.set_selection(if beg == end { None } else { Some(TextBufferSelection { beg, end }) });
@@ -1698,6 +2715,254 @@ impl TextBuffer {
                 && cursor.visual_pos.y <= self.stats.visual_lines
         );
         self.cursor = cursor;
+        self.folds_reveal(cursor.logical_pos.y);
+    }
+
+    /// Forgets the auto-closed pairs that the caret has left.
+    fn auto_closed_prune(&mut self) {
+        let off = self.cursor.offset;
+        self.auto_closed.retain(|pair| pair.start < off && off <= pair.end);
+    }
+
+    /// Moves the cursor to the partner of the bracket at or before the cursor.
+    pub fn jump_to_matching_bracket(&mut self) {
+        if let Some([_, partner]) = self.find_bracket_pair(usize::MAX) {
+            self.cursor_move_to_offset(partner);
+            self.make_cursor_visible();
+        }
+    }
+
+    /// Selects the text from the bracket at or before the cursor up to and including its partner.
+    pub fn select_to_matching_bracket(&mut self) {
+        if let Some(pair) = self.find_bracket_pair(usize::MAX) {
+            let [beg, end] = minmax(pair[0], pair[1]);
+            self.cursor_move_to_offset(beg);
+            self.selection_update_offset(end + 1);
+            self.make_cursor_visible();
+        }
+    }
+
+    /// Returns the offsets of the bracket at the cursor and its partner, for highlighting them.
+    /// Unlike [`TextBuffer::find_bracket_pair`], it's cached and bounded by
+    /// [`BRACKET_SEARCH_DISTANCE`], because it runs whenever the cursor moves.
+    fn bracket_pair(&mut self) -> Option<[usize; 2]> {
+        let generation = self.buffer.generation();
+        let off = self.cursor.offset;
+        if let Some((g, o, pair)) = self.bracket_pair
+            && g == generation
+            && o == off
+        {
+            return pair;
+        }
+
+        let pair = self.find_bracket_pair(BRACKET_SEARCH_DISTANCE);
+        self.bracket_pair = Some((generation, off, pair));
+        pair
+    }
+
+    /// Returns the offsets of the bracket at the cursor and its partner,
+    /// if it's at most `max_distance` bytes away.
+    /// If the cursor isn't in front of a bracket with a partner, the one before it is used.
+    fn find_bracket_pair(&self, max_distance: usize) -> Option<[usize; 2]> {
+        let off = self.cursor.offset;
+        [off, off.wrapping_sub(1)]
+            .into_iter()
+            .filter(|&o| o < self.text_length())
+            .find_map(|o| Some([o, brackets::find_partner_within(&self.buffer, o, max_distance)?]))
+    }
+
+    /// Sets which kinds of regions can be folded, in addition to indented blocks.
+    pub fn set_fold_options(&mut self, options: FoldOptions) {
+        self.fold_options = options;
+    }
+
+    /// Folds the innermost unfolded region that contains the cursor.
+    pub fn fold_at_cursor(&mut self) {
+        let cursor_y = self.cursor.logical_pos.y;
+
+        for y in (0..=cursor_y).rev() {
+            if let Some(region) = self.fold_region_at(y)
+                && cursor_y < region.end
+                && !self.folds.contains(&region)
+            {
+                if cursor_y != y {
+                    self.cursor_move_to_logical(Point { x: CoordType::MAX, y });
+                }
+                self.folds_insert(region);
+                self.folds_update_rows();
+                self.make_cursor_visible();
+                return;
+            }
+        }
+    }
+
+    /// Unfolds the regions on the cursor line.
+    pub fn unfold_at_cursor(&mut self) {
+        let y = self.cursor.logical_pos.y;
+        let len = self.folds.len();
+        self.folds.retain(|f| f.start != y);
+        if self.folds.len() != len {
+            self.folds_update_rows();
+            self.make_cursor_visible();
+        }
+    }
+
+    /// Folds all regions in the document.
+    pub fn fold_all(&mut self) {
+        let folds = folding::all_regions(&self.buffer, self.tab_size, self.fold_options);
+
+        // Move the cursor out of the folds, onto the header of the outermost one around it.
+        let cursor_y = self.cursor.logical_pos.y;
+        if let Some(fold) = folds.iter().find(|f| f.start < cursor_y && cursor_y < f.end) {
+            self.cursor_move_to_logical(Point { x: CoordType::MAX, y: fold.start });
+        }
+
+        self.folds = folds;
+        self.folds_update_rows();
+        self.make_cursor_visible();
+    }
+
+    /// Unfolds all regions in the document.
+    pub fn unfold_all(&mut self) {
+        if !self.folds.is_empty() {
+            self.folds.clear();
+            self.folds_update_rows();
+            self.make_cursor_visible();
+        }
+    }
+
+    /// Folds or unfolds the region on the given visual line, e.g. when its toggle in the margin was clicked.
+    pub fn fold_toggle_visual(&mut self, visual_y: CoordType) {
+        let pos = Point { x: 0, y: self.fold_row_to_layout(visual_y) };
+        let y = self.cursor_move_to_visual_internal(self.cursor, pos).logical_pos.y;
+
+        let len = self.folds.len();
+        self.folds.retain(|f| f.start != y);
+        if self.folds.len() == len {
+            let Some(region) = self.fold_region_at(y) else {
+                return;
+            };
+
+            let cursor_y = self.cursor.logical_pos.y;
+            if region.start < cursor_y && cursor_y < region.end {
+                self.cursor_move_to_logical(Point { x: CoordType::MAX, y });
+            }
+            self.folds_insert(region);
+        }
+
+        self.folds_update_rows();
+    }
+
+    /// Returns the foldable region whose header is the logical line `y`, if any.
+    fn fold_region_at(&self, y: CoordType) -> Option<Range<CoordType>> {
+        let line = self.goto_line_start(self.cursor, y);
+        if line.logical_pos.y != y {
+            return None;
+        }
+        let len = folding::region_len(&self.buffer, line.offset, self.tab_size, self.fold_options);
+        (len > 0).then(|| y..y + len + 1)
+    }
+
+    fn folds_insert(&mut self, region: Range<CoordType>) {
+        let i = self.folds.partition_point(|f| f.start <= region.start);
+        self.folds.insert(i, region);
+    }
+
+    /// Unfolds the regions that hide the logical line `y`.
+    fn folds_reveal(&mut self, y: CoordType) {
+        if self.folds.iter().any(|f| f.start < y && y < f.end) {
+            self.folds.retain(|f| !(f.start < y && y < f.end));
+            self.folds_update_rows();
+        }
+    }
+
+    /// Adjusts the folds after `removed` lines were replaced with `added` ones, starting at line `y`.
+    /// Regions that were edited into get unfolded.
+    fn folds_edited(&mut self, y: CoordType, removed: CoordType, added: CoordType) {
+        if self.folds.is_empty() {
+            return;
+        }
+
+        self.folds.retain_mut(|f| {
+            let touched = y < f.end && y + removed >= f.start;
+            let header_only = y == f.start && removed == 0 && added == 0;
+            if touched && !header_only {
+                return false;
+            }
+            if y < f.start {
+                f.start += added - removed;
+                f.end += added - removed;
+            }
+            true
+        });
+    }
+
+    /// Recomputes `fold_rows` from `folds`.
+    fn folds_update_rows(&mut self) {
+        let mut rows = mem::take(&mut self.fold_rows);
+        rows.clear();
+
+        // The cached cursor and the visual lines of the search hits may now be in the wrong place.
+        self.cursor_for_rendering = None;
+        if let Some(search) = &mut self.search {
+            search.get_mut().matches_generation = None;
+        }
+
+        let mut cursor = Cursor::default();
+        let mut row = |y: CoordType| {
+            if self.word_wrap_column <= 0 {
+                y
+            } else if y >= self.stats.logical_lines {
+                self.stats.visual_lines
+            } else {
+                cursor = self.cursor_move_to_logical_internal(cursor, Point { x: 0, y });
+                cursor.visual_pos.y
+            }
+        };
+
+        for f in &self.folds {
+            let beg = f.start + 1;
+            let end = f.end.min(self.stats.logical_lines);
+            match rows.last_mut() {
+                // Nested regions have already been hidden by their parent.
+                Some(last) if beg <= last.end => last.end = last.end.max(end),
+                _ if beg < end => rows.push(beg..end),
+                _ => {}
+            }
+        }
+
+        for r in &mut rows {
+            *r = row(r.start)..row(r.end);
+        }
+
+        self.fold_rows = rows;
+    }
+
+    /// Converts a visual line (with folded regions removed) into a layout row.
+    fn fold_row_to_layout(&self, mut y: CoordType) -> CoordType {
+        for r in &self.fold_rows {
+            if r.start > y {
+                break;
+            }
+            y += r.end - r.start;
+        }
+        y
+    }
+
+    /// Converts a layout row into a visual line (with folded regions removed).
+    /// Hidden rows map to the last row of their region's header.
+    fn fold_row_from_layout(&self, y: CoordType) -> CoordType {
+        let mut hidden = 0;
+        for r in &self.fold_rows {
+            if y < r.start {
+                break;
+            }
+            if y < r.end {
+                return r.start - 1 - hidden;
+            }
+            hidden += r.end - r.start;
+        }
+        y - hidden
     }
 
     /// Extracts a rectangular region of the text buffer and writes it to the framebuffer.
@@ -1727,22 +2992,66 @@ impl TextBuffer {
         let mut cursor = {
             let a = self.cursor;
             let b = self.cursor_for_rendering.unwrap_or_default();
-            let da = (a.visual_pos.y - origin.y).abs();
-            let db = (b.visual_pos.y - origin.y).abs();
+            let origin_y = self.fold_row_to_layout(origin.y);
+            let da = (a.visual_pos.y - origin_y).abs();
+            let db = (b.visual_pos.y - origin_y).abs();
             if da < db { a } else { b }
         };
 
-        let [selection_beg, selection_end] = match self.selection {
-            None => [Point::MIN, Point::MIN],
-            Some(TextBufferSelection { beg, end }) => minmax(beg, end),
+        // The ranges of all selections, sorted by their start.
+        let mut selections = Vec::new_in(&*scratch);
+        selections.extend(self.selection.map(|s| minmax(s.beg, s.end)));
+        selections
+            .extend(self.carets.iter().filter_map(|c| c.selection.map(|s| minmax(s.beg, s.end))));
+        selections.sort_unstable_by_key(|r: &[Point; 2]| r[0]);
+
+        let selection_bg = {
+            let mut bg = fb.indexed(IndexedColor::Foreground).oklab_blend(fb.indexed_alpha(
+                IndexedColor::BrightBlue,
+                1,
+                2,
+            ));
+            if !focused {
+                bg = bg.oklab_blend(fb.indexed_alpha(IndexedColor::Background, 1, 2));
+            };
+            bg
         };
+        let selection_fg = fb.contrasted(selection_bg);
+        let match_bg = fb.indexed_alpha(IndexedColor::Yellow, 1, 2);
+        let mut selection_offs: Vec<Range<usize>, _> = Vec::new_in(&*scratch);
+        let mut highlight_line_y = -1;
+        let mut highlight_line_off = 0;
 
         line.reserve(width as usize * 2);
 
+        // The hits of the active search are highlighted. Only the lines in view are searched,
+        // from the start of the first to the end of the last one, so that hits crossing
+        // the edges of the view are found as well.
+        let mut matches = Vec::new_in(&*scratch);
+        if let Some(mut search) = self.search.take() {
+            let first = self.cursor_move_to_visual_internal(
+                cursor,
+                Point { x: 0, y: self.fold_row_to_layout(origin.y) },
+            );
+            let first = self.goto_line_start(first, first.logical_pos.y);
+            let last = self.cursor_move_to_visual_internal(
+                first,
+                Point { x: 0, y: self.fold_row_to_layout(origin.y + height - 1) },
+            );
+            let last = self.cursor_move_to_logical_internal(
+                last,
+                Point { x: COORD_TYPE_SAFE_MAX, y: last.logical_pos.y },
+            );
+            matches =
+                self.find_collect_matches(&scratch, search.get_mut(), first.offset..last.offset);
+            self.search = Some(search);
+        }
+
         for y in 0..height {
             line.clear();
 
-            let visual_line = origin.y + y;
+            // The layout row, which skips the folded regions.
+            let visual_line = self.fold_row_to_layout(origin.y + y);
             let mut cursor_beg =
                 self.cursor_move_to_visual_internal(cursor, Point { x: origin.x, y: visual_line });
             let cursor_end = self.cursor_move_to_visual_internal(
@@ -1765,8 +3074,25 @@ impl TextBuffer {
                     unsafe { std::hint::assert_unchecked(off < MARGIN_TEMPLATE.len()) };
                     line.push_str(&MARGIN_TEMPLATE[off..]);
                 } else if self.word_wrap_column <= 0 || cursor_beg.logical_pos.x == 0 {
-                    // Regular line? Place "123 | " in the margin.
-                    _ = write!(line, "{:1$} │ ", cursor_beg.logical_pos.y + 1, line_number_width);
+                    // Regular line? Place "123 | " in the margin, with a fold toggle in front of the "|".
+                    let line_y = cursor_beg.logical_pos.y;
+                    let toggle = if self.folds.iter().any(|f| f.start == line_y) {
+                        "▸"
+                    } else {
+                        let line_off = if cursor_beg.logical_pos.x == 0 {
+                            cursor_beg.offset
+                        } else {
+                            self.goto_line_start(cursor_beg, line_y).offset
+                        };
+                        let foldable = folding::is_region_start(
+                            &self.buffer,
+                            line_off,
+                            self.tab_size,
+                            self.fold_options,
+                        );
+                        if foldable { "▾" } else { " " }
+                    };
+                    _ = write!(line, "{:2$}{}│ ", line_y + 1, toggle, line_number_width);
                 } else {
                     // Wrapped line? Place " ... | " in the margin.
                     let number_width = (cursor_beg.logical_pos.y + 1).ilog10() as usize + 1;
@@ -1792,59 +3118,132 @@ impl TextBuffer {
                 }
             }
 
-            let mut selection_off = 0..0;
-
-            // Figure out the selection range on this line, if any.
+            // Color the text according to the syntax highlighting.
+            // It's temporarily taken out of `self` so that we can move cursors while looking at the spans.
             if cursor_beg.visual_pos.y == visual_line
-                && selection_beg <= cursor_end.logical_pos
-                && selection_end >= cursor_beg.logical_pos
+                && cursor_beg.offset != cursor_end.offset
+                && let Some(mut highlighter) = self.highlighter.take()
             {
+                let line_y = cursor_beg.logical_pos.y;
+                if line_y != highlight_line_y {
+                    highlight_line_off = if cursor_beg.logical_pos.x == 0 {
+                        cursor_beg.offset
+                    } else {
+                        self.cursor_move_to_logical_internal(cursor_beg, Point { x: 0, y: line_y })
+                            .offset
+                    };
+                    highlight_line_y = line_y;
+                }
+
+                let spans = highlighter.highlight_line(&self.buffer, line_y, highlight_line_off);
+                let left = destination.left + self.margin_width - origin.x;
+                let top = destination.top + y;
                 let mut cursor = cursor_beg;
 
-                // By default, we assume the entire line is selected.
-                let mut selection_pos_beg = 0;
-                let mut selection_pos_end = COORD_TYPE_SAFE_MAX;
-                selection_off.start = cursor_beg.offset;
-                selection_off.end = cursor_end.offset;
+                for span in spans {
+                    let beg = highlight_line_off + span.beg;
+                    let end = highlight_line_off + span.end;
+                    if end <= cursor_beg.offset {
+                        continue;
+                    }
+                    if beg >= cursor_end.offset {
+                        break;
+                    }
 
-                // The start of the selection is within this line. We need to update selection_beg.
-                if selection_beg <= cursor_end.logical_pos
-                    && selection_beg >= cursor_beg.logical_pos
-                {
-                    cursor = self.cursor_move_to_logical_internal(cursor, selection_beg);
-                    selection_off.start = cursor.offset;
-                    selection_pos_beg = cursor.visual_pos.x;
+                    cursor =
+                        self.cursor_move_to_offset_internal(cursor, beg.max(cursor_beg.offset));
+                    let x_beg = cursor.visual_pos.x;
+                    cursor =
+                        self.cursor_move_to_offset_internal(cursor, end.min(cursor_end.offset));
+                    let x_end = cursor.visual_pos.x;
+
+                    let rect = Rect {
+                        left: left + x_beg.max(origin.x),
+                        top,
+                        right: left + x_end.min(origin.x + text_width),
+                        bottom: top + 1,
+                    };
+                    fb.blend_fg(rect, fb.indexed(span.kind.color()));
                 }
 
-                // The end of the selection is within this line. We need to update selection_end.
-                if selection_end <= cursor_end.logical_pos
-                    && selection_end >= cursor_beg.logical_pos
-                {
-                    cursor = self.cursor_move_to_logical_internal(cursor, selection_end);
-                    selection_off.end = cursor.offset;
-                    selection_pos_end = cursor.visual_pos.x;
-                }
+                self.highlighter = Some(highlighter);
+            }
 
+            // Highlight the search hits on this line.
+            if cursor_beg.visual_pos.y == visual_line && cursor_beg.offset != cursor_end.offset {
                 let left = destination.left + self.margin_width - origin.x;
                 let top = destination.top + y;
-                let rect = Rect {
-                    left: left + selection_pos_beg.max(origin.x),
-                    top,
-                    right: left + selection_pos_end.min(origin.x + text_width),
-                    bottom: top + 1,
-                };
+                let mut cursor = cursor_beg;
+                let first = matches.partition_point(|r| r.end <= cursor_beg.offset);
 
-                let mut bg = fb.indexed(IndexedColor::Foreground).oklab_blend(fb.indexed_alpha(
-                    IndexedColor::BrightBlue,
-                    1,
-                    2,
-                ));
-                if !focused {
-                    bg = bg.oklab_blend(fb.indexed_alpha(IndexedColor::Background, 1, 2));
-                };
-                let fg = fb.contrasted(bg);
-                fb.blend_bg(rect, bg);
-                fb.blend_fg(rect, fg);
+                for r in &matches[first..] {
+                    if r.start >= cursor_end.offset {
+                        break;
+                    }
+
+                    cursor =
+                        self.cursor_move_to_offset_internal(cursor, r.start.max(cursor_beg.offset));
+                    let x_beg = cursor.visual_pos.x;
+                    cursor =
+                        self.cursor_move_to_offset_internal(cursor, r.end.min(cursor_end.offset));
+                    let x_end = cursor.visual_pos.x;
+
+                    let rect = Rect {
+                        left: left + x_beg.max(origin.x),
+                        top,
+                        right: left + x_end.min(origin.x + text_width),
+                        bottom: top + 1,
+                    };
+                    fb.blend_bg(rect, match_bg);
+                }
+            }
+
+            selection_offs.clear();
+
+            // Figure out the selection ranges on this line, if any.
+            if cursor_beg.visual_pos.y == visual_line {
+                let mut cursor = cursor_beg;
+
+                for &[selection_beg, selection_end] in &selections {
+                    if selection_beg > cursor_end.logical_pos {
+                        break;
+                    }
+                    if selection_end < cursor_beg.logical_pos {
+                        continue;
+                    }
+
+                    // By default, we assume the entire line is selected.
+                    let mut selection_pos_beg = 0;
+                    let mut selection_pos_end = COORD_TYPE_SAFE_MAX;
+                    let mut selection_off = cursor_beg.offset..cursor_end.offset;
+
+                    // The start of the selection is within this line. We need to update selection_beg.
+                    if selection_beg >= cursor_beg.logical_pos {
+                        cursor = self.cursor_move_to_logical_internal(cursor, selection_beg);
+                        selection_off.start = cursor.offset;
+                        selection_pos_beg = cursor.visual_pos.x;
+                    }
+
+                    // The end of the selection is within this line. We need to update selection_end.
+                    if selection_end <= cursor_end.logical_pos {
+                        cursor = self.cursor_move_to_logical_internal(cursor, selection_end);
+                        selection_off.end = cursor.offset;
+                        selection_pos_end = cursor.visual_pos.x;
+                    }
+
+                    let left = destination.left + self.margin_width - origin.x;
+                    let top = destination.top + y;
+                    let rect = Rect {
+                        left: left + selection_pos_beg.max(origin.x),
+                        top,
+                        right: left + selection_pos_end.min(origin.x + text_width),
+                        bottom: top + 1,
+                    };
+
+                    fb.blend_bg(rect, selection_bg);
+                    fb.blend_fg(rect, selection_fg);
+                    selection_offs.push(selection_off);
+                }
             }
 
             // Nothing to do if the entire line is empty.
@@ -1885,7 +3284,7 @@ impl TextBuffer {
 
                         if ch == ' ' || ch == '\t' {
                             let is_tab = ch == '\t';
-                            let visualize = selection_off.contains(&global_off);
+                            let visualize = selection_offs.iter().any(|r| r.contains(&global_off));
                             let mut whitespace = TAB_WHITESPACE;
                             let mut prefix_add = 0;
 
@@ -1915,7 +3314,7 @@ impl TextBuffer {
                                         + self.margin_width
                                         + cursor_line.visual_pos.x
                                         - origin.x;
-                                    let top = destination.top + cursor_line.visual_pos.y - origin.y;
+                                    let top = destination.top + y;
                                     Rect { left, top, right: left + 1, bottom: top + 1 }
                                 };
                                 fb.blend_fg(
@@ -1945,7 +3344,7 @@ impl TextBuffer {
                                 let left =
                                     destination.left + self.margin_width + cursor_line.visual_pos.x
                                         - origin.x;
-                                let top = destination.top + cursor_line.visual_pos.y - origin.y;
+                                let top = destination.top + y;
                                 Rect { left, top, right: left + 1, bottom: top + 1 }
                             };
                             let bg = fb.indexed(IndexedColor::Yellow);
@@ -1963,6 +3362,21 @@ impl TextBuffer {
                 visual_pos_x_max = visual_pos_x_max.max(cursor_end.visual_pos.x);
             }
 
+            // Mark the end of a folded header with a dimmed "⋯".
+            if cursor_end.visual_pos.y == visual_line
+                && self.fold_rows.iter().any(|r| r.start == visual_line + 1)
+                && (origin.x..origin.x + text_width - 2).contains(&cursor_end.visual_pos.x)
+            {
+                let left =
+                    destination.left + self.margin_width + cursor_end.visual_pos.x - origin.x;
+                let top = destination.top + y;
+                line.push_str(" ⋯");
+                fb.blend_fg(
+                    Rect { left, top, right: left + 2, bottom: top + 1 },
+                    fb.indexed_alpha(IndexedColor::Foreground, 1, 2),
+                );
+            }
+
             fb.replace_text(destination.top + y, destination.left, destination.right, &line);
 
             cursor = cursor_end;
@@ -1991,32 +3405,71 @@ impl TextBuffer {
         }
 
         if focused {
-            let mut x = self.cursor.visual_pos.x;
-            let mut y = self.cursor.visual_pos.y;
-
-            if self.word_wrap_column > 0 && x >= self.word_wrap_column {
-                // The line the cursor is on wraps exactly on the word wrap column which
-                // means the cursor is invisible. We need to move it to the next line.
-                x = 0;
-                y += 1;
-            }
-
-            // Move the cursor into screen space.
-            x += destination.left - origin.x + self.margin_width;
-            y += destination.top - origin.y;
-
-            let cursor = Point { x, y };
+            let bracket_pair = self.bracket_pair();
             let text = Rect {
                 left: destination.left + self.margin_width,
                 top: destination.top,
                 right: destination.right,
                 bottom: destination.bottom,
             };
+            let to_screen = |cursor: Cursor| {
+                let mut x = cursor.visual_pos.x;
+                let mut y = self.fold_row_from_layout(cursor.visual_pos.y);
+
+                if self.word_wrap_column > 0 && x >= self.word_wrap_column {
+                    // The line the cursor is on wraps exactly on the word wrap column which
+                    // means the cursor is invisible. We need to move it to the next line.
+                    x = 0;
+                    y += 1;
+                }
+
+                // Move the cursor into screen space.
+                x += destination.left - origin.x + self.margin_width;
+                y += destination.top - origin.y;
+                Point { x, y }
+            };
+
+            // Highlight the bracket at the cursor and its partner, unless one of them is folded away.
+            if let Some(pair) = bracket_pair {
+                let bg = fb.indexed_alpha(IndexedColor::BrightCyan, 1, 2);
+                let mut cursor = self.cursor;
+                for off in pair {
+                    cursor = self.cursor_move_to_offset_internal(cursor, off);
+                    let y = cursor.logical_pos.y;
+                    if self.folds.iter().any(|f| f.start < y && y < f.end) {
+                        continue;
+                    }
+                    let pos = to_screen(cursor);
+                    if text.contains(pos) {
+                        fb.blend_bg(
+                            Rect { left: pos.x, top: pos.y, right: pos.x + 1, bottom: pos.y + 1 },
+                            bg,
+                        );
+                    }
+                }
+            }
+
+            // The terminal only has a single cursor. The others are drawn as inverted cells.
+            let mut caret = self.cursor;
+            for c in &self.carets {
+                caret = self.cursor_move_to_logical_internal(caret, c.pos);
+                let pos = to_screen(caret);
+                if text.contains(pos) {
+                    fb.reverse(Rect {
+                        left: pos.x,
+                        top: pos.y,
+                        right: pos.x + 1,
+                        bottom: pos.y + 1,
+                    });
+                }
+            }
+
+            let cursor = to_screen(self.cursor);
 
             if text.contains(cursor) {
                 fb.set_cursor(cursor, self.overtype);
 
-                if self.line_highlight_enabled && selection_beg >= selection_end {
+                if self.line_highlight_enabled && self.selection.is_none() {
                     fb.blend_bg(
                         Rect {
                             left: destination.left,
@@ -2042,10 +3495,34 @@ impl TextBuffer {
     }
 
     fn cut_copy(&mut self, clipboard: &mut Clipboard, cut: bool) {
-        let line_copy = !self.has_selection();
-        let selection = self.extract_selection(cut);
-        clipboard.write(selection);
+        let line_copy = !self.has_selection() && self.carets.iter().all(|c| c.selection.is_none());
+        let block_copy = self.block_selection.is_some() && !line_copy;
+
+        if self.carets.is_empty() {
+            let selection = self.extract_selection(cut);
+            clipboard.write(selection);
+            clipboard.write_was_line_copy(line_copy);
+            clipboard.write_was_block_copy(block_copy);
+            return;
+        }
+
+        // With multiple cursors, the selections are joined with newlines, top to bottom.
+        let mut selections = Vec::new();
+        self.for_each_caret(|tb| selections.push(tb.extract_selection(cut)));
+
+        let newline: &[u8] = if self.newlines_are_crlf { b"\r\n" } else { b"\n" };
+        let mut data = Vec::new();
+        for (i, selection) in selections.iter().rev().enumerate() {
+            // Line copies already end with a newline.
+            if i > 0 && !line_copy {
+                data.extend_from_slice(newline);
+            }
+            data.extend_from_slice(selection);
+        }
+
+        clipboard.write(data);
         clipboard.write_was_line_copy(line_copy);
+        clipboard.write_was_block_copy(block_copy);
     }
 
     pub fn paste(&mut self, clipboard: &Clipboard) {
@@ -2054,33 +3531,119 @@ impl TextBuffer {
             return;
         }
 
+        let line_copy = clipboard.is_line_copy();
+
+        if self.carets.is_empty() {
+            if clipboard.is_block_copy() {
+                self.paste_block(data);
+            } else {
+                self.paste_internal(data, line_copy);
+            }
+            return;
+        }
+
+        // If there are as many lines as cursors, each cursor gets one line.
+        // Otherwise, each one gets the entire clipboard contents.
+        let mut lines = Vec::new();
+        let mut beg = 0;
+        while beg < data.len() {
+            let (end, _) = simd::lines_fwd(data, beg, 0, 1);
+            lines.push(if line_copy {
+                &data[beg..end]
+            } else {
+                unicode::strip_newline(&data[beg..end])
+            });
+            beg = end;
+        }
+
+        if lines.len() != self.caret_count() {
+            self.for_each_caret(|tb| tb.paste_internal(data, line_copy));
+            return;
+        }
+
+        // `for_each_caret` goes bottom-up.
+        self.for_each_caret(|tb| {
+            let line = lines.pop().unwrap_or_default();
+            tb.paste_internal(line, line_copy);
+        });
+    }
+
+    /// Inserts the lines in `data` as a rectangle: Starting at the cursor,
+    /// each line goes to the same visual column on the following lines.
+    fn paste_block(&mut self, data: &[u8]) {
+        self.edit_begin_grouping();
+
+        if self.has_selection() {
+            self.write(b"", self.cursor, true);
+        }
+
+        let column = self.cursor.column;
+        let mut y = self.cursor.logical_pos.y;
+        let mut text = Vec::new();
+        let mut beg = 0;
+
+        while beg < data.len() {
+            let (end, _) = simd::lines_fwd(data, beg, 0, 1);
+            let line = unicode::strip_newline(&data[beg..end]);
+            beg = end;
+
+            // Extend the document if the block reaches past its end.
+            let mut cursor = self.cursor_move_to_logical_internal(self.cursor, Point { x: 0, y });
+            while cursor.logical_pos.y < y {
+                let end = self.cursor_move_to_logical_internal(cursor, Point::MAX);
+                self.write(if self.newlines_are_crlf { b"\r\n" } else { b"\n" }, end, true);
+                cursor = self.cursor;
+            }
+
+            // Lines that are too short get padded with whitespace.
+            cursor = self.cursor_move_to_column_internal(cursor, Point { x: column, y });
+            text.clear();
+            text.resize((column - cursor.column).max(0) as usize, b' ');
+            text.extend_from_slice(line);
+
+            self.write(&text, cursor, true);
+            y += 1;
+        }
+
+        self.edit_end_grouping();
+    }
+
+    fn paste_internal(&mut self, data: &[u8], line_copy: bool) {
+        if data.is_empty() {
+            return;
+        }
+
         let pos = self.cursor_logical_pos();
-        let at = if clipboard.is_line_copy() {
-            self.goto_line_start(self.cursor, pos.y)
-        } else {
-            self.cursor
-        };
+        let at = if line_copy { self.goto_line_start(self.cursor, pos.y) } else { self.cursor };
 
         self.write(data, at, true);
 
-        if clipboard.is_line_copy() {
+        if line_copy {
             self.cursor_move_to_logical(Point { x: pos.x, y: pos.y + 1 });
         }
     }
 
-    /// Inserts the user input `text` at the current cursor position.
+    /// Inserts the user input `text` at the current cursor position(s).
     /// Replaces tabs with whitespace if needed, etc.
     pub fn write_canon(&mut self, text: &[u8]) {
-        self.write(text, self.cursor, false);
+        self.for_each_caret(|tb| tb.write(text, tb.cursor, false));
     }
 
-    /// Inserts `text` as-is at the current cursor position.
+    /// Inserts `text` as-is at the current cursor position(s).
     /// The only transformation applied is that newlines are normalized.
     pub fn write_raw(&mut self, text: &[u8]) {
-        self.write(text, self.cursor, true);
+        self.for_each_caret(|tb| tb.write(text, tb.cursor, true));
     }
 
     fn write(&mut self, text: &[u8], at: Cursor, raw: bool) {
+        if !raw
+            && self.auto_close
+            && let &[ch] = text
+            && self.write_auto_close(ch)
+        {
+            return;
+        }
+
         let history_type = if raw { HistoryType::Other } else { HistoryType::Write };
         let mut edit_begun = false;
 
@@ -2103,13 +3666,31 @@ impl TextBuffer {
             return;
         }
 
+        let scratch = scratch_arena(None);
+        let indent_after = self.language().map_or("", |l| l.indent_after).as_bytes();
+
         if !edit_begun {
-            self.edit_begin(history_type, at);
+            // A closing bracket typed as the first character on its line gets the indentation
+            // of the line with its partner. It's the same edit, so that undo reverts both at once.
+            let reindent = match text {
+                &[ch] if !raw => self.closing_bracket_indentation(&scratch, at, ch, indent_after),
+                _ => None,
+            };
+            match reindent {
+                Some((line, indentation)) => {
+                    self.edit_begin(HistoryType::Other, line);
+                    if at.offset > line.offset {
+                        self.edit_delete(at);
+                    }
+                    self.edit_write(indentation.as_bytes());
+                }
+                None => self.edit_begin(history_type, at),
+            }
         }
 
         let mut offset = 0;
-        let scratch = scratch_arena(None);
         let mut newline_buffer = ArenaString::new_in(&scratch);
+        let mut closing_line = ArenaString::new_in(&scratch);
 
         loop {
             // Can't use `unicode::newlines_forward` because bracketed paste uses CR instead of LF/CRLF.
@@ -2186,20 +3767,36 @@ impl TextBuffer {
                     off += chunk.len();
                 }
 
-                // If tabs are enabled, add as many tabs as we can.
-                if self.indent_with_tabs {
-                    let tab_count = newline_indentation / self.tab_size;
-                    newline_buffer.push_repeat('\t', tab_count as usize);
-                    newline_indentation -= tab_count * self.tab_size;
+                // After an opening bracket (or similar, depending on the language) we indent
+                // one more level. If the cursor is right in front of the closing bracket,
+                // that one goes onto a line of its own with the original indentation.
+                let before = self.line_char_skipping_whitespace(self.cursor.offset, -1);
+                if let Some(before) = before
+                    && indent_after.contains(&before)
+                {
+                    if matches!(&text[offset..], b"\n" | b"\r\n" | b"\r")
+                        && let Some((closing, true)) = brackets::partner(before)
+                        && self.line_char_skipping_whitespace(self.cursor.offset, 1)
+                            == Some(closing)
+                    {
+                        closing_line.push_str(&newline_buffer);
+                        self.push_indentation(&mut closing_line, newline_indentation);
+                    }
+                    newline_indentation += self.tab_size_eval(newline_indentation);
                 }
 
-                // If tabs are disabled, or if the indentation wasn't a multiple of the tab size,
-                // add spaces to make up the difference.
-                newline_buffer.push_repeat(' ', newline_indentation as usize);
+                self.push_indentation(&mut newline_buffer, newline_indentation);
             }
 
             self.edit_write(newline_buffer.as_bytes());
 
+            if !closing_line.is_empty() {
+                let cursor = self.cursor;
+                self.edit_write(closing_line.as_bytes());
+                self.set_cursor_internal(cursor);
+                break;
+            }
+
             // Skip one CR/LF/CRLF.
             if offset >= text.len() {
                 break;
@@ -2237,12 +3834,155 @@ impl TextBuffer {
         self.edit_end();
     }
 
+    /// Handles typing `ch` if it's part of a bracket or quote pair. Returns `false` if it isn't,
+    /// or if it should be written as usual.
+    fn write_auto_close(&mut self, ch: u8) -> bool {
+        let closing = brackets::closing_pair(ch);
+
+        // Wrap the selection in the pair, in a single undo step.
+        if let Some((beg, end)) = self.selection_range_internal(false) {
+            let Some(closing) = closing else {
+                return false;
+            };
+
+            self.set_selection(None);
+            self.edit_begin_grouping();
+            // The closing one goes first, so that `beg` remains valid.
+            self.edit_begin(HistoryType::Other, end);
+            self.edit_write(&[closing]);
+            self.edit_end();
+            self.edit_begin(HistoryType::Other, beg);
+            self.edit_write(&[ch]);
+            self.edit_end();
+            self.edit_end_grouping();
+
+            let beg = self.cursor;
+            let end = self.cursor_move_to_offset_internal(beg, end.offset + 1);
+            self.set_cursor_internal(end);
+            self.set_selection(Some(TextBufferSelection {
+                beg: beg.logical_pos,
+                end: end.logical_pos,
+            }));
+            return true;
+        }
+
+        let prev = self.read_backward(self.cursor.offset).last().copied();
+        let next = self.read_forward(self.cursor.offset).first().copied();
+
+        // Type over the closing bracket or quote we inserted instead of adding another one.
+        let is_closing = matches!(brackets::partner(ch), Some((_, false))) || closing == Some(ch);
+        if is_closing
+            && next == Some(ch)
+            && let Some(i) = self.auto_closed.iter().position(|r| r.end == self.cursor.offset)
+        {
+            self.auto_closed.swap_remove(i);
+            let cursor = self.cursor_move_delta_internal(self.cursor, CursorMovement::Grapheme, 1);
+            self.set_cursor_internal(cursor);
+            return true;
+        }
+
+        let Some(closing) = closing else {
+            return false;
+        };
+        // Only add the closing one if the pair doesn't end up in front of a word,
+        // and quotes only if they don't follow one either. Think of "don't".
+        if self.overtype
+            || next.is_some_and(|c| !b" \t\r\n;:.,=)]}>".contains(&c))
+            || (closing == ch && prev.is_some_and(|c| c.is_ascii_alphanumeric() || c == b'_'))
+        {
+            return false;
+        }
+
+        self.write(&[ch, closing], self.cursor, false);
+        let cursor = self.cursor_move_delta_internal(self.cursor, CursorMovement::Grapheme, -1);
+        self.set_cursor_internal(cursor);
+        self.auto_closed.push(cursor.offset - 1..cursor.offset);
+        true
+    }
+
+    /// If `ch` is a closing bracket that would be the first character on its line at `at`,
+    /// returns the start of the line and the indentation of the line with its partner.
+    fn closing_bracket_indentation<'a>(
+        &self,
+        arena: &'a Arena,
+        at: Cursor,
+        ch: u8,
+        indent_after: &[u8],
+    ) -> Option<(Cursor, ArenaString<'a>)> {
+        let (opening, false) = brackets::partner(ch)? else {
+            return None;
+        };
+        if !indent_after.contains(&opening)
+            || self.line_char_skipping_whitespace(at.offset, -1).is_some()
+        {
+            return None;
+        }
+
+        let opening =
+            brackets::find_opening_before(&self.buffer, at.offset, ch, BRACKET_SEARCH_DISTANCE)?;
+        let opening = self.cursor_move_to_offset_internal(at, opening);
+        let columns = self.indent_end_internal(opening).column;
+        // Everything in front of `at` is indentation.
+        if columns == at.column {
+            return None;
+        }
+
+        let mut indentation = ArenaString::new_in(arena);
+        self.push_indentation(&mut indentation, columns);
+        Some((self.goto_line_start(at, at.logical_pos.y), indentation))
+    }
+
+    /// Appends whitespace that is `columns` wide, made of tabs if they're enabled.
+    fn push_indentation(&self, buf: &mut ArenaString, mut columns: CoordType) {
+        // If tabs are enabled, add as many tabs as we can.
+        if self.indent_with_tabs {
+            let tab_count = columns / self.tab_size;
+            buf.push_repeat('\t', tab_count as usize);
+            columns -= tab_count * self.tab_size;
+        }
+
+        // If tabs are disabled, or if the indentation wasn't a multiple of the tab size,
+        // add spaces to make up the difference.
+        buf.push_repeat(' ', columns as usize);
+    }
+
+    /// Returns the first character before (`direction` < 0) or at/after (> 0) `offset`
+    /// that isn't a space or tab, unless there's none on this line.
+    fn line_char_skipping_whitespace(&self, mut offset: usize, direction: CoordType) -> Option<u8> {
+        loop {
+            let chunk =
+                if direction < 0 { self.read_backward(offset) } else { self.read_forward(offset) };
+            if chunk.is_empty() {
+                return None;
+            }
+
+            let mut it = chunk.iter();
+            let next = if direction < 0 {
+                it.rposition(|&c| c != b' ' && c != b'\t')
+            } else {
+                it.position(|&c| c != b' ' && c != b'\t')
+            };
+            match next {
+                Some(i) => {
+                    let c = chunk[i];
+                    return if c == b'\r' || c == b'\n' { None } else { Some(c) };
+                }
+                None if direction < 0 => offset -= chunk.len(),
+                None => offset += chunk.len(),
+            }
+        }
+    }
+
     /// Deletes 1 grapheme cluster from the buffer.
     /// `cursor_movements` is expected to be -1 for backspace and 1 for delete.
     /// If there's a current selection, it will be deleted and `cursor_movements` ignored.
     /// The selection is cleared after the call.
     /// Deletes characters from the buffer based on a delta from the cursor.
     pub fn delete(&mut self, granularity: CursorMovement, delta: CoordType) {
+        self.for_each_caret(|tb| tb.delete_internal(granularity, delta));
+    }
+
+    fn delete_internal(&mut self, granularity: CursorMovement, delta: CoordType) {
         if delta == 0 {
             return;
         }
@@ -2268,6 +4008,16 @@ impl TextBuffer {
             if beg.offset > end.offset {
                 mem::swap(&mut beg, &mut end);
             }
+
+            // Backspace between an empty pair of brackets or quotes that auto-close
+            // inserted deletes both.
+            if self.auto_close
+                && delta == -1
+                && matches!(granularity, CursorMovement::Grapheme)
+                && self.auto_closed.contains(&(beg.offset..end.offset))
+            {
+                end = self.cursor_move_delta_internal(end, CursorMovement::Grapheme, 1);
+            }
         }
 
         self.edit_begin(HistoryType::Delete, beg);
@@ -2277,16 +4027,74 @@ impl TextBuffer {
         self.set_selection(None);
     }
 
+    /// Removes the spaces and tabs at the end of all lines. It can be undone in a single step.
+    pub fn trim_trailing_whitespace(&mut self) {
+        let mut ranges = Vec::new();
+        let mut run_beg = None;
+        let mut off = 0;
+
+        loop {
+            let chunk = self.read_forward(off);
+            if chunk.is_empty() {
+                break;
+            }
+            for (i, &c) in chunk.iter().enumerate() {
+                match c {
+                    b' ' | b'\t' => _ = run_beg.get_or_insert(off + i),
+                    b'\r' | b'\n' => {
+                        if let Some(beg) = run_beg.take() {
+                            ranges.push(beg..off + i);
+                        }
+                    }
+                    _ => run_beg = None,
+                }
+            }
+            off += chunk.len();
+        }
+        if let Some(beg) = run_beg {
+            ranges.push(beg..off);
+        }
+        if ranges.is_empty() {
+            return;
+        }
+
+        let cursor_pos = self.cursor.logical_pos;
+        self.set_selection(None);
+        self.edit_begin_grouping();
+
+        // Back to front, so that the offsets of the remaining ranges stay valid.
+        for range in ranges.into_iter().rev() {
+            let beg = self.cursor_move_to_offset_internal(self.cursor, range.start);
+            let end = self.cursor_move_to_offset_internal(beg, range.end);
+            // `Other`, because the ranges aren't contiguous and mustn't be merged.
+            self.edit_begin(HistoryType::Other, beg);
+            self.edit_delete(end);
+            self.edit_end();
+        }
+
+        self.edit_end_grouping();
+        self.cursor_move_to_logical(cursor_pos);
+    }
+
     /// Returns the logical position of the first character on this line.
     /// Return `.x == 0` if there are no non-whitespace characters.
     pub fn indent_end_logical_pos(&self) -> Point {
-        let cursor = self.goto_line_start(self.cursor, self.cursor.logical_pos.y);
-        let (chars, _) = self.measure_indent_internal(cursor.offset, CoordType::MAX);
-        Point { x: chars, y: cursor.logical_pos.y }
+        self.indent_end_internal(self.cursor).logical_pos
+    }
+
+    /// Returns the cursor at the first character on the line of `cursor`.
+    fn indent_end_internal(&self, cursor: Cursor) -> Cursor {
+        let line = self.goto_line_start(cursor, cursor.logical_pos.y);
+        let (chars, _) = self.measure_indent_internal(line.offset, CoordType::MAX);
+        self.cursor_move_to_logical_internal(line, Point { x: chars, y: line.logical_pos.y })
     }
 
-    /// Indents/unindents the current selection or line.
+    /// Indents/unindents the current selection(s) or line(s).
     pub fn indent_change(&mut self, direction: CoordType) {
+        self.for_each_caret(|tb| tb.indent_change_internal(direction));
+    }
+
+    fn indent_change_internal(&mut self, direction: CoordType) {
         let selection = self.selection;
         let mut selection_beg = self.cursor.logical_pos;
         let mut selection_end = selection_beg;
@@ -2353,6 +4161,218 @@ impl TextBuffer {
         );
     }
 
+    /// Comments out the current selection(s) or line(s) with the language's line comments,
+    /// or uncomments them, if they all are comments already. It can be undone in a single step.
+    pub fn toggle_line_comment(&mut self) {
+        self.for_each_caret(|tb| tb.toggle_line_comment_internal());
+    }
+
+    fn toggle_line_comment_internal(&mut self) {
+        let Some(language) = self.language() else {
+            return;
+        };
+        // Languages without line comments (e.g. Markdown) get a block comment on each line.
+        let (open, close) = match (language.line_comment, language.block_comment) {
+            (Some(open), _) => (open, ""),
+            (None, Some(pair)) => pair,
+            (None, None) => return,
+        };
+
+        let selection = self.selection;
+
+        // Blank lines are left alone. The others are commented at their common indentation.
+        let mut lines = Vec::new();
+        let mut text = Vec::new();
+        let mut min_columns = CoordType::MAX;
+        let mut all_commented = true;
+
+        for y in self.selected_lines() {
+            let line_beg = self.cursor_move_to_logical_internal(self.cursor, Point { x: 0, y });
+            let line_end =
+                self.cursor_move_to_logical_internal(line_beg, Point { x: CoordType::MAX, y });
+            text.clear();
+            self.buffer.extract_raw(line_beg.offset..line_end.offset, &mut text, 0);
+
+            let trimmed = text.trim_ascii();
+            if trimmed.is_empty() {
+                continue;
+            }
+
+            let (chars, columns) = self.measure_indent_internal(line_beg.offset, CoordType::MAX);
+            let content_end = self.cursor_move_to_offset_internal(
+                line_beg,
+                line_beg.offset + text.trim_ascii_end().len(),
+            );
+            min_columns = min_columns.min(columns);
+            all_commented &= trimmed.starts_with(open.as_bytes())
+                && trimmed.ends_with(close.as_bytes())
+                && trimmed.len() >= open.len() + close.len();
+            lines.push((line_beg, chars, content_end.logical_pos.x));
+        }
+
+        if lines.is_empty() {
+            return;
+        }
+
+        let mut points = [
+            self.cursor.logical_pos,
+            selection.map_or(Point::default(), |s| s.beg),
+            selection.map_or(Point::default(), |s| s.end),
+        ];
+        self.edit_begin_grouping();
+
+        // Bottom-up, so that the positions of the remaining lines stay valid.
+        for &(line_beg, chars, content_end) in lines.iter().rev() {
+            let y = line_beg.logical_pos.y;
+
+            // The end goes first, so that the position of the start remains valid.
+            if all_commented {
+                if !close.is_empty() {
+                    let mut x = content_end - close.len() as CoordType;
+                    let mut len = close.len() as CoordType;
+                    if self.comment_has_space(Point { x: x - 1, y }) {
+                        x -= 1;
+                        len += 1;
+                    }
+                    self.comment_edit(Point { x, y }, len, &[], false, &mut points);
+                }
+
+                let mut len = open.len() as CoordType;
+                if self.comment_has_space(Point { x: chars + len, y }) {
+                    len += 1;
+                }
+                self.comment_edit(Point { x: chars, y }, len, &[], true, &mut points);
+            } else {
+                if !close.is_empty() {
+                    let at = Point { x: content_end, y };
+                    self.comment_edit(at, 0, &[" ", close], false, &mut points);
+                }
+
+                let (x, _) = self.measure_indent_internal(line_beg.offset, min_columns);
+                self.comment_edit(Point { x, y }, 0, &[open, " "], true, &mut points);
+            }
+        }
+
+        self.edit_end_grouping();
+
+        let [cursor, beg, end] = points;
+        self.set_cursor_internal(self.cursor_move_to_logical_internal(self.cursor, cursor));
+        self.set_selection(selection.map(|_| TextBufferSelection { beg, end }));
+    }
+
+    /// Wraps the current selection(s) in the language's block comment, or removes it, if there's
+    /// one already. Without a selection, the text of the current line is used.
+    /// Languages without block comments get line comments instead.
+    pub fn toggle_block_comment(&mut self) {
+        self.for_each_caret(|tb| tb.toggle_block_comment_internal());
+    }
+
+    fn toggle_block_comment_internal(&mut self) {
+        let Some(language) = self.language() else {
+            return;
+        };
+        let Some((open, close)) = language.block_comment else {
+            self.toggle_line_comment_internal();
+            return;
+        };
+
+        let selection = self.selection;
+        let (beg, end) = match self.selection_range_internal(false) {
+            Some(range) => range,
+            None => {
+                let y = self.cursor.logical_pos.y;
+                let beg = self.goto_line_start(self.cursor, y);
+                let end = self.cursor_move_to_logical_internal(beg, Point { x: CoordType::MAX, y });
+                (beg, end)
+            }
+        };
+
+        let mut text = Vec::new();
+        self.buffer.extract_raw(beg.offset..end.offset, &mut text, 0);
+        let trimmed = text.trim_ascii();
+        if trimmed.is_empty() {
+            return;
+        }
+
+        // Leading and trailing whitespace stays outside the comment.
+        let lead = text.len() - text.trim_ascii_start().len();
+        let beg = self.cursor_move_to_offset_internal(beg, beg.offset + lead);
+        let end = self.cursor_move_to_offset_internal(beg, beg.offset + trimmed.len());
+        let (beg, end) = (beg.logical_pos, end.logical_pos);
+
+        let mut points = [
+            self.cursor.logical_pos,
+            selection.map_or(Point::default(), |s| s.beg),
+            selection.map_or(Point::default(), |s| s.end),
+        ];
+        self.edit_begin_grouping();
+
+        // The end goes first, so that the position of the start remains valid.
+        if trimmed.starts_with(open.as_bytes())
+            && trimmed.ends_with(close.as_bytes())
+            && trimmed.len() >= open.len() + close.len()
+        {
+            let mut x = end.x - close.len() as CoordType;
+            let mut len = close.len() as CoordType;
+            if trimmed.len() > open.len() + close.len()
+                && self.comment_has_space(Point { x: x - 1, y: end.y })
+            {
+                x -= 1;
+                len += 1;
+            }
+            self.comment_edit(Point { x, y: end.y }, len, &[], false, &mut points);
+
+            let mut len = open.len() as CoordType;
+            if self.comment_has_space(Point { x: beg.x + len, y: beg.y }) {
+                len += 1;
+            }
+            self.comment_edit(beg, len, &[], true, &mut points);
+        } else {
+            self.comment_edit(end, 0, &[" ", close], false, &mut points);
+            self.comment_edit(beg, 0, &[open, " "], true, &mut points);
+        }
+
+        self.edit_end_grouping();
+
+        let [cursor, beg, end] = points;
+        self.set_cursor_internal(self.cursor_move_to_logical_internal(self.cursor, cursor));
+        self.set_selection(selection.map(|_| TextBufferSelection { beg, end }));
+    }
+
+    /// Replaces `remove` characters at `at` with the concatenated `text`, which mustn't contain newlines.
+    /// The `points` on that line are shifted accordingly. Those right at `at` are only shifted
+    /// behind the `text` if `shift_at` is true.
+    fn comment_edit(
+        &mut self,
+        at: Point,
+        remove: CoordType,
+        text: &[&str],
+        shift_at: bool,
+        points: &mut [Point],
+    ) {
+        let beg = self.cursor_move_to_logical_internal(self.cursor, at);
+        let end = self.cursor_move_to_logical_internal(beg, Point { x: at.x + remove, y: at.y });
+        self.edit_begin(HistoryType::Other, beg);
+        self.edit_delete(end);
+        for t in text {
+            self.edit_write(t.as_bytes());
+        }
+        self.edit_end();
+
+        let added = text.iter().map(|t| t.len()).sum::<usize>() as CoordType;
+        for p in points {
+            if p.y == at.y && (p.x > at.x || (shift_at && p.x == at.x)) {
+                p.x = (p.x - remove).max(at.x) + added;
+            }
+        }
+    }
+
+    /// Returns whether there's a space at `at`, which comment tokens are padded with.
+    fn comment_has_space(&self, at: Point) -> bool {
+        let cursor = self.cursor_move_to_logical_internal(self.cursor, at);
+        cursor.logical_pos == at && self.read_forward(cursor.offset).first() == Some(&b' ')
+    }
+
     fn measure_indent_internal(
         &self,
         mut offset: usize,
@@ -2460,6 +4480,178 @@ impl TextBuffer {
         }));
     }
 
+    /// Sorts the selected lines, or all of them without a selection.
+    pub fn sort_lines(&mut self, options: SortOptions) {
+        self.edit_lines(self.selected_lines_or_all(), |lines| line_ops::sort(lines, options));
+    }
+
+    /// Removes lines that repeat an earlier one from the selection, or the whole document.
+    pub fn remove_duplicate_lines(&mut self) {
+        self.edit_lines(self.selected_lines_or_all(), line_ops::remove_duplicates);
+    }
+
+    /// Reverses the order of the selected lines, or all of them without a selection.
+    pub fn reverse_lines(&mut self) {
+        self.edit_lines(self.selected_lines_or_all(), |lines| lines.reverse());
+    }
+
+    /// Puts the selected lines, or all of them without a selection, into a random order.
+    pub fn shuffle_lines(&mut self) {
+        self.edit_lines(self.selected_lines_or_all(), |lines| line_ops::shuffle(lines));
+    }
+
+    /// Joins the selected lines, or the cursor's line and the next one, with spaces.
+    pub fn join_lines(&mut self) {
+        let mut lines = self.selected_lines();
+        if lines.len() < 2 {
+            lines.end = lines.start + 2;
+        }
+        self.edit_lines(lines, line_ops::join);
+    }
+
+    /// Duplicates the selected lines, or the cursor's line. The cursor and selection end up
+    /// on the upper copy for [`MoveLineDirection::Up`] and on the lower one otherwise.
+    pub fn duplicate_lines(&mut self, direction: MoveLineDirection) {
+        let selection = self.selection;
+        let cursor = self.cursor.logical_pos;
+        let lines = self.selected_lines();
+        let delta = match direction {
+            MoveLineDirection::Up => 0,
+            MoveLineDirection::Down => lines.len() as CoordType,
+        };
+
+        self.edit_lines(lines, |lines| lines.extend_from_within(..));
+
+        self.cursor_move_to_logical(Point { x: cursor.x, y: cursor.y + delta });
+        self.set_selection(selection.map(|mut s| {
+            s.beg.y += delta;
+            s.end.y += delta;
+            s
+        }));
+    }
+
+    /// Deletes the selected lines, or the cursor's line.
+    pub fn delete_lines(&mut self) {
+        let cursor = self.cursor.logical_pos;
+        let lines = self.selected_lines();
+
+        let mut beg =
+            self.cursor_move_to_logical_internal(self.cursor, Point { x: 0, y: lines.start });
+        let end = self.cursor_move_to_logical_internal(beg, Point { x: 0, y: lines.end });
+        // The last line has no newline of its own, so the one in front of it goes instead.
+        if end.logical_pos.y < lines.end && lines.start > 0 {
+            beg = self.cursor_move_to_logical_internal(
+                beg,
+                Point { x: CoordType::MAX, y: lines.start - 1 },
+            );
+        }
+        if beg.offset >= end.offset {
+            return;
+        }
+
+        self.set_selection(None);
+        self.edit_begin(HistoryType::Other, beg);
+        self.edit_delete(end);
+        self.edit_end();
+
+        self.cursor_move_to_logical(Point { x: cursor.x, y: lines.start });
+    }
+
+    /// Inserts an empty line above or below the cursor's line and moves the cursor onto it.
+    /// Like with Enter, it gets the indentation of the cursor's line.
+    pub fn insert_blank_line(&mut self, direction: MoveLineDirection) {
+        let y = self.cursor.logical_pos.y;
+        self.set_selection(None);
+
+        match direction {
+            MoveLineDirection::Up => {
+                let beg = self.goto_line_start(self.cursor, y);
+                let (_, columns) = self.measure_indent_internal(beg.offset, CoordType::MAX);
+                let scratch = scratch_arena(None);
+                let mut text = ArenaString::new_in(&scratch);
+                self.push_indentation(&mut text, columns);
+                let x = text.len() as CoordType;
+                text.push_str(if self.newlines_are_crlf { "\r\n" } else { "\n" });
+
+                self.edit_begin(HistoryType::Other, beg);
+                self.edit_write(text.as_bytes());
+                self.edit_end();
+                self.cursor_move_to_logical(Point { x, y });
+            }
+            MoveLineDirection::Down => {
+                let end = self
+                    .cursor_move_to_logical_internal(self.cursor, Point { x: CoordType::MAX, y });
+                self.write(b"\n", end, false);
+            }
+        }
+    }
+
+    /// Returns the lines covered by the selection, or the cursor's line.
+    /// A selection that ends at the start of a line doesn't include that line.
+    fn selected_lines(&self) -> Range<CoordType> {
+        let [beg, end] = match self.selection {
+            Some(s) => minmax(s.beg, s.end),
+            None => [self.cursor.logical_pos; 2],
+        };
+        let last = if end.y > beg.y && end.x == 0 { end.y - 1 } else { end.y };
+        beg.y..last + 1
+    }
+
+    /// Like [`TextBuffer::selected_lines`], but all lines without a selection,
+    /// except for the empty one after a final newline.
+    fn selected_lines_or_all(&self) -> Range<CoordType> {
+        if self.selection.is_some() {
+            return self.selected_lines();
+        }
+        let mut end = self.stats.logical_lines;
+        if self.read_backward(self.text_length()).last() == Some(&b'\n') {
+            end -= 1;
+        }
+        0..end
+    }
+
+    /// Replaces the `lines` with the result of `f`, which receives them without newlines.
+    /// It can be undone in a single step. If there's a selection, it covers the new lines.
+    fn edit_lines(&mut self, lines: Range<CoordType>, f: impl FnOnce(&mut Vec<Vec<u8>>)) {
+        let lines = lines.start..lines.end.min(self.stats.logical_lines);
+        if lines.is_empty() {
+            return;
+        }
+
+        let beg = self.cursor_move_to_logical_internal(self.cursor, Point { x: 0, y: lines.start });
+        let end = self
+            .cursor_move_to_logical_internal(beg, Point { x: CoordType::MAX, y: lines.end - 1 });
+        let mut text = Vec::new();
+        self.buffer.extract_raw(beg.offset..end.offset, &mut text, 0);
+
+        let mut list: Vec<Vec<u8>> = text
+            .split(|&c| c == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line).to_vec())
+            .collect();
+        f(&mut list);
+
+        let newline: &[u8] = if self.newlines_are_crlf { b"\r\n" } else { b"\n" };
+        let replacement = list.join(newline);
+        if replacement == text {
+            return;
+        }
+
+        let selection = self.selection;
+        let cursor = self.cursor.logical_pos;
+        self.set_selection(None);
+        self.edit_begin(HistoryType::Other, beg);
+        self.edit_delete(end);
+        self.edit_write(&replacement);
+        self.edit_end();
+
+        if selection.is_some() {
+            let end = self.cursor.logical_pos;
+            self.set_selection(Some(TextBufferSelection { beg: beg.logical_pos, end }));
+        } else {
+            self.cursor_move_to_logical(cursor);
+        }
+    }
+
     /// Extracts the contents of the current selection.
     /// May optionally delete it, if requested. This is meant to be used for Ctrl+X.
     fn extract_selection(&mut self, delete: bool) -> Vec<u8> {
@@ -2532,16 +4724,28 @@ impl TextBuffer {
     }
 
     fn edit_begin_grouping(&mut self) {
+        if let Some(info) = &mut self.active_edit_group {
+            info.depth += 1;
+            return;
+        }
+
         self.active_edit_group = Some(ActiveEditGroupInfo {
             cursor_before: self.cursor.logical_pos,
             selection_before: self.selection,
+            carets_before: self.carets.clone(),
             stats_before: self.stats,
             generation_before: self.buffer.generation(),
+            depth: 1,
         });
     }
 
     fn edit_end_grouping(&mut self) {
-        self.active_edit_group = None;
+        if let Some(info) = &mut self.active_edit_group {
+            info.depth -= 1;
+            if info.depth == 0 {
+                self.active_edit_group = None;
+            }
+        }
     }
 
     /// Starts a new edit operation.
@@ -2552,6 +4756,10 @@ impl TextBuffer {
             return;
         }
 
+        // The cursors of a block selection go their own ways once the text changes.
+        self.block_selection = None;
+        self.highlighter_invalidate(cursor.logical_pos.y);
+
         let cursor_before = self.cursor;
         self.set_cursor_internal(cursor);
 
@@ -2559,31 +4767,31 @@ impl TextBuffer {
         if history_type != self.last_history_type
             || !matches!(history_type, HistoryType::Write | HistoryType::Delete)
         {
-            self.redo_stack.clear();
-            while self.undo_stack.len() > 1000 {
-                self.undo_stack.pop_front();
-            }
-
             self.last_history_type = history_type;
-            self.undo_stack.push_back(SemiRefCell::new(HistoryEntry {
+
+            let mut entry = HistoryEntry {
                 cursor_before: cursor_before.logical_pos,
                 selection_before: self.selection,
+                carets_before: self.carets.clone(),
                 stats_before: self.stats,
                 generation_before: self.buffer.generation(),
                 cursor: cursor.logical_pos,
                 deleted: Vec::new(),
                 added: Vec::new(),
-            }));
+            };
 
-            if let Some(info) = &self.active_edit_group
-                && let Some(entry) = self.undo_stack.back()
-            {
-                let mut entry = entry.borrow_mut();
+            if let Some(info) = &self.active_edit_group {
                 entry.cursor_before = info.cursor_before;
                 entry.selection_before = info.selection_before;
+                entry.carets_before = info.carets_before.clone();
                 entry.stats_before = info.stats_before;
                 entry.generation_before = info.generation_before;
             }
+
+            let generation = self.history.push_entry(entry, self.buffer.generation());
+            self.buffer.set_generation(generation);
+        } else {
+            self.history.touch();
         }
 
         self.active_edit_off = cursor.offset;
@@ -2613,18 +4821,31 @@ impl TextBuffer {
 
         // Copy the written portion into the undo entry.
         {
-            let mut undo = self.undo_stack.back_mut().unwrap().borrow_mut();
+            let undo = self.history.current_entry_mut();
             undo.added.extend_from_slice(text);
         }
 
+        for pair in &mut self.auto_closed {
+            if pair.start >= self.active_edit_off {
+                pair.start += text.len();
+            }
+            if pair.end >= self.active_edit_off {
+                pair.end += text.len();
+            }
+        }
+
+        self.find_scope_written(self.active_edit_off, text.len());
+
         // Write!
         self.buffer.replace(self.active_edit_off..self.active_edit_off, text);
+        self.line_cache.insert(&self.buffer, self.active_edit_off, logical_y_before, text.len());
 
         // Move self.cursor to the end of the newly written text. Can't use `self.set_cursor_internal`,
         // because we're still in the progress of recalculating the line stats.
         self.active_edit_off += text.len();
         self.cursor = self.cursor_move_to_offset_internal(self.cursor, self.active_edit_off);
         self.stats.logical_lines += self.cursor.logical_pos.y - logical_y_before;
+        self.folds_edited(logical_y_before, 0, self.cursor.logical_pos.y - logical_y_before);
     }
 
     /// Deletes the text between the current cursor position and `to`.
@@ -2636,7 +4857,7 @@ impl TextBuffer {
         let off = self.active_edit_off;
         let mut out_off = usize::MAX;
 
-        let mut undo = self.undo_stack.back_mut().unwrap().borrow_mut();
+        let undo = self.history.current_entry_mut();
 
         // If this is a continued backspace operation,
         // we need to prepend the deleted portion to the undo entry.
@@ -2651,7 +4872,21 @@ impl TextBuffer {
 
         // Delete the portion from the buffer by enlarging the gap.
         let count = to.offset - off;
+        self.auto_closed.retain_mut(|pair| {
+            let shift = |o: &mut usize| {
+                if *o >= to.offset {
+                    *o -= count;
+                    true
+                } else {
+                    *o < off
+                }
+            };
+            shift(&mut pair.start) && shift(&mut pair.end)
+        });
+        self.find_scope_deleted(off..to.offset);
         self.buffer.allocate_gap(off, 0, count);
+        self.line_cache.delete(off..to.offset, to.logical_pos.y - logical_y_before);
+        self.folds_edited(logical_y_before, to.logical_pos.y - logical_y_before, 0);
 
         self.stats.logical_lines += logical_y_before - to.logical_pos.y;
     }
@@ -2667,14 +4902,17 @@ impl TextBuffer {
 
         #[cfg(debug_assertions)]
         {
-            let entry = self.undo_stack.back_mut().unwrap().borrow_mut();
+            let entry = self.history.current_entry_mut();
             debug_assert!(!entry.deleted.is_empty() || !entry.added.is_empty());
         }
 
         if let Some(info) = self.active_edit_line_info.take() {
-            let deleted_count = self.undo_stack.back_mut().unwrap().borrow_mut().deleted.len();
+            let deleted_count = self.history.current_entry_mut().deleted.len();
             let target = self.cursor.logical_pos;
 
+            // Rows measured during the edit may be off, since the edited line was rewrapped.
+            self.line_cache.drop_measured_after(info.safe_start.offset);
+
             // From our safe position we can measure the actual visual position of the cursor.
             self.set_cursor_internal(self.cursor_move_to_logical_internal(info.safe_start, target));
 
@@ -2701,140 +4939,10 @@ impl TextBuffer {
             self.stats.visual_lines = self.stats.logical_lines;
         }
 
+        self.history.update_generation(self.buffer.generation());
         self.recalc_after_content_changed();
     }
 
-    /// Undo the last edit operation.
-    pub fn undo(&mut self) {
-        self.undo_redo(true);
-    }
-
-    /// Redo the last undo operation.
-    pub fn redo(&mut self) {
-        self.undo_redo(false);
-    }
-
-    fn undo_redo(&mut self, undo: bool) {
-        let buffer_generation = self.buffer.generation();
-        let mut entry_buffer_generation = None;
-
-        loop {
-            // Transfer the last entry from the undo stack to the redo stack or vice versa.
-            {
-                let (from, to) = if undo {
-                    (&mut self.undo_stack, &mut self.redo_stack)
-                } else {
-                    (&mut self.redo_stack, &mut self.undo_stack)
-                };
-
-                if let Some(g) = entry_buffer_generation
-                    && from.back().is_none_or(|c| c.borrow().generation_before != g)
-                {
-                    break;
-                }
-
-                let Some(list) = from.cursor_back_mut().remove_current_as_list() else {
-                    break;
-                };
-
-                to.cursor_back_mut().splice_after(list);
-            }
-
-            let change = {
-                let to = if undo { &self.redo_stack } else { &self.undo_stack };
-                to.back().unwrap()
-            };
-
-            // Remember the buffer generation of the change so we can stop popping undos/redos.
-            // Also, move to the point where the modification took place.
-            let cursor = {
-                let change = change.borrow();
-                entry_buffer_generation = Some(change.generation_before);
-                self.cursor_move_to_logical_internal(self.cursor, change.cursor)
-            };
-
-            let safe_cursor = if self.word_wrap_column > 0 {
-                // If word-wrap is enabled, we need to move the cursor to the beginning of the line.
-                // This is because the undo/redo operation may have changed the visual position of the cursor.
-                self.goto_line_start(cursor, cursor.logical_pos.y)
-            } else {
-                cursor
-            };
-
-            {
-                let mut change = change.borrow_mut();
-                let change = &mut *change;
-
-                // Undo: Whatever was deleted is now added and vice versa.
-                mem::swap(&mut change.deleted, &mut change.added);
-
-                // Delete the inserted portion.
-                self.buffer.allocate_gap(cursor.offset, 0, change.deleted.len());
-
-                // Reinsert the deleted portion.
-                {
-                    let added = &change.added[..];
-                    let mut beg = 0;
-                    let mut offset = cursor.offset;
-
-                    while beg < added.len() {
-                        let (end, line) = simd::lines_fwd(added, beg, 0, 1);
-                        let has_newline = line != 0;
-                        let link = &added[beg..end];
-                        let line = unicode::strip_newline(link);
-                        let mut written;
-
-                        {
-                            let gap = self.buffer.allocate_gap(offset, line.len() + 2, 0);
-                            written = slice_copy_safe(gap, line);
-
-                            if has_newline {
-                                if self.newlines_are_crlf && written < gap.len() {
-                                    gap[written] = b'\r';
-                                    written += 1;
-                                }
-                                if written < gap.len() {
-                                    gap[written] = b'\n';
-                                    written += 1;
-                                }
-                            }
-
-                            self.buffer.commit_gap(written);
-                        }
-
-                        beg = end;
-                        offset += written;
-                    }
-                }
-
-                // Restore the previous line statistics.
-                mem::swap(&mut self.stats, &mut change.stats_before);
-
-                // Restore the previous selection.
-                mem::swap(&mut self.selection, &mut change.selection_before);
-
-                // Pretend as if the buffer was never modified.
-                self.buffer.set_generation(change.generation_before);
-                change.generation_before = buffer_generation;
-
-                // Restore the previous cursor.
-                let cursor_before =
-                    self.cursor_move_to_logical_internal(safe_cursor, change.cursor_before);
-                change.cursor_before = self.cursor.logical_pos;
-                // Can't use `set_cursor_internal` here, because we haven't updated the line stats yet.
-                self.cursor = cursor_before;
-
-                if self.undo_stack.is_empty() {
-                    self.last_history_type = HistoryType::Other;
-                }
-            }
-        }
-
-        if entry_buffer_generation.is_some() {
-            self.recalc_after_content_changed();
-        }
-    }
-
     /// For interfacing with ICU.
     pub(crate) fn read_backward(&self, off: usize) -> &[u8] {
         self.buffer.read_backward(off)
@@ -2883,3 +4991,550 @@ fn detect_bom(bytes: &[u8]) -> Option<&'static str> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a small buffer with LF line endings that contains `text`.
+    pub(crate) fn buffer_from(text: &str) -> TextBuffer {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.set_crlf(false);
+        tb.write_raw(text.as_bytes());
+        tb
+    }
+
+    /// Returns the text of the buffer and marks it as clean.
+    pub(crate) fn contents(tb: &mut TextBuffer) -> String {
+        let mut s = String::new();
+        tb.save_as_string(&mut s);
+        s
+    }
+
+    #[test]
+    fn test_trim_trailing_whitespace() {
+        let mut tb = buffer_from("a  \n\t\nb \t c\t\n  ");
+        tb.cursor_move_to_logical(Point { x: 4, y: 2 });
+        tb.trim_trailing_whitespace();
+        assert_eq!(contents(&mut tb), "a\n\nb \t c\n");
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 4, y: 2 });
+
+        tb.undo();
+        assert_eq!(contents(&mut tb), "a  \n\t\nb \t c\t\n  ");
+    }
+
+    #[test]
+    fn test_find_all_and_replace_all() {
+        let mut tb = buffer_from("foo = 1\nbar = foo(2)\n");
+        let options = SearchOptions { use_regex: true, ..Default::default() };
+
+        let hits = tb.find_all(r"f(o+)", options, Some(b"x$1"), usize::MAX).unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!((hits[1].beg, hits[1].end), (Point { x: 6, y: 1 }, Point { x: 9, y: 1 }));
+        assert_eq!(hits[1].line, "bar = foo(2)");
+        assert_eq!(hits[1].line_range, 6..9);
+        assert_eq!(hits[1].replacement.as_deref(), Some("xoo"));
+        assert!(!tb.has_selection());
+
+        tb.find_and_replace_all(r"f(o+)", options, b"x$1").unwrap();
+        assert_eq!(contents(&mut tb), "xoo = 1\nbar = xoo(2)\n");
+
+        // Replace All is a single undo step.
+        tb.undo();
+        assert_eq!(contents(&mut tb), "foo = 1\nbar = foo(2)\n");
+    }
+
+    #[test]
+    fn test_find_match_count() {
+        let count =
+            |current, total, incomplete| Some(SearchMatchCount { current, total, incomplete });
+        let mut tb = buffer_from("ab\nab ab\nb\n");
+        assert_eq!(tb.find_match_count(), None);
+
+        tb.cursor_move_to_logical(Point { x: 0, y: 0 });
+        tb.find_and_select("ab", SearchOptions::default()).unwrap();
+        tb.find_and_select("ab", SearchOptions::default()).unwrap();
+        assert_eq!(tb.find_match_count(), count(None, 0, true));
+
+        // Each step counts at least one hit.
+        tb.find_count_step(Duration::ZERO);
+        assert_eq!(tb.find_match_count(), count(None, 1, true));
+        tb.find_count_step(Duration::MAX);
+        assert!(!tb.find_is_counting());
+        assert_eq!(tb.find_match_count(), count(Some(2), 3, false));
+        assert_eq!(tb.find_match_lines(), &[0, 1]);
+
+        // The count follows edits of the buffer.
+        tb.cursor_move_to_logical(Point { x: 0, y: 2 });
+        tb.write_canon(b"a");
+        assert!(tb.find_is_counting());
+        tb.find_count_step(Duration::MAX);
+        assert_eq!(tb.find_match_count(), count(None, 4, false));
+        assert_eq!(tb.find_match_lines(), &[0, 1, 2]);
+
+        tb.find_clear();
+        assert_eq!(tb.find_match_count(), None);
+    }
+
+    #[test]
+    fn test_find_match_count_limit() {
+        let mut tb = buffer_from(&"a".repeat(MAX_SEARCH_MATCHES + 1));
+        tb.cursor_move_to_logical(Point { x: 0, y: 0 });
+        tb.find_and_select("a", SearchOptions::default()).unwrap();
+        tb.find_count_step(Duration::MAX);
+        assert!(!tb.find_is_counting());
+        assert_eq!(
+            tb.find_match_count(),
+            Some(SearchMatchCount {
+                current: Some(1),
+                total: MAX_SEARCH_MATCHES,
+                incomplete: true
+            })
+        );
+        assert_eq!(tb.find_match_lines(), &[0]);
+    }
+
+    #[test]
+    fn test_find_in_selection() {
+        let mut tb = buffer_from("a1 a2\na3 a4\na5 a6\n");
+        let options = SearchOptions { use_regex: true, in_selection: true, ..Default::default() };
+
+        // Select "a2\na3 a4\na5".
+        tb.cursor_move_to_logical(Point { x: 3, y: 0 });
+        tb.selection_update_logical(Point { x: 2, y: 2 });
+
+        for _ in 0..4 {
+            tb.find_and_select(r"a(\d)", options).unwrap();
+        }
+        assert_eq!(tb.extract_selection(false), b"a5");
+        // Wraps around to the start of the selection.
+        tb.find_and_select(r"a(\d)", options).unwrap();
+        assert_eq!(tb.extract_selection(false), b"a2");
+
+        // The replacements are longer than the hits, which moves the end of the selection.
+        tb.find_and_replace_all(r"a(\d)", options, b"<b$1>").unwrap();
+        assert_eq!(contents(&mut tb), "a1 <b2>\n<b3> <b4>\n<b5> a6\n");
+
+        // Select "a2 a3" and replace the hits one by one. The hit outside stays untouched.
+        let mut tb = buffer_from("a1 a2 a3\n");
+        tb.cursor_move_to_logical(Point { x: 3, y: 0 });
+        tb.selection_update_logical(Point { x: 8, y: 0 });
+        tb.find_and_select(r"a(\d)", options).unwrap();
+        assert_eq!(tb.extract_selection(false), b"a2");
+        tb.find_and_replace(r"a(\d)", options, b"xx$1").unwrap();
+        assert_eq!(tb.extract_selection(false), b"a3");
+        tb.find_and_replace(r"a(\d)", options, b"xx$1").unwrap();
+        assert_eq!(contents(&mut tb), "a1 xx2 xx3\n");
+
+        // Selecting something else restricts the search to the new selection.
+        tb.cursor_move_to_logical(Point { x: 0, y: 0 });
+        tb.selection_update_logical(Point { x: 2, y: 0 });
+        tb.find_and_select(r"a(\d)", options).unwrap();
+        assert_eq!(tb.extract_selection(false), b"a1");
+
+        // Undoing and redoing an edit above the selection moves it along.
+        let mut tb = buffer_from(
+            "a1
+a2 a3
+",
+        );
+        tb.cursor_move_to_logical(Point { x: 0, y: 1 });
+        tb.selection_update_logical(Point { x: 2, y: 1 });
+        tb.find_and_select(r"a(\d)", options).unwrap();
+        let scope = |tb: &mut TextBuffer| tb.search.as_mut().unwrap().get_mut().scope.clone();
+        tb.cursor_move_to_logical(Point { x: 0, y: 0 });
+        tb.write_canon(b"xx");
+        assert_eq!(scope(&mut tb), Some(5..7));
+        tb.undo();
+        assert_eq!(scope(&mut tb), Some(3..5));
+        tb.redo();
+        assert_eq!(scope(&mut tb), Some(5..7));
+    }
+
+    #[test]
+    fn test_multi_cursor_write() {
+        let mut tb = buffer_from("abc\ndef\nghi\n");
+        tb.cursor_move_to_logical(Point { x: 1, y: 0 });
+        tb.add_caret_vertical(1, 1);
+        tb.add_caret_vertical(1, 1);
+        assert_eq!(tb.caret_count(), 3);
+
+        tb.write_canon(b"X");
+        tb.write_canon(b"Y");
+        assert_eq!(contents(&mut tb), "aXYbc\ndXYef\ngXYhi\n");
+
+        tb.delete(CursorMovement::Grapheme, -1);
+        assert_eq!(contents(&mut tb), "aXbc\ndXef\ngXhi\n");
+
+        // Each multi-cursor edit is a single undo step.
+        tb.undo();
+        assert_eq!(contents(&mut tb), "aXYbc\ndXYef\ngXYhi\n");
+        tb.undo();
+        tb.undo();
+        assert_eq!(contents(&mut tb), "abc\ndef\nghi\n");
+        assert_eq!(tb.caret_count(), 3);
+
+        tb.redo();
+        assert_eq!(contents(&mut tb), "aXbc\ndXef\ngXhi\n");
+    }
+
+    #[test]
+    fn test_multi_cursor_next_occurrence() {
+        let mut tb = buffer_from("foo bar\nfoo foo\n");
+        tb.cursor_move_to_logical(Point { x: 1, y: 0 });
+        tb.add_caret_next_occurrence();
+        tb.add_caret_next_occurrence();
+        tb.add_caret_next_occurrence();
+        tb.add_caret_next_occurrence();
+        assert_eq!(tb.caret_count(), 3);
+
+        tb.write_canon(b"x");
+        assert_eq!(contents(&mut tb), "x bar\nx x\n");
+    }
+
+    #[test]
+    fn test_multi_cursor_copy_paste() {
+        let mut clipboard = Clipboard::default();
+        let mut tb = buffer_from("a1\nb2\nc3\n");
+        tb.cursor_move_to_logical(Point { x: 1, y: 0 });
+        tb.add_caret_vertical(1, 1);
+        tb.add_caret_vertical(1, 1);
+        tb.for_each_caret(|tb| tb.selection_update_delta(CursorMovement::Grapheme, 1));
+        tb.copy(&mut clipboard);
+        assert_eq!(clipboard.read(), b"1\n2\n3");
+
+        // One line per cursor.
+        tb.for_each_caret(|tb| {
+            tb.cursor_move_to_logical(Point { x: 0, y: tb.cursor.logical_pos.y })
+        });
+        tb.paste(&clipboard);
+        assert_eq!(contents(&mut tb), "1a1\n2b2\n3c3\n");
+    }
+
+    #[test]
+    fn test_block_selection() {
+        let mut clipboard = Clipboard::default();
+        let mut tb = buffer_from("abcd\nef\n\tgh\nijkl\n");
+        tb.cursor_move_to_logical(Point { x: 1, y: 0 });
+        tb.block_selection_update_delta(Point { x: 0, y: 3 });
+        tb.block_selection_update_delta(Point { x: 1, y: 0 });
+        tb.block_selection_update_delta(Point { x: 1, y: 0 });
+        // The line with the tab starts past the right edge of the block and is skipped.
+        assert_eq!(tb.caret_count(), 3);
+
+        tb.copy(&mut clipboard);
+        assert!(clipboard.is_block_copy());
+        assert_eq!(clipboard.read(), b"bc\nf\njk");
+
+        tb.write_canon(b"-");
+        assert_eq!(contents(&mut tb), "a-d\ne-\n\tgh\ni-l\n");
+
+        // Pasting a block inserts it as a rectangle, padding short lines and extending the document.
+        tb.cursor_move_to_logical(Point { x: 2, y: 2 });
+        tb.paste(&clipboard);
+        assert_eq!(contents(&mut tb), "a-d\ne-\n\tgbch\ni-l  f\n     jk");
+    }
+
+    #[test]
+    fn test_line_cache_seeking() {
+        let text: String = (0..20000).map(|i| format!("{i:05}\n")).collect();
+        let mut tb = buffer_from(&text);
+
+        let goto_line = |tb: &mut TextBuffer, y| {
+            tb.cursor_move_to_logical(Point { x: 0, y });
+            tb.cursor.offset
+        };
+
+        assert_eq!(goto_line(&mut tb, 15000), 90000);
+        assert_eq!(goto_line(&mut tb, 10), 60);
+
+        tb.cursor_move_to_logical(Point { x: 0, y: 3 });
+        tb.write_raw(b"a\nb\n");
+        assert_eq!(goto_line(&mut tb, 15002), 90004);
+        assert_eq!(goto_line(&mut tb, 4100), 24592);
+
+        tb.cursor_move_to_logical(Point { x: 0, y: 4000 });
+        tb.selection_update_logical(Point { x: 0, y: 4500 });
+        tb.delete(CursorMovement::Grapheme, 1);
+        assert_eq!(goto_line(&mut tb, 14502), 90004 - 6 * 500);
+
+        tb.undo();
+        tb.undo();
+        assert_eq!(goto_line(&mut tb, 15000), 90000);
+        assert_eq!(goto_line(&mut tb, 8192), 8192 * 6);
+
+        tb.redo();
+        assert_eq!(goto_line(&mut tb, 8194), 8192 * 6 + 4);
+    }
+
+    #[test]
+    fn test_line_cache_seeking_wrapped() {
+        // Every third line wraps into 3 rows at a width of 10.
+        let text: String = (0..12000)
+            .map(|i| if i % 3 == 0 { format!("{}\n", "x".repeat(25)) } else { "y\n".into() })
+            .collect();
+        let long_lines_before = |y: CoordType| (y + 2) / 3;
+        let row = |y| y + 2 * long_lines_before(y);
+        let offset = |y| (26 * long_lines_before(y) + 2 * (y - long_lines_before(y))) as usize;
+
+        let mut tb = buffer_from(&text);
+        tb.set_word_wrap(true);
+        tb.set_width(10);
+
+        let goto_line = |tb: &mut TextBuffer, y| {
+            tb.cursor_move_to_logical(Point { x: 0, y });
+            tb.cursor_visual_pos().y
+        };
+        let goto_offset = |tb: &mut TextBuffer, off| {
+            tb.cursor_move_to_offset(off);
+            tb.cursor_visual_pos()
+        };
+
+        assert_eq!(goto_line(&mut tb, 11000), row(11000));
+        // The rows of the cache points on the way were measured, so they can be seeked from.
+        assert!(tb.line_cache.next_unmeasured().is_none_or(|p| p.line > 11000));
+        assert_eq!(goto_line(&mut tb, 10), row(10));
+        assert_eq!(goto_line(&mut tb, 9345), row(9345));
+        assert_eq!(goto_offset(&mut tb, offset(6999) + 12), Point { x: 2, y: row(6999) + 1 });
+        assert_eq!(goto_offset(&mut tb, offset(11500)), Point { x: 0, y: row(11500) });
+
+        // Insert another long line at the top, which moves all rows after it.
+        tb.cursor_move_to_logical(Point { x: 0, y: 1 });
+        tb.write_raw(format!("{}\n", "x".repeat(25)).as_bytes());
+        assert_eq!(goto_line(&mut tb, 11001), row(11000) + 3);
+        assert_eq!(goto_offset(&mut tb, offset(11500) + 26), Point { x: 0, y: row(11500) + 3 });
+
+        tb.undo();
+        assert_eq!(goto_line(&mut tb, 11000), row(11000));
+        assert_eq!(goto_line(&mut tb, 8000), row(8000));
+    }
+
+    #[test]
+    fn test_matching_bracket() {
+        let mut tb = buffer_from("f(a[1]);\n");
+        // The bracket in front of the cursor takes precedence over the one before it.
+        tb.cursor_move_to_offset(3);
+        tb.jump_to_matching_bracket();
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 5, y: 0 });
+        tb.jump_to_matching_bracket();
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 3, y: 0 });
+
+        tb.cursor_move_to_offset(7);
+        tb.select_to_matching_bracket();
+        assert_eq!(tb.selection_range().map(|(b, e)| (b.offset, e.offset)), Some((1, 7)));
+
+        // Nothing happens away from brackets.
+        tb.cursor_move_to_offset(8);
+        tb.jump_to_matching_bracket();
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 8, y: 0 });
+    }
+
+    #[test]
+    fn test_auto_indent() {
+        let mut tb = buffer_from("");
+        tb.set_language(crate::syntax::language_from_name("rust"));
+        tb.set_indent_with_tabs(false);
+        tb.set_tab_size(4);
+
+        // Enter between a pair of brackets puts the closing one on a line of its own.
+        tb.write_canon(b"fn a() {}");
+        tb.cursor_move_delta(CursorMovement::Grapheme, -1);
+        tb.write_canon(b"\n");
+        assert_eq!(contents(&mut tb), "fn a() {\n    \n}");
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 4, y: 1 });
+
+        // The indentation is carried over, and the closing bracket unindents again.
+        for text in ["if b {", "\n", "c;", "\n", "}"] {
+            tb.write_canon(text.as_bytes());
+        }
+        assert_eq!(contents(&mut tb), "fn a() {\n    if b {\n        c;\n    }\n}");
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 5, y: 3 });
+
+        // Undoing the closing bracket restores the indentation in the same step.
+        tb.undo();
+        assert_eq!(contents(&mut tb), "fn a() {\n    if b {\n        c;\n        \n}");
+        tb.redo();
+
+        // Without a language, only the indentation is carried over.
+        tb.select_all();
+        tb.set_language(None);
+        tb.write_canon(b"  a {\nb");
+        assert_eq!(contents(&mut tb), "  a {\n  b");
+    }
+
+    #[test]
+    fn test_auto_close() {
+        let mut tb = buffer_from("");
+        tb.set_auto_close(true);
+
+        // Openers get closed, closers are typed over.
+        for text in ["f", "(", "[", "\"", "a", "\"", "]", ")"] {
+            tb.write_canon(text.as_bytes());
+        }
+        assert_eq!(contents(&mut tb), "f([\"a\"])");
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 8, y: 0 });
+
+        // No pairs in front of words, and no quotes after them.
+        tb.cursor_move_to_offset(1);
+        tb.write_canon(b"{");
+        tb.cursor_move_to_offset(9);
+        tb.write_canon(b"b");
+        tb.write_canon(b"'");
+        assert_eq!(contents(&mut tb), "f{([\"a\"])b'");
+
+        // Closers that weren't inserted automatically aren't typed over.
+        tb.select_all();
+        tb.write_canon(b"g");
+        tb.write_raw(b"()");
+        tb.cursor_move_to_offset(2);
+        tb.write_canon(b")");
+        assert_eq!(contents(&mut tb), "g())");
+
+        // Backspace deletes an empty pair.
+        tb.select_all();
+        tb.write_canon(b"x");
+        tb.write_canon(b"(");
+        tb.delete(CursorMovement::Grapheme, -1);
+        assert_eq!(contents(&mut tb), "x");
+
+        // ...but only one that auto-close inserted.
+        tb.write_raw(b"()");
+        tb.cursor_move_to_offset(2);
+        tb.delete(CursorMovement::Grapheme, -1);
+        assert_eq!(contents(&mut tb), "x)");
+
+        // Once the caret leaves the pair, it's no longer typed over.
+        tb.select_all();
+        tb.write_canon(b"");
+        tb.write_canon(b"(");
+        tb.cursor_move_delta(CursorMovement::Grapheme, -1);
+        tb.cursor_move_delta(CursorMovement::Grapheme, 1);
+        tb.write_canon(b")");
+        assert_eq!(contents(&mut tb), "())");
+
+        // Neither after an undo.
+        tb.select_all();
+        tb.write_canon(b"");
+        tb.write_canon(b"(");
+        tb.write_raw(b"y");
+        tb.undo();
+        assert_eq!(contents(&mut tb), "()");
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 1, y: 0 });
+        tb.write_canon(b")");
+        assert_eq!(contents(&mut tb), "())");
+        tb.select_all();
+        tb.write_canon(b"x");
+
+        // The selection gets wrapped, which is undone in one step.
+        tb.select_all();
+        tb.write_canon(b"[");
+        assert_eq!(contents(&mut tb), "[x]");
+        assert_eq!(tb.selection_range().map(|(b, e)| (b.offset, e.offset)), Some((1, 2)));
+        tb.undo();
+        assert_eq!(contents(&mut tb), "x");
+    }
+
+    #[test]
+    fn test_toggle_comment() {
+        let mut tb = buffer_from("fn a() {\n    b;\n\n  c;\n}\n");
+        tb.set_language(crate::syntax::language_from_name("rust"));
+
+        // Lines are commented at their common indentation, skipping blank ones.
+        tb.cursor_move_to_logical(Point { x: 0, y: 1 });
+        tb.selection_update_logical(Point { x: 0, y: 4 });
+        tb.toggle_line_comment();
+        assert_eq!(contents(&mut tb), "fn a() {\n  //   b;\n\n  // c;\n}\n");
+        tb.toggle_line_comment();
+        assert_eq!(contents(&mut tb), "fn a() {\n    b;\n\n  c;\n}\n");
+        tb.toggle_line_comment();
+        tb.undo();
+        assert_eq!(contents(&mut tb), "fn a() {\n    b;\n\n  c;\n}\n");
+
+        // Without a selection, the block comment wraps the line's text.
+        tb.cursor_move_to_logical(Point { x: 6, y: 1 });
+        tb.toggle_block_comment();
+        assert_eq!(contents(&mut tb), "fn a() {\n    /* b; */\n\n  c;\n}\n");
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 9, y: 1 });
+        tb.toggle_block_comment();
+        assert_eq!(contents(&mut tb), "fn a() {\n    b;\n\n  c;\n}\n");
+
+        // Languages without line comments use block comments on each line.
+        tb.set_language(crate::syntax::language_from_name("markdown"));
+        tb.cursor_move_to_logical(Point { x: 0, y: 0 });
+        tb.toggle_line_comment();
+        assert_eq!(contents(&mut tb), "<!-- fn a() { -->\n    b;\n\n  c;\n}\n");
+    }
+
+    #[test]
+    fn test_line_operations() {
+        let mut tb = buffer_from("c\na\nb\na\n");
+
+        // Without a selection, the whole document is sorted, in a single undo step.
+        tb.sort_lines(SortOptions::default());
+        assert_eq!(contents(&mut tb), "a\na\nb\nc\n");
+        tb.undo();
+        assert_eq!(contents(&mut tb), "c\na\nb\na\n");
+
+        // With a selection, only the selected lines are affected and remain selected.
+        tb.cursor_move_to_logical(Point { x: 0, y: 1 });
+        tb.selection_update_logical(Point { x: 0, y: 4 });
+        tb.remove_duplicate_lines();
+        assert_eq!(contents(&mut tb), "c\na\nb\n");
+        tb.reverse_lines();
+        assert_eq!(contents(&mut tb), "c\nb\na\n");
+        assert_eq!(tb.selection_range().map(|(b, e)| (b.offset, e.offset)), Some((2, 5)));
+
+        tb.join_lines();
+        assert_eq!(contents(&mut tb), "c\nb a\n");
+
+        tb.clear_selection();
+        tb.cursor_move_to_logical(Point { x: 1, y: 0 });
+        tb.duplicate_lines(MoveLineDirection::Down);
+        assert_eq!(contents(&mut tb), "c\nc\nb a\n");
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 1, y: 1 });
+
+        tb.delete_lines();
+        assert_eq!(contents(&mut tb), "c\nb a\n");
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 1, y: 1 });
+
+        tb.insert_blank_line(MoveLineDirection::Up);
+        assert_eq!(contents(&mut tb), "c\n\nb a\n");
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 0, y: 1 });
+    }
+
+    #[test]
+    fn test_folding() {
+        let mut tb = buffer_from("// a\nfn a() {\n    b;\n    c;\n}\nd\n");
+        tb.cursor_move_to_logical(Point { x: 0, y: 3 });
+        tb.fold_at_cursor();
+        // The cursor moves onto the header and the closing bracket stays visible.
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 8, y: 1 });
+        assert_eq!(tb.visual_line_count(), 5);
+
+        // Visual navigation skips the folded lines.
+        tb.cursor_move_to_visual(Point { x: 0, y: 2 });
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 0, y: 4 });
+        assert_eq!(tb.cursor_visual_pos(), Point { x: 0, y: 2 });
+        tb.cursor_move_delta(CursorMovement::Grapheme, -1);
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 8, y: 1 });
+
+        // Edits before the fold move it along.
+        tb.cursor_move_to_logical(Point { x: 0, y: 0 });
+        tb.write_raw(b"\n");
+        assert_eq!(tb.visual_line_count(), 6);
+        tb.fold_toggle_visual(2);
+        assert_eq!(tb.visual_line_count(), 8);
+        tb.fold_toggle_visual(2);
+        assert_eq!(tb.visual_line_count(), 6);
+
+        // Moving into a fold or editing it unfolds it.
+        tb.cursor_move_to_logical(Point { x: 0, y: 3 });
+        assert_eq!(tb.visual_line_count(), 8);
+        tb.fold_all();
+        assert_eq!(tb.visual_line_count(), 6);
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 8, y: 2 });
+        tb.select_all();
+        tb.write_raw(b"x");
+        assert_eq!(tb.visual_line_count(), 1);
+    }
+}
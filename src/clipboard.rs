@@ -8,6 +8,7 @@
 pub struct Clipboard {
     data: Vec<u8>,
     line_copy: bool,
+    block_copy: bool,
     wants_host_sync: bool,
 }
 
@@ -32,6 +33,12 @@ impl Clipboard {
         self.line_copy
     }
 
+    /// True if the contents were copied from a block selection, with one line per row
+    /// of the block. Pasting such contents inserts them as a rectangle again.
+    pub fn is_block_copy(&self) -> bool {
+        self.block_copy
+    }
+
     /// Returns the current contents of the clipboard.
     pub fn read(&self) -> &[u8] {
         &self.data
@@ -42,6 +49,7 @@ impl Clipboard {
         if !data.is_empty() {
             self.data = data;
             self.line_copy = false;
+            self.block_copy = false;
             self.wants_host_sync = true;
         }
     }
@@ -50,4 +58,9 @@ impl Clipboard {
     pub fn write_was_line_copy(&mut self, line_copy: bool) {
         self.line_copy = line_copy;
     }
+
+    /// See [`Clipboard::is_block_copy`].
+    pub fn write_was_block_copy(&mut self, block_copy: bool) {
+        self.block_copy = block_copy;
+    }
 }
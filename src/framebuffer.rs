@@ -79,6 +79,29 @@ pub const DEFAULT_THEME: [StraightRgba; INDEXED_COLORS_COUNT] = [
     StraightRgba::from_be(0xbebebeff), // Foreground
 ];
 
+/// A light counterpart to [`DEFAULT_THEME`].
+pub const LIGHT_THEME: [StraightRgba; INDEXED_COLORS_COUNT] = [
+    StraightRgba::from_be(0x000000ff), // Black
+    StraightRgba::from_be(0xcd3131ff), // Red
+    StraightRgba::from_be(0x00bc00ff), // Green
+    StraightRgba::from_be(0x949800ff), // Yellow
+    StraightRgba::from_be(0x0451a5ff), // Blue
+    StraightRgba::from_be(0xbc05bcff), // Magenta
+    StraightRgba::from_be(0x0598bcff), // Cyan
+    StraightRgba::from_be(0x555555ff), // White
+    StraightRgba::from_be(0x666666ff), // BrightBlack
+    StraightRgba::from_be(0xcd3131ff), // BrightRed
+    StraightRgba::from_be(0x14ce14ff), // BrightGreen
+    StraightRgba::from_be(0xb5ba00ff), // BrightYellow
+    StraightRgba::from_be(0x0451a5ff), // BrightBlue
+    StraightRgba::from_be(0xbc05bcff), // BrightMagenta
+    StraightRgba::from_be(0x0598bcff), // BrightCyan
+    StraightRgba::from_be(0xa5a5a5ff), // BrightWhite
+    // --------
+    StraightRgba::from_be(0xffffffff), // Background
+    StraightRgba::from_be(0x333333ff), // Foreground
+];
+
 /// A shoddy framebuffer for terminal applications.
 ///
 /// The idea is that you create a [`Framebuffer`], draw a bunch of text and
@@ -143,6 +166,16 @@ impl Framebuffer {
         }
     }
 
+    /// Sets a color palette of our own, as opposed to the terminal's.
+    ///
+    /// Unlike with [`Framebuffer::set_indexed_colors`], the default colors of
+    /// the terminal don't match the palette, so we paint them explicitly.
+    pub fn set_theme(&mut self, colors: [StraightRgba; INDEXED_COLORS_COUNT]) {
+        self.set_indexed_colors(colors);
+        self.background_fill = colors[IndexedColor::Background as usize];
+        self.foreground_fill = colors[IndexedColor::Foreground as usize];
+    }
+
     /// Begins a new frame with the given `size`.
     pub fn flip(&mut self, size: Size) {
         if size != self.buffers[0].bg_bitmap.size {
@@ -197,12 +230,14 @@ impl Framebuffer {
     ///   In absolute viewport coordinates.
     /// * `content_offset`: The current offset of the scrollarea.
     /// * `content_height`: The height of the scrollarea content.
+    /// * `markers`: Lines of the content to mark on the track, e.g. search hits.
     pub fn draw_scrollbar(
         &mut self,
         clip_rect: Rect,
         track: Rect,
         content_offset: CoordType,
         content_height: CoordType,
+        markers: &[CoordType],
     ) -> CoordType {
         let track_clipped = track.intersect(clip_rect);
         if track_clipped.is_empty() {
@@ -275,6 +310,24 @@ impl Framebuffer {
         self.blend_bg(track_clipped, self.indexed(IndexedColor::BrightBlack));
         self.blend_fg(track_clipped, self.indexed(IndexedColor::BrightWhite));
 
+        // Draw the markers, except where they'd be covered by the thumb.
+        let thumb_covered = thumb_top - (top_fract != 0) as CoordType
+            ..thumb_bottom + (bottom_fract != 0) as CoordType;
+        for &line in markers {
+            let y = track.top + (line as i64 * viewport_height / content_height) as CoordType;
+            if y < track_clipped.top || y >= track_clipped.bottom || thumb_covered.contains(&y) {
+                continue;
+            }
+            self.replace_text(y, track_clipped.left, track_clipped.right, "━");
+            let rect = Rect {
+                left: track_clipped.left,
+                top: y,
+                right: track_clipped.right,
+                bottom: y + 1,
+            };
+            self.blend_fg(rect, self.indexed(IndexedColor::BrightYellow));
+        }
+
         // Draw the full blocks.
         for y in thumb_top..thumb_bottom {
             self.replace_text(y, track_clipped.left, track_clipped.right, "█");
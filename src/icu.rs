@@ -520,11 +520,7 @@ fn utext_access_impl<'a>(
             }
         }
 
-        loop {
-            let Some(c) = it.next() else {
-                break;
-            };
-
+        while let Some(c) = it.next() {
             // Thanks to our `if utf16_len >= UTF16_LEN_LIMIT` check,
             // we can safely assume that this will fit.
             unsafe {
@@ -678,6 +674,16 @@ impl Regex {
         unsafe { (f.uregex_reset64)(self.0, offset as i64, &mut status) };
     }
 
+    /// Restricts the regex to the given range of the underlying text and sets it to its start.
+    /// [`Regex::reset`] lifts the restriction again.
+    pub fn set_region(&mut self, range: Range<usize>) {
+        let f = assume_loaded();
+        let mut status = icu_ffi::U_ZERO_ERROR;
+        unsafe {
+            (f.uregex_setRegion64)(self.0, range.start as i64, range.end as i64, &mut status)
+        };
+    }
+
     /// Gets captured group count.
     pub fn group_count(&mut self) -> i32 {
         let f = assume_loaded();
@@ -916,6 +922,7 @@ struct LibraryFunctions {
     uregex_setTimeLimit: icu_ffi::uregex_setTimeLimit,
     uregex_setUText: icu_ffi::uregex_setUText,
     uregex_reset64: icu_ffi::uregex_reset64,
+    uregex_setRegion64: icu_ffi::uregex_setRegion64,
     uregex_findNext: icu_ffi::uregex_findNext,
     uregex_groupCount: icu_ffi::uregex_groupCount,
     uregex_start64: icu_ffi::uregex_start64,
@@ -944,7 +951,7 @@ const LIBICUUC_PROC_NAMES: [*const c_char; 10] = [
 ];
 
 // Found in libicui18n.so on UNIX, icuin.dll/icu.dll on Windows.
-const LIBICUI18N_PROC_NAMES: [*const c_char; 11] = [
+const LIBICUI18N_PROC_NAMES: [*const c_char; 12] = [
     proc_name!("ucol_open"),
     proc_name!("ucol_strcollUTF8"),
     proc_name!("uregex_open"),
@@ -952,6 +959,7 @@ const LIBICUI18N_PROC_NAMES: [*const c_char; 11] = [
     proc_name!("uregex_setTimeLimit"),
     proc_name!("uregex_setUText"),
     proc_name!("uregex_reset64"),
+    proc_name!("uregex_setRegion64"),
     proc_name!("uregex_findNext"),
     proc_name!("uregex_groupCount"),
     proc_name!("uregex_start64"),
@@ -1299,6 +1307,12 @@ mod icu_ffi {
     );
     pub type uregex_reset64 =
         unsafe extern "C" fn(regexp: *mut URegularExpression, index: i64, status: &mut UErrorCode);
+    pub type uregex_setRegion64 = unsafe extern "C" fn(
+        regexp: *mut URegularExpression,
+        region_start: i64,
+        region_limit: i64,
+        status: &mut UErrorCode,
+    );
     pub type uregex_findNext =
         unsafe extern "C" fn(regexp: *mut URegularExpression, status: &mut UErrorCode) -> bool;
     pub type uregex_groupCount =
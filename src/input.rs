@@ -54,7 +54,7 @@ impl InputKey {
         (self.0 & modifier.0) != 0
     }
 
-    pub(crate) const fn with_modifiers(&self, modifiers: InputKeyMod) -> Self {
+    pub const fn with_modifiers(&self, modifiers: InputKeyMod) -> Self {
         Self(self.0 | modifiers.0)
     }
 }
@@ -202,6 +202,11 @@ pub mod vk {
     pub const F22: InputKey = InputKey::new(0x85);
     pub const F23: InputKey = InputKey::new(0x86);
     pub const F24: InputKey = InputKey::new(0x87);
+
+    /// The `/?` key on US keyboards.
+    pub const OEM_2: InputKey = InputKey::new(0xBF);
+    /// The `\|` key on US keyboards.
+    pub const OEM_5: InputKey = InputKey::new(0xDC);
 }
 
 /// Keyboard modifiers.
@@ -338,6 +343,8 @@ impl<'input> Iterator for Stream<'_, '_, 'input> {
                         let key = ch as u32 | 0x40;
                         return Some(Input::Keyboard(kbmod::CTRL | InputKey::new(key)));
                     }
+                    '\x1c' => return Some(Input::Keyboard(kbmod::CTRL | vk::OEM_5)),
+                    '\x1f' => return Some(Input::Keyboard(kbmod::CTRL | vk::OEM_2)),
                     '\x7f' => return Some(Input::Keyboard(vk::BACK)),
                     _ => {}
                 },
@@ -0,0 +1,246 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! User-defined keybindings.
+//!
+//! The application and the [`crate::tui`] widgets only know about their hard-coded
+//! default shortcuts (e.g. `kbmod::CTRL | vk::S` to save). Instead of teaching
+//! each of them about custom keybindings, the [`Keymap`] sits in front of them
+//! and translates the keys the user pressed into the default shortcut of the
+//! command they're bound to, before anyone else gets to see them.
+//!
+//! This also makes multi-key chords like "Ctrl+K Ctrl+C" possible:
+//! The prefix is swallowed until the chord is complete.
+
+use crate::input::{InputKey, kbmod, vk};
+
+/// Names for all keys that aren't letters, digits or function keys.
+const KEY_NAMES: &[(&str, InputKey)] = &[
+    ("Backspace", vk::BACK),
+    ("Tab", vk::TAB),
+    ("Enter", vk::RETURN),
+    ("Escape", vk::ESCAPE),
+    ("Space", vk::SPACE),
+    ("PageUp", vk::PRIOR),
+    ("PageDown", vk::NEXT),
+    ("End", vk::END),
+    ("Home", vk::HOME),
+    ("Left", vk::LEFT),
+    ("Up", vk::UP),
+    ("Right", vk::RIGHT),
+    ("Down", vk::DOWN),
+    ("Insert", vk::INSERT),
+    ("Delete", vk::DELETE),
+    ("/", vk::OEM_2),
+    ("\\", vk::OEM_5),
+];
+
+struct Binding {
+    chord: Vec<InputKey>,
+    /// The default shortcut of the bound command, or [`vk::NULL`] if the chord is unbound.
+    target: InputKey,
+}
+
+/// Maps key chords to the default shortcuts of the commands they're bound to.
+#[derive(Default)]
+pub struct Keymap {
+    bindings: Vec<Binding>,
+    /// The keys of an incomplete multi-key chord so far.
+    pending: Vec<InputKey>,
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds `chord` to the command whose default shortcut is `target`.
+    ///
+    /// The default shortcut itself stops working, unless it's bound to something.
+    /// Binding a chord to [`vk::NULL`] unbinds it.
+    pub fn bind(&mut self, chord: Vec<InputKey>, target: InputKey) {
+        if chord.is_empty() {
+            return;
+        }
+        match self.bindings.iter_mut().find(|b| b.chord == chord) {
+            Some(b) => b.target = target,
+            None => self.bindings.push(Binding { chord, target }),
+        }
+    }
+
+    /// Returns true if we're in the middle of a multi-key chord.
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Translates a key the user pressed.
+    ///
+    /// Returns `None` if the key should be ignored, because it's either
+    /// the prefix of a multi-key chord, or a shortcut that got remapped.
+    pub fn translate(&mut self, key: InputKey) -> Option<InputKey> {
+        if self.bindings.is_empty() {
+            return Some(key);
+        }
+
+        let was_pending = self.is_pending();
+        self.pending.push(key);
+
+        let pending = &self.pending[..];
+        if self
+            .bindings
+            .iter()
+            .any(|b| b.chord.len() > pending.len() && b.chord.starts_with(pending))
+        {
+            return None;
+        }
+
+        let bound = self.bindings.iter().find(|b| b.chord == pending).map(|b| b.target);
+        self.pending.clear();
+
+        match bound {
+            Some(target) if target != vk::NULL => Some(target),
+            // Unbound chord.
+            Some(_) => None,
+            // An unknown multi-key chord is swallowed entirely.
+            None if was_pending => None,
+            // Shortcuts that were moved to another chord are disabled.
+            None if self.bindings.iter().any(|b| b.target == key) => None,
+            None => Some(key),
+        }
+    }
+
+    /// Aborts an incomplete multi-key chord. Returns true if there was one.
+    pub fn cancel(&mut self) -> bool {
+        let was_pending = self.is_pending();
+        self.pending.clear();
+        was_pending
+    }
+
+    /// Returns the chord the user needs to press to trigger the given default `shortcut`,
+    /// or `None` if it's been unbound.
+    pub fn chord_for<'a>(&'a self, shortcut: &'a InputKey) -> Option<&'a [InputKey]> {
+        if let Some(b) = self.bindings.iter().find(|b| b.target == *shortcut) {
+            Some(&b.chord)
+        } else if self.bindings.iter().any(|b| b.chord.len() == 1 && b.chord[0] == *shortcut) {
+            None
+        } else {
+            Some(std::slice::from_ref(shortcut))
+        }
+    }
+}
+
+/// Parses a chord like "Ctrl+K Ctrl+C" or "alt+shift+up". Case-insensitive.
+pub fn parse_chord(str: &str) -> Option<Vec<InputKey>> {
+    let mut chord = Vec::new();
+
+    for stroke in str.split_whitespace() {
+        let mut parts = stroke.split('+');
+        let key = parse_key(parts.next_back()?)?;
+        let mut modifiers = kbmod::NONE;
+
+        for m in parts {
+            modifiers |= if m.eq_ignore_ascii_case("ctrl") {
+                kbmod::CTRL
+            } else if m.eq_ignore_ascii_case("alt") {
+                kbmod::ALT
+            } else if m.eq_ignore_ascii_case("shift") {
+                kbmod::SHIFT
+            } else {
+                return None;
+            };
+        }
+
+        chord.push(key.with_modifiers(modifiers));
+    }
+
+    if chord.is_empty() { None } else { Some(chord) }
+}
+
+fn parse_key(name: &str) -> Option<InputKey> {
+    if let &[ch] = name.as_bytes()
+        && ch.is_ascii_alphanumeric()
+    {
+        return Some(InputKey::new(ch.to_ascii_uppercase() as u32));
+    }
+
+    if let Some(n) = name.strip_prefix(['f', 'F'])
+        && let Ok(n) = n.parse::<u32>()
+        && (1..=24).contains(&n)
+    {
+        return Some(InputKey::new(vk::F1.value() + n - 1));
+    }
+
+    KEY_NAMES.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|&(_, key)| key)
+}
+
+/// Appends the display name of `key`, without modifiers, to `out`.
+/// Returns false if the key has no name.
+pub(crate) fn push_key_name(out: &mut impl std::fmt::Write, key: InputKey) -> bool {
+    let key = key.key();
+    let value = key.value();
+
+    if let Ok(ch) = u8::try_from(value)
+        && (ch.is_ascii_uppercase() || ch.is_ascii_digit())
+    {
+        _ = out.write_char(ch as char);
+    } else if (vk::F1.value()..=vk::F24.value()).contains(&value) {
+        _ = write!(out, "F{}", value - vk::F1.value() + 1);
+    } else if let Some(&(name, _)) = KEY_NAMES.iter().find(|&&(_, k)| k == key) {
+        _ = out.write_str(name);
+    } else {
+        return false;
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chord() {
+        assert!(parse_chord("ctrl+s") == Some(vec![kbmod::CTRL | vk::S]));
+        assert!(parse_chord("Ctrl+Shift+F12") == Some(vec![kbmod::CTRL_SHIFT | vk::F12]));
+        assert!(parse_chord("alt+PageDown") == Some(vec![kbmod::ALT | vk::NEXT]));
+        assert!(
+            parse_chord(" ctrl+k  ctrl+c ") == Some(vec![kbmod::CTRL | vk::K, kbmod::CTRL | vk::C])
+        );
+        assert!(parse_chord("ctrl+k 1") == Some(vec![kbmod::CTRL | vk::K, vk::N1]));
+        assert!(parse_chord("ctrl+shift+\\") == Some(vec![kbmod::CTRL_SHIFT | vk::OEM_5]));
+        assert!(parse_chord("ctrl+/") == Some(vec![kbmod::CTRL | vk::OEM_2]));
+        assert!(parse_chord("").is_none());
+        assert!(parse_chord("ctrl+").is_none());
+        assert!(parse_chord("meta+a").is_none());
+        assert!(parse_chord("f25").is_none());
+    }
+
+    #[test]
+    fn test_translate() {
+        let mut keymap = Keymap::new();
+        keymap.bind(vec![kbmod::CTRL | vk::K, kbmod::CTRL | vk::C], kbmod::CTRL | vk::C);
+        keymap.bind(vec![kbmod::CTRL | vk::Y], kbmod::CTRL | vk::Z);
+        keymap.bind(vec![kbmod::CTRL | vk::Q], vk::NULL);
+
+        // Unaffected keys pass through.
+        assert!(keymap.translate(kbmod::CTRL | vk::S) == Some(kbmod::CTRL | vk::S));
+        // Remapped, unbound and moved shortcuts.
+        assert!(keymap.translate(kbmod::CTRL | vk::Y) == Some(kbmod::CTRL | vk::Z));
+        assert!(keymap.translate(kbmod::CTRL | vk::Q).is_none());
+        assert!(keymap.translate(kbmod::CTRL | vk::Z).is_none());
+        assert!(keymap.translate(kbmod::CTRL | vk::C).is_none());
+
+        // Multi-key chords.
+        assert!(keymap.translate(kbmod::CTRL | vk::K).is_none());
+        assert!(keymap.is_pending());
+        assert!(keymap.translate(kbmod::CTRL | vk::C) == Some(kbmod::CTRL | vk::C));
+        assert!(!keymap.is_pending());
+        assert!(keymap.translate(kbmod::CTRL | vk::K).is_none());
+        assert!(keymap.translate(vk::X).is_none());
+        assert!(keymap.translate(vk::X) == Some(vk::X));
+
+        assert!(keymap.chord_for(&(kbmod::CTRL | vk::Z)) == Some(&[kbmod::CTRL | vk::Y][..]));
+        assert!(keymap.chord_for(&(kbmod::CTRL | vk::Q)).is_none());
+        assert!(keymap.chord_for(&(kbmod::CTRL | vk::S)) == Some(&[kbmod::CTRL | vk::S][..]));
+    }
+}
@@ -5,7 +5,6 @@
     allocator_api,
     breakpoint,
     cold_path,
-    linked_list_cursors,
     maybe_uninit_fill,
     maybe_uninit_slice,
     maybe_uninit_uninit_array_transpose
@@ -32,9 +31,12 @@ pub mod hash;
 pub mod helpers;
 pub mod icu;
 pub mod input;
+pub mod keymap;
 pub mod oklab;
 pub mod path;
+pub mod regex;
 pub mod simd;
+pub mod syntax;
 pub mod sys;
 pub mod tui;
 pub mod unicode;
@@ -0,0 +1,925 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A small regex engine for searching when ICU isn't available.
+//!
+//! It supports a practical subset of the ICU syntax: literals and escapes, `.`, character
+//! classes including `\d`, `\w` and `\s`, the anchors `^`, `$`, `\b` and `\B`, capturing and
+//! non-capturing groups, alternations and greedy or lazy quantifiers. `^` and `$` always match
+//! at line breaks, like with ICU's `MULTILINE` flag. Case-insensitive matching uses simple
+//! case folding. Like ICU's `UText`, it works directly on the chunks of a [`ReadableDocument`].
+//!
+//! The matcher is a backtracking one, but it remembers which branches it already tried at
+//! which offset. Since those failed, they fail for later attempts of the same search, too.
+//! A search thus takes time linear in the length of the text it looks at.
+
+use std::ops::Range;
+
+use crate::apperr::{self, APP_REGEX_INVALID};
+use crate::document::ReadableDocument;
+use crate::simd::memchr2;
+use crate::unicode::Utf8Chars;
+
+/// Counted repetitions are expanded, so they're limited to keep the program small.
+const MAX_REPEAT: u32 = 1000;
+/// The size limit of the compiled program.
+const MAX_INSTS: usize = 100 * MAX_REPEAT as usize;
+
+/// The zeros of all decimal digits (General_Category=Nd) as of Unicode 16.0.
+/// Unicode guarantees that they're encoded in runs of 10, from 0 to 9.
+const DECIMAL_DIGIT_ZEROS: [u32; 76] = [
+    0x0030, 0x0660, 0x06F0, 0x07C0, 0x0966, 0x09E6, 0x0A66, 0x0AE6, 0x0B66, 0x0BE6, 0x0C66, 0x0CE6,
+    0x0D66, 0x0DE6, 0x0E50, 0x0ED0, 0x0F20, 0x1040, 0x1090, 0x17E0, 0x1810, 0x1946, 0x19D0, 0x1A80,
+    0x1A90, 0x1B50, 0x1BB0, 0x1C40, 0x1C50, 0xA620, 0xA8D0, 0xA900, 0xA9D0, 0xA9F0, 0xAA50, 0xABF0,
+    0xFF10, 0x104A0, 0x10D30, 0x10D40, 0x11066, 0x110F0, 0x11136, 0x111D0, 0x112F0, 0x11450,
+    0x114D0, 0x11650, 0x116C0, 0x116D0, 0x116DA, 0x11730, 0x118E0, 0x11950, 0x11BF0, 0x11C50,
+    0x11D50, 0x11DA0, 0x11F50, 0x16130, 0x16A60, 0x16AC0, 0x16B50, 0x16D70, 0x1CCF0, 0x1D7CE,
+    0x1D7D8, 0x1D7E2, 0x1D7EC, 0x1D7F6, 0x1E140, 0x1E2F0, 0x1E4F0, 0x1E5F1, 0x1E950, 0x1FBF0,
+];
+
+#[derive(Clone)]
+enum ClassItem {
+    Range(char, char),
+    Digit(bool),
+    Word(bool),
+    Space(bool),
+}
+
+impl ClassItem {
+    fn matches(&self, ch: char) -> bool {
+        match *self {
+            Self::Range(beg, end) => (beg..=end).contains(&ch),
+            Self::Digit(negated) => is_decimal_digit(ch) != negated,
+            Self::Word(negated) => is_word_char(ch) != negated,
+            Self::Space(negated) => ch.is_whitespace() != negated,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Class {
+    items: Vec<ClassItem>,
+    negated: bool,
+}
+
+impl Class {
+    fn matches(&self, ch: char, case_insensitive: bool) -> bool {
+        let hit = |ch| self.items.iter().any(|item| item.matches(ch));
+        let mut res = hit(ch);
+        if !res && case_insensitive {
+            res = hit(fold(ch)) || hit(upper(ch));
+        }
+        res != self.negated
+    }
+}
+
+#[derive(Clone)]
+enum Node {
+    Empty,
+    Char(char),
+    Any,
+    Class(Class),
+    LineStart,
+    LineEnd,
+    WordBoundary(bool),
+    /// A group and its capture index, if it's a capturing one.
+    Group(Option<usize>, Box<Node>),
+    Concat(Vec<Node>),
+    Alternation(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+}
+
+enum Inst {
+    Char(char),
+    Any,
+    Class(usize),
+    LineStart,
+    LineEnd,
+    WordBoundary(bool),
+    /// Tries the first branch and backtracks into the second.
+    /// The third value is the index of the split among all of them, for [`Regex::visited`].
+    Split(usize, usize, usize),
+    Jump(usize),
+    Save(usize),
+    Match,
+}
+
+enum Job {
+    Try { pc: usize, off: usize },
+    Restore { slot: usize, value: Option<usize> },
+}
+
+pub struct Regex {
+    insts: Vec<Inst>,
+    classes: Vec<Class>,
+    group_count: usize,
+    case_insensitive: bool,
+    /// Bytes that any match must start with, for quickly skipping to possible matches.
+    prefix: Option<(u8, u8)>,
+
+    /// The number of [`Inst::Split`]s.
+    split_count: usize,
+
+    // The state of the last match. It's kept around to reuse the allocations.
+    slots: Vec<Option<usize>>,
+    stack: Vec<Job>,
+    /// A bitset of the splits that were tried at each offset from `visited_base` on.
+    /// The bit of split `i` at offset `off` is `(off - visited_base) * split_count + i`.
+    visited: Vec<u64>,
+    visited_base: usize,
+}
+
+impl Regex {
+    /// Enable case-insensitive matching.
+    pub const CASE_INSENSITIVE: i32 = 1;
+
+    /// Treat the given pattern as a literal string.
+    pub const LITERAL: i32 = 2;
+
+    pub fn new(pattern: &str, flags: i32) -> apperr::Result<Self> {
+        let case_insensitive = flags & Self::CASE_INSENSITIVE != 0;
+
+        let node = if flags & Self::LITERAL != 0 {
+            Node::Concat(pattern.chars().map(Node::Char).collect())
+        } else {
+            let mut parser = Parser { pattern, pos: 0, group_count: 0 };
+            let node = parser.parse_alternation()?;
+            if parser.pos != pattern.len() {
+                // An unbalanced closing parenthesis.
+                return Err(APP_REGEX_INVALID);
+            }
+            node
+        };
+
+        let mut compiler = Compiler { insts: Vec::new(), classes: Vec::new(), case_insensitive };
+        compiler.insts.push(Inst::Save(0));
+        compiler.compile(node)?;
+        compiler.insts.push(Inst::Save(1));
+        compiler.insts.push(Inst::Match);
+
+        let mut group_count = 0;
+        let mut split_count = 0;
+        for inst in &mut compiler.insts {
+            match inst {
+                Inst::Save(slot) => group_count = group_count.max(*slot / 2),
+                Inst::Split(.., index) => {
+                    *index = split_count;
+                    split_count += 1;
+                }
+                _ => {}
+            }
+        }
+
+        let prefix = match compiler.insts.get(1) {
+            Some(&Inst::Char(ch)) if ch.is_ascii() => {
+                let b = ch as u8;
+                Some(if case_insensitive {
+                    (b.to_ascii_lowercase(), b.to_ascii_uppercase())
+                } else {
+                    (b, b)
+                })
+            }
+            Some(&Inst::Char(ch)) if !case_insensitive => {
+                let mut buf = [0; 4];
+                let b = ch.encode_utf8(&mut buf).as_bytes()[0];
+                Some((b, b))
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            insts: compiler.insts,
+            classes: compiler.classes,
+            group_count,
+            case_insensitive,
+            prefix,
+            split_count,
+            slots: vec![None; (group_count + 1) * 2],
+            stack: Vec::new(),
+            visited: Vec::new(),
+            visited_base: 0,
+        })
+    }
+
+    /// The number of capturing groups, excluding the implicit group 0 of the entire match.
+    pub fn group_count(&self) -> usize {
+        self.group_count
+    }
+
+    /// The range of the given capturing group in the last match.
+    pub fn group(&self, group: usize) -> Option<Range<usize>> {
+        let beg = (*self.slots.get(group * 2)?)?;
+        let end = (*self.slots.get(group * 2 + 1)?)?;
+        Some(beg..end.max(beg))
+    }
+
+    /// Finds the first match that starts at or after `off`.
+    pub fn find_at(&mut self, doc: &dyn ReadableDocument, off: usize) -> Option<Range<usize>> {
+        self.find_in(doc, off, usize::MAX)
+    }
+
+    /// Finds the first match that starts at or after `off`, but not after `limit`.
+    pub fn find_in(
+        &mut self,
+        doc: &dyn ReadableDocument,
+        mut off: usize,
+        limit: usize,
+    ) -> Option<Range<usize>> {
+        self.visited.clear();
+        self.visited_base = off;
+
+        loop {
+            if let Some((b1, b2)) = self.prefix {
+                // Skip to the next possible start of a match.
+                loop {
+                    let chunk = doc.read_forward(off);
+                    if chunk.is_empty() {
+                        return None;
+                    }
+                    let chunk = &chunk[..chunk.len().min(limit.saturating_sub(off))];
+                    let i = memchr2(b1, b2, chunk, 0);
+                    off += i;
+                    if i < chunk.len() || off >= limit {
+                        break;
+                    }
+                }
+            }
+
+            if off > limit {
+                return None;
+            }
+            // Once the previous attempts didn't get this far, none of what they tried can be
+            // reached anymore. This avoids growing the bitset while skipping through the text.
+            if (off - self.visited_base) * self.split_count >= self.visited.len() * 64 {
+                self.visited.clear();
+                self.visited_base = off;
+            }
+            if self.match_at(doc, off) {
+                return self.group(0);
+            }
+
+            let (_, len) = char_at(doc, off)?;
+            off += len;
+        }
+    }
+
+    fn match_at(&mut self, doc: &dyn ReadableDocument, off: usize) -> bool {
+        self.slots.fill(None);
+        self.stack.clear();
+        self.stack.push(Job::Try { pc: 0, off });
+
+        while let Some(job) = self.stack.pop() {
+            let (mut pc, mut off) = match job {
+                Job::Try { pc, off } => (pc, off),
+                Job::Restore { slot, value } => {
+                    self.slots[slot] = value;
+                    continue;
+                }
+            };
+
+            loop {
+                match self.insts[pc] {
+                    Inst::Char(expected) => match char_at(doc, off) {
+                        Some((ch, len))
+                            if ch == expected
+                                || (self.case_insensitive && fold(ch) == expected) =>
+                        {
+                            off += len;
+                            pc += 1;
+                        }
+                        _ => break,
+                    },
+                    Inst::Any => match char_at(doc, off) {
+                        Some((ch, len)) if !is_line_break(ch) => {
+                            off += len;
+                            pc += 1;
+                        }
+                        _ => break,
+                    },
+                    Inst::Class(i) => match char_at(doc, off) {
+                        Some((ch, len)) if self.classes[i].matches(ch, self.case_insensitive) => {
+                            off += len;
+                            pc += 1;
+                        }
+                        _ => break,
+                    },
+                    Inst::LineStart => {
+                        let prev = char_before(doc, off);
+                        let next = char_at(doc, off).map(|(ch, _)| ch);
+                        if prev.is_some_and(is_line_break)
+                            && !(prev == Some('\r') && next == Some('\n'))
+                            || prev.is_none()
+                        {
+                            pc += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    Inst::LineEnd => {
+                        let prev = char_before(doc, off);
+                        let next = char_at(doc, off).map(|(ch, _)| ch);
+                        if next.is_none_or(is_line_break)
+                            && !(prev == Some('\r') && next == Some('\n'))
+                        {
+                            pc += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    Inst::WordBoundary(expected) => {
+                        let prev = char_before(doc, off).is_some_and(is_word_char);
+                        let next = char_at(doc, off).is_some_and(|(ch, _)| is_word_char(ch));
+                        if (prev != next) == expected {
+                            pc += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    Inst::Split(first, second, index) => {
+                        // If this branch was already tried at this offset, it failed back then,
+                        // because captures don't affect the outcome of the rest of the match.
+                        if !self.visit((off - self.visited_base) * self.split_count + index) {
+                            break;
+                        }
+                        self.stack.push(Job::Try { pc: second, off });
+                        pc = first;
+                    }
+                    Inst::Jump(target) => pc = target,
+                    Inst::Save(slot) => {
+                        self.stack.push(Job::Restore { slot, value: self.slots[slot] });
+                        self.slots[slot] = Some(off);
+                        pc += 1;
+                    }
+                    Inst::Match => return true,
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Sets the given bit in [`Regex::visited`] and returns whether it wasn't set yet.
+    fn visit(&mut self, bit: usize) -> bool {
+        let (word, mask) = (bit / 64, 1 << (bit % 64));
+        if word >= self.visited.len() {
+            self.visited.resize(word + 1, 0);
+        }
+        let res = self.visited[word] & mask == 0;
+        self.visited[word] |= mask;
+        res
+    }
+}
+
+struct Parser<'a> {
+    pattern: &'a str,
+    pos: usize,
+    group_count: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.pattern[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.pos += ch.len_utf8();
+        Some(ch)
+    }
+
+    fn eat(&mut self, ch: char) -> bool {
+        let res = self.peek() == Some(ch);
+        if res {
+            self.pos += ch.len_utf8();
+        }
+        res
+    }
+
+    fn parse_alternation(&mut self) -> apperr::Result<Node> {
+        let mut alternatives = vec![self.parse_concat()?];
+        while self.eat('|') {
+            alternatives.push(self.parse_concat()?);
+        }
+        Ok(if alternatives.len() == 1 {
+            alternatives.pop().unwrap()
+        } else {
+            Node::Alternation(alternatives)
+        })
+    }
+
+    fn parse_concat(&mut self) -> apperr::Result<Node> {
+        let mut nodes = Vec::new();
+        while let Some(ch) = self.peek()
+            && ch != '|'
+            && ch != ')'
+        {
+            nodes.push(self.parse_repeat()?);
+        }
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().unwrap(),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn parse_repeat(&mut self) -> apperr::Result<Node> {
+        let mut node = self.parse_atom()?;
+
+        loop {
+            let (min, max) = match self.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') => {
+                    self.next();
+                    let min = self.parse_number().ok_or(APP_REGEX_INVALID)?;
+                    let max = if self.eat(',') {
+                        if self.peek() == Some('}') {
+                            None
+                        } else {
+                            Some(self.parse_number().ok_or(APP_REGEX_INVALID)?)
+                        }
+                    } else {
+                        Some(min)
+                    };
+                    if self.peek() != Some('}') || max.is_some_and(|max| max < min) {
+                        return Err(APP_REGEX_INVALID);
+                    }
+                    (min, max)
+                }
+                _ => break,
+            };
+            self.next();
+
+            let greedy = !self.eat('?');
+            node = Node::Repeat { node: Box::new(node), min, max, greedy };
+        }
+
+        Ok(node)
+    }
+
+    fn parse_number(&mut self) -> Option<u32> {
+        let beg = self.pos;
+        while self.peek().is_some_and(|ch| ch.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.pattern[beg..self.pos].parse().ok().filter(|&n| n <= MAX_REPEAT)
+    }
+
+    fn parse_atom(&mut self) -> apperr::Result<Node> {
+        let ch = self.next().ok_or(APP_REGEX_INVALID)?;
+        Ok(match ch {
+            '(' => {
+                let index = if self.eat('?') {
+                    // Only non-capturing groups are supported, but no lookarounds, etc.
+                    if !self.eat(':') {
+                        return Err(APP_REGEX_INVALID);
+                    }
+                    None
+                } else {
+                    self.group_count += 1;
+                    Some(self.group_count)
+                };
+                let node = self.parse_alternation()?;
+                if !self.eat(')') {
+                    return Err(APP_REGEX_INVALID);
+                }
+                Node::Group(index, Box::new(node))
+            }
+            '[' => Node::Class(self.parse_class()?),
+            '.' => Node::Any,
+            '^' => Node::LineStart,
+            '$' => Node::LineEnd,
+            '\\' if self.eat('b') => Node::WordBoundary(true),
+            '\\' if self.eat('B') => Node::WordBoundary(false),
+            '\\' => match self.parse_escape()? {
+                Ok(item) => Node::Class(Class { items: vec![item], negated: false }),
+                Err(ch) => Node::Char(ch),
+            },
+            '*' | '+' | '?' | '{' => return Err(APP_REGEX_INVALID),
+            ch => Node::Char(ch),
+        })
+    }
+
+    /// Parses the escape sequence after a `\` into a class like `\d` or a literal char.
+    fn parse_escape(&mut self) -> apperr::Result<Result<ClassItem, char>> {
+        let ch = self.next().ok_or(APP_REGEX_INVALID)?;
+        Ok(Ok(match ch {
+            'd' => ClassItem::Digit(false),
+            'D' => ClassItem::Digit(true),
+            'w' => ClassItem::Word(false),
+            'W' => ClassItem::Word(true),
+            's' => ClassItem::Space(false),
+            'S' => ClassItem::Space(true),
+            'n' => return Ok(Err('\n')),
+            'r' => return Ok(Err('\r')),
+            't' => return Ok(Err('\t')),
+            'f' => return Ok(Err('\x0c')),
+            'v' => return Ok(Err('\x0b')),
+            'a' => return Ok(Err('\x07')),
+            'e' => return Ok(Err('\x1b')),
+            'x' | 'u' => {
+                let braced = ch == 'x' && self.eat('{');
+                let len = match (braced, ch) {
+                    (true, _) => 6,
+                    (false, 'x') => 2,
+                    _ => 4,
+                };
+                let beg = self.pos;
+                while self.pos - beg < len && self.peek().is_some_and(|ch| ch.is_ascii_hexdigit()) {
+                    self.pos += 1;
+                }
+                let digits = &self.pattern[beg..self.pos];
+                if (!braced && digits.len() != len) || (braced && !self.eat('}')) {
+                    return Err(APP_REGEX_INVALID);
+                }
+                let ch = u32::from_str_radix(digits, 16).ok().and_then(char::from_u32);
+                return Ok(Err(ch.ok_or(APP_REGEX_INVALID)?));
+            }
+            // Like ICU with `UREGEX_ERROR_ON_UNKNOWN_ESCAPES`, unknown letters are an error.
+            ch if ch.is_ascii_alphanumeric() => return Err(APP_REGEX_INVALID),
+            ch => return Ok(Err(ch)),
+        }))
+    }
+
+    fn parse_class(&mut self) -> apperr::Result<Class> {
+        let negated = self.eat('^');
+        let mut items = Vec::new();
+        let mut first = true;
+
+        loop {
+            let ch = self.next().ok_or(APP_REGEX_INVALID)?;
+            // A leading `]` is a literal one.
+            if ch == ']' && !first {
+                break;
+            }
+            first = false;
+
+            let beg = if ch == '\\' {
+                match self.parse_escape()? {
+                    Ok(item) => {
+                        items.push(item);
+                        continue;
+                    }
+                    Err(ch) => ch,
+                }
+            } else {
+                ch
+            };
+
+            let mut end = beg;
+            if self.pattern[self.pos..].starts_with('-')
+                && !self.pattern[self.pos..].starts_with("-]")
+            {
+                self.next();
+                end = match self.next().ok_or(APP_REGEX_INVALID)? {
+                    '\\' => match self.parse_escape()? {
+                        Ok(_) => return Err(APP_REGEX_INVALID),
+                        Err(ch) => ch,
+                    },
+                    ch => ch,
+                };
+                if end < beg {
+                    return Err(APP_REGEX_INVALID);
+                }
+            }
+            items.push(ClassItem::Range(beg, end));
+        }
+
+        Ok(Class { items, negated })
+    }
+}
+
+struct Compiler {
+    insts: Vec<Inst>,
+    classes: Vec<Class>,
+    case_insensitive: bool,
+}
+
+impl Compiler {
+    fn compile(&mut self, node: Node) -> apperr::Result<()> {
+        match node {
+            Node::Empty => {}
+            Node::Char(ch) => {
+                let ch = if self.case_insensitive { fold(ch) } else { ch };
+                self.insts.push(Inst::Char(ch));
+            }
+            Node::Any => self.insts.push(Inst::Any),
+            Node::Class(class) => {
+                self.insts.push(Inst::Class(self.classes.len()));
+                self.classes.push(class);
+            }
+            Node::LineStart => self.insts.push(Inst::LineStart),
+            Node::LineEnd => self.insts.push(Inst::LineEnd),
+            Node::WordBoundary(expected) => self.insts.push(Inst::WordBoundary(expected)),
+            Node::Group(index, node) => {
+                if let Some(i) = index {
+                    self.insts.push(Inst::Save(i * 2));
+                }
+                self.compile(*node)?;
+                if let Some(i) = index {
+                    self.insts.push(Inst::Save(i * 2 + 1));
+                }
+            }
+            Node::Concat(nodes) => {
+                for node in nodes {
+                    self.compile(node)?;
+                }
+            }
+            Node::Alternation(nodes) => {
+                let count = nodes.len();
+                let mut jumps = Vec::new();
+                for (i, node) in nodes.into_iter().enumerate() {
+                    if i + 1 < count {
+                        let split = self.insts.len();
+                        self.insts.push(Inst::Split(split + 1, 0, 0));
+                        self.compile(node)?;
+                        jumps.push(self.insts.len());
+                        self.insts.push(Inst::Jump(0));
+                        self.insts[split] = Inst::Split(split + 1, self.insts.len(), 0);
+                    } else {
+                        self.compile(node)?;
+                    }
+                }
+                let end = self.insts.len();
+                for jump in jumps {
+                    self.insts[jump] = Inst::Jump(end);
+                }
+            }
+            Node::Repeat { node, min, max, greedy } => {
+                self.compile_repeat(&node, min, max, greedy)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_repeat(
+        &mut self,
+        node: &Node,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    ) -> apperr::Result<()> {
+        let split = |body, exit| {
+            if greedy { Inst::Split(body, exit, 0) } else { Inst::Split(exit, body, 0) }
+        };
+
+        let copies = max.unwrap_or(min) as usize;
+        let mut copy = 0;
+
+        for _ in 0..min {
+            copy += 1;
+            self.compile_repeat_copy(node, copies - copy)?;
+        }
+
+        match max {
+            None => {
+                let beg = self.insts.len();
+                self.insts.push(Inst::Jump(0));
+                self.compile(node.clone())?;
+                self.insts.push(Inst::Jump(beg));
+                self.insts[beg] = split(beg + 1, self.insts.len());
+            }
+            Some(max) => {
+                let mut splits = Vec::new();
+                for _ in min..max {
+                    copy += 1;
+                    splits.push(self.insts.len());
+                    self.insts.push(Inst::Jump(0));
+                    self.compile_repeat_copy(node, copies - copy)?;
+                }
+                let end = self.insts.len();
+                for beg in splits {
+                    self.insts[beg] = split(beg + 1, end);
+                }
+            }
+        }
+
+        if self.insts.len() > MAX_INSTS {
+            return Err(APP_REGEX_INVALID);
+        }
+        Ok(())
+    }
+
+    /// Compiles one copy of a repeated `node`. Fails if the `remaining` copies would exceed
+    /// the size limit, before expanding them. Otherwise nested repetitions like `(?:a{1000}){1000}`
+    /// would allocate a million instructions first.
+    fn compile_repeat_copy(&mut self, node: &Node, remaining: usize) -> apperr::Result<()> {
+        let beg = self.insts.len();
+        self.compile(node.clone())?;
+        // +1 for the `Split` in front of optional copies.
+        let len = self.insts.len() - beg + 1;
+        if self.insts.len().saturating_add(len.saturating_mul(remaining)) > MAX_INSTS {
+            return Err(APP_REGEX_INVALID);
+        }
+        Ok(())
+    }
+}
+
+fn is_decimal_digit(ch: char) -> bool {
+    if ch.is_ascii() {
+        return ch.is_ascii_digit();
+    }
+    let ch = ch as u32;
+    let i = DECIMAL_DIGIT_ZEROS.partition_point(|&zero| zero <= ch);
+    i > 0 && ch - DECIMAL_DIGIT_ZEROS[i - 1] < 10
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+fn is_line_break(ch: char) -> bool {
+    matches!(ch, '\n' | '\r' | '\u{85}' | '\u{2028}' | '\u{2029}')
+}
+
+/// Simple case folding: The lowercase form of `ch`, if it's a single char.
+fn fold(ch: char) -> char {
+    if ch.is_ascii() {
+        return ch.to_ascii_lowercase();
+    }
+    let mut lower = ch.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(lower), None) => lower,
+        _ => ch,
+    }
+}
+
+fn upper(ch: char) -> char {
+    if ch.is_ascii() {
+        return ch.to_ascii_uppercase();
+    }
+    let mut upper = ch.to_uppercase();
+    match (upper.next(), upper.next()) {
+        (Some(upper), None) => upper,
+        _ => ch,
+    }
+}
+
+/// Decodes the char at `off` and returns it with its length in bytes.
+fn char_at(doc: &dyn ReadableDocument, off: usize) -> Option<(char, usize)> {
+    let chunk = doc.read_forward(off);
+    let &first = chunk.first()?;
+    if first < 0x80 {
+        return Some((first as char, 1));
+    }
+
+    // Chars shouldn't straddle chunks, but better safe than sorry.
+    let mut buf = [0; 4];
+    let mut len = 0;
+    while len < buf.len() {
+        let chunk = doc.read_forward(off + len);
+        if chunk.is_empty() {
+            break;
+        }
+        let n = chunk.len().min(buf.len() - len);
+        buf[len..len + n].copy_from_slice(&chunk[..n]);
+        len += n;
+    }
+
+    let mut it = Utf8Chars::new(&buf[..len], 0);
+    let ch = it.next()?;
+    Some((ch, it.offset()))
+}
+
+/// Decodes the char that ends at `off`.
+fn char_before(doc: &dyn ReadableDocument, off: usize) -> Option<char> {
+    let mut buf = [0; 4];
+    let mut len = 0;
+    while len < buf.len() && len < off {
+        let chunk = doc.read_backward(off - len);
+        if chunk.is_empty() {
+            break;
+        }
+        let n = chunk.len().min(buf.len() - len);
+        let end = buf.len() - len;
+        buf[end - n..end].copy_from_slice(&chunk[chunk.len() - n..]);
+        len += n;
+    }
+
+    let bytes = &buf[buf.len() - len..];
+    let mut beg = bytes.len().checked_sub(1)?;
+    while beg > 0 && bytes[beg] & 0xC0 == 0x80 {
+        beg -= 1;
+    }
+    let mut it = Utf8Chars::new(&bytes[beg..], 0);
+    let ch = it.next()?;
+    // Invalid sequences decode as a single U+FFFD, like in `char_at`.
+    Some(if it.offset() == bytes.len() - beg { ch } else { '\u{FFFD}' })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find_all(pattern: &str, flags: i32, text: &str) -> Vec<(usize, usize)> {
+        let mut re = Regex::new(pattern, flags).unwrap();
+        let doc = text.as_bytes();
+        let mut res = Vec::new();
+        let mut off = 0;
+        while let Some(range) = re.find_at(&doc, off) {
+            off = if range.is_empty() { range.end + 1 } else { range.end };
+            res.push((range.start, range.end));
+        }
+        res
+    }
+
+    #[test]
+    fn test_literal() {
+        assert_eq!(find_all("a.b", Regex::LITERAL, "axb a.b"), [(4, 7)]);
+        assert_eq!(
+            find_all("ÄB", Regex::LITERAL | Regex::CASE_INSENSITIVE, "äb Äb"),
+            [(0, 3), (4, 7)]
+        );
+        assert_eq!(find_all("foo", 0, "Foo foo"), [(4, 7)]);
+        assert_eq!(find_all("foo", Regex::CASE_INSENSITIVE, "Foo fOO"), [(0, 3), (4, 7)]);
+    }
+
+    #[test]
+    fn test_regex() {
+        assert_eq!(find_all(r"\d+", 0, "a12 b3"), [(1, 3), (5, 6)]);
+        assert_eq!(find_all(r"\bfoo\b", 0, "foo food _foo foo"), [(0, 3), (14, 17)]);
+        assert_eq!(find_all(r"^\w+$", 0, "ab\ncd e\r\nfg"), [(0, 2), (9, 11)]);
+        assert_eq!(find_all(r"a.*?b", 0, "aXbYb\nab"), [(0, 3), (6, 8)]);
+        assert_eq!(find_all(r"a.*b", 0, "aXbYb"), [(0, 5)]);
+        assert_eq!(find_all(r"x{2,3}", 0, "x xx xxxx"), [(2, 4), (5, 8)]);
+        assert_eq!(find_all(r"[^a-c\s]+", 0, "abxyz cd"), [(2, 5), (7, 8)]);
+        assert_eq!(find_all(r"[]x-]", 0, "a]b-x"), [(1, 2), (3, 4), (4, 5)]);
+        assert_eq!(find_all(r"cat|dog", 0, "hotdog cats"), [(3, 6), (7, 10)]);
+        assert_eq!(find_all(r"(?:ab)+", 0, "ababa"), [(0, 4)]);
+        assert_eq!(find_all(r"\x41ä\.", 0, "Aä."), [(0, 4)]);
+        // Empty loops must terminate.
+        assert_eq!(find_all(r"(a*)*b", 0, "aac"), []);
+        // Only decimal digits are digits, not "²" or "Ⅻ".
+        assert_eq!(find_all(r"\d+", 0, "١٢ ² Ⅻ ９"), [(0, 4), (12, 15)]);
+        assert_eq!(find_all(r"\D", 0, "²1"), [(0, 2)]);
+        // Failed attempts aren't repeated from each later offset.
+        assert_eq!(find_all(r"[^x]*y", 0, &"a".repeat(100_000)), []);
+
+        for pattern in ["(", "a)", "[a", "*a", r"\q", "(?=a)", "a{3,2}", r"\x4"] {
+            assert!(Regex::new(pattern, 0).is_err(), "{pattern}");
+        }
+    }
+
+    #[test]
+    fn test_groups() {
+        let mut re = Regex::new(r"(\w+)=(\d+)?", 0).unwrap();
+        assert_eq!(re.group_count(), 2);
+        assert_eq!(re.find_at(&"x = 1, ab=12".as_bytes(), 0), Some(7..12));
+        assert_eq!(re.group(1), Some(7..9));
+        assert_eq!(re.group(2), Some(10..12));
+        assert_eq!(re.find_at(&"ab=".as_bytes(), 0), Some(0..3));
+        assert_eq!(re.group(2), None);
+    }
+
+    #[test]
+    fn test_find_in() {
+        let mut re = Regex::new("ab", 0).unwrap();
+        let doc = "xxab ab".as_bytes();
+        assert_eq!(re.find_in(&doc, 0, 1), None);
+        assert_eq!(re.find_in(&doc, 0, 2), Some(2..4));
+        assert_eq!(re.find_in(&doc, 3, 4), None);
+        assert_eq!(re.find_in(&doc, 3, 5), Some(5..7));
+
+        let mut re = Regex::new(r"\s", 0).unwrap();
+        assert_eq!(re.find_in(&doc, 0, 3), None);
+        assert_eq!(re.find_in(&doc, 0, 4), Some(4..5));
+    }
+
+    #[test]
+    fn test_chunks() {
+        // A document that splits its text into chunks of one byte.
+        struct Bytes<'a>(&'a [u8]);
+        impl ReadableDocument for Bytes<'_> {
+            fn read_forward(&self, off: usize) -> &[u8] {
+                let off = off.min(self.0.len());
+                &self.0[off..(off + 1).min(self.0.len())]
+            }
+            fn read_backward(&self, off: usize) -> &[u8] {
+                let off = off.min(self.0.len());
+                &self.0[off.saturating_sub(1)..off]
+            }
+        }
+
+        let mut re = Regex::new(r"\bé+\b", Regex::CASE_INSENSITIVE).unwrap();
+        assert_eq!(re.find_at(&Bytes("xé Éé".as_bytes()), 0), Some(4..8));
+    }
+
+    #[test]
+    fn test_repeat_limit() {
+        assert!(Regex::new(r"(?:ab){1000}", 0).is_ok());
+        assert!(Regex::new(r"(?:a{100}){100}", 0).is_ok());
+
+        // These fail on the first copy of the outer repetition, instead of expanding it first.
+        for pattern in
+            [r"(?:(?:a{1000}){1000})", r"(?:(?:(?:a{1000}){1000}){1000})", r"(?:a{1000}){101,}"]
+        {
+            assert!(Regex::new(pattern, 0).is_err(), "{pattern}");
+        }
+    }
+}
@@ -0,0 +1,295 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The built-in language definitions.
+
+use super::*;
+
+pub static LANGUAGES: &[&Language] = &[&RUST, &C, &PYTHON, &JSON, &MARKDOWN, &SHELL, &TOML];
+
+const C_STRINGS: &[StringRule] = &[
+    StringRule { open: "\"", close: "\"", escape: Some(b'\\'), multiline: false },
+    StringRule { open: "'", close: "'", escape: Some(b'\\'), multiline: false },
+];
+
+pub static RUST: Language = Language {
+    name: "Rust",
+    extensions: &["rs"],
+    line_comment: Some("//"),
+    block_comment: Some(("/*", "*/")),
+    nested_comments: true,
+    strings: &[
+        StringRule { open: "r#\"", close: "\"#", escape: None, multiline: true },
+        StringRule { open: "r\"", close: "\"", escape: None, multiline: true },
+        StringRule { open: "b\"", close: "\"", escape: Some(b'\\'), multiline: true },
+        StringRule { open: "\"", close: "\"", escape: Some(b'\\'), multiline: true },
+        StringRule { open: "b'", close: "'", escape: Some(b'\\'), multiline: false },
+        StringRule { open: "'", close: "'", escape: Some(b'\\'), multiline: false },
+    ],
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+        "extern", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+        "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait", "type",
+        "union", "unsafe", "use", "where", "while", "yield",
+    ],
+    types: &[
+        "bool", "char", "f32", "f64", "i8", "i16", "i32", "i64", "i128", "isize", "str", "u8",
+        "u16", "u32", "u64", "u128", "usize",
+    ],
+    constants: &["true", "false", "None", "Some", "Ok", "Err"],
+    capitalized_types: true,
+    lifetimes: true,
+    bang_macros: true,
+    attribute_prefix: Some("#["),
+    indent_after: "{[(",
+    ..Language::DEFAULT
+};
+
+pub static C: Language = Language {
+    name: "C",
+    extensions: &["c", "h", "cc", "cpp", "cxx", "hh", "hpp", "hxx", "inl"],
+    line_comment: Some("//"),
+    block_comment: Some(("/*", "*/")),
+    strings: C_STRINGS,
+    keywords: &[
+        "auto",
+        "break",
+        "case",
+        "catch",
+        "class",
+        "const",
+        "constexpr",
+        "continue",
+        "default",
+        "delete",
+        "do",
+        "else",
+        "enum",
+        "extern",
+        "for",
+        "friend",
+        "goto",
+        "if",
+        "inline",
+        "namespace",
+        "new",
+        "noexcept",
+        "operator",
+        "private",
+        "protected",
+        "public",
+        "register",
+        "return",
+        "sizeof",
+        "static",
+        "struct",
+        "switch",
+        "template",
+        "this",
+        "throw",
+        "try",
+        "typedef",
+        "typename",
+        "union",
+        "using",
+        "virtual",
+        "volatile",
+        "while",
+    ],
+    types: &[
+        "bool",
+        "char",
+        "double",
+        "float",
+        "int",
+        "int8_t",
+        "int16_t",
+        "int32_t",
+        "int64_t",
+        "long",
+        "ptrdiff_t",
+        "short",
+        "signed",
+        "size_t",
+        "ssize_t",
+        "uint8_t",
+        "uint16_t",
+        "uint32_t",
+        "uint64_t",
+        "uintptr_t",
+        "unsigned",
+        "void",
+        "wchar_t",
+    ],
+    constants: &["true", "false", "NULL", "nullptr"],
+    preprocessor: true,
+    indent_after: "{[(",
+    ..Language::DEFAULT
+};
+
+pub static PYTHON: Language = Language {
+    name: "Python",
+    extensions: &["py", "pyi", "pyw"],
+    line_comment: Some("#"),
+    strings: &[
+        StringRule { open: "\"\"\"", close: "\"\"\"", escape: Some(b'\\'), multiline: true },
+        StringRule { open: "'''", close: "'''", escape: Some(b'\\'), multiline: true },
+        StringRule { open: "\"", close: "\"", escape: Some(b'\\'), multiline: false },
+        StringRule { open: "'", close: "'", escape: Some(b'\\'), multiline: false },
+    ],
+    keywords: &[
+        "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+        "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in", "is",
+        "lambda", "match", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+        "with", "yield",
+    ],
+    types: &["bool", "bytes", "dict", "float", "int", "list", "object", "set", "str", "tuple"],
+    constants: &["True", "False", "None", "self"],
+    capitalized_types: true,
+    attribute_prefix: Some("@"),
+    indent_after: "{[(:",
+    ..Language::DEFAULT
+};
+
+pub static JSON: Language = Language {
+    name: "JSON",
+    extensions: &["json", "jsonc", "json5"],
+    filenames: &[".prettierrc", ".babelrc", ".eslintrc"],
+    line_comment: Some("//"),
+    block_comment: Some(("/*", "*/")),
+    strings: &[StringRule { open: "\"", close: "\"", escape: Some(b'\\'), multiline: false }],
+    constants: &["true", "false", "null"],
+    string_keys: true,
+    indent_after: "{[",
+    ..Language::DEFAULT
+};
+
+pub static MARKDOWN: Language = Language {
+    name: "Markdown",
+    extensions: &["md", "markdown"],
+    lex: lex_markdown,
+    block_comment: Some(("<!--", "-->")),
+    ..Language::DEFAULT
+};
+
+pub static SHELL: Language = Language {
+    name: "Shell",
+    extensions: &["sh", "bash", "zsh", "ksh"],
+    filenames: &[".bashrc", ".bash_profile", ".profile", ".zshrc", "PKGBUILD"],
+    line_comment: Some("#"),
+    comment_after_space: true,
+    strings: &[
+        StringRule { open: "\"", close: "\"", escape: Some(b'\\'), multiline: true },
+        StringRule { open: "'", close: "'", escape: None, multiline: true },
+    ],
+    keywords: &[
+        "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if",
+        "in", "local", "readonly", "return", "select", "then", "until", "while",
+    ],
+    constants: &["true", "false"],
+    variables: true,
+    indent_after: "{(",
+    ..Language::DEFAULT
+};
+
+pub static TOML: Language = Language {
+    name: "TOML",
+    extensions: &["toml"],
+    filenames: &["Cargo.lock"],
+    line_comment: Some("#"),
+    strings: &[
+        StringRule { open: "\"\"\"", close: "\"\"\"", escape: Some(b'\\'), multiline: true },
+        StringRule { open: "'''", close: "'''", escape: None, multiline: true },
+        StringRule { open: "\"", close: "\"", escape: Some(b'\\'), multiline: false },
+        StringRule { open: "'", close: "'", escape: None, multiline: false },
+    ],
+    constants: &["true", "false", "inf", "nan"],
+    key_value_pairs: true,
+    indent_after: "[{",
+    ..Language::DEFAULT
+};
+
+/// Markdown is line-oriented enough that it's easier to lex by hand:
+/// Headings, block quotes and fenced code blocks are decided by the start
+/// of the line, while `code`, *emphasis* and [links](...) are inline.
+fn lex_markdown(_: &Language, state: LexerState, line: &[u8], spans: &mut Vec<Span>) -> LexerState {
+    let first = skip_whitespace(line, 0);
+    let is_fence = line[first..].starts_with(b"```") || line[first..].starts_with(b"~~~");
+
+    if state == LexerState::Fenced {
+        push(spans, 0, line.len(), HighlightKind::Code);
+        return if is_fence { LexerState::Normal } else { LexerState::Fenced };
+    }
+
+    if is_fence {
+        push(spans, first, line.len(), HighlightKind::Code);
+        return LexerState::Fenced;
+    }
+
+    match line.get(first) {
+        Some(b'#') => {
+            push(spans, first, line.len(), HighlightKind::Heading);
+            return LexerState::Normal;
+        }
+        Some(b'>') => {
+            push(spans, first, line.len(), HighlightKind::Comment);
+            return LexerState::Normal;
+        }
+        _ => {}
+    }
+
+    // Indented code blocks.
+    if first >= 4 || line.first() == Some(&b'\t') {
+        push(spans, 0, line.len(), HighlightKind::Code);
+        return LexerState::Normal;
+    }
+
+    let mut i = first;
+    while i < line.len() {
+        match line[i] {
+            b'\\' => i += 2,
+            b'`' => {
+                let end = find_from(line, i + 1, b"`").map_or(line.len(), |e| e + 1);
+                push(spans, i, end, HighlightKind::Code);
+                i = end;
+            }
+            c @ (b'*' | b'_') => {
+                let delim: &[u8] =
+                    if line.get(i + 1) == Some(&c) { &line[i..i + 2] } else { &line[i..i + 1] };
+                let next = line.get(i + delim.len()).copied().unwrap_or(b' ');
+
+                // `* ` at the start of a line is a list item and `a_b` isn't emphasis.
+                if next == b' ' || (c == b'_' && i > 0 && is_ident(line[i - 1])) {
+                    i += delim.len();
+                    continue;
+                }
+
+                match find_from(line, i + delim.len(), delim) {
+                    Some(e) => {
+                        let end = e + delim.len();
+                        push(spans, i, end, HighlightKind::Emphasis);
+                        i = end;
+                    }
+                    None => i += delim.len(),
+                }
+            }
+            b'[' => {
+                let Some(close) = find_from(line, i + 1, b"](") else {
+                    i += 1;
+                    continue;
+                };
+                let end = find_from(line, close + 2, b")").map_or(line.len(), |e| e + 1);
+                push(spans, i, end, HighlightKind::Link);
+                i = end;
+            }
+            _ => i += 1,
+        }
+    }
+
+    LexerState::Normal
+}
+
+fn find_from(haystack: &[u8], beg: usize, needle: &[u8]) -> Option<usize> {
+    let beg = beg.min(haystack.len());
+    haystack[beg..].windows(needle.len()).position(|w| w == needle).map(|p| beg + p)
+}
@@ -0,0 +1,851 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Syntax highlighting.
+//!
+//! Each [`Language`] comes with a small, line-based lexer. It's handed a single
+//! line of text together with the [`LexerState`] at the start of that line,
+//! and returns the state at the end of it. Since the state is tiny (it only
+//! needs to track constructs that span multiple lines, like block comments),
+//! [`Highlighter`] can cheaply remember it for every couple hundred lines.
+//! That way, rendering a far-away part of a document only needs to lex from
+//! the closest remembered line instead of from the very beginning.
+//!
+//! Most languages share the same generic, table-driven lexer ([`lex_generic`]).
+//! Languages that don't fit that mold, like Markdown, bring their own.
+
+mod grammars;
+
+use std::path::Path;
+
+pub use grammars::LANGUAGES;
+
+use crate::document::ReadableDocument;
+use crate::framebuffer::IndexedColor;
+use crate::helpers::{CoordType, KIBI};
+use crate::simd::memchr2;
+
+/// The lexer state is remembered for every Nth line.
+const CHECKPOINT_INTERVAL: CoordType = 256;
+/// Only this many bytes of each line are highlighted, so that huge lines, like those
+/// of minified files, don't get copied and lexed in full. The lexer state at the
+/// end of such a line is the one at this length.
+const MAX_LINE_LENGTH: usize = 64 * KIBI;
+
+/// What a piece of text is, as far as coloring it is concerned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HighlightKind {
+    Comment,
+    String,
+    Number,
+    Keyword,
+    Type,
+    Constant,
+    Function,
+    Attribute,
+    Property,
+    Variable,
+    Heading,
+    Emphasis,
+    Code,
+    Link,
+}
+
+impl HighlightKind {
+    /// The color the text should be drawn with.
+    pub fn color(self) -> IndexedColor {
+        match self {
+            Self::Comment => IndexedColor::Green,
+            Self::String => IndexedColor::Red,
+            Self::Number => IndexedColor::BrightGreen,
+            Self::Keyword => IndexedColor::BrightBlue,
+            Self::Type => IndexedColor::BrightCyan,
+            Self::Constant => IndexedColor::BrightMagenta,
+            Self::Function => IndexedColor::BrightYellow,
+            Self::Attribute => IndexedColor::Magenta,
+            Self::Property => IndexedColor::Cyan,
+            Self::Variable => IndexedColor::Cyan,
+            Self::Heading => IndexedColor::BrightBlue,
+            Self::Emphasis => IndexedColor::BrightMagenta,
+            Self::Code => IndexedColor::Yellow,
+            Self::Link => IndexedColor::Cyan,
+        }
+    }
+}
+
+/// A highlighted range of bytes within a line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub beg: usize,
+    pub end: usize,
+    pub kind: HighlightKind,
+}
+
+/// The state carried from the end of one line to the start of the next.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum LexerState {
+    #[default]
+    Normal,
+    /// Inside a (possibly nested) block comment.
+    BlockComment { depth: u16 },
+    /// Inside a multi-line string. Indexes into [`Language::strings`].
+    String { rule: u8 },
+    /// Inside a fenced code block (Markdown).
+    Fenced,
+}
+
+/// The signature of a lexer. It appends the spans of the given `line`
+/// (without trailing newline) to `spans` and returns the state at its end.
+pub type LexFn = fn(&Language, LexerState, &[u8], &mut Vec<Span>) -> LexerState;
+
+/// Describes a string literal for [`lex_generic`].
+pub struct StringRule {
+    pub open: &'static str,
+    pub close: &'static str,
+    /// The escape character, if any. It causes the next character to be skipped.
+    pub escape: Option<u8>,
+    /// If false, the string ends at the end of the line, even if it wasn't closed.
+    pub multiline: bool,
+}
+
+/// A language definition.
+///
+/// Most of the fields configure [`lex_generic`]. Custom lexers may ignore them.
+pub struct Language {
+    pub name: &'static str,
+    /// File extensions (without the leading period) this language is used for.
+    pub extensions: &'static [&'static str],
+    /// File names this language is used for, regardless of their extension.
+    pub filenames: &'static [&'static str],
+    pub lex: LexFn,
+
+    pub line_comment: Option<&'static str>,
+    pub block_comment: Option<(&'static str, &'static str)>,
+    /// Whether block comments nest like in Rust.
+    pub nested_comments: bool,
+    /// Whether line comments must be preceded by whitespace, like in shell scripts.
+    pub comment_after_space: bool,
+    /// The rules are tried in order, so e.g. `"""` must come before `"`.
+    pub strings: &'static [StringRule],
+    pub keywords: &'static [&'static str],
+    pub types: &'static [&'static str],
+    pub constants: &'static [&'static str],
+    /// Identifiers starting with an uppercase letter are types.
+    pub capitalized_types: bool,
+    /// `'a'` is a character literal, but `'a` a lifetime (Rust).
+    pub lifetimes: bool,
+    /// Identifiers followed by `!` are (macro) function calls (Rust).
+    pub bang_macros: bool,
+    /// Attributes such as `#[derive]` (Rust) or `@decorator` (Python) start with this.
+    pub attribute_prefix: Option<&'static str>,
+    /// Lines starting with `#` are preprocessor directives (C).
+    pub preprocessor: bool,
+    /// `$name` and `${...}` are variables (shell).
+    pub variables: bool,
+    /// Strings followed by `:` are object keys (JSON).
+    pub string_keys: bool,
+    /// `key = value` pairs and `[table]` headers at the start of a line (TOML).
+    pub key_value_pairs: bool,
+    /// Pressing Enter after one of these characters indents the next line, e.g. `{` or `:` (Python).
+    /// Typing the closing bracket of one on an otherwise blank line unindents it again.
+    pub indent_after: &'static str,
+}
+
+impl Language {
+    /// Used as the base for defining languages.
+    pub const DEFAULT: Self = Self {
+        name: "",
+        extensions: &[],
+        filenames: &[],
+        lex: lex_generic,
+
+        line_comment: None,
+        block_comment: None,
+        nested_comments: false,
+        comment_after_space: false,
+        strings: &[],
+        keywords: &[],
+        types: &[],
+        constants: &[],
+        capitalized_types: false,
+        lifetimes: false,
+        bang_macros: false,
+        attribute_prefix: None,
+        preprocessor: false,
+        variables: false,
+        string_keys: false,
+        key_value_pairs: false,
+        indent_after: "",
+    };
+}
+
+/// Picks the language based on the file name or extension of `path`.
+pub fn language_from_path(path: &Path) -> Option<&'static Language> {
+    let filename = path.file_name()?.to_str()?;
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+
+    LANGUAGES.iter().copied().find(|lang| {
+        lang.filenames.contains(&filename)
+            || (!extension.is_empty()
+                && lang.extensions.iter().any(|e| e.eq_ignore_ascii_case(extension)))
+    })
+}
+
+/// Looks up a language by its [`Language::name`], ignoring case.
+pub fn language_from_name(name: &str) -> Option<&'static Language> {
+    LANGUAGES.iter().copied().find(|lang| lang.name.eq_ignore_ascii_case(name))
+}
+
+/// Highlights a document line by line, remembering the lexer state
+/// every [`CHECKPOINT_INTERVAL`] lines to make random access fast.
+pub struct Highlighter {
+    language: &'static Language,
+    /// The offset and lexer state at the start of every `CHECKPOINT_INTERVAL`-th line.
+    checkpoints: Vec<(usize, LexerState)>,
+    /// The logical line whose spans are in `spans`.
+    line: CoordType,
+    state_after: LexerState,
+    line_buf: Vec<u8>,
+    spans: Vec<Span>,
+}
+
+impl Highlighter {
+    pub fn new(language: &'static Language) -> Self {
+        Self {
+            language,
+            checkpoints: vec![(0, LexerState::Normal)],
+            line: -1,
+            state_after: LexerState::Normal,
+            line_buf: Vec::new(),
+            spans: Vec::new(),
+        }
+    }
+
+    pub fn language(&self) -> &'static Language {
+        self.language
+    }
+
+    /// Must be called whenever the document is modified.
+    /// `line` is the first logical line that was modified.
+    pub fn invalidate(&mut self, line: CoordType) {
+        let keep = (line.max(0) / CHECKPOINT_INTERVAL) as usize + 1;
+        self.checkpoints.truncate(keep);
+        self.line = -1;
+    }
+
+    /// Lexes the logical line `line`, which starts at `offset` in `doc`, and returns its spans.
+    /// The span offsets are relative to the start of the line and end by [`MAX_LINE_LENGTH`].
+    ///
+    /// Calling this for consecutive lines is cheap. Jumping around
+    /// needs to lex up to [`CHECKPOINT_INTERVAL`] lines.
+    pub fn highlight_line(
+        &mut self,
+        doc: &dyn ReadableDocument,
+        line: CoordType,
+        offset: usize,
+    ) -> &[Span] {
+        if line != self.line {
+            let state = if line == self.line + 1 && self.line >= 0 {
+                self.state_after
+            } else {
+                self.state_at(doc, line)
+            };
+
+            read_line(doc, offset, &mut self.line_buf);
+            self.spans.clear();
+            self.state_after =
+                (self.language.lex)(self.language, state, &self.line_buf, &mut self.spans);
+            self.line = line;
+        }
+
+        &self.spans
+    }
+
+    /// Returns the lexer state at the start of the logical line `line`.
+    fn state_at(&mut self, doc: &dyn ReadableDocument, line: CoordType) -> LexerState {
+        let idx = ((line / CHECKPOINT_INTERVAL) as usize).min(self.checkpoints.len() - 1);
+        let (mut offset, mut state) = self.checkpoints[idx];
+        let mut y = idx as CoordType * CHECKPOINT_INTERVAL;
+        let mut spans = Vec::new();
+
+        while y < line {
+            let len = read_line(doc, offset, &mut self.line_buf);
+            if len == 0 {
+                // Reached the end of the document.
+                break;
+            }
+
+            spans.clear();
+            state = (self.language.lex)(self.language, state, &self.line_buf, &mut spans);
+            offset += len;
+            y += 1;
+
+            if y % CHECKPOINT_INTERVAL == 0
+                && (y / CHECKPOINT_INTERVAL) as usize == self.checkpoints.len()
+            {
+                self.checkpoints.push((offset, state));
+            }
+        }
+
+        state
+    }
+}
+
+/// Copies up to [`MAX_LINE_LENGTH`] bytes of the line starting at `offset` into `buf`,
+/// without its trailing newline. Returns the length of the line including the newline.
+fn read_line(doc: &dyn ReadableDocument, mut offset: usize, buf: &mut Vec<u8>) -> usize {
+    let beg = offset;
+    let mut len = 0;
+    buf.clear();
+
+    loop {
+        let chunk = doc.read_forward(offset);
+        if chunk.is_empty() {
+            break;
+        }
+
+        let end = memchr2(b'\n', b'\n', chunk, 0);
+        let room = MAX_LINE_LENGTH - buf.len();
+        buf.extend_from_slice(&chunk[..end.min(room)]);
+        offset += end;
+        len += end;
+
+        if end < chunk.len() {
+            offset += 1;
+            break;
+        }
+    }
+
+    // Only a line that wasn't cut off ends in the CR of a CRLF.
+    if buf.len() == len && buf.last() == Some(&b'\r') {
+        buf.pop();
+    }
+
+    offset - beg
+}
+
+fn is_ident_start(c: u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'_' || c >= 0x80
+}
+
+fn is_ident(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c >= 0x80
+}
+
+fn skip_whitespace(line: &[u8], mut i: usize) -> usize {
+    while i < line.len() && (line[i] == b' ' || line[i] == b'\t') {
+        i += 1;
+    }
+    i
+}
+
+fn push(spans: &mut Vec<Span>, beg: usize, end: usize, kind: HighlightKind) {
+    if beg < end {
+        spans.push(Span { beg, end, kind });
+    }
+}
+
+/// Continues a block comment at `i` that is `depth` levels deep.
+/// `beg` is where the highlighted span should start.
+fn lex_block_comment(
+    lang: &Language,
+    line: &[u8],
+    beg: usize,
+    mut i: usize,
+    mut depth: u16,
+    spans: &mut Vec<Span>,
+) -> (usize, LexerState) {
+    let Some((open, close)) = lang.block_comment else {
+        return (i, LexerState::Normal);
+    };
+
+    while i < line.len() {
+        if line[i..].starts_with(close.as_bytes()) {
+            i += close.len();
+            depth -= 1;
+            if depth == 0 {
+                push(spans, beg, i, HighlightKind::Comment);
+                return (i, LexerState::Normal);
+            }
+        } else if lang.nested_comments && line[i..].starts_with(open.as_bytes()) {
+            i += open.len();
+            depth += 1;
+        } else {
+            i += 1;
+        }
+    }
+
+    push(spans, beg, i, HighlightKind::Comment);
+    (i, LexerState::BlockComment { depth })
+}
+
+/// Continues a string at `i` (after the opening delimiter, if any).
+/// Returns the end of the string and the state at the end of the line.
+fn lex_string(
+    rule_idx: usize,
+    rule: &StringRule,
+    line: &[u8],
+    mut i: usize,
+) -> (usize, LexerState) {
+    while i < line.len() {
+        if Some(line[i]) == rule.escape {
+            i += 2;
+        } else if line[i..].starts_with(rule.close.as_bytes()) {
+            return (i + rule.close.len(), LexerState::Normal);
+        } else {
+            i += 1;
+        }
+    }
+
+    let state = if rule.multiline {
+        LexerState::String { rule: rule_idx as u8 }
+    } else {
+        LexerState::Normal
+    };
+    (i.min(line.len()), state)
+}
+
+/// The table-driven lexer used by most languages. See [`Language`] for the knobs.
+pub fn lex_generic(
+    lang: &Language,
+    state: LexerState,
+    line: &[u8],
+    spans: &mut Vec<Span>,
+) -> LexerState {
+    let mut i = 0;
+
+    // Finish whatever construct the previous line left open.
+    match state {
+        LexerState::BlockComment { depth } => {
+            let (end, state) = lex_block_comment(lang, line, 0, 0, depth, spans);
+            if state != LexerState::Normal {
+                return state;
+            }
+            i = end;
+        }
+        LexerState::String { rule } => {
+            let rule_idx = rule as usize;
+            let Some(rule) = lang.strings.get(rule_idx) else {
+                return LexerState::Normal;
+            };
+            let (end, state) = lex_string(rule_idx, rule, line, 0);
+            push(spans, 0, end, HighlightKind::String);
+            if state != LexerState::Normal {
+                return state;
+            }
+            i = end;
+        }
+        _ => {}
+    }
+
+    let first = skip_whitespace(line, i);
+
+    if lang.preprocessor && i == 0 && line.get(first) == Some(&b'#') {
+        let mut end = skip_whitespace(line, first + 1);
+        while end < line.len() && is_ident(line[end]) {
+            end += 1;
+        }
+        push(spans, first, end, HighlightKind::Attribute);
+        i = end;
+    }
+
+    if lang.key_value_pairs && i == 0 {
+        i = lex_key_value(line, first, spans);
+    }
+
+    while i < line.len() {
+        let c = line[i];
+        let rest = &line[i..];
+
+        if c == b' ' || c == b'\t' {
+            i += 1;
+            continue;
+        }
+
+        if let Some(token) = lang.line_comment
+            && rest.starts_with(token.as_bytes())
+            && (!lang.comment_after_space || i == 0 || matches!(line[i - 1], b' ' | b'\t'))
+        {
+            push(spans, i, line.len(), HighlightKind::Comment);
+            return LexerState::Normal;
+        }
+
+        if let Some((open, _)) = lang.block_comment
+            && rest.starts_with(open.as_bytes())
+        {
+            let (end, state) = lex_block_comment(lang, line, i, i + open.len(), 1, spans);
+            if state != LexerState::Normal {
+                return state;
+            }
+            i = end;
+            continue;
+        }
+
+        if let Some(prefix) = lang.attribute_prefix
+            && rest.starts_with(prefix.as_bytes())
+        {
+            let end = lex_attribute(line, i, prefix);
+            push(spans, i, end, HighlightKind::Attribute);
+            i = end;
+            continue;
+        }
+
+        if c == b'\''
+            && lang.lifetimes
+            && let Some(end) = lex_lifetime(line, i)
+        {
+            push(spans, i, end, HighlightKind::Type);
+            i = end;
+            continue;
+        }
+
+        if let Some((rule_idx, rule)) =
+            lang.strings.iter().enumerate().find(|(_, r)| rest.starts_with(r.open.as_bytes()))
+        {
+            let (end, state) = lex_string(rule_idx, rule, line, i + rule.open.len());
+            let mut kind = HighlightKind::String;
+            if lang.string_keys
+                && state == LexerState::Normal
+                && line.get(skip_whitespace(line, end)) == Some(&b':')
+            {
+                kind = HighlightKind::Property;
+            }
+            push(spans, i, end, kind);
+            if state != LexerState::Normal {
+                return state;
+            }
+            i = end;
+            continue;
+        }
+
+        if lang.variables && c == b'$' {
+            let end = lex_variable(line, i);
+            push(spans, i, end, HighlightKind::Variable);
+            i = end;
+            continue;
+        }
+
+        if c.is_ascii_digit() {
+            let end = lex_number(line, i);
+            push(spans, i, end, HighlightKind::Number);
+            i = end;
+            continue;
+        }
+
+        if is_ident_start(c) {
+            let mut end = i + 1;
+            while end < line.len() && is_ident(line[end]) {
+                end += 1;
+            }
+
+            let word = &line[i..end];
+            let next = line.get(skip_whitespace(line, end)).copied();
+            let kind = if lang.keywords.iter().any(|k| k.as_bytes() == word) {
+                Some(HighlightKind::Keyword)
+            } else if lang.types.iter().any(|k| k.as_bytes() == word) {
+                Some(HighlightKind::Type)
+            } else if lang.constants.iter().any(|k| k.as_bytes() == word) {
+                Some(HighlightKind::Constant)
+            } else if next == Some(b'(') {
+                Some(HighlightKind::Function)
+            } else if lang.bang_macros
+                && line.get(end) == Some(&b'!')
+                && line.get(end + 1) != Some(&b'=')
+            {
+                end += 1;
+                Some(HighlightKind::Function)
+            } else if lang.capitalized_types && c.is_ascii_uppercase() {
+                Some(HighlightKind::Type)
+            } else {
+                None
+            };
+
+            if let Some(kind) = kind {
+                push(spans, i, end, kind);
+            }
+            i = end;
+            continue;
+        }
+
+        i += 1;
+    }
+
+    LexerState::Normal
+}
+
+/// Numbers are lexed generously: `0x1F`, `1_000u32`, `1.5e-3`, `1979-05-27`, ...
+fn lex_number(line: &[u8], mut i: usize) -> usize {
+    while i < line.len() {
+        let c = line[i];
+        let continues = c.is_ascii_alphanumeric()
+            || c == b'_'
+            || (c == b'.' && line.get(i + 1).is_some_and(|c| c.is_ascii_digit()))
+            || ((c == b'-' || c == b'+') && matches!(line[i - 1], b'e' | b'E'));
+        if !continues {
+            break;
+        }
+        i += 1;
+    }
+    i
+}
+
+/// Lexes an attribute like `#[derive(Debug)]` or `@decorator`, starting at `i`.
+fn lex_attribute(line: &[u8], i: usize, prefix: &str) -> usize {
+    let mut end = i + prefix.len();
+
+    if prefix.ends_with('[') {
+        // Skip to the matching bracket.
+        let mut depth = 1;
+        while end < line.len() && depth > 0 {
+            match line[end] {
+                b'[' => depth += 1,
+                b']' => depth -= 1,
+                _ => {}
+            }
+            end += 1;
+        }
+    } else {
+        while end < line.len() && (is_ident(line[end]) || line[end] == b'.') {
+            end += 1;
+        }
+    }
+
+    end
+}
+
+/// Distinguishes lifetimes (`'a`) from character literals (`'a'`, `'\n'`).
+/// Returns the end of the lifetime, or `None` if it's a character literal.
+fn lex_lifetime(line: &[u8], i: usize) -> Option<usize> {
+    let next = *line.get(i + 1)?;
+    if next == b'\\' || !is_ident_start(next) {
+        return None;
+    }
+
+    let mut end = i + 1;
+    while end < line.len() && is_ident(line[end]) {
+        end += 1;
+    }
+
+    // A single character followed by a quote is a character literal.
+    let char_len = match next {
+        0x00..0x80 => 1,
+        0xC0..0xE0 => 2,
+        0xE0..0xF0 => 3,
+        _ => 4,
+    };
+    if line.get(i + 1 + char_len) == Some(&b'\'') {
+        return None;
+    }
+
+    Some(end)
+}
+
+/// Lexes `$name`, `${...}`, `$1`, `$?` and similar shell variables, starting at `i`.
+fn lex_variable(line: &[u8], i: usize) -> usize {
+    let mut end = i + 1;
+
+    match line.get(end) {
+        Some(b'{') => {
+            while end < line.len() && line[end] != b'}' {
+                end += 1;
+            }
+            (end + 1).min(line.len())
+        }
+        Some(&c) if is_ident(c) => {
+            while end < line.len() && is_ident(line[end]) {
+                end += 1;
+            }
+            end
+        }
+        Some(b'?' | b'#' | b'@' | b'*' | b'!' | b'$' | b'-') => end + 1,
+        _ => end,
+    }
+}
+
+/// Lexes `[table]` headers and the `key` of `key = value` pairs, starting at `i`.
+/// Returns where the remainder of the line starts.
+fn lex_key_value(line: &[u8], i: usize, spans: &mut Vec<Span>) -> usize {
+    if line.get(i) == Some(&b'[') {
+        let end = line[i..].iter().rposition(|&c| c == b']').map_or(line.len(), |p| i + p + 1);
+        push(spans, i, end, HighlightKind::Type);
+        return end;
+    }
+
+    let mut end = i;
+    while end < line.len() {
+        match line[end] {
+            b'=' => break,
+            b'"' | b'\'' => {
+                // Quoted keys.
+                let quote = line[end];
+                end += 1;
+                while end < line.len() && line[end] != quote {
+                    end += 1;
+                }
+                end += 1;
+            }
+            c if is_ident(c) || matches!(c, b'-' | b'.' | b' ' | b'\t') => end += 1,
+            _ => return i,
+        }
+    }
+
+    if end >= line.len() {
+        return i;
+    }
+
+    let mut key_end = end;
+    while key_end > i && matches!(line[key_end - 1], b' ' | b'\t') {
+        key_end -= 1;
+    }
+
+    push(spans, i, key_end, HighlightKind::Property);
+    end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(name: &str, lines: &[&str]) -> Vec<Vec<(String, HighlightKind)>> {
+        let lang = language_from_name(name).unwrap();
+        let mut state = LexerState::Normal;
+        let mut result = Vec::new();
+
+        for line in lines {
+            let mut spans = Vec::new();
+            state = (lang.lex)(lang, state, line.as_bytes(), &mut spans);
+            result.push(spans.iter().map(|s| (line[s.beg..s.end].to_string(), s.kind)).collect());
+        }
+
+        result
+    }
+
+    fn span(text: &str, kind: HighlightKind) -> (String, HighlightKind) {
+        (text.to_string(), kind)
+    }
+
+    #[test]
+    fn test_rust() {
+        use HighlightKind::*;
+
+        let res = lex(
+            "Rust",
+            &[
+                "fn main() { /* a /* nested */",
+                "comment */ let c = 'x'; }",
+                "fn f<'a>(s: &'a str) -> Vec<u8> { println!(\"{s}\\\"\") }",
+            ],
+        );
+        assert_eq!(
+            res[0],
+            [span("fn", Keyword), span("main", Function), span("/* a /* nested */", Comment)]
+        );
+        assert_eq!(
+            res[1],
+            [span("comment */", Comment), span("let", Keyword), span("'x'", String)]
+        );
+        assert_eq!(
+            res[2],
+            [
+                span("fn", Keyword),
+                span("'a", Type),
+                span("'a", Type),
+                span("str", Type),
+                span("Vec", Type),
+                span("u8", Type),
+                span("println!", Function),
+                span("\"{s}\\\"\"", String),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multiline_strings() {
+        use HighlightKind::*;
+
+        let res = lex("Python", &["x = \"\"\"doc", "string\"\"\" # comment"]);
+        assert_eq!(res[0], [span("\"\"\"doc", String)]);
+        assert_eq!(res[1], [span("string\"\"\"", String), span("# comment", Comment)]);
+    }
+
+    #[test]
+    fn test_key_value() {
+        use HighlightKind::*;
+
+        let res = lex("TOML", &["[package]", "name = \"edit\" # yes", "lto = true"]);
+        assert_eq!(res[0], [span("[package]", Type)]);
+        assert_eq!(
+            res[1],
+            [span("name", Property), span("\"edit\"", String), span("# yes", Comment)]
+        );
+        assert_eq!(res[2], [span("lto", Property), span("true", Constant)]);
+
+        let res = lex("JSON", &["{\"a\": [1.5e-3, null, \"b\"]}"]);
+        assert_eq!(
+            res[0],
+            [
+                span("\"a\"", Property),
+                span("1.5e-3", Number),
+                span("null", Constant),
+                span("\"b\"", String)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_markdown() {
+        use HighlightKind::*;
+
+        let res = lex(
+            "Markdown",
+            &["# Title", "Some `code` and **bold**.", "```rust", "# not a heading", "```"],
+        );
+        assert_eq!(res[0], [span("# Title", Heading)]);
+        assert_eq!(res[1], [span("`code`", Code), span("**bold**", Emphasis)]);
+        assert_eq!(res[2], [span("```rust", Code)]);
+        assert_eq!(res[3], [span("# not a heading", Code)]);
+        assert_eq!(res[4], [span("```", Code)]);
+    }
+
+    #[test]
+    fn test_highlighter_checkpoints() {
+        let mut text = String::from("/*\n");
+        for _ in 0..1000 {
+            text.push_str("x\n");
+        }
+        text.push_str("*/ let\n");
+
+        let mut h = Highlighter::new(language_from_name("Rust").unwrap());
+        let off = text.len() - 7;
+        assert_eq!(h.highlight_line(&text, 1001, off).last().unwrap().kind, HighlightKind::Keyword);
+        assert_eq!(h.checkpoints.len(), 4);
+        assert_eq!(h.checkpoints[3], (3 + 767 * 2, LexerState::BlockComment { depth: 1 }));
+
+        // Removing the comment start must be picked up after invalidation.
+        let text = text.replacen("/*", "  ", 1);
+        h.invalidate(0);
+        assert_eq!(h.checkpoints.len(), 1);
+        assert_eq!(h.highlight_line(&text, 1001, off).len(), 1);
+    }
+
+    #[test]
+    fn test_highlighter_long_line() {
+        let text = format!("/* {}\nlet\n", "x".repeat(MAX_LINE_LENGTH));
+        let mut h = Highlighter::new(language_from_name("Rust").unwrap());
+        assert_eq!(
+            h.highlight_line(&text, 0, 0),
+            [Span { beg: 0, end: MAX_LINE_LENGTH, kind: HighlightKind::Comment }]
+        );
+        // The next line is still in the comment.
+        let off = text.len() - 4;
+        assert_eq!(h.highlight_line(&text, 1, off)[0].kind, HighlightKind::Comment);
+    }
+
+    #[test]
+    fn test_language_from_path() {
+        assert_eq!(language_from_path(Path::new("/a/b.rs")).unwrap().name, "Rust");
+        assert_eq!(language_from_path(Path::new("Cargo.lock")).unwrap().name, "TOML");
+        assert_eq!(language_from_path(Path::new("x.H")).unwrap().name, "C");
+        assert!(language_from_path(Path::new("foo")).is_none());
+    }
+}
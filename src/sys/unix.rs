@@ -10,7 +10,8 @@ use std::ffi::{CStr, c_char, c_int, c_void};
 use std::fs::File;
 use std::mem::{self, ManuallyDrop, MaybeUninit};
 use std::os::fd::{AsRawFd as _, FromRawFd as _};
-use std::path::Path;
+use std::os::unix::fs::MetadataExt as _;
+use std::path::{Path, PathBuf};
 use std::ptr::{self, NonNull, null_mut};
 use std::{thread, time};
 
@@ -39,6 +40,9 @@ struct State {
     stdout: libc::c_int,
     stdout_initial_termios: Option<libc::termios>,
     inject_resize: bool,
+    // The inotify instance watching the directories of open files, or -1.
+    watch_fd: libc::c_int,
+    watch_triggered: bool,
     // Buffer for incomplete UTF-8 sequences (max 4 bytes needed)
     utf8_buf: [u8; 4],
     utf8_len: usize,
@@ -50,6 +54,8 @@ static mut STATE: State = State {
     stdout: libc::STDOUT_FILENO,
     stdout_initial_termios: None,
     inject_resize: false,
+    watch_fd: -1,
+    watch_triggered: false,
     utf8_buf: [0; 4],
     utf8_len: 0,
 };
@@ -76,7 +82,8 @@ pub fn switch_modes() -> apperr::Result<()> {
 
         // Set STATE.inject_resize to true whenever we get a SIGWINCH.
         let mut sigwinch_action: libc::sigaction = mem::zeroed();
-        sigwinch_action.sa_sigaction = sigwinch_handler as libc::sighandler_t;
+        // Newer toolchains reject casting a function item directly into an integer.
+        sigwinch_action.sa_sigaction = sigwinch_handler as *const () as libc::sighandler_t;
         check_int_return(libc::sigaction(libc::SIGWINCH, &sigwinch_action, null_mut()))?;
 
         // Get the original terminal modes so we can disable raw mode on exit.
@@ -205,10 +212,15 @@ pub fn read_stdin(arena: &Arena, mut timeout: time::Duration) -> Option<ArenaStr
         }
 
         loop {
-            if timeout != time::Duration::MAX {
+            // We also need to poll if we're watching files, so that we can wake up on changes.
+            if timeout != time::Duration::MAX || STATE.watch_fd >= 0 {
                 let beg = time::Instant::now();
 
-                let mut pollfd = libc::pollfd { fd: STATE.stdin, events: libc::POLLIN, revents: 0 };
+                // poll() ignores negative file descriptors.
+                let mut pollfds = [
+                    libc::pollfd { fd: STATE.stdin, events: libc::POLLIN, revents: 0 },
+                    libc::pollfd { fd: STATE.watch_fd, events: libc::POLLIN, revents: 0 },
+                ];
                 let ret;
                 #[cfg(target_os = "linux")]
                 {
@@ -216,18 +228,34 @@ pub fn read_stdin(arena: &Arena, mut timeout: time::Duration) -> Option<ArenaStr
                         tv_sec: timeout.as_secs() as libc::time_t,
                         tv_nsec: timeout.subsec_nanos() as libc::c_long,
                     };
-                    ret = libc::ppoll(&mut pollfd, 1, &ts, ptr::null());
+                    let ts = if timeout == time::Duration::MAX { ptr::null() } else { &ts };
+                    ret = libc::ppoll(pollfds.as_mut_ptr(), 2, ts, ptr::null());
                 }
                 #[cfg(not(target_os = "linux"))]
                 {
-                    ret = libc::poll(&mut pollfd, 1, timeout.as_millis() as libc::c_int);
+                    let ms = if timeout == time::Duration::MAX {
+                        -1
+                    } else {
+                        timeout.as_millis() as libc::c_int
+                    };
+                    ret = libc::poll(pollfds.as_mut_ptr(), 2, ms);
                 }
                 if ret < 0 {
-                    return None; // Error? Let's assume it's an EOF.
+                    match errno() {
+                        libc::EINTR if STATE.inject_resize => break,
+                        libc::EINTR => continue,
+                        _ => return None, // Error? Let's assume it's an EOF.
+                    }
                 }
                 if ret == 0 {
                     break; // Timeout? We can stop reading.
                 }
+                if pollfds[1].revents != 0 {
+                    drain_watch_events();
+                    if pollfds[0].revents == 0 {
+                        break; // Return an empty string, just like on timeout.
+                    }
+                }
 
                 timeout = timeout.saturating_sub(beg.elapsed());
             };
@@ -379,6 +407,223 @@ pub fn file_id(file: Option<&File>, path: &Path) -> apperr::Result<FileId> {
     }
 }
 
+/// A snapshot of a file's modification time, size and inode.
+/// If it changes, someone else modified or replaced the file.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    mtime: (i64, i64),
+    size: u64,
+    ino: u64,
+}
+
+/// Returns the current [`FileStamp`] of the file at the given path.
+pub fn file_stamp(path: &Path) -> apperr::Result<FileStamp> {
+    let meta = std::fs::metadata(path)?;
+    Ok(FileStamp { mtime: (meta.mtime(), meta.mtime_nsec()), size: meta.size(), ino: meta.ino() })
+}
+
+/// Creates a new file that only the current user can access.
+/// Fails if it already exists.
+pub fn create_private_file(path: &Path) -> apperr::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt as _;
+    Ok(File::options().write(true).create_new(true).mode(0o600).open(path)?)
+}
+
+/// Creates a directory and its parents. Those that are created can only be accessed by the current user.
+pub fn create_private_dir_all(path: &Path) -> apperr::Result<()> {
+    use std::os::unix::fs::DirBuilderExt as _;
+    Ok(std::fs::DirBuilder::new().recursive(true).mode(0o700).create(path)?)
+}
+
+/// Flushes the directory containing `path` to disk, so that a preceding rename survives a crash.
+pub fn sync_parent_dir(path: &Path) -> apperr::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Atomically replaces the file at `dst` with the one at `src`.
+///
+/// `src` first receives the permissions, ownership and extended attributes of `dst`.
+/// If that's not possible without losing any of them, for instance because we lack
+/// the privileges to chown, or if `dst` has other hard links, this fails and `dst` stays untouched.
+pub fn replace_file(src: &Path, dst: &Path) -> apperr::Result<()> {
+    let meta = std::fs::metadata(dst)?;
+    // Renaming over a hard link would detach it from the other links.
+    if meta.nlink() > 1 {
+        return Err(errno_to_apperr(libc::EMLINK));
+    }
+
+    let file = File::options().write(true).open(src)?;
+    let fd = file.as_raw_fd();
+    let src_meta = file.metadata()?;
+
+    unsafe {
+        if src_meta.uid() != meta.uid() || src_meta.gid() != meta.gid() {
+            check_int_return(libc::fchown(fd, meta.uid(), meta.gid()))?;
+        }
+        // After the chown, since it may clear the setuid and setgid bits.
+        check_int_return(libc::fchmod(fd, (meta.mode() & 0o7777) as libc::mode_t))?;
+    }
+
+    #[cfg(target_os = "linux")]
+    copy_xattrs(dst, fd)?;
+
+    drop(file);
+    std::fs::rename(src, dst)?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn copy_xattrs(from: &Path, to: libc::c_int) -> apperr::Result<()> {
+    use std::os::unix::ffi::OsStrExt as _;
+
+    let Ok(from) = std::ffi::CString::new(from.as_os_str().as_bytes()) else {
+        return Err(errno_to_apperr(libc::EINVAL));
+    };
+
+    unsafe {
+        // Fails with ENOTSUP if the filesystem doesn't support xattrs, in which case there's nothing to copy.
+        let len = libc::listxattr(from.as_ptr(), null_mut(), 0);
+        if len <= 0 {
+            return Ok(());
+        }
+
+        let mut names = vec![0u8; len as usize];
+        let len = libc::listxattr(from.as_ptr(), names.as_mut_ptr() as *mut c_char, names.len());
+        if len < 0 {
+            return Err(errno_to_apperr(errno()));
+        }
+        names.truncate(len as usize);
+
+        let mut value = Vec::new();
+        // The list consists of NUL terminated names.
+        for name in names.split_inclusive(|&b| b == 0) {
+            let name = name.as_ptr() as *const c_char;
+            let len = libc::getxattr(from.as_ptr(), name, null_mut(), 0);
+            if len < 0 {
+                return Err(errno_to_apperr(errno()));
+            }
+
+            value.resize(len as usize, 0);
+            let len =
+                libc::getxattr(from.as_ptr(), name, value.as_mut_ptr() as *mut _, value.len());
+            if len < 0 {
+                return Err(errno_to_apperr(errno()));
+            }
+
+            check_int_return(libc::fsetxattr(
+                to,
+                name,
+                value.as_ptr() as *const _,
+                len as usize,
+                0,
+            ))?;
+        }
+    }
+
+    Ok(())
+}
+
+// The inotify watch descriptors and how many `DirWatch`es refer to each.
+#[cfg(target_os = "linux")]
+static WATCH_REFS: std::sync::Mutex<Vec<(libc::c_int, usize)>> = std::sync::Mutex::new(Vec::new());
+
+/// A directory watch returned by [`watch_directory`].
+/// Dropping it stops the watch, unless another `DirWatch` refers to the same directory.
+#[cfg(target_os = "linux")]
+pub struct DirWatch {
+    wd: libc::c_int,
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for DirWatch {
+    fn drop(&mut self) {
+        let mut refs = WATCH_REFS.lock().unwrap();
+        let Some(i) = refs.iter().position(|&(wd, _)| wd == self.wd) else {
+            return;
+        };
+        refs[i].1 -= 1;
+        if refs[i].1 == 0 {
+            refs.swap_remove(i);
+            unsafe { libc::inotify_rm_watch(STATE.watch_fd, self.wd) };
+        }
+    }
+}
+
+/// A directory watch returned by [`watch_directory`]. Not implemented on this platform.
+#[cfg(not(target_os = "linux"))]
+pub struct DirWatch;
+
+/// Starts watching the given directory for changes to the files within it,
+/// until the returned [`DirWatch`] is dropped. We watch directories instead of files,
+/// because tools often replace files by renaming a new one over it,
+/// which a watch on the old inode wouldn't notice.
+///
+/// Afterwards, [`read_stdin`] returns early on changes, and [`take_watch_triggered`]
+/// tells you whether that happened. This is best-effort and may do nothing.
+#[cfg(target_os = "linux")]
+pub fn watch_directory(path: &Path) -> Option<DirWatch> {
+    use std::os::unix::ffi::OsStrExt as _;
+
+    unsafe {
+        if STATE.watch_fd < 0 {
+            STATE.watch_fd = libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC);
+            if STATE.watch_fd < 0 {
+                return None;
+            }
+        }
+
+        let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+        // For a directory that's already watched, the kernel returns the existing descriptor.
+        let wd = libc::inotify_add_watch(
+            STATE.watch_fd,
+            path.as_ptr(),
+            libc::IN_CLOSE_WRITE
+                | libc::IN_CREATE
+                | libc::IN_DELETE
+                | libc::IN_MOVED_FROM
+                | libc::IN_MOVED_TO,
+        );
+        if wd < 0 {
+            return None;
+        }
+
+        let mut refs = WATCH_REFS.lock().unwrap();
+        match refs.iter_mut().find(|(w, _)| *w == wd) {
+            Some((_, count)) => *count += 1,
+            None => refs.push((wd, 1)),
+        }
+        Some(DirWatch { wd })
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn watch_directory(_path: &Path) -> Option<DirWatch> {
+    None
+}
+
+/// Returns whether a watched directory changed since the last call.
+pub fn take_watch_triggered() -> bool {
+    unsafe {
+        let triggered = STATE.watch_triggered;
+        STATE.watch_triggered = false;
+        triggered
+    }
+}
+
+fn drain_watch_events() {
+    unsafe {
+        // We don't care about the individual events, just that something happened.
+        let mut buf = [0u8; 4096];
+        while libc::read(STATE.watch_fd, buf.as_mut_ptr() as *mut _, buf.len()) > 0 {}
+        STATE.watch_triggered = true;
+    }
+}
+
 /// Reserves a virtual memory region of the given size.
 /// To commit the memory, use `virtual_commit`.
 /// To release the memory, use `virtual_release`.
@@ -583,6 +828,31 @@ where
     }
 }
 
+/// Returns whether a process with the given ID is still running.
+pub fn process_exists(pid: u32) -> bool {
+    // Signal 0 only checks whether we could send a signal. EPERM means it exists, but isn't ours.
+    unsafe { libc::kill(pid as libc::pid_t, 0) == 0 || errno() == libc::EPERM }
+}
+
+/// Returns the directory for the user's configuration files.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("edit"))
+}
+
+/// Returns the directory for application state that should persist
+/// across sessions, following the XDG Base Directory Specification.
+pub fn state_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(base.join("edit"))
+}
+
 pub fn preferred_languages(arena: &Arena) -> Vec<ArenaString<'_>, &Arena> {
     let mut locales = Vec::new_in(arena);
 
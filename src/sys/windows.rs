@@ -486,6 +486,69 @@ fn file_id_from_handle(file: &File) -> apperr::Result<FileId> {
     }
 }
 
+/// A snapshot of a file's modification time and size.
+/// If it changes, someone else modified or replaced the file.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    mtime: Option<time::SystemTime>,
+    size: u64,
+}
+
+/// Returns the current [`FileStamp`] of the file at the given path.
+pub fn file_stamp(path: &Path) -> apperr::Result<FileStamp> {
+    let meta = fs::metadata(path)?;
+    Ok(FileStamp { mtime: meta.modified().ok(), size: meta.len() })
+}
+
+/// Creates a new file. Fails if it already exists.
+/// It inherits the ACLs of its directory, which usually only grant access to the owner anyway.
+pub fn create_private_file(path: &Path) -> apperr::Result<File> {
+    Ok(File::options().write(true).create_new(true).open(path)?)
+}
+
+/// Creates a directory and its parents.
+pub fn create_private_dir_all(path: &Path) -> apperr::Result<()> {
+    Ok(fs::create_dir_all(path)?)
+}
+
+/// Directories can't be flushed on Windows. NTFS journals renames instead.
+pub fn sync_parent_dir(_path: &Path) -> apperr::Result<()> {
+    Ok(())
+}
+
+/// Atomically replaces the file at `dst` with the one at `src`.
+/// `ReplaceFileW` takes care of preserving the attributes and ACLs of `dst`.
+pub fn replace_file(src: &Path, dst: &Path) -> apperr::Result<()> {
+    use std::os::windows::ffi::OsStrExt as _;
+
+    let src: Vec<u16> = src.as_os_str().encode_wide().chain(Some(0)).collect();
+    let dst: Vec<u16> = dst.as_os_str().encode_wide().chain(Some(0)).collect();
+
+    unsafe {
+        check_bool_return(FileSystem::ReplaceFileW(
+            dst.as_ptr(),
+            src.as_ptr(),
+            null(),
+            FileSystem::REPLACEFILE_IGNORE_MERGE_ERRORS,
+            null(),
+            null(),
+        ))
+    }
+}
+
+/// A directory watch returned by [`watch_directory`]. Not implemented on Windows.
+pub struct DirWatch;
+
+/// Starts watching the given directory for changes. Not implemented on Windows.
+pub fn watch_directory(_path: &Path) -> Option<DirWatch> {
+    None
+}
+
+/// Returns whether a watched directory changed since the last call.
+pub fn take_watch_triggered() -> bool {
+    false
+}
+
 /// Canonicalizes the given path.
 ///
 /// This differs from [`fs::canonicalize`] in that it strips the `\\?\` UNC
@@ -637,6 +700,33 @@ pub fn load_icu() -> apperr::Result<LibIcu> {
     }
 }
 
+/// Returns whether a process with the given ID is still running.
+pub fn process_exists(pid: u32) -> bool {
+    unsafe {
+        let handle = Threading::OpenProcess(Threading::PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if handle.is_null() {
+            return false;
+        }
+
+        let mut code = 0;
+        let ok = Threading::GetExitCodeProcess(handle, &mut code) != 0;
+        Foundation::CloseHandle(handle);
+        ok && code == Foundation::STILL_ACTIVE as u32
+    }
+}
+
+/// Returns the directory for the user's configuration files.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("APPDATA").filter(|dir| !dir.is_empty())?;
+    Some(PathBuf::from(base).join("edit"))
+}
+
+/// Returns the directory for application state that should persist across sessions.
+pub fn state_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("LOCALAPPDATA").filter(|dir| !dir.is_empty())?;
+    Some(PathBuf::from(base).join("edit"))
+}
+
 /// Returns a list of preferred languages for the current user.
 pub fn preferred_languages(arena: &Arena) -> Vec<ArenaString<'_>, &Arena> {
     // If the GetUserPreferredUILanguages() don't fit into 512 characters,
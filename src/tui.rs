@@ -158,6 +158,7 @@ use crate::framebuffer::{Attributes, Framebuffer, INDEXED_COLORS_COUNT, IndexedC
 use crate::hash::*;
 use crate::helpers::*;
 use crate::input::{InputKeyMod, kbmod, vk};
+use crate::keymap::{self, Keymap};
 use crate::oklab::StraightRgba;
 use crate::{apperr, arena_format, input, simd, unicode};
 
@@ -316,6 +317,7 @@ pub struct Tui {
     framebuffer: Framebuffer,
 
     modifier_translations: ModifierTranslations,
+    keymap: Keymap,
     floater_default_bg: StraightRgba,
     floater_default_fg: StraightRgba,
     modal_default_bg: StraightRgba,
@@ -392,6 +394,7 @@ impl Tui {
                 alt: "Alt",
                 shift: "Shift",
             },
+            keymap: Keymap::new(),
             floater_default_bg: StraightRgba::zero(),
             floater_default_fg: StraightRgba::zero(),
             modal_default_bg: StraightRgba::zero(),
@@ -430,11 +433,21 @@ impl Tui {
         self.framebuffer.set_indexed_colors(colors);
     }
 
+    /// Sets up a color palette of our own. See [`Framebuffer::set_theme`].
+    pub fn setup_theme(&mut self, colors: [StraightRgba; INDEXED_COLORS_COUNT]) {
+        self.framebuffer.set_theme(colors);
+    }
+
     /// Set up translations for Ctrl/Alt/Shift modifiers.
     pub fn setup_modifier_translations(&mut self, translations: ModifierTranslations) {
         self.modifier_translations = translations;
     }
 
+    /// Sets up the user's custom keybindings.
+    pub fn setup_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
     /// Set the default background color for floaters (dropdowns, etc.).
     pub fn set_floater_default_bg(&mut self, color: StraightRgba) {
         self.floater_default_bg = color;
@@ -557,6 +570,16 @@ impl Tui {
                     let ch = text.as_bytes()[0];
                     input_keyboard = InputKey::from_ascii(ch as char)
                 }
+
+                // If the keymap remapped or swallowed the key, the text must go as well.
+                if let Some(key) = input_keyboard {
+                    input_keyboard = self.keymap.translate(key);
+                    if input_keyboard != Some(key) {
+                        input_text = None;
+                    }
+                } else if self.keymap.cancel() {
+                    input_text = None;
+                }
             }
             Some(Input::Paste(paste)) => {
                 let clipboard = self.clipboard_mut();
@@ -565,7 +588,7 @@ impl Tui {
                 input_keyboard = Some(kbmod::CTRL | vk::V);
             }
             Some(Input::Keyboard(keyboard)) => {
-                input_keyboard = Some(keyboard);
+                input_keyboard = self.keymap.translate(keyboard);
             }
             Some(Input::Mouse(mouse)) => {
                 let mut next_state = mouse.state;
@@ -1006,6 +1029,7 @@ impl Tui {
                         track,
                         tc.scroll_offset.y,
                         tb.visual_line_count() + inner.height() - 1,
+                        tb.find_match_lines(),
                     );
                 }
             }
@@ -1022,6 +1046,7 @@ impl Tui {
                     track,
                     sc.scroll_offset.y,
                     content.intrinsic_size.height,
+                    &[],
                 );
             }
             _ => {}
@@ -1199,7 +1224,7 @@ impl Tui {
                 match &node.content {
                     NodeContent::Text(content) => {
                         result.push_repeat(' ', depth * 2);
-                        _ = write!(result, "  text:         \"{}\"\r\n", &content.text);
+                        _ = write!(result, "  text:         \"{}\"\r\n", content.text);
                     }
                     NodeContent::Textarea(content) => {
                         let tb = content.buffer.borrow();
@@ -1702,6 +1727,41 @@ impl<'a> Context<'a, '_> {
         self.input_consumed = true;
     }
 
+    /// Formats the chord that triggers the given default `shortcut`, taking
+    /// custom keybindings into account, e.g. "Ctrl+K Ctrl+C".
+    /// Returns an empty string if it's [`vk::NULL`] or unbound.
+    pub fn shortcut_text(&self, shortcut: InputKey) -> ArenaString<'a> {
+        let mut text = ArenaString::new_in(self.arena());
+
+        if shortcut != vk::NULL
+            && let Some(chord) = self.tui.keymap.chord_for(&shortcut)
+        {
+            for (i, &key) in chord.iter().enumerate() {
+                if i > 0 {
+                    text.push(' ');
+                }
+                if key.modifiers_contains(kbmod::CTRL) {
+                    text.push_str(self.tui.modifier_translations.ctrl);
+                    text.push('+');
+                }
+                if key.modifiers_contains(kbmod::ALT) {
+                    text.push_str(self.tui.modifier_translations.alt);
+                    text.push('+');
+                }
+                if key.modifiers_contains(kbmod::SHIFT) {
+                    text.push_str(self.tui.modifier_translations.shift);
+                    text.push('+');
+                }
+                if !keymap::push_key_name(&mut text, key) {
+                    text.clear();
+                    break;
+                }
+            }
+        }
+
+        text
+    }
+
     /// Returns whether the mouse was pressed down on the current node.
     pub fn was_mouse_down(&mut self) -> bool {
         let last_node = self.tree.last_node.borrow();
@@ -2225,6 +2285,12 @@ impl<'a> Context<'a, '_> {
                 right: inner.right,
                 bottom: inner.bottom,
             };
+            let margin_rect = Rect {
+                left: inner.left,
+                top: inner.top,
+                right: text_rect.left,
+                bottom: inner.bottom,
+            };
             let pos = Point {
                 x: mouse.x - inner.left - tb.margin_width() + tc.scroll_offset.x,
                 y: mouse.y - inner.top + tc.scroll_offset.y,
@@ -2232,7 +2298,11 @@ impl<'a> Context<'a, '_> {
 
             if text_rect.contains(self.tui.mouse_down_position) {
                 if self.tui.mouse_is_drag {
-                    tb.selection_update_visual(pos);
+                    if self.input_mouse_modifiers.contains(kbmod::ALT) {
+                        tb.block_selection_update_visual(pos);
+                    } else {
+                        tb.selection_update_visual(pos);
+                    }
                     tc.preferred_column = tb.cursor_visual_pos().x;
 
                     let height = inner.height();
@@ -2311,6 +2381,12 @@ impl<'a> Context<'a, '_> {
                                 as CoordType;
                     }
                 }
+            } else if margin_rect.contains(self.tui.mouse_down_position)
+                && self.tui.mouse_state == InputMouseState::Left
+                && !self.tui.mouse_is_drag
+            {
+                // Clicking on a line number toggles the fold on that line.
+                tb.fold_toggle_visual(pos.y);
             }
 
             self.set_input_consumed();
@@ -2355,8 +2431,9 @@ impl<'a> Context<'a, '_> {
                     write = b"\n";
                 }
                 vk::ESCAPE => {
-                    // If there was a selection, clear it and show the cursor (= fallthrough).
-                    if !tb.clear_selection() {
+                    // If there were multiple cursors or a selection,
+                    // clear them and show the cursor (= fallthrough).
+                    if !tb.clear_carets() && !tb.clear_selection() {
                         if single_line {
                             // If this is just a simple input field, don't consume the escape key
                             // (early return) and don't show the cursor (= return false).
@@ -2415,92 +2492,100 @@ impl<'a> Context<'a, '_> {
                     }
                 }
                 vk::END => {
-                    let logical_before = tb.cursor_logical_pos();
-                    let destination = if modifiers.contains(kbmod::CTRL) {
-                        Point::MAX
-                    } else {
-                        Point { x: CoordType::MAX, y: tb.cursor_visual_pos().y }
-                    };
+                    tb.for_each_caret(|tb| {
+                        let logical_before = tb.cursor_logical_pos();
+                        let destination = if modifiers.contains(kbmod::CTRL) {
+                            Point::MAX
+                        } else {
+                            Point { x: CoordType::MAX, y: tb.cursor_visual_pos().y }
+                        };
 
-                    if modifiers.contains(kbmod::SHIFT) {
-                        tb.selection_update_visual(destination);
-                    } else {
-                        tb.cursor_move_to_visual(destination);
-                    }
+                        if modifiers.contains(kbmod::SHIFT) {
+                            tb.selection_update_visual(destination);
+                        } else {
+                            tb.cursor_move_to_visual(destination);
+                        }
 
-                    if !modifiers.contains(kbmod::CTRL) {
-                        let logical_after = tb.cursor_logical_pos();
-
-                        // If word-wrap is enabled and the user presses End the first time,
-                        // it moves to the start of the visual line. The second time they
-                        // press it, it moves to the start of the logical line.
-                        if tb.is_word_wrap_enabled() && logical_after == logical_before {
-                            if modifiers == kbmod::SHIFT {
-                                tb.selection_update_logical(Point {
-                                    x: CoordType::MAX,
-                                    y: tb.cursor_logical_pos().y,
-                                });
-                            } else {
-                                tb.cursor_move_to_logical(Point {
-                                    x: CoordType::MAX,
-                                    y: tb.cursor_logical_pos().y,
-                                });
+                        if !modifiers.contains(kbmod::CTRL) {
+                            let logical_after = tb.cursor_logical_pos();
+
+                            // If word-wrap is enabled and the user presses End the first time,
+                            // it moves to the start of the visual line. The second time they
+                            // press it, it moves to the start of the logical line.
+                            if tb.is_word_wrap_enabled() && logical_after == logical_before {
+                                if modifiers == kbmod::SHIFT {
+                                    tb.selection_update_logical(Point {
+                                        x: CoordType::MAX,
+                                        y: tb.cursor_logical_pos().y,
+                                    });
+                                } else {
+                                    tb.cursor_move_to_logical(Point {
+                                        x: CoordType::MAX,
+                                        y: tb.cursor_logical_pos().y,
+                                    });
+                                }
                             }
                         }
-                    }
+                    });
                 }
                 vk::HOME => {
-                    let logical_before = tb.cursor_logical_pos();
-                    let destination = if modifiers.contains(kbmod::CTRL) {
-                        Default::default()
-                    } else {
-                        Point { x: 0, y: tb.cursor_visual_pos().y }
-                    };
+                    tb.for_each_caret(|tb| {
+                        let logical_before = tb.cursor_logical_pos();
+                        let destination = if modifiers.contains(kbmod::CTRL) {
+                            Default::default()
+                        } else {
+                            Point { x: 0, y: tb.cursor_visual_pos().y }
+                        };
 
-                    if modifiers.contains(kbmod::SHIFT) {
-                        tb.selection_update_visual(destination);
-                    } else {
-                        tb.cursor_move_to_visual(destination);
-                    }
+                        if modifiers.contains(kbmod::SHIFT) {
+                            tb.selection_update_visual(destination);
+                        } else {
+                            tb.cursor_move_to_visual(destination);
+                        }
 
-                    if !modifiers.contains(kbmod::CTRL) {
-                        let mut logical_after = tb.cursor_logical_pos();
-
-                        // If word-wrap is enabled and the user presses Home the first time,
-                        // it moves to the start of the visual line. The second time they
-                        // press it, it moves to the start of the logical line.
-                        if tb.is_word_wrap_enabled() && logical_after == logical_before {
-                            if modifiers == kbmod::SHIFT {
-                                tb.selection_update_logical(Point {
-                                    x: 0,
-                                    y: tb.cursor_logical_pos().y,
-                                });
-                            } else {
-                                tb.cursor_move_to_logical(Point {
-                                    x: 0,
-                                    y: tb.cursor_logical_pos().y,
-                                });
+                        if !modifiers.contains(kbmod::CTRL) {
+                            let mut logical_after = tb.cursor_logical_pos();
+
+                            // If word-wrap is enabled and the user presses Home the first time,
+                            // it moves to the start of the visual line. The second time they
+                            // press it, it moves to the start of the logical line.
+                            if tb.is_word_wrap_enabled() && logical_after == logical_before {
+                                if modifiers == kbmod::SHIFT {
+                                    tb.selection_update_logical(Point {
+                                        x: 0,
+                                        y: tb.cursor_logical_pos().y,
+                                    });
+                                } else {
+                                    tb.cursor_move_to_logical(Point {
+                                        x: 0,
+                                        y: tb.cursor_logical_pos().y,
+                                    });
+                                }
+                                logical_after = tb.cursor_logical_pos();
                             }
-                            logical_after = tb.cursor_logical_pos();
-                        }
 
-                        // If the line has some indentation and the user pressed Home,
-                        // the first time it'll stop at the indentation. The second time
-                        // they press it, it'll move to the true start of the line.
-                        //
-                        // If the cursor is already at the start of the line,
-                        // we move it back to the end of the indentation.
-                        if logical_after.x == 0
-                            && let indent_end = tb.indent_end_logical_pos()
-                            && (logical_before > indent_end || logical_before.x == 0)
-                        {
-                            if modifiers == kbmod::SHIFT {
-                                tb.selection_update_logical(indent_end);
-                            } else {
-                                tb.cursor_move_to_logical(indent_end);
+                            // If the line has some indentation and the user pressed Home,
+                            // the first time it'll stop at the indentation. The second time
+                            // they press it, it'll move to the true start of the line.
+                            //
+                            // If the cursor is already at the start of the line,
+                            // we move it back to the end of the indentation.
+                            if logical_after.x == 0
+                                && let indent_end = tb.indent_end_logical_pos()
+                                && (logical_before > indent_end || logical_before.x == 0)
+                            {
+                                if modifiers == kbmod::SHIFT {
+                                    tb.selection_update_logical(indent_end);
+                                } else {
+                                    tb.cursor_move_to_logical(indent_end);
+                                }
                             }
                         }
-                    }
+                    });
+                }
+                // On macOS, Alt+Shift+Left/Right selects words instead. See `KBMOD_FOR_WORD_NAV`.
+                vk::LEFT if modifiers == kbmod::ALT_SHIFT && !cfg!(target_os = "macos") => {
+                    tb.block_selection_update_delta(Point { x: -1, y: 0 });
                 }
                 vk::LEFT => {
                     let granularity = if modifiers.contains(KBMOD_FOR_WORD_NAV) {
@@ -2508,13 +2593,15 @@ impl<'a> Context<'a, '_> {
                     } else {
                         CursorMovement::Grapheme
                     };
-                    if modifiers.contains(kbmod::SHIFT) {
-                        tb.selection_update_delta(granularity, -1);
-                    } else if let Some((beg, _)) = tb.selection_range() {
-                        unsafe { tb.set_cursor(beg) };
-                    } else {
-                        tb.cursor_move_delta(granularity, -1);
-                    }
+                    tb.for_each_caret(|tb| {
+                        if modifiers.contains(kbmod::SHIFT) {
+                            tb.selection_update_delta(granularity, -1);
+                        } else if let Some((beg, _)) = tb.selection_range() {
+                            unsafe { tb.set_cursor(beg) };
+                        } else {
+                            tb.cursor_move_delta(granularity, -1);
+                        }
+                    });
                 }
                 vk::UP => {
                     if single_line {
@@ -2522,61 +2609,84 @@ impl<'a> Context<'a, '_> {
                     }
                     match modifiers {
                         kbmod::NONE => {
-                            let mut x = tc.preferred_column;
-                            let mut y = tb.cursor_visual_pos().y - 1;
-
-                            // If there's a selection we put the cursor above it.
-                            if let Some((beg, _)) = tb.selection_range() {
-                                x = beg.visual_pos.x;
-                                y = beg.visual_pos.y - 1;
-                                tc.preferred_column = x;
-                            }
+                            let multi = tb.caret_count() > 1;
+                            tb.for_each_caret(|tb| {
+                                // With multiple cursors, each one keeps its own column.
+                                if multi {
+                                    tc.preferred_column = tb.cursor_visual_pos().x;
+                                }
 
-                            // If the cursor was already on the first line,
-                            // move it to the start of the buffer.
-                            if y < 0 {
-                                x = 0;
-                                tc.preferred_column = 0;
-                            }
+                                let mut x = tc.preferred_column;
+                                let mut y = tb.cursor_visual_pos().y - 1;
+
+                                // If there's a selection we put the cursor above it.
+                                if let Some((beg, _)) = tb.selection_range() {
+                                    unsafe { tb.set_cursor(beg) };
+                                    x = tb.cursor_visual_pos().x;
+                                    y = tb.cursor_visual_pos().y - 1;
+                                    tc.preferred_column = x;
+                                }
 
-                            tb.cursor_move_to_visual(Point { x, y });
+                                // If the cursor was already on the first line,
+                                // move it to the start of the buffer.
+                                if y < 0 {
+                                    x = 0;
+                                    tc.preferred_column = 0;
+                                }
+
+                                tb.cursor_move_to_visual(Point { x, y });
+                            });
                         }
                         kbmod::CTRL => {
                             tc.scroll_offset.y -= 1;
                             make_cursor_visible = false;
                         }
                         kbmod::SHIFT => {
-                            // If the cursor was already on the first line,
-                            // move it to the start of the buffer.
-                            if tb.cursor_visual_pos().y == 0 {
-                                tc.preferred_column = 0;
-                            }
+                            let multi = tb.caret_count() > 1;
+                            tb.for_each_caret(|tb| {
+                                // With multiple cursors, each one keeps its own column.
+                                if multi {
+                                    tc.preferred_column = tb.cursor_visual_pos().x;
+                                }
+
+                                // If the cursor was already on the first line,
+                                // move it to the start of the buffer.
+                                if tb.cursor_visual_pos().y == 0 {
+                                    tc.preferred_column = 0;
+                                }
 
-                            tb.selection_update_visual(Point {
-                                x: tc.preferred_column,
-                                y: tb.cursor_visual_pos().y - 1,
+                                tb.selection_update_visual(Point {
+                                    x: tc.preferred_column,
+                                    y: tb.cursor_visual_pos().y - 1,
+                                });
                             });
                         }
                         kbmod::ALT => tb.move_selected_lines(MoveLineDirection::Up),
-                        kbmod::CTRL_ALT => {
-                            // TODO: Add cursor above
-                        }
+                        kbmod::ALT_SHIFT => tb.block_selection_update_delta(Point { x: 0, y: -1 }),
+                        kbmod::CTRL_ALT => tb.add_caret_vertical(-1, tc.preferred_column),
+                        kbmod::CTRL_SHIFT => tb.fold_at_cursor(),
+                        kbmod::CTRL_ALT_SHIFT => tb.fold_all(),
                         _ => return false,
                     }
                 }
+                vk::RIGHT if modifiers == kbmod::ALT_SHIFT && !cfg!(target_os = "macos") => {
+                    tb.block_selection_update_delta(Point { x: 1, y: 0 });
+                }
                 vk::RIGHT => {
                     let granularity = if modifiers.contains(KBMOD_FOR_WORD_NAV) {
                         CursorMovement::Word
                     } else {
                         CursorMovement::Grapheme
                     };
-                    if modifiers.contains(kbmod::SHIFT) {
-                        tb.selection_update_delta(granularity, 1);
-                    } else if let Some((_, end)) = tb.selection_range() {
-                        unsafe { tb.set_cursor(end) };
-                    } else {
-                        tb.cursor_move_delta(granularity, 1);
-                    }
+                    tb.for_each_caret(|tb| {
+                        if modifiers.contains(kbmod::SHIFT) {
+                            tb.selection_update_delta(granularity, 1);
+                        } else if let Some((_, end)) = tb.selection_range() {
+                            unsafe { tb.set_cursor(end) };
+                        } else {
+                            tb.cursor_move_delta(granularity, 1);
+                        }
+                    });
                 }
                 vk::DOWN => {
                     if single_line {
@@ -2584,54 +2694,72 @@ impl<'a> Context<'a, '_> {
                     }
                     match modifiers {
                         kbmod::NONE => {
-                            let mut x = tc.preferred_column;
-                            let mut y = tb.cursor_visual_pos().y + 1;
-
-                            // If there's a selection we put the cursor below it.
-                            if let Some((_, end)) = tb.selection_range() {
-                                x = end.visual_pos.x;
-                                y = end.visual_pos.y + 1;
-                                tc.preferred_column = x;
-                            }
+                            let multi = tb.caret_count() > 1;
+                            tb.for_each_caret(|tb| {
+                                // With multiple cursors, each one keeps its own column.
+                                if multi {
+                                    tc.preferred_column = tb.cursor_visual_pos().x;
+                                }
 
-                            // If the cursor was already on the last line,
-                            // move it to the end of the buffer.
-                            if y >= tb.visual_line_count() {
-                                x = CoordType::MAX;
-                            }
+                                let mut x = tc.preferred_column;
+                                let mut y = tb.cursor_visual_pos().y + 1;
 
-                            tb.cursor_move_to_visual(Point { x, y });
+                                // If there's a selection we put the cursor below it.
+                                if let Some((_, end)) = tb.selection_range() {
+                                    unsafe { tb.set_cursor(end) };
+                                    x = tb.cursor_visual_pos().x;
+                                    y = tb.cursor_visual_pos().y + 1;
+                                    tc.preferred_column = x;
+                                }
 
-                            // If we fell into the `if y >= tb.get_visual_line_count()` above, we wanted to
-                            // update the `preferred_column` but didn't know yet what it was. Now we know!
-                            if x == CoordType::MAX {
-                                tc.preferred_column = tb.cursor_visual_pos().x;
-                            }
+                                // If the cursor was already on the last line,
+                                // move it to the end of the buffer.
+                                if y >= tb.visual_line_count() {
+                                    x = CoordType::MAX;
+                                }
+
+                                tb.cursor_move_to_visual(Point { x, y });
+
+                                // If we fell into the `if y >= tb.get_visual_line_count()` above, we wanted to
+                                // update the `preferred_column` but didn't know yet what it was. Now we know!
+                                if x == CoordType::MAX {
+                                    tc.preferred_column = tb.cursor_visual_pos().x;
+                                }
+                            });
                         }
                         kbmod::CTRL => {
                             tc.scroll_offset.y += 1;
                             make_cursor_visible = false;
                         }
                         kbmod::SHIFT => {
-                            // If the cursor was already on the last line,
-                            // move it to the end of the buffer.
-                            if tb.cursor_visual_pos().y >= tb.visual_line_count() - 1 {
-                                tc.preferred_column = CoordType::MAX;
-                            }
+                            let multi = tb.caret_count() > 1;
+                            tb.for_each_caret(|tb| {
+                                // With multiple cursors, each one keeps its own column.
+                                if multi {
+                                    tc.preferred_column = tb.cursor_visual_pos().x;
+                                }
 
-                            tb.selection_update_visual(Point {
-                                x: tc.preferred_column,
-                                y: tb.cursor_visual_pos().y + 1,
-                            });
+                                // If the cursor was already on the last line,
+                                // move it to the end of the buffer.
+                                if tb.cursor_visual_pos().y >= tb.visual_line_count() - 1 {
+                                    tc.preferred_column = CoordType::MAX;
+                                }
 
-                            if tc.preferred_column == CoordType::MAX {
-                                tc.preferred_column = tb.cursor_visual_pos().x;
-                            }
+                                tb.selection_update_visual(Point {
+                                    x: tc.preferred_column,
+                                    y: tb.cursor_visual_pos().y + 1,
+                                });
+
+                                if tc.preferred_column == CoordType::MAX {
+                                    tc.preferred_column = tb.cursor_visual_pos().x;
+                                }
+                            });
                         }
                         kbmod::ALT => tb.move_selected_lines(MoveLineDirection::Down),
-                        kbmod::CTRL_ALT => {
-                            // TODO: Add cursor above
-                        }
+                        kbmod::ALT_SHIFT => tb.block_selection_update_delta(Point { x: 0, y: 1 }),
+                        kbmod::CTRL_ALT => tb.add_caret_vertical(1, tc.preferred_column),
+                        kbmod::CTRL_SHIFT => tb.unfold_at_cursor(),
+                        kbmod::CTRL_ALT_SHIFT => tb.unfold_all(),
                         _ => return false,
                     }
                 }
@@ -2647,6 +2775,7 @@ impl<'a> Context<'a, '_> {
                 },
                 vk::A => match modifiers {
                     kbmod::CTRL => tb.select_all(),
+                    kbmod::ALT_SHIFT => tb.toggle_block_comment(),
                     _ => return false,
                 },
                 vk::B => match modifiers {
@@ -2657,6 +2786,10 @@ impl<'a> Context<'a, '_> {
                     }
                     _ => return false,
                 },
+                vk::D => match modifiers {
+                    kbmod::CTRL => tb.add_caret_next_occurrence(),
+                    _ => return false,
+                },
                 vk::F => match modifiers {
                     kbmod::ALT if cfg!(target_os = "macos") => {
                         // On macOS, terminals commonly emit the Emacs style
@@ -2685,6 +2818,15 @@ impl<'a> Context<'a, '_> {
                     kbmod::CTRL => tb.paste(self.clipboard_ref()),
                     _ => return false,
                 },
+                vk::OEM_2 => match modifiers {
+                    kbmod::CTRL => tb.toggle_line_comment(),
+                    _ => return false,
+                },
+                // Terminals can't tell Ctrl+Shift+\ apart from Ctrl+\.
+                vk::OEM_5 => match modifiers {
+                    kbmod::CTRL | kbmod::CTRL_SHIFT => tb.jump_to_matching_bracket(),
+                    _ => return false,
+                },
                 vk::Y => match modifiers {
                     kbmod::CTRL => tb.redo(),
                     _ => return false,
@@ -3311,23 +3453,9 @@ impl<'a> Context<'a, '_> {
     }
 
     fn menubar_shortcut(&mut self, shortcut: InputKey) {
-        let shortcut_letter = shortcut.value() as u8 as char;
-        if shortcut_letter.is_ascii_uppercase() {
-            let mut shortcut_text = ArenaString::new_in(self.arena());
-            if shortcut.modifiers_contains(kbmod::CTRL) {
-                shortcut_text.push_str(self.tui.modifier_translations.ctrl);
-                shortcut_text.push('+');
-            }
-            if shortcut.modifiers_contains(kbmod::ALT) {
-                shortcut_text.push_str(self.tui.modifier_translations.alt);
-                shortcut_text.push('+');
-            }
-            if shortcut.modifiers_contains(kbmod::SHIFT) {
-                shortcut_text.push_str(self.tui.modifier_translations.shift);
-                shortcut_text.push('+');
-            }
-            shortcut_text.push(shortcut_letter);
+        let shortcut_text = self.shortcut_text(shortcut);
 
+        if !shortcut_text.is_empty() {
             self.label("shortcut", &shortcut_text);
         } else {
             self.block_begin("shortcut");